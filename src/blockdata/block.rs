@@ -1,11 +1,15 @@
 /// Rust Blockchain Sample Project
 
-use hashes::{sha256d, Hash};
+use std::io;
 
-use util;
+use hashes::{sha256d, Hash, HashEngine};
+
+use blockdata;
 use blockdata::Error::{BlockProofOfWorkError, BlockTargetError};
+use consensus::encode::{self, serialize, Encodable, Decodable};
+use consensus::params::{Network, max_target};
 use util::hash::{BlockchainHash, MerkleRoot, blockchain_merkle_root};
-use util::uint::Uint256; // not implemented
+use util::uint::Uint256;
 
 use blockdata::transaction::Transaction;
 
@@ -40,20 +44,57 @@ pub struct Block {
     pub txdata: Vec<Transaction>
 }
 
+/// Prefix of the coinbase output script committing to the witness data:
+/// OP_RETURN, push of 36 bytes, and the 4-byte commitment header
+pub const WITNESS_COMMITMENT_PREFIX: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
 impl Block {
     /// Check if header merkle root matched the merkle root of the transactions
     pub fn check_merkle_root (&self) -> bool {
-        self.header.merkle_root == self.merke_root()
+        self.header.merkle_root == self.merkle_root()
+    }
+
+    /// Compute the merkle root of the witness transaction ids,
+    /// the coinbase counting as all zeroes
+    pub fn witness_root(&self) -> sha256d::Hash {
+        let mut wtxids: Vec<sha256d::Hash> = self.txdata.iter().map(|tx| tx.wtxid()).collect();
+        if let Some(first) = wtxids.first_mut() {
+            *first = Default::default();
+        }
+        blockchain_merkle_root(wtxids)
+    }
+
+    /// Return the witness commitment of the coinbase - the last output matching the pattern
+    pub fn witness_commitment(&self) -> Option<sha256d::Hash> {
+        let coinbase = self.txdata.first()?;
+        coinbase.output_ent.iter().rev()
+            .map(|txout| txout.pkscript.as_bytes())
+            .find(|script| script.len() >= 38 && script[0..6] == WITNESS_COMMITMENT_PREFIX)
+            .map(|script| sha256d::Hash::from_slice(&script[6..38]).unwrap())
     }
 
     /// Check if witness commitment in coinbase is matching the transactions list
-    pub check_witness_commit(&self) -> bool {
-        /// ...
+    pub fn check_witness_commit(&self) -> bool {
+        let commitment = match self.witness_commitment() {
+            Some(commitment) => commitment,
+            // Without a commitment no transaction may carry witness data
+            None => return !self.txdata.iter().any(|tx| tx.has_witness()),
+        };
+        // The coinbase witness holds the single 32-byte reserved value
+        let reserved = match self.txdata[0].input_ent.first().map(|txin| &txin.witness) {
+            Some(witness) if witness.len() == 1 && witness[0].len() == 32 => &witness[0],
+            _ => return false,
+        };
+        let mut encoder = sha256d::Hash::engine();
+        self.witness_root().encode(&mut encoder).unwrap();
+        encoder.input(reserved);
+        sha256d::Hash::from_engine(encoder) == commitment
     }
-    false
 }
 
-imple MerkleRoot for Block {
+consensus_encoding!(Block, header, txdata);
+
+impl MerkleRoot for Block {
     fn merkle_root(&self) -> sha256d::Hash {
         blockchain_merkle_root(self.txdata.iter().map(|obj| obj.txid()).collect())
     }
@@ -61,17 +102,22 @@ imple MerkleRoot for Block {
 
 impl BlockHeader {
     /// Computes the target [0, T] that a blockhash must land in to be valid
-    pub fn compute_target(&self) -> u256 {
+    pub fn compute_target(&self) -> Uint256 {
+        BlockHeader::compute_target_from_compact(self.target)
+    }
+
+    /// Computes the target value in Uint256 format from float format.
+    pub fn compute_target_from_compact(bits: u32) -> Uint256 {
         // This is a floating-point "compact" encoding originally used by
         // OpenSSL, which satoshi put into consensus code
         // The exponent needs to have 3 subtracted from it, hence
         // this goofy decoding code:
         let (mant, expt) = {
-            let unshifted_expt = self.bits >> 24;
+            let unshifted_expt = bits >> 24;
             if unshifted_expt <= 3 {
-                ((self.bits & 0xFFFFFF) >> (8 * (3 - unshifted_expt as usize)), 0)
+                ((bits & 0xFFFFFF) >> (8 * (3 - unshifted_expt as usize)), 0)
             } else {
-                (self.bits & 0xFFFFFF, 8 * ((self.bits >> 24) - 3))
+                (bits & 0xFFFFFF, 8 * ((bits >> 24) - 3))
             }
         };
 
@@ -85,11 +131,11 @@ impl BlockHeader {
 
     /// Computes the target value in float format from Uint256 format.
     pub fn compute_compact_target_from_u256(value: &Uint256) -> u32 {
-        let mut size = (value.bits() + 7) / 8;
+        let mut size = ((value.bits() + 7) / 8) as u32;
         let mut compact = if size <= 3 {
             (value.low_u64() << (8 * (3 - size))) as u32
         } else {
-            let bn = *value >> (8 * (size - 3));
+            let bn = *value >> (8 * (size - 3) as usize);
             bn.low_u32()
         };
 
@@ -98,54 +144,91 @@ impl BlockHeader {
             size += 1;
         }
 
-        compact | (size << 24) as u32
+        compact | (size << 24)
     }
 
     /// Compute the popular "difficulty" measure for mining
     pub fn compute_difficulty(&self, network: Network) -> u64 {
-        // implement max_target()
-        (max_target(network) / self.target()).low_u64()
+        (max_target(network) / self.compute_target()).low_u64()
     }
 
     /// Validate the block proof of work
-    pub fn validate_pow(&self, target_required: &Uint256) -> Result<(), util::Error> {
-        use byteorder::{ByteOrder, LittleEndian};
-
-        let target =&self.compute_target();
+    pub fn validate_pow(&self, target_required: &Uint256) -> Result<(), blockdata::Error> {
+        let target = &self.compute_target();
         if target != target_required {
             return Err(BlockTargetError);
         }
         let data: [u8; 32] = self.blockchain_hash().into_inner();
-        let mut ret = [0u64; 4];
-        LittleEndian::read_u64_into(&data, &mut ret);
-        let hash = &Uint256(ret);
+        let hash = &Uint256::from_le_bytes(&data);
         if hash <= target { Ok(()) } else { Err(BlockProofOfWorkError) }
     }
 
     /// Return the total work of the block
     pub fn total_work(&self) -> Uint256 {
         // 2**256 / (target + 1) == ~target / (target+1) + 1    (eqn shamelessly stolen from bitcoind)
-        let mut ret = !self.target();
-        let mut ret1 = self.target();
+        let mut ret = !self.compute_target();
+        let mut ret1 = self.compute_target();
         ret1.increment();
         ret = ret / ret1;
         ret.increment();
         ret
     }
 
-    /// Construct the next block
-    pub fn next(&self, value: T) -> Block {
-        // implement
+    /// Construct the block following this header from its transactions,
+    /// the coinbase first; the nonce is left for the miner to find
+    pub fn next(&self, txdata: Vec<Transaction>, timestamp: u32, target: u32) -> Block {
+        let merkle_root = blockchain_merkle_root(txdata.iter().map(|tx| tx.txid()).collect());
+        Block {
+            header: BlockHeader {
+                block_version:  self.block_version,
+                prev_blockhash: self.blockchain_hash(),
+                merkle_root:    merkle_root,
+                timestamp:      timestamp,
+                height:         self.height + 1,
+                target:         target,
+                nonce:          0,
+            },
+            txdata: txdata,
+        }
     }
 }
 
 impl BlockchainHash for BlockHeader {
     fn blockchain_hash(&self) -> sha256d::Hash {
-        // implement serialize()
         sha256d::Hash::hash(&serialize(self))
     }
 }
 
+/// The header is serialized in the 80-byte Bitcoin format; `height` is
+/// derived from the position of the header in the chain and is not part of it
+impl Encodable for BlockHeader {
+    #[inline]
+    fn encode<Wr: io::Write>(&self, mut wr: Wr) -> Result<usize, encode::Error> {
+        let length = self.block_version.encode(&mut wr)?
+            + self.prev_blockhash.encode(&mut wr)?
+            + self.merkle_root.encode(&mut wr)?
+            + self.timestamp.encode(&mut wr)?
+            + self.target.encode(&mut wr)?
+            + self.nonce.encode(&mut wr)?;
+        Ok(length)
+    }
+}
+
+impl Decodable for BlockHeader {
+    #[inline]
+    fn decode<Decoder: io::Read>(mut decoder: Decoder) -> Result<Self, encode::Error> {
+        Ok(BlockHeader {
+            block_version:  Decodable::decode(&mut decoder)?,
+            prev_blockhash: Decodable::decode(&mut decoder)?,
+            merkle_root:    Decodable::decode(&mut decoder)?,
+            timestamp:      Decodable::decode(&mut decoder)?,
+            height:         0,
+            target:         Decodable::decode(&mut decoder)?,
+            nonce:          Decodable::decode(&mut decoder)?,
+        })
+    }
+}
+
 impl BlockchainHash for Block {
     fn blockchain_hash(&self) -> sha256d::Hash {
         self.header.blockchain_hash()
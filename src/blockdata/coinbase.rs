@@ -0,0 +1,134 @@
+/// Construction of coinbase transactions
+///
+/// The coinbase script starts with the block height (BIP34), followed by an
+/// extra nonce miners roll once the header nonce range is exhausted and any
+/// extra data such as a pool tag. Blocks carrying witness data commit to the
+/// witness transaction ids of the block in an additional coinbase output.
+
+use hashes::{sha256d, Hash, HashEngine};
+
+use blockdata;
+use blockdata::block::WITNESS_COMMITMENT_PREFIX;
+use blockdata::script::{Builder, Script};
+use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut, SEQUENCE_FINAL};
+use consensus::encode::Encodable;
+
+/// Minimum size of a coinbase script
+pub const MIN_COINBASE_SCRIPT_SIZE: usize = 2;
+
+/// Maximum size of a coinbase script
+pub const MAX_COINBASE_SCRIPT_SIZE: usize = 100;
+
+/// Value of the coinbase witness the witness commitment is computed with
+pub const WITNESS_RESERVED_VALUE: [u8; 32] = [0; 32];
+
+/// Return the coinbase script prefix holding the block height (BIP34)
+pub fn height_prefix(height: u64) -> Vec<u8> {
+    Builder::new().push_int(height as i64).as_bytes().to_vec()
+}
+
+/// Return the output script committing to the witness root of a block
+pub fn witness_commitment_script(witness_root: &sha256d::Hash, reserved: &[u8]) -> Script {
+    let mut encoder = sha256d::Hash::engine();
+    witness_root.encode(&mut encoder).unwrap();
+    encoder.input(reserved);
+    let commitment = sha256d::Hash::from_engine(encoder);
+
+    let mut script = WITNESS_COMMITMENT_PREFIX.to_vec();
+    script.extend_from_slice(&commitment.into_inner());
+    Script::from(script)
+}
+
+/// Builder of the coinbase transaction of a block
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CoinbaseBuilder {
+    height: u64,
+    extra_nonce: u64,
+    extra_data: Vec<u8>,
+    payouts: Vec<TxOut>,
+    witness_root: Option<sha256d::Hash>,
+}
+
+impl CoinbaseBuilder {
+    /// Start the coinbase of a block at the given height
+    pub fn new(height: u64) -> CoinbaseBuilder {
+        CoinbaseBuilder {
+            height:       height,
+            extra_nonce:  0,
+            extra_data:   Vec::new(),
+            payouts:      Vec::new(),
+            witness_root: None,
+        }
+    }
+
+    /// Set the extra nonce
+    pub fn extra_nonce(mut self, extra_nonce: u64) -> CoinbaseBuilder {
+        self.extra_nonce = extra_nonce;
+        self
+    }
+
+    /// Set data appended to the coinbase script
+    pub fn extra_data(mut self, data: &[u8]) -> CoinbaseBuilder {
+        self.extra_data = data.to_vec();
+        self
+    }
+
+    /// Add an output paying `value` to `pkscript`
+    pub fn pay_to(mut self, pkscript: Script, value: u64) -> CoinbaseBuilder {
+        self.payouts.push(TxOut { value_out: value, pkscript: pkscript });
+        self
+    }
+
+    /// Commit to the witness root of the block, the coinbase counting as zero
+    pub fn witness_root(mut self, witness_root: sha256d::Hash) -> CoinbaseBuilder {
+        self.witness_root = Some(witness_root);
+        self
+    }
+
+    /// Return the coinbase script. The extra nonce is always pushed as 8 bytes,
+    /// so rolling it never changes the size of the transaction
+    pub fn script_sig(&self) -> Script {
+        let mut nonce = Vec::with_capacity(8);
+        self.extra_nonce.encode(&mut nonce).unwrap();
+        let mut builder = Builder::new()
+            .push_int(self.height as i64)
+            .push_slice(&nonce);
+        if !self.extra_data.is_empty() {
+            builder = builder.push_slice(&self.extra_data);
+        }
+        builder.into_script()
+    }
+
+    /// Build the coinbase transaction
+    pub fn build(&self) -> Result<Transaction, blockdata::Error> {
+        let script_sig = self.script_sig();
+        if script_sig.len() < MIN_COINBASE_SCRIPT_SIZE || script_sig.len() > MAX_COINBASE_SCRIPT_SIZE {
+            return Err(blockdata::Error::TransactionMalformedError);
+        }
+
+        let mut input = TxIn {
+            previous_out: OutPoint::null(),
+            sig_script:   script_sig,
+            sequence:     SEQUENCE_FINAL,
+            witness:      Vec::new(),
+        };
+        let mut output_ent = self.payouts.clone();
+        if let Some(ref witness_root) = self.witness_root {
+            input.witness = vec![WITNESS_RESERVED_VALUE.to_vec()];
+            output_ent.push(TxOut {
+                value_out: 0,
+                pkscript:  witness_commitment_script(witness_root, &WITNESS_RESERVED_VALUE),
+            });
+        }
+        if output_ent.is_empty() {
+            return Err(blockdata::Error::TransactionMalformedError);
+        }
+
+        Ok(Transaction {
+            version:    1,
+            lock_time:  0,
+            input_ent:  vec![input],
+            output_ent: output_ent,
+        })
+    }
+}
@@ -0,0 +1,29 @@
+/// Hard-coded data every node agrees on, starting with the genesis block
+
+use hashes::sha256d;
+use hashes::hex::FromHex;
+
+use blockdata::block::BlockHeader;
+use consensus::params::Network;
+
+/// Merkle root of the genesis block - the hash of its only (coinbase) transaction
+const GENESIS_MERKLE_ROOT: &str = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+
+/// Return the header of the first block of the network
+pub fn genesis_header(network: Network) -> BlockHeader {
+    let merkle_root = sha256d::Hash::from_hex(GENESIS_MERKLE_ROOT).unwrap();
+    let (timestamp, target, nonce) = match network {
+        Network::Bitcoin => (1231006505, 0x1d00ffff, 2083236893),
+        Network::Testnet => (1296688602, 0x1d00ffff, 414098458),
+        Network::Regtest => (1296688602, 0x207fffff, 2),
+    };
+    BlockHeader {
+        block_version:  1,
+        prev_blockhash: Default::default(),
+        merkle_root:    merkle_root,
+        timestamp:      timestamp,
+        height:         0,
+        target:         target,
+        nonce:          nonce,
+    }
+}
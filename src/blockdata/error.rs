@@ -4,35 +4,70 @@ use std::{error, fmt};
 
 #[derive(Debug)]
 pub enum Error {
-    // ...
+    // Block errors
     /// Hash value inside the blockcheader is not below the target
     BlockProofOfWorkError,
     /// `Target` field inside the blockheader did not match the expected one
     BlockTargetError,
+    /// Merkle root inside the blockheader does not match the transactions
+    BlockMerkleRootError,
+    /// Block version is below the minimum accepted one
+    BlockVersionObsoleteError,
+    /// Block is already known
+    BlockDuplicateError,
+    /// Block timestamp is not above the median time of the previous blocks
+    BlockTimeTooOldError,
+    /// Block timestamp is too far ahead of the network-adjusted time
+    BlockTimeTooNewError,
+    /// Block forks from the chain below the last checkpoint
+    BlockCheckpointError,
+    /// Block violates consensus rules
+    BlockInvalidError,
+
+    // Transaction errors
+    /// Transaction could not be parsed or is structurally broken
+    TransactionMalformedError,
+    /// Transaction violates consensus rules
+    TransactionInvalidError,
+    /// Transaction is already known
+    TransactionDuplicateError,
+    /// Transaction is valid but does not meet the relay policy
+    TransactionNonStandardError,
+    /// Transaction contains an output below the dust limit
+    TransactionDustError,
+    /// Transaction fee is below the minimum relay fee
+    TransactionFeeError,
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Error::BlockProofOfWorkError | Error::BlockTargetError => f.write_str(error::Error::description(self)),
-        }
+        f.write_str(error::Error::description(self))
     }
 }
 
 impl error::Error for Error {
-    fn reason(&self) -> Option<&error::Error> {
-        match *self {
-            Error::BlockProofOfWorkError(ref e) => Some(e),
-            Error::BlockTargetError(ref e)      => Some(e),
-        }
+    fn cause(&self) -> Option<&error::Error> {
+        None
     }
 
     /// Use hardcoded string value saved inside the stack
     fn description(&self) -> &str {
         match *self {
-            Error::BlockProofOfWorkError => "block target not below the target",
-            Error::BlockTargetError      => "incorrect block target",
-
+            Error::BlockProofOfWorkError       => "block target not below the target",
+            Error::BlockTargetError            => "incorrect block target",
+            Error::BlockMerkleRootError        => "block merkle root mismatch",
+            Error::BlockVersionObsoleteError   => "obsolete block version",
+            Error::BlockDuplicateError         => "duplicate block",
+            Error::BlockTimeTooOldError        => "block timestamp too early",
+            Error::BlockTimeTooNewError        => "block timestamp too far in the future",
+            Error::BlockCheckpointError        => "block forks below the last checkpoint",
+            Error::BlockInvalidError           => "invalid block",
+            Error::TransactionMalformedError   => "malformed transaction",
+            Error::TransactionInvalidError     => "invalid transaction",
+            Error::TransactionDuplicateError   => "duplicate transaction",
+            Error::TransactionNonStandardError => "non-standard transaction",
+            Error::TransactionDustError        => "transaction output below dust limit",
+            Error::TransactionFeeError         => "insufficient transaction fee",
         }
     }
 }
@@ -0,0 +1,311 @@
+/// Compact block filters (BIP158)
+///
+/// The basic filter of a block is a Golomb-coded set of every output script
+/// created by the block, except data carriers, and of every script spent by
+/// its inputs. Each element is hashed with SipHash-2-4, keyed with the first
+/// 16 bytes of the block hash, into the range `[0, N * M)`; the sorted hashes
+/// are stored as Golomb-Rice coded differences with parameter `P`.
+///
+/// Filters of a chain are committed to by filter headers, each one the double
+/// SHA256 of the filter hash and the previous filter header, starting from
+/// zeroes before the genesis block.
+
+use std::collections::HashSet;
+use std::io;
+
+use hashes::{sha256d, Hash, HashEngine};
+
+use blockdata::block::Block;
+use blockdata::script::Script;
+use consensus::encode::{self, serialize, Decodable, VarInt};
+use util::hash::{BlockchainHash, siphash24};
+
+/// Type of the basic filter
+pub const BASIC_FILTER_TYPE: u8 = 0;
+
+/// Golomb-Rice parameter of the basic filter
+const BASIC_FILTER_P: u8 = 19;
+
+/// Inverse false positive rate of the basic filter
+const BASIC_FILTER_M: u64 = 784_931;
+
+/// Writes bits, most significant first
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// Bits used in the last byte, 8 when it is full
+    used: u8,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter { bytes: Vec::new(), used: 8 }
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        if self.used == 8 {
+            self.bytes.push(0);
+            self.used = 0;
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> self.used;
+        }
+        self.used += 1;
+    }
+
+    /// Write the `count` low bits of a value
+    fn write_bits(&mut self, value: u64, count: u8) {
+        for shift in (0..count).rev() {
+            self.write_bit((value >> shift) & 1 == 1);
+        }
+    }
+}
+
+/// Reads bits written by `BitWriter`
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> BitReader<'a> {
+        BitReader { bytes: bytes, position: 0 }
+    }
+
+    fn read_bit(&mut self) -> Result<bool, encode::Error> {
+        let byte = self.bytes.get(self.position / 8).ok_or(encode::Error::ErrorParse("filter ended early"))?;
+        let bit = byte & (0x80 >> (self.position % 8)) != 0;
+        self.position += 1;
+        Ok(bit)
+    }
+
+    fn read_bits(&mut self, count: u8) -> Result<u64, encode::Error> {
+        let mut value = 0;
+        for _ in 0..count {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+        Ok(value)
+    }
+}
+
+/// Golomb-coded set parameters bound to a block
+struct GcsKey {
+    k0: u64,
+    k1: u64,
+    p: u8,
+    m: u64,
+}
+
+impl GcsKey {
+    /// Key of the basic filter of a block
+    fn basic(block_hash: &sha256d::Hash) -> GcsKey {
+        let hash = block_hash.into_inner();
+        let mut k0 = [0u8; 8];
+        let mut k1 = [0u8; 8];
+        k0.copy_from_slice(&hash[0..8]);
+        k1.copy_from_slice(&hash[8..16]);
+        GcsKey {
+            k0: u64::from_le_bytes(k0),
+            k1: u64::from_le_bytes(k1),
+            p:  BASIC_FILTER_P,
+            m:  BASIC_FILTER_M,
+        }
+    }
+
+    /// Map an element uniformly into `[0, n * m)`
+    fn hash_to_range(&self, element: &[u8], n: u64) -> u64 {
+        let hash = siphash24(self.k0, self.k1, element);
+        ((hash as u128 * (n as u128 * self.m as u128)) >> 64) as u64
+    }
+
+    /// Return the sorted hashes of the elements of a set of `n` elements
+    fn hashed_set<'a, I: Iterator<Item = &'a [u8]>>(&self, elements: I, n: u64) -> Vec<u64> {
+        let mut hashes: Vec<u64> = elements.map(|element| self.hash_to_range(element, n)).collect();
+        hashes.sort();
+        hashes
+    }
+
+    /// Encode a set of distinct elements
+    fn encode(&self, elements: &[&[u8]]) -> Vec<u8> {
+        let n = elements.len() as u64;
+        let mut content = serialize(&VarInt(n));
+        let mut writer = BitWriter::new();
+        let mut last = 0;
+        for hash in self.hashed_set(elements.iter().cloned(), n) {
+            let delta = hash - last;
+            // Quotient in unary, remainder in binary
+            for _ in 0..(delta >> self.p) {
+                writer.write_bit(true);
+            }
+            writer.write_bit(false);
+            writer.write_bits(delta, self.p);
+            last = hash;
+        }
+        content.extend(writer.bytes);
+        content
+    }
+
+    /// Check if any of the queried elements may be in an encoded set
+    fn match_any(&self, content: &[u8], queries: &[&[u8]]) -> Result<bool, encode::Error> {
+        let mut decoder = io::Cursor::new(content);
+        let n = VarInt::decode(&mut decoder)?.0;
+        if n == 0 || queries.is_empty() {
+            return Ok(false);
+        }
+        // Every element takes at least `P + 1` bits
+        let data = &content[decoder.position() as usize..];
+        if n > (data.len() as u64 * 8) / (self.p as u64 + 1) {
+            return Err(encode::Error::ErrorParse("filter element count exceeds its data"));
+        }
+        let queries = self.hashed_set(queries.iter().cloned(), n);
+        let mut reader = BitReader::new(data);
+
+        let mut queries = queries.into_iter().peekable();
+        let mut value = 0;
+        for _ in 0..n {
+            let mut quotient = 0;
+            while reader.read_bit()? {
+                quotient += 1;
+            }
+            value += (quotient << self.p) + reader.read_bits(self.p)?;
+            while let Some(query) = queries.peek().cloned() {
+                if query == value {
+                    return Ok(true);
+                }
+                if query > value {
+                    break;
+                }
+                queries.next();
+            }
+            if queries.peek().is_none() {
+                break;
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Compact filter of a block
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlockFilter {
+    /// Type of the filter
+    pub filter_type: u8,
+    /// Hash of the filtered block
+    pub block_hash: sha256d::Hash,
+    /// Encoded filter
+    pub content: Vec<u8>,
+}
+
+impl BlockFilter {
+    /// Build the basic filter of a block from the block and the output
+    /// scripts spent by its inputs
+    pub fn new_basic(block: &Block, spent_scripts: &[Script]) -> BlockFilter {
+        let mut elements: HashSet<&[u8]> = HashSet::new();
+        for tx in block.txdata.iter() {
+            for txout in tx.output_ent.iter() {
+                let script = txout.pkscript.as_bytes();
+                if !script.is_empty() && !txout.pkscript.is_op_return() {
+                    elements.insert(script);
+                }
+            }
+        }
+        for script in spent_scripts.iter().filter(|script| !script.is_empty()) {
+            elements.insert(script.as_bytes());
+        }
+
+        let block_hash = block.blockchain_hash();
+        let elements: Vec<&[u8]> = elements.into_iter().collect();
+        BlockFilter {
+            filter_type: BASIC_FILTER_TYPE,
+            block_hash:  block_hash,
+            content:     GcsKey::basic(&block_hash).encode(&elements),
+        }
+    }
+
+    /// Return the set parameters of the filter
+    fn key(&self) -> Result<GcsKey, encode::Error> {
+        match self.filter_type {
+            BASIC_FILTER_TYPE => Ok(GcsKey::basic(&self.block_hash)),
+            _                 => Err(encode::Error::ErrorParse("unknown filter type")),
+        }
+    }
+
+    /// Check if any of the scripts may be created or spent by the block;
+    /// false positives happen at a rate of `1 / M` per script
+    pub fn match_any(&self, scripts: &[&[u8]]) -> Result<bool, encode::Error> {
+        self.key()?.match_any(&self.content, scripts)
+    }
+
+    /// Hash of the encoded filter
+    pub fn filter_hash(&self) -> sha256d::Hash {
+        sha256d::Hash::hash(&self.content)
+    }
+
+    /// Filter header following the header of the previous block's filter
+    pub fn filter_header(&self, previous: &sha256d::Hash) -> sha256d::Hash {
+        filter_header(&self.filter_hash(), previous)
+    }
+}
+
+/// Chain a filter hash onto the previous filter header
+pub fn filter_header(filter_hash: &sha256d::Hash, previous: &sha256d::Hash) -> sha256d::Hash {
+    let mut engine = sha256d::Hash::engine();
+    engine.input(&filter_hash.into_inner());
+    engine.input(&previous.into_inner());
+    sha256d::Hash::from_engine(engine)
+}
+
+consensus_encoding!(BlockFilter, filter_type, block_hash, content);
+
+#[cfg(test)]
+mod tests {
+    use hashes::sha256d;
+    use hashes::hex::FromHex;
+
+    use blockdata::block::Block;
+    use consensus::encode::deserialize;
+    use util::hash::BlockchainHash;
+    use super::{BlockFilter, BASIC_FILTER_TYPE};
+
+    /// Genesis block of the test network
+    const TESTNET_GENESIS: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4adae5494dffff001d1aa4ae180101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+
+    #[test]
+    fn basic_filter_vectors() {
+        // First vector of the BIP158 testnet test vectors
+        let block: Block = deserialize(&Vec::<u8>::from_hex(TESTNET_GENESIS).unwrap()).unwrap();
+        assert_eq!(block.blockchain_hash(),
+                   sha256d::Hash::from_hex("000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943").unwrap());
+
+        let filter = BlockFilter::new_basic(&block, &[]);
+        assert_eq!(filter.filter_type, BASIC_FILTER_TYPE);
+        assert_eq!(filter.content, Vec::<u8>::from_hex("019dfca8").unwrap());
+        assert_eq!(filter.filter_header(&Default::default()),
+                   sha256d::Hash::from_hex("21584579b7eb08997773e5aeff3a7f932700042d0ed2a6129012b7d7ae81b750").unwrap());
+
+        let script = block.txdata[0].output_ent[0].pkscript.as_bytes();
+        assert_eq!(filter.match_any(&[script]).unwrap(), true);
+        assert_eq!(filter.match_any(&[&[0x51u8][..]]).unwrap(), false);
+    }
+
+    #[test]
+    fn empty_filter() {
+        // Blocks whose only output is a data carrier have an empty filter
+        let mut block: Block = deserialize(&Vec::<u8>::from_hex(TESTNET_GENESIS).unwrap()).unwrap();
+        block.txdata[0].output_ent[0].pkscript = vec![0x6a].into();
+        let filter = BlockFilter::new_basic(&block, &[]);
+        assert_eq!(filter.content, vec![0x00]);
+        assert_eq!(filter.match_any(&[&[0x6au8][..]]).unwrap(), false);
+    }
+
+    #[test]
+    fn oversized_element_count() {
+        let block: Block = deserialize(&Vec::<u8>::from_hex(TESTNET_GENESIS).unwrap()).unwrap();
+        let mut filter = BlockFilter::new_basic(&block, &[]);
+        // Three bytes of data cannot hold two elements of 20 bits
+        filter.content[0] = 0x02;
+        assert!(filter.match_any(&[&[0x51u8][..]]).is_err());
+        // Nor can they hold 2^64 - 1 elements, which must not overflow the range
+        filter.content = Vec::<u8>::from_hex("ffffffffffffffffff9dfca8").unwrap();
+        assert!(filter.match_any(&[&[0x51u8][..]]).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+/// Partial merkle trees (BIP37)
+///
+/// A partial merkle tree proves that some transactions are part of a block
+/// with the given merkle root, without sending the other transactions. The tree
+/// is walked depth-first; one flag bit per visited node tells whether the node
+/// is an ancestor of a matched transaction. Below a node without a match only
+/// its hash is sent, matched leaves are sent as their transaction id.
+
+use std::cmp;
+use std::io;
+
+use hashes::{sha256d, Hash, HashEngine};
+
+use blockdata;
+use blockdata::Error::BlockMerkleRootError;
+use consensus::encode::{self, Encodable, Decodable};
+use validation::block::MAX_BLOCK_WEIGHT;
+
+/// Weight of the smallest transaction, bounding the transactions of a block
+const MIN_TRANSACTION_WEIGHT: usize = 4 * 60;
+
+/// Hash of two merkle tree nodes
+fn merkle_parent(left: &sha256d::Hash, right: &sha256d::Hash) -> sha256d::Hash {
+    let mut engine = sha256d::Hash::engine();
+    engine.input(&left.into_inner());
+    engine.input(&right.into_inner());
+    sha256d::Hash::from_engine(engine)
+}
+
+/// Merkle tree of a block, pruned to the branches of matched transactions
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PartialMerkleTree {
+    /// Number of transactions in the block
+    tx_count: u32,
+    /// Node hashes, in depth-first order
+    hashes: Vec<sha256d::Hash>,
+    /// Node flags, in depth-first order
+    bits: Vec<bool>,
+}
+
+impl PartialMerkleTree {
+    /// Build the tree of the given transaction ids, keeping the branches of
+    /// those whose `matches` flag is set. Without transactions the tree is
+    /// empty, which `extract_matches` rejects like any block without one.
+    pub fn from_txids(txids: &[sha256d::Hash], matches: &[bool]) -> PartialMerkleTree {
+        let mut tree = PartialMerkleTree {
+            tx_count: txids.len() as u32,
+            hashes:   Vec::new(),
+            bits:     Vec::new(),
+        };
+        if txids.is_empty() {
+            return tree;
+        }
+        let mut height = 0;
+        while tree.width(height) > 1 {
+            height += 1;
+        }
+        tree.build(height, 0, txids, matches);
+        tree
+    }
+
+    /// Number of transactions in the block
+    pub fn tx_count(&self) -> u32 {
+        self.tx_count
+    }
+
+    /// Number of nodes at the given height, leaves being at height 0
+    fn width(&self, height: u32) -> u32 {
+        (self.tx_count + (1 << height) - 1) >> height
+    }
+
+    /// Compute the hash of a node from all transaction ids
+    fn node_hash(&self, height: u32, position: u32, txids: &[sha256d::Hash]) -> sha256d::Hash {
+        if height == 0 {
+            return txids[position as usize];
+        }
+        let left = self.node_hash(height - 1, position * 2, txids);
+        // An odd node out is paired with itself
+        let right = if position * 2 + 1 < self.width(height - 1) {
+            self.node_hash(height - 1, position * 2 + 1, txids)
+        } else {
+            left
+        };
+        merkle_parent(&left, &right)
+    }
+
+    fn build(&mut self, height: u32, position: u32, txids: &[sha256d::Hash], matches: &[bool]) {
+        let start = (position << height) as usize;
+        let end = cmp::min(((position + 1) << height) as usize, txids.len());
+        let parent_of_match = matches[start..end].iter().any(|matched| *matched);
+        self.bits.push(parent_of_match);
+        if height == 0 || !parent_of_match {
+            let hash = self.node_hash(height, position, txids);
+            self.hashes.push(hash);
+        } else {
+            self.build(height - 1, position * 2, txids, matches);
+            if position * 2 + 1 < self.width(height - 1) {
+                self.build(height - 1, position * 2 + 1, txids, matches);
+            }
+        }
+    }
+
+    fn extract(&self, height: u32, position: u32, bits_used: &mut usize, hashes_used: &mut usize,
+               matches: &mut Vec<(u32, sha256d::Hash)>) -> Result<sha256d::Hash, blockdata::Error> {
+        let parent_of_match = *self.bits.get(*bits_used).ok_or(BlockMerkleRootError)?;
+        *bits_used += 1;
+        if height == 0 || !parent_of_match {
+            let hash = *self.hashes.get(*hashes_used).ok_or(BlockMerkleRootError)?;
+            *hashes_used += 1;
+            if height == 0 && parent_of_match {
+                matches.push((position, hash));
+            }
+            return Ok(hash);
+        }
+        let left = self.extract(height - 1, position * 2, bits_used, hashes_used, matches)?;
+        let right = if position * 2 + 1 < self.width(height - 1) {
+            let right = self.extract(height - 1, position * 2 + 1, bits_used, hashes_used, matches)?;
+            // Identical siblings would allow forging a different transaction list
+            if right == left {
+                return Err(BlockMerkleRootError);
+            }
+            right
+        } else {
+            left
+        };
+        Ok(merkle_parent(&left, &right))
+    }
+
+    /// Check the tree and return its merkle root together with the matched
+    /// transaction ids and their positions in the block
+    pub fn extract_matches(&self) -> Result<(sha256d::Hash, Vec<(u32, sha256d::Hash)>), blockdata::Error> {
+        if self.tx_count == 0 || self.tx_count as usize > MAX_BLOCK_WEIGHT / MIN_TRANSACTION_WEIGHT
+            || self.hashes.len() > self.tx_count as usize || self.bits.len() < self.hashes.len() {
+            return Err(BlockMerkleRootError);
+        }
+        let mut height = 0;
+        while self.width(height) > 1 {
+            height += 1;
+        }
+        let mut bits_used = 0;
+        let mut hashes_used = 0;
+        let mut matches = Vec::new();
+        let root = self.extract(height, 0, &mut bits_used, &mut hashes_used, &mut matches)?;
+        // Every hash and every byte of flags has to be used
+        if hashes_used != self.hashes.len() || (bits_used + 7) / 8 != (self.bits.len() + 7) / 8 {
+            return Err(BlockMerkleRootError);
+        }
+        Ok((root, matches))
+    }
+}
+
+/// Flags are packed eight to a byte, least significant bit first
+impl Encodable for PartialMerkleTree {
+    fn encode<Wr: io::Write>(&self, mut wr: Wr) -> Result<usize, encode::Error> {
+        let mut flags = vec![0u8; (self.bits.len() + 7) / 8];
+        for (index, bit) in self.bits.iter().enumerate() {
+            flags[index / 8] |= (*bit as u8) << (index % 8);
+        }
+        let length = self.tx_count.encode(&mut wr)?
+            + self.hashes.encode(&mut wr)?
+            + flags.encode(&mut wr)?;
+        Ok(length)
+    }
+}
+
+impl Decodable for PartialMerkleTree {
+    fn decode<Decoder: io::Read>(mut decoder: Decoder) -> Result<Self, encode::Error> {
+        let tx_count = Decodable::decode(&mut decoder)?;
+        let hashes = Decodable::decode(&mut decoder)?;
+        let flags: Vec<u8> = Decodable::decode(&mut decoder)?;
+        let bits = (0..flags.len() * 8).map(|index| flags[index / 8] & (1 << (index % 8)) != 0).collect();
+        Ok(PartialMerkleTree {
+            tx_count: tx_count,
+            hashes:   hashes,
+            bits:     bits,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::{sha256d, Hash};
+
+    use consensus::encode::{serialize, deserialize};
+    use util::hash::blockchain_merkle_root;
+    use super::PartialMerkleTree;
+
+    fn txids(count: u32) -> Vec<sha256d::Hash> {
+        (0..count).map(|index| sha256d::Hash::hash(&serialize(&index))).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        for &count in [1, 2, 3, 7, 16, 17, 100].iter() {
+            let txids = txids(count);
+            for &step in [1, 3, 7, 101].iter() {
+                let matches: Vec<bool> = (0..count).map(|index| index % step == 0).collect();
+                let tree = PartialMerkleTree::from_txids(&txids, &matches);
+                let decoded: PartialMerkleTree = deserialize(&serialize(&tree)).unwrap();
+                assert_eq!(decoded.tx_count(), count);
+
+                let (root, found) = decoded.extract_matches().unwrap();
+                assert_eq!(root, blockchain_merkle_root(txids.clone()));
+                let expected: Vec<(u32, sha256d::Hash)> = (0..count)
+                    .filter(|index| matches[*index as usize])
+                    .map(|index| (index, txids[index as usize]))
+                    .collect();
+                assert_eq!(found, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn no_transactions() {
+        let tree = PartialMerkleTree::from_txids(&[], &[]);
+        assert_eq!(tree.tx_count(), 0);
+        assert!(tree.extract_matches().is_err());
+    }
+
+    #[test]
+    fn bad_trees() {
+        let txids = txids(7);
+        let matches = [false, true, false, false, true, false, false];
+        let tree = PartialMerkleTree::from_txids(&txids, &matches);
+
+        let mut changed = tree.clone();
+        changed.hashes[0] = Default::default();
+        assert!(changed.extract_matches().unwrap().0 != blockchain_merkle_root(txids.clone()));
+
+        let mut extra_hash = tree.clone();
+        extra_hash.hashes.push(Default::default());
+        assert!(extra_hash.extract_matches().is_err());
+
+        let mut short_bits = tree.clone();
+        short_bits.bits.truncate(1);
+        assert!(short_bits.extract_matches().is_err());
+
+        // Duplicating the last transactions keeps the merkle root but is refused
+        let mut duplicated = txids.clone();
+        duplicated.push(txids[6]);
+        let tree = PartialMerkleTree::from_txids(&duplicated, &[true; 8]);
+        assert!(tree.extract_matches().is_err());
+    }
+}
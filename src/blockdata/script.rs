@@ -0,0 +1,395 @@
+/// Bitcoin scripts - the locking conditions of outputs and the
+/// data unlocking them inside inputs
+
+use std::fmt;
+use std::io;
+
+use consensus::encode::{self, Encodable, Decodable};
+
+/// Maximum size of a script that can be executed
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+
+/// Maximum size of a single data push
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+
+/// Opcode marking a script as invalid, used for data carrier outputs
+pub const OP_RETURN: u8 = 0x6a;
+
+// Opcodes, by value
+pub const OP_0: u8 = 0x00;
+pub const OP_PUSHDATA1: u8 = 0x4c;
+pub const OP_PUSHDATA2: u8 = 0x4d;
+pub const OP_PUSHDATA4: u8 = 0x4e;
+pub const OP_1NEGATE: u8 = 0x4f;
+pub const OP_RESERVED: u8 = 0x50;
+pub const OP_1: u8 = 0x51;
+pub const OP_16: u8 = 0x60;
+pub const OP_NOP: u8 = 0x61;
+pub const OP_VER: u8 = 0x62;
+pub const OP_IF: u8 = 0x63;
+pub const OP_NOTIF: u8 = 0x64;
+pub const OP_VERIF: u8 = 0x65;
+pub const OP_VERNOTIF: u8 = 0x66;
+pub const OP_ELSE: u8 = 0x67;
+pub const OP_ENDIF: u8 = 0x68;
+pub const OP_VERIFY: u8 = 0x69;
+pub const OP_TOALTSTACK: u8 = 0x6b;
+pub const OP_FROMALTSTACK: u8 = 0x6c;
+pub const OP_2DROP: u8 = 0x6d;
+pub const OP_2DUP: u8 = 0x6e;
+pub const OP_3DUP: u8 = 0x6f;
+pub const OP_2OVER: u8 = 0x70;
+pub const OP_2ROT: u8 = 0x71;
+pub const OP_2SWAP: u8 = 0x72;
+pub const OP_IFDUP: u8 = 0x73;
+pub const OP_DEPTH: u8 = 0x74;
+pub const OP_DROP: u8 = 0x75;
+pub const OP_DUP: u8 = 0x76;
+pub const OP_NIP: u8 = 0x77;
+pub const OP_OVER: u8 = 0x78;
+pub const OP_PICK: u8 = 0x79;
+pub const OP_ROLL: u8 = 0x7a;
+pub const OP_ROT: u8 = 0x7b;
+pub const OP_SWAP: u8 = 0x7c;
+pub const OP_TUCK: u8 = 0x7d;
+pub const OP_CAT: u8 = 0x7e;
+pub const OP_SUBSTR: u8 = 0x7f;
+pub const OP_LEFT: u8 = 0x80;
+pub const OP_RIGHT: u8 = 0x81;
+pub const OP_SIZE: u8 = 0x82;
+pub const OP_INVERT: u8 = 0x83;
+pub const OP_AND: u8 = 0x84;
+pub const OP_OR: u8 = 0x85;
+pub const OP_XOR: u8 = 0x86;
+pub const OP_EQUAL: u8 = 0x87;
+pub const OP_EQUALVERIFY: u8 = 0x88;
+pub const OP_RESERVED1: u8 = 0x89;
+pub const OP_RESERVED2: u8 = 0x8a;
+pub const OP_1ADD: u8 = 0x8b;
+pub const OP_1SUB: u8 = 0x8c;
+pub const OP_2MUL: u8 = 0x8d;
+pub const OP_2DIV: u8 = 0x8e;
+pub const OP_NEGATE: u8 = 0x8f;
+pub const OP_ABS: u8 = 0x90;
+pub const OP_NOT: u8 = 0x91;
+pub const OP_0NOTEQUAL: u8 = 0x92;
+pub const OP_ADD: u8 = 0x93;
+pub const OP_SUB: u8 = 0x94;
+pub const OP_MUL: u8 = 0x95;
+pub const OP_DIV: u8 = 0x96;
+pub const OP_MOD: u8 = 0x97;
+pub const OP_LSHIFT: u8 = 0x98;
+pub const OP_RSHIFT: u8 = 0x99;
+pub const OP_BOOLAND: u8 = 0x9a;
+pub const OP_BOOLOR: u8 = 0x9b;
+pub const OP_NUMEQUAL: u8 = 0x9c;
+pub const OP_NUMEQUALVERIFY: u8 = 0x9d;
+pub const OP_NUMNOTEQUAL: u8 = 0x9e;
+pub const OP_LESSTHAN: u8 = 0x9f;
+pub const OP_GREATERTHAN: u8 = 0xa0;
+pub const OP_LESSTHANOREQUAL: u8 = 0xa1;
+pub const OP_GREATERTHANOREQUAL: u8 = 0xa2;
+pub const OP_MIN: u8 = 0xa3;
+pub const OP_MAX: u8 = 0xa4;
+pub const OP_WITHIN: u8 = 0xa5;
+pub const OP_RIPEMD160: u8 = 0xa6;
+pub const OP_SHA1: u8 = 0xa7;
+pub const OP_SHA256: u8 = 0xa8;
+pub const OP_HASH160: u8 = 0xa9;
+pub const OP_HASH256: u8 = 0xaa;
+pub const OP_CODESEPARATOR: u8 = 0xab;
+pub const OP_CHECKSIG: u8 = 0xac;
+pub const OP_CHECKSIGVERIFY: u8 = 0xad;
+pub const OP_CHECKMULTISIG: u8 = 0xae;
+pub const OP_CHECKMULTISIGVERIFY: u8 = 0xaf;
+pub const OP_NOP1: u8 = 0xb0;
+pub const OP_CHECKLOCKTIMEVERIFY: u8 = 0xb1;
+pub const OP_CHECKSEQUENCEVERIFY: u8 = 0xb2;
+pub const OP_NOP4: u8 = 0xb3;
+pub const OP_NOP10: u8 = 0xb9;
+
+/// Sigops counted for a multisig whose key count is unknown
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+
+/// A single opcode with the data it pushes, if any
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Instruction<'a> {
+    pub opcode: u8,
+    pub data: &'a [u8],
+}
+
+/// Iterator over the instructions of a script, stopping at the first
+/// push running past the end of the script
+pub struct Instructions<'a> {
+    data: &'a [u8],
+    position: usize,
+    failed: bool,
+}
+
+impl<'a> Instructions<'a> {
+    /// Iterate over the instructions of raw script bytes
+    pub fn new(data: &'a [u8]) -> Instructions<'a> {
+        Instructions { data: data, position: 0, failed: false }
+    }
+
+    /// Return the offset of the next instruction
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Check if iteration stopped on a malformed push
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    fn read_length(&mut self, size: usize) -> Option<usize> {
+        if self.position + size > self.data.len() {
+            return None;
+        }
+        let mut length = 0usize;
+        for (shift, byte) in self.data[self.position..self.position + size].iter().enumerate() {
+            length |= (*byte as usize) << (8 * shift);
+        }
+        self.position += size;
+        Some(length)
+    }
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Instruction<'a>;
+
+    fn next(&mut self) -> Option<Instruction<'a>> {
+        if self.failed || self.position >= self.data.len() {
+            return None;
+        }
+        let opcode = self.data[self.position];
+        self.position += 1;
+
+        let length = match opcode {
+            0x01..=0x4b  => Some(opcode as usize),
+            OP_PUSHDATA1 => self.read_length(1),
+            OP_PUSHDATA2 => self.read_length(2),
+            OP_PUSHDATA4 => self.read_length(4),
+            _            => Some(0),
+        };
+        match length {
+            Some(length) if self.position + length <= self.data.len() => {
+                let data = &self.data[self.position..self.position + length];
+                self.position += length;
+                Some(Instruction { opcode: opcode, data: data })
+            },
+            _ => {
+                self.failed = true;
+                None
+            }
+        }
+    }
+}
+
+/// Raw script bytes
+#[derive(Clone, Default, PartialOrd, Ord, PartialEq, Eq, Hash)]
+pub struct Script(Box<[u8]>);
+
+impl Script {
+    /// Create an empty script
+    pub fn new() -> Script {
+        Script(vec![].into_boxed_slice())
+    }
+
+    /// Return the script bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Return the script length in bytes
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Check if the script is empty
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Check if the script starts with OP_RETURN
+    pub fn is_op_return(&self) -> bool {
+        !self.0.is_empty() && self.0[0] == OP_RETURN
+    }
+
+    /// Check if no input can ever spend an output locked by the script
+    pub fn is_provably_unspendable(&self) -> bool {
+        self.is_op_return() || self.0.len() > MAX_SCRIPT_SIZE
+    }
+
+    /// Iterate over the instructions of the script
+    pub fn instructions(&self) -> Instructions {
+        Instructions::new(&self.0)
+    }
+
+    /// Check if the script only pushes data
+    pub fn is_push_only(&self) -> bool {
+        let mut instructions = self.instructions();
+        let push_only = instructions.by_ref().all(|ins| ins.opcode <= OP_16);
+        push_only && !instructions.failed()
+    }
+
+    /// Check if the script is a pay-to-script-hash output
+    pub fn is_p2sh(&self) -> bool {
+        self.0.len() == 23 && self.0[0] == OP_HASH160 && self.0[1] == 0x14 && self.0[22] == OP_EQUAL
+    }
+
+    /// Check if the script is a pay-to-pubkey-hash output
+    pub fn is_p2pkh(&self) -> bool {
+        self.0.len() == 25 && self.0[0] == OP_DUP && self.0[1] == OP_HASH160 && self.0[2] == 0x14
+            && self.0[23] == OP_EQUALVERIFY && self.0[24] == OP_CHECKSIG
+    }
+
+    /// Return the version and program of a segwit output:
+    /// a version opcode followed by a single push of 2 to 40 bytes
+    pub fn witness_program(&self) -> Option<(u8, &[u8])> {
+        let len = self.0.len();
+        if len < 4 || len > 42 || self.0[1] as usize + 2 != len {
+            return None;
+        }
+        match self.0[0] {
+            OP_0         => Some((0, &self.0[2..])),
+            OP_1..=OP_16 => Some((self.0[0] - OP_1 + 1, &self.0[2..])),
+            _            => None,
+        }
+    }
+
+    /// Count the signature operations of the script. When `accurate`, multisigs
+    /// preceded by a key count cost that many operations instead of the maximum.
+    pub fn count_sigops(&self, accurate: bool) -> usize {
+        let mut count = 0;
+        let mut last_opcode = 0xff;
+        for ins in self.instructions() {
+            match ins.opcode {
+                OP_CHECKSIG | OP_CHECKSIGVERIFY => count += 1,
+                OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                    count += if accurate && last_opcode >= OP_1 && last_opcode <= OP_16 {
+                        (last_opcode - OP_1 + 1) as usize
+                    } else {
+                        MAX_PUBKEYS_PER_MULTISIG
+                    };
+                },
+                _ => {}
+            }
+            last_opcode = ins.opcode;
+        }
+        count
+    }
+
+    /// Return the data pushed by the last instruction
+    pub fn last_push(&self) -> Option<&[u8]> {
+        self.instructions().last().map(|ins| ins.data)
+    }
+}
+
+/// Encode a number the way script arithmetic reads it: little-endian
+/// magnitude with the sign in the highest bit, using as few bytes as possible
+pub fn serialize_script_num(value: i64) -> Vec<u8> {
+    let mut result = Vec::new();
+    if value == 0 {
+        return result;
+    }
+    let negative = value < 0;
+    let mut magnitude = value.wrapping_abs() as u64;
+    while magnitude > 0 {
+        result.push((magnitude & 0xff) as u8);
+        magnitude >>= 8;
+    }
+    // The sign bit needs a byte of its own when the top bit is taken
+    let last = result.len() - 1;
+    if result[last] & 0x80 != 0 {
+        result.push(if negative { 0x80 } else { 0x00 });
+    } else if negative {
+        result[last] |= 0x80;
+    }
+    result
+}
+
+/// Builder of scripts from opcodes and data pushes
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Builder(Vec<u8>);
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder(Vec::new())
+    }
+
+    /// Append an opcode
+    pub fn push_opcode(mut self, opcode: u8) -> Builder {
+        self.0.push(opcode);
+        self
+    }
+
+    /// Append a push of the data, using the shortest push opcode
+    pub fn push_slice(mut self, data: &[u8]) -> Builder {
+        let len = data.len();
+        if len < OP_PUSHDATA1 as usize {
+            self.0.push(len as u8);
+        } else if len <= 0xff {
+            self.0.push(OP_PUSHDATA1);
+            self.0.push(len as u8);
+        } else if len <= 0xffff {
+            self.0.push(OP_PUSHDATA2);
+            self.0.extend_from_slice(&[len as u8, (len >> 8) as u8]);
+        } else {
+            self.0.push(OP_PUSHDATA4);
+            self.0.extend_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]);
+        }
+        self.0.extend_from_slice(data);
+        self
+    }
+
+    /// Append a number, using the small integer opcodes when possible
+    pub fn push_int(self, value: i64) -> Builder {
+        match value {
+            -1     => self.push_opcode(OP_1NEGATE),
+            0      => self.push_opcode(OP_0),
+            1..=16 => self.push_opcode(OP_1 + value as u8 - 1),
+            _      => self.push_slice(&serialize_script_num(value)),
+        }
+    }
+
+    /// Return the script bytes built so far
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Finish the script
+    pub fn into_script(self) -> Script {
+        Script::from(self.0)
+    }
+}
+
+impl From<Vec<u8>> for Script {
+    fn from(data: Vec<u8>) -> Script {
+        Script(data.into_boxed_slice())
+    }
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Script(")?;
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        f.write_str(")")
+    }
+}
+
+impl Encodable for Script {
+    #[inline]
+    fn encode<Wr: io::Write>(&self, wr: Wr) -> Result<usize, encode::Error> {
+        self.0.to_vec().encode(wr)
+    }
+}
+
+impl Decodable for Script {
+    #[inline]
+    fn decode<Decoder: io::Read>(decoder: Decoder) -> Result<Self, encode::Error> {
+        Ok(Script(Vec::<u8>::decode(decoder)?.into_boxed_slice()))
+    }
+}
@@ -7,7 +7,35 @@ use std::io;
 use hashes::{sha256d, Hash};
 
 use util::hash::BlockchainHash;
-use blockdata::script::Script; // not implemented
+use blockdata::script::{Instructions, Script, OP_CODESEPARATOR};
+use consensus::encode::{self, Encodable, Decodable};
+
+/// Lock times below this value are block heights, above it timestamps
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// Sequence value disabling the lock time of an input
+pub const SEQUENCE_FINAL: u32 = 0xffffffff;
+
+/// Highest sequence value signalling the transaction can be replaced (BIP125)
+pub const MAX_BIP125_RBF_SEQUENCE: u32 = 0xfffffffd;
+
+/// Sequence flag disabling the relative lock time of an input (BIP68)
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+
+/// Sequence flag making the relative lock time count in units of 512 seconds
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+
+/// Bits of the sequence holding the relative lock time
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
+
+/// Shift converting a time-based relative lock time to seconds
+pub const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9;
+
+// Signature hash types
+pub const SIGHASH_ALL: u32 = 0x01;
+pub const SIGHASH_NONE: u32 = 0x02;
+pub const SIGHASH_SINGLE: u32 = 0x03;
+pub const SIGHASH_ANYONECANPAY: u32 = 0x80;
 
 /// Reference to a transaction output
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
@@ -57,6 +85,8 @@ impl fmt::Display for OutPoint {
     }
 }
 
+consensus_encoding!(OutPoint, txid, vout);
+
 /// Types of errors for OutPoints to be implemented
 
 /// Transaction input implementation
@@ -65,15 +95,48 @@ impl fmt::Display for OutPoint {
 pub struct TxIn {
     // Non-witness
     pub previous_out: OutPoint,
+    pub sig_script: Script,
     pub sequence: u32,
 
     // Witness
-    pub value_in: i64,
-    pub sig_script: Script,
-    // ...
+    /// Witness stack items, not part of the transaction id
+    pub witness: Vec<Vec<u8>>,
+}
+
+impl Default for TxIn {
+    fn default() -> TxIn {
+        TxIn {
+            previous_out: OutPoint::null(),
+            sig_script:   Script::new(),
+            sequence:     u32::max_value(),
+            witness:      Vec::new(),
+        }
+    }
 }
 
 // Serialization
+// The witness is serialized separately, after all outputs of the transaction
+impl Encodable for TxIn {
+    #[inline]
+    fn encode<Wr: io::Write>(&self, mut wr: Wr) -> Result<usize, encode::Error> {
+        let length = self.previous_out.encode(&mut wr)?
+            + self.sig_script.encode(&mut wr)?
+            + self.sequence.encode(&mut wr)?;
+        Ok(length)
+    }
+}
+
+impl Decodable for TxIn {
+    #[inline]
+    fn decode<Decoder: io::Read>(mut decoder: Decoder) -> Result<Self, encode::Error> {
+        Ok(TxIn {
+            previous_out: Decodable::decode(&mut decoder)?,
+            sig_script:   Decodable::decode(&mut decoder)?,
+            sequence:     Decodable::decode(&mut decoder)?,
+            witness:      Vec::new(),
+        })
+    }
+}
 
 /// Transaction output implementation
 /// defines source of input
@@ -85,11 +148,12 @@ pub struct TxOut {
 
 impl Default for TxOut {
     fn default() -> TxOut {
-        TxOut { value_out: 0xffffffffffffffffff, sig_script: Script::new() }
+        TxOut { value_out: 0xffffffffffffffff, pkscript: Script::new() }
     }
 }
 
 // Serialization
+consensus_encoding!(TxOut, value_out, pkscript);
 
 /// Transaction implementation - holds inputs and outputs
 #[derive(Clone, PartialEq, Eq, Debug, Hash)]
@@ -103,7 +167,7 @@ pub struct Transaction {
     pub lock_time: u32,
 
     pub input_ent:  Vec<TxIn>,
-    pub output_ent: Vec<TxOut>, 
+    pub output_ent: Vec<TxOut>,
 }
 
 impl Transaction {
@@ -112,19 +176,289 @@ impl Transaction {
         let clone = Transaction {
             version:    self.version,
             lock_time:  self.lock_time,
-            input_ent:  self.input_ent.iter().map(|txin| TxIn { sig_script: Script::new(), .. *txin[]}).collect(),
+            input_ent:  self.input_ent.iter().map(|txin| TxIn { sig_script: Script::new(), witness: vec![], .. *txin }).collect(),
             output_ent: self.output_ent.clone(),
         };
         clone.blockchain_hash()
     }
 
-    /// Compute a signature hash for an input index with a given sighah flag
-    /// ...
-    
+    /// Computes the transaction id - the hash of the transaction without witness data
+    pub fn txid(&self) -> sha256d::Hash {
+        let mut encoder = sha256d::Hash::engine();
+        self.encode_without_witness(&mut encoder).unwrap();
+        sha256d::Hash::from_engine(encoder)
+    }
+
+    /// Computes the witness transaction id - the hash of the transaction
+    /// including witness data; equal to `txid` if there is none
+    pub fn wtxid(&self) -> sha256d::Hash {
+        let mut encoder = sha256d::Hash::engine();
+        self.encode(&mut encoder).unwrap();
+        sha256d::Hash::from_engine(encoder)
+    }
+
+    /// Check if any input carries witness data
+    pub fn has_witness(&self) -> bool {
+        self.input_ent.iter().any(|txin| !txin.witness.is_empty())
+    }
+
+    /// Encode the transaction in the pre-segwit format
+    fn encode_without_witness<Wr: io::Write>(&self, mut wr: Wr) -> Result<usize, encode::Error> {
+        let length = self.version.encode(&mut wr)?
+            + self.input_ent.encode(&mut wr)?
+            + self.output_ent.encode(&mut wr)?
+            + self.lock_time.encode(&mut wr)?;
+        Ok(length)
+    }
+
+    /// Size in bytes of the transaction serialized without witness data
+    pub fn base_size(&self) -> usize {
+        self.encode_without_witness(io::sink()).unwrap()
+    }
+
+    /// Size in bytes of the transaction serialized with witness data
+    pub fn total_size(&self) -> usize {
+        self.encode(io::sink()).unwrap()
+    }
+
+    /// Sum of the values of all outputs
+    pub fn output_value(&self) -> u64 {
+        self.output_ent.iter().map(|txout| txout.value_out).sum()
+    }
+
+    /// Weight of the transaction: witness bytes count once, other bytes four times
+    pub fn weight(&self) -> usize {
+        self.base_size() * 3 + self.total_size()
+    }
+
+    /// Virtual size of the transaction: its weight divided by four, rounded up
+    pub fn vsize(&self) -> usize {
+        (self.weight() + 3) / 4
+    }
+
+    /// Compute the signature hash of input `input` signed with `script_code`,
+    /// code separators removed. SIGHASH_SINGLE without a matching output signs
+    /// the hash 1, as the original client did.
+    pub fn signature_hash(&self, input: usize, script_code: &[u8], sighash_type: u32) -> sha256d::Hash {
+        let base_type = sighash_type & 0x1f;
+        if input >= self.input_ent.len() || (base_type == SIGHASH_SINGLE && input >= self.output_ent.len()) {
+            let mut one = [0u8; 32];
+            one[0] = 1;
+            return sha256d::Hash::from_slice(&one).unwrap();
+        }
+
+        let mut code = Vec::with_capacity(script_code.len());
+        let mut instructions = Instructions::new(script_code);
+        let mut start = 0;
+        loop {
+            let position = instructions.position();
+            match instructions.next() {
+                Some(ins) if ins.opcode == OP_CODESEPARATOR => {
+                    code.extend_from_slice(&script_code[start..position]);
+                    start = instructions.position();
+                },
+                Some(_) => {},
+                None    => break,
+            }
+        }
+        code.extend_from_slice(&script_code[start..]);
+        let code = Script::from(code);
+
+        let signed_inputs: Vec<usize> = if sighash_type & SIGHASH_ANYONECANPAY != 0 {
+            vec![input]
+        } else {
+            (0..self.input_ent.len()).collect()
+        };
+        let input_ent = signed_inputs.into_iter().map(|index| {
+            let txin = &self.input_ent[index];
+            // Other inputs may be updated freely when not all outputs are signed
+            let other_sequence_free = base_type == SIGHASH_NONE || base_type == SIGHASH_SINGLE;
+            TxIn {
+                previous_out: txin.previous_out,
+                sig_script:   if index == input { code.clone() } else { Script::new() },
+                sequence:     if index != input && other_sequence_free { 0 } else { txin.sequence },
+                witness:      Vec::new(),
+            }
+        }).collect();
+        let output_ent = match base_type {
+            SIGHASH_NONE   => Vec::new(),
+            SIGHASH_SINGLE => {
+                let mut outputs = vec![TxOut::default(); input];
+                outputs.push(self.output_ent[input].clone());
+                outputs
+            },
+            _              => self.output_ent.clone(),
+        };
+        let stripped = Transaction {
+            version:    self.version,
+            lock_time:  self.lock_time,
+            input_ent:  input_ent,
+            output_ent: output_ent,
+        };
+
+        let mut encoder = sha256d::Hash::engine();
+        stripped.encode_without_witness(&mut encoder).unwrap();
+        sighash_type.encode(&mut encoder).unwrap();
+        sha256d::Hash::from_engine(encoder)
+    }
+
+    /// Compute the signature hash of input `input` spending a witness
+    /// version 0 output worth `value` (BIP143)
+    pub fn segwit_signature_hash(&self, input: usize, script_code: &[u8], value: u64, sighash_type: u32) -> sha256d::Hash {
+        let base_type = sighash_type & 0x1f;
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+        let zero = sha256d::Hash::default();
+
+        let hash_prevouts = if anyone_can_pay {
+            zero
+        } else {
+            let mut encoder = sha256d::Hash::engine();
+            for txin in self.input_ent.iter() {
+                txin.previous_out.encode(&mut encoder).unwrap();
+            }
+            sha256d::Hash::from_engine(encoder)
+        };
+        let hash_sequence = if anyone_can_pay || base_type == SIGHASH_NONE || base_type == SIGHASH_SINGLE {
+            zero
+        } else {
+            let mut encoder = sha256d::Hash::engine();
+            for txin in self.input_ent.iter() {
+                txin.sequence.encode(&mut encoder).unwrap();
+            }
+            sha256d::Hash::from_engine(encoder)
+        };
+        let hash_outputs = if base_type != SIGHASH_NONE && base_type != SIGHASH_SINGLE {
+            let mut encoder = sha256d::Hash::engine();
+            for txout in self.output_ent.iter() {
+                txout.encode(&mut encoder).unwrap();
+            }
+            sha256d::Hash::from_engine(encoder)
+        } else if base_type == SIGHASH_SINGLE && input < self.output_ent.len() {
+            let mut encoder = sha256d::Hash::engine();
+            self.output_ent[input].encode(&mut encoder).unwrap();
+            sha256d::Hash::from_engine(encoder)
+        } else {
+            zero
+        };
+
+        let txin = &self.input_ent[input];
+        let mut encoder = sha256d::Hash::engine();
+        self.version.encode(&mut encoder).unwrap();
+        hash_prevouts.encode(&mut encoder).unwrap();
+        hash_sequence.encode(&mut encoder).unwrap();
+        txin.previous_out.encode(&mut encoder).unwrap();
+        script_code.to_vec().encode(&mut encoder).unwrap();
+        value.encode(&mut encoder).unwrap();
+        txin.sequence.encode(&mut encoder).unwrap();
+        hash_outputs.encode(&mut encoder).unwrap();
+        self.lock_time.encode(&mut encoder).unwrap();
+        sighash_type.encode(&mut encoder).unwrap();
+        sha256d::Hash::from_engine(encoder)
+    }
+
     /// Verify the transaction is able to spend its output
     /// ...
-    
+
     pub fn is_coin_base(&self) -> bool {
         self.input_ent.len() == 1 && self.input_ent[0].previous_out.is_null()
     }
-}
\ No newline at end of file
+
+    /// Check if the lock time allows the transaction inside a block at the
+    /// given height whose lock time cutoff is `time`
+    pub fn is_final(&self, height: u64, time: u32) -> bool {
+        if self.lock_time == 0 {
+            return true;
+        }
+        let cutoff = if self.lock_time < LOCKTIME_THRESHOLD { height } else { time as u64 };
+        if (self.lock_time as u64) < cutoff {
+            return true;
+        }
+        self.input_ent.iter().all(|txin| txin.sequence == SEQUENCE_FINAL)
+    }
+
+    /// Check if the transaction explicitly signals it can be replaced (BIP125)
+    pub fn signals_rbf(&self) -> bool {
+        self.input_ent.iter().any(|txin| txin.sequence <= MAX_BIP125_RBF_SEQUENCE)
+    }
+
+    /// Make the transaction signal replaceability or not. Signalling lowers the
+    /// sequence of inputs above the signalling range; opting out raises the
+    /// signalling sequences to just below final, which keeps the lock time
+    /// enforced but drops their relative lock time. Inputs already above the
+    /// signalling range, final ones included, are left alone.
+    pub fn set_rbf(&mut self, enable: bool) {
+        for txin in self.input_ent.iter_mut() {
+            if enable {
+                if txin.sequence > MAX_BIP125_RBF_SEQUENCE {
+                    txin.sequence = MAX_BIP125_RBF_SEQUENCE;
+                }
+            } else if txin.sequence <= MAX_BIP125_RBF_SEQUENCE {
+                txin.sequence = MAX_BIP125_RBF_SEQUENCE + 1;
+            }
+        }
+    }
+}
+
+impl BlockchainHash for Transaction {
+    fn blockchain_hash(&self) -> sha256d::Hash {
+        self.txid()
+    }
+}
+
+/// Transactions with witness data are serialized as described in BIP144:
+/// a zero marker and a flag follow the version, and the witness of every
+/// input follows the outputs
+impl Encodable for Transaction {
+    fn encode<Wr: io::Write>(&self, mut wr: Wr) -> Result<usize, encode::Error> {
+        if !self.has_witness() {
+            return self.encode_without_witness(wr);
+        }
+        let mut length = self.version.encode(&mut wr)?;
+        length += 0u8.encode(&mut wr)?;
+        length += 1u8.encode(&mut wr)?;
+        length += self.input_ent.encode(&mut wr)?;
+        length += self.output_ent.encode(&mut wr)?;
+        for txin in self.input_ent.iter() {
+            length += txin.witness.encode(&mut wr)?;
+        }
+        length += self.lock_time.encode(&mut wr)?;
+        Ok(length)
+    }
+}
+
+impl Decodable for Transaction {
+    fn decode<Decoder: io::Read>(mut decoder: Decoder) -> Result<Self, encode::Error> {
+        let version = Decodable::decode(&mut decoder)?;
+        let mut input_ent: Vec<TxIn> = Decodable::decode(&mut decoder)?;
+
+        // An empty inputs list is the segwit marker
+        if !input_ent.is_empty() {
+            return Ok(Transaction {
+                version:    version,
+                input_ent:  input_ent,
+                output_ent: Decodable::decode(&mut decoder)?,
+                lock_time:  Decodable::decode(&mut decoder)?,
+            });
+        }
+
+        let flag: u8 = Decodable::decode(&mut decoder)?;
+        if flag != 1 {
+            return Err(encode::Error::ErrorParse("unsupported segwit flag"));
+        }
+        input_ent = Decodable::decode(&mut decoder)?;
+        let output_ent = Decodable::decode(&mut decoder)?;
+        for txin in input_ent.iter_mut() {
+            txin.witness = Decodable::decode(&mut decoder)?;
+        }
+        if !input_ent.iter().any(|txin| !txin.witness.is_empty()) {
+            return Err(encode::Error::ErrorParse("superfluous witness record"));
+        }
+        Ok(Transaction {
+            version:    version,
+            input_ent:  input_ent,
+            output_ent: output_ent,
+            lock_time:  Decodable::decode(&mut decoder)?,
+        })
+    }
+}
+
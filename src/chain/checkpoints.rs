@@ -0,0 +1,90 @@
+/// Hard-coded checkpoints and assumed-valid blocks
+///
+/// Checkpoints pin the hash of the block at a few heights of each network,
+/// so a peer cannot feed us a fork of known-good history. Scripts of blocks
+/// that are ancestors of the assumed-valid block are not verified, since the
+/// result of their verification is already known.
+
+use hashes::sha256d;
+use hashes::hex::FromHex;
+
+use chain::headertree::{HeaderTree, HeaderEntry};
+use chain::locator::HeaderChain;
+use consensus::params::Network;
+
+/// Heights and hashes of the main network checkpoints
+const CHECKPOINTS_BITCOIN: &[(u64, &str)] = &[
+    ( 11111, "0000000069e244f73d78e8fd29ba2fd2ed618bd6fa2ee92559f542fdb26e7c1d"),
+    ( 33333, "000000002dd5588a74784eaa7ab0507a18ad16a236e7b1ce69f00d7ddfb5d0a6"),
+    ( 74000, "0000000000573993a3c9e41ce34471c079dcf5f52a0e824a81e7f953b8661a20"),
+    (105000, "00000000000291ce28027faea320c8d2b054b2e0fe44a773f3eefb151d6bdc97"),
+    (134444, "00000000000005b12ffd4cd315cd34ffd4a594f430ac814c91184a0d42d2b0fe"),
+    (168000, "000000000000099e61ea72015e79632f216fe6cb33d7899acb35b75c8303b763"),
+    (193000, "000000000000059f452a5f7340de6682a977387c17010ff6e6c3bd83ca8b1317"),
+    (210000, "000000000000048b95347e83192f69cf0366076336c639f9b7228e9ba171342e"),
+    (216116, "00000000000001b4f4b433e81ee46494af945cf96014816a4e2370f11b23df4e"),
+    (225430, "00000000000001c108384350f74090433e7fcf79a606b8e797f065b130575932"),
+    (250000, "000000000000003887df1f29024b06fc2200b55f8af8f35453d7be294df2d214"),
+    (279000, "0000000000000001ae8c72a0b0c301f67e3afca10e819efa9041e458e9bd7e40"),
+    (295000, "00000000000000004d9b4ef50f0f9d686fd69db2e03af35a100370c64632a983"),
+];
+
+/// Heights and hashes of the test network checkpoints
+const CHECKPOINTS_TESTNET: &[(u64, &str)] = &[
+    (546, "000000002a936ca763904c3c35fce2f3556c559c0214345d31b1bcebf76acb70"),
+];
+
+/// Block below which scripts are assumed valid on the main network
+const ASSUMED_VALID_BITCOIN: &str = "0000000000000000000b9d2ec5a352ecba0592946514a92f14319dc2b367fc72";
+
+/// Block below which scripts are assumed valid on the test network
+const ASSUMED_VALID_TESTNET: &str = "000000000000006433d1efec504c53ca332b64963c425395515b01977bd7b3b0";
+
+/// Return the checkpoints of a network, ordered by height
+pub fn checkpoints(network: Network) -> Vec<(u64, sha256d::Hash)> {
+    let table = match network {
+        Network::Bitcoin => CHECKPOINTS_BITCOIN,
+        Network::Testnet => CHECKPOINTS_TESTNET,
+        Network::Regtest => &[],
+    };
+    table.iter()
+        .map(|&(height, hash)| (height, sha256d::Hash::from_hex(hash).unwrap()))
+        .collect()
+}
+
+/// Return the assumed-valid block of a network, if any
+pub fn assumed_valid(network: Network) -> Option<sha256d::Hash> {
+    match network {
+        Network::Bitcoin => Some(sha256d::Hash::from_hex(ASSUMED_VALID_BITCOIN).unwrap()),
+        Network::Testnet => Some(sha256d::Hash::from_hex(ASSUMED_VALID_TESTNET).unwrap()),
+        Network::Regtest => None,
+    }
+}
+
+/// Return the checkpoint at the given height, if there is one
+pub fn checkpoint_at(network: Network, height: u64) -> Option<sha256d::Hash> {
+    checkpoints(network).into_iter()
+        .find(|&(checkpoint_height, _)| checkpoint_height == height)
+        .map(|(_, hash)| hash)
+}
+
+/// Return the height of the highest checkpoint we already have a header for
+pub fn last_checkpoint_height(tree: &HeaderTree) -> Option<u64> {
+    tree.checkpoints().iter()
+        .rev()
+        .find(|&&(_, ref hash)| tree.contains(hash))
+        .map(|&(height, _)| height)
+}
+
+/// Check if script verification can be skipped for a block: it has to be an
+/// ancestor of the assumed-valid block, which has to be on our best chain
+pub fn skip_script_verification(tree: &HeaderTree, entry: &HeaderEntry) -> bool {
+    let assumed = match assumed_valid(tree.params().network) {
+        Some(hash) => hash,
+        None       => return false,
+    };
+    if tree.height_of(&assumed).is_none() {
+        return false;
+    }
+    tree.ancestor(&assumed, entry.height()).map_or(false, |ancestor| ancestor.hash == entry.hash)
+}
@@ -0,0 +1,63 @@
+/// Difficulty retargeting
+///
+/// Every 2016 blocks the target is adjusted so that the period would have
+/// lasted two weeks, limited to a quarter and four times the previous value.
+/// Test networks allow a block to use the lowest difficulty when it comes more
+/// than 20 minutes after its parent; regtest never changes difficulty.
+
+use blockdata::block::BlockHeader;
+use chain::headertree::{HeaderTree, HeaderEntry};
+use consensus::params::Params;
+use util::uint::Uint256;
+
+/// Compute the compact target required for the header following `prev`
+/// that claims the given timestamp
+pub fn next_target_required(tree: &HeaderTree, prev: &HeaderEntry, timestamp: u32) -> u32 {
+    let params = tree.params();
+    let pow_limit = BlockHeader::compute_compact_target_from_u256(&params.pow_limit);
+    let interval = params.difficulty_adjustment_interval();
+    let height = prev.height() + 1;
+
+    if height % interval != 0 {
+        if params.allow_min_difficulty_blocks {
+            if timestamp as u64 > prev.header.timestamp as u64 + params.pow_target_spacing * 2 {
+                return pow_limit;
+            }
+            // Go back to the last block that did not use the min-difficulty exception
+            let mut entry = prev;
+            while entry.height() % interval != 0 && entry.header.target == pow_limit {
+                match tree.get(&entry.header.prev_blockhash) {
+                    Some(parent) => entry = parent,
+                    None         => break,
+                }
+            }
+            return entry.header.target;
+        }
+        return prev.header.target;
+    }
+
+    let first = tree.ancestor(&prev.hash, prev.height() + 1 - interval)
+        .expect("ancestor of a known header is known");
+    calculate_next_target(prev, first.header.timestamp, params)
+}
+
+/// Compute the compact target of a new difficulty period, given the last
+/// header of the previous period and the timestamp of its first header
+pub fn calculate_next_target(last: &HeaderEntry, first_timestamp: u32, params: &Params) -> u32 {
+    if params.no_pow_retargeting {
+        return last.header.target;
+    }
+
+    let timespan = params.pow_target_timespan as i64;
+    let actual_timespan = (last.header.timestamp as i64 - first_timestamp as i64)
+        .max(timespan / 4)
+        .min(timespan * 4);
+
+    let mut target = BlockHeader::compute_target_from_compact(last.header.target);
+    target = target.mul_u32(actual_timespan as u32);
+    target = target / Uint256::from_u64(timespan as u64).unwrap();
+    if target > params.pow_limit {
+        target = params.pow_limit;
+    }
+    BlockHeader::compute_compact_target_from_u256(&target)
+}
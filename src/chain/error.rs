@@ -0,0 +1,62 @@
+/// Implementation of errors used in chain namespace
+///
+use std::{error, fmt, io};
+
+use blockdata;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Header does not connect to any header we know of
+    ErrorUnconnectedHeader,
+    /// Headers inside a message do not follow each other
+    ErrorNonContinuousHeaders,
+    /// Header failed validation
+    ErrorInvalidHeader(blockdata::Error),
+    /// Reading or writing the headers store failed
+    ErrorStore(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::ErrorUnconnectedHeader    => f.write_str(error::Error::description(self)),
+            Error::ErrorNonContinuousHeaders => f.write_str(error::Error::description(self)),
+            Error::ErrorInvalidHeader(ref e) => write!(f, "{}: {}", error::Error::description(self), e),
+            Error::ErrorStore(ref e)         => write!(f, "{}: {}", error::Error::description(self), e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::ErrorUnconnectedHeader    => None,
+            Error::ErrorNonContinuousHeaders => None,
+            Error::ErrorInvalidHeader(ref e) => Some(e),
+            Error::ErrorStore(ref e)         => Some(e),
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::ErrorUnconnectedHeader    => "header does not connect to the known chain",
+            Error::ErrorNonContinuousHeaders => "headers are not continuous",
+            Error::ErrorInvalidHeader(..)    => "invalid header",
+            Error::ErrorStore(..)            => "headers store error",
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<blockdata::Error> for Error {
+    fn from(e: blockdata::Error) -> Error {
+        Error::ErrorInvalidHeader(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::ErrorStore(e)
+    }
+}
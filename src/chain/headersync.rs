@@ -0,0 +1,300 @@
+/// Header-first chain synchronization
+///
+/// The whole header chain is downloaded with `getheaders` before any block is
+/// requested. A single peer is asked at a time, in batches of up to 2000
+/// headers; a peer that does not answer in time is reported as stalled and the
+/// next best peer takes over. Accepted headers are appended to a file so that
+/// the download resumes from where it stopped after a restart. Headers are
+/// kept in a `HeaderTree`, so peers on a competing chain with more work
+/// switch our best chain.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use hashes::sha256d;
+
+use blockdata::block::BlockHeader;
+use chain::error::Error;
+use chain::headertree::{HeaderTree, Reorg};
+use chain::locator::{self, HeaderChain};
+use chain::time::{self, AdjustedTime};
+use consensus::encode::{serialize, deserialize};
+use consensus::params::Params;
+use util::constants::MAX_BLOCK_HEADERS_PER_MSG;
+use util::hash::BlockchainHash;
+use wire::msgblock::{GetHeadersMessage, HeadersMessage};
+use wire::msgver::VersionMessage;
+
+/// Identifier the node assigns to each connected peer
+pub type PeerId = u64;
+
+/// Time a peer has to answer a `getheaders` before it is considered stalled
+pub const HEADERS_RESPONSE_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+
+/// Size of a serialized header inside the store
+const HEADER_SIZE: usize = 80;
+
+/// What we know about the chain of a peer
+struct PeerState {
+    /// Best height the peer claimed or proved to have
+    best_height: u64,
+    /// Hash of the best header the peer announced
+    best_hash: Option<sha256d::Hash>,
+    /// Time the outstanding `getheaders` was sent to the peer
+    request_sent: Option<Instant>,
+    /// Peer let a request time out; it is not asked again until it sends headers
+    stalled: bool,
+}
+
+/// Result of processing a `headers` message
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HeadersOutcome {
+    /// Number of headers added to the tree
+    pub accepted: usize,
+    /// Changes of the best chain caused by the headers, in order
+    pub reorgs: Vec<Reorg>,
+    /// Follow-up request when the peer has more headers to send
+    pub request: Option<GetHeadersMessage>,
+}
+
+/// Header-first synchronization engine
+pub struct HeaderSync {
+    tree: HeaderTree,
+    /// Network-adjusted time, fed by the clocks of our peers
+    time: AdjustedTime,
+    peers: HashMap<PeerId, PeerState>,
+    /// File the accepted headers are appended to
+    store: Option<File>,
+}
+
+impl HeaderSync {
+    /// Create an engine knowing only the genesis header, without persistence
+    pub fn new(params: Params) -> HeaderSync {
+        HeaderSync {
+            tree:  HeaderTree::new(params),
+            time:  AdjustedTime::new(),
+            peers: HashMap::new(),
+            store: None,
+        }
+    }
+
+    /// Create an engine persisting headers to the given file, resuming
+    /// from the headers already stored inside it
+    pub fn open<P: AsRef<Path>>(params: Params, path: P) -> Result<HeaderSync, Error> {
+        let mut sync = HeaderSync::new(params);
+        let mut file = OpenOptions::new().read(true).write(true).create(true).open(path)?;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        // The store starts with genesis and every header follows its parent;
+        // anything after the first header that fails to connect - such as a
+        // header partially written before a crash - is dropped and downloaded again
+        let mut valid = 0;
+        let now = time::local_time();
+        for (position, chunk) in data.chunks(HEADER_SIZE).enumerate() {
+            if chunk.len() < HEADER_SIZE {
+                break;
+            }
+            let header: BlockHeader = match deserialize(chunk) {
+                Ok(header) => header,
+                Err(..)    => break,
+            };
+            if position == 0 {
+                if sync.tree.get(&header.blockchain_hash()).map_or(true, |entry| entry.height() != 0) {
+                    return Err(Error::ErrorUnconnectedHeader);
+                }
+            } else if sync.tree.contains(&header.blockchain_hash()) || sync.tree.insert(header, now).is_err() {
+                break;
+            }
+            valid += HEADER_SIZE;
+        }
+
+        file.set_len(valid as u64)?;
+        file.seek(SeekFrom::End(0))?;
+        if valid == 0 {
+            file.write_all(&serialize(&sync.tree.best_tip().header))?;
+        }
+        file.sync_data()?;
+
+        sync.store = Some(file);
+        Ok(sync)
+    }
+
+    /// Tree of the downloaded headers
+    pub fn tree(&self) -> &HeaderTree {
+        &self.tree
+    }
+
+    /// Network-adjusted time tracker
+    pub fn adjusted_time(&self) -> &AdjustedTime {
+        &self.time
+    }
+
+    /// Header of the best tip
+    pub fn tip(&self) -> &BlockHeader {
+        &self.tree.best_tip().header
+    }
+
+    /// Hash of the best tip
+    pub fn tip_hash(&self) -> sha256d::Hash {
+        self.tree.best_tip().hash
+    }
+
+    /// Start tracking a peer after receiving its `version` message, taking
+    /// its height and a sample of its clock
+    pub fn add_peer(&mut self, peer: PeerId, version: &VersionMessage) {
+        self.time.add_sample(peer, version.timestamp, time::local_time());
+        self.peers.insert(peer, PeerState {
+            best_height:  if version.last_block > 0 { version.last_block as u64 } else { 0 },
+            best_hash:    None,
+            request_sent: None,
+            stalled:      false,
+        });
+    }
+
+    /// Stop tracking a disconnected peer
+    pub fn remove_peer(&mut self, peer: PeerId) {
+        self.peers.remove(&peer);
+    }
+
+    /// Record a block the peer announced through `inv` or `headers`
+    pub fn peer_announced(&mut self, peer: PeerId, hash: sha256d::Hash) {
+        let height = self.tree.get(&hash).map(|entry| entry.height());
+        if let Some(state) = self.peers.get_mut(&peer) {
+            state.best_hash = Some(hash);
+            if let Some(height) = height {
+                state.best_height = state.best_height.max(height);
+            }
+        }
+    }
+
+    /// Check whether a peer is ahead of us
+    fn peer_is_ahead(&self, state: &PeerState) -> bool {
+        let tip_height = self.tree.best_height();
+        state.best_height > tip_height ||
+            state.best_hash.map_or(false, |hash| !self.tree.contains(&hash))
+    }
+
+    /// Check if no known peer has headers we are missing
+    pub fn is_synced(&self) -> bool {
+        !self.peers.values().any(|state| self.peer_is_ahead(state))
+    }
+
+    /// Build the `getheaders` message continuing from our tip
+    fn get_headers_msg(&self) -> GetHeadersMessage {
+        GetHeadersMessage::new_get_headers_msg(locator::build_locator(&self.tree), Default::default())
+    }
+
+    /// Build the `getheaders` message continuing from a header of the tree,
+    /// which may be on a competing chain
+    fn get_headers_msg_from(&self, hash: &sha256d::Hash) -> GetHeadersMessage {
+        let height = self.tree.get(hash).map_or(0, |entry| entry.height());
+        let locator = locator::locator_heights(height).into_iter()
+            .filter_map(|height| self.tree.ancestor(hash, height).map(|entry| entry.hash))
+            .collect();
+        GetHeadersMessage::new_get_headers_msg(locator, Default::default())
+    }
+
+    /// Pick the peer to download headers from next, if there is no request
+    /// outstanding; returns the peer and the message to send to it
+    pub fn next_request(&mut self, now: Instant) -> Option<(PeerId, GetHeadersMessage)> {
+        if self.peers.values().any(|state| state.request_sent.is_some()) {
+            return None;
+        }
+        let peer = self.peers.iter()
+            .filter(|&(_, state)| !state.stalled && self.peer_is_ahead(state))
+            .max_by_key(|&(_, state)| state.best_height)
+            .map(|(peer, _)| *peer)?;
+
+        let msg = self.get_headers_msg();
+        self.peers.get_mut(&peer).unwrap().request_sent = Some(now);
+        Some((peer, msg))
+    }
+
+    /// Process a `headers` message received from a peer
+    pub fn on_headers(&mut self, peer: PeerId, msg: &HeadersMessage, now: Instant) -> Result<HeadersOutcome, Error> {
+        if let Some(state) = self.peers.get_mut(&peer) {
+            state.request_sent = None;
+            state.stalled = false;
+        }
+
+        let mut outcome = HeadersOutcome { accepted: 0, reorgs: Vec::new(), request: None };
+        let first = match msg.headers.first() {
+            Some(first) => first,
+            None        => return Ok(outcome),
+        };
+        if !self.tree.contains(&first.prev_blockhash) {
+            return Err(Error::ErrorUnconnectedHeader);
+        }
+        for pair in msg.headers.windows(2) {
+            if pair[1].prev_blockhash != pair[0].blockchain_hash() {
+                return Err(Error::ErrorNonContinuousHeaders);
+            }
+        }
+
+        let mut new_headers = Vec::new();
+        let mut result = Ok(());
+        let adjusted_time = self.time.now();
+        for header in msg.headers.iter() {
+            if self.tree.contains(&header.blockchain_hash()) {
+                continue;
+            }
+            match self.tree.insert(*header, adjusted_time) {
+                Ok(reorg) => outcome.reorgs.extend(reorg),
+                Err(e)    => {
+                    result = Err(e);
+                    break;
+                }
+            }
+            new_headers.extend(serialize(header));
+            outcome.accepted += 1;
+        }
+
+        // Headers accepted before an invalid one are kept
+        if let Some(ref mut file) = self.store {
+            file.write_all(&new_headers)?;
+            file.sync_data()?;
+        }
+        result?;
+
+        let last_hash = msg.headers.last().unwrap().blockchain_hash();
+        let last_height = self.tree.get(&last_hash).map(|entry| entry.height());
+        // A full batch means the peer may have more, even when we already
+        // had all of these headers, so continue from the last one it sent
+        let full_batch = msg.headers.len() == MAX_BLOCK_HEADERS_PER_MSG as usize;
+        let request = if full_batch { Some(self.get_headers_msg_from(&last_hash)) } else { None };
+
+        if let Some(state) = self.peers.get_mut(&peer) {
+            state.best_hash = Some(last_hash);
+            if let Some(height) = last_height {
+                state.best_height = state.best_height.max(height);
+            }
+            if request.is_some() {
+                state.request_sent = Some(now);
+            }
+        }
+        outcome.request = request;
+        Ok(outcome)
+    }
+
+    /// Return the peers that did not answer a `getheaders` in time;
+    /// their request is dropped and they are skipped by `next_request`
+    /// so that another peer is asked
+    pub fn stalled_peers(&mut self, now: Instant) -> Vec<PeerId> {
+        let mut stalled = Vec::new();
+        for (peer, state) in self.peers.iter_mut() {
+            let expired = state.request_sent
+                .map_or(false, |sent| now.duration_since(sent) > HEADERS_RESPONSE_TIMEOUT);
+            if expired {
+                state.request_sent = None;
+                state.stalled = true;
+                stalled.push(*peer);
+            }
+        }
+        stalled
+    }
+}
@@ -0,0 +1,267 @@
+/// In-memory tree of all known block headers
+///
+/// Every header is stored with its height and the work accumulated from
+/// genesis up to it. The best tip is the header with the most chainwork,
+/// the first one seen winning a tie. Each entry keeps a skip pointer to an
+/// ancestor further back so that ancestors at any height are found in a
+/// logarithmic number of steps.
+
+use std::collections::HashMap;
+
+use hashes::sha256d;
+
+use blockdata::block::BlockHeader;
+use blockdata::constants::genesis_header;
+use blockdata::Error::{BlockTimeTooOldError, BlockTimeTooNewError, BlockCheckpointError};
+use chain::checkpoints::{self, last_checkpoint_height};
+use chain::difficulty::next_target_required;
+use chain::error::Error;
+use chain::time::{median_time_past, MAX_FUTURE_BLOCK_TIME};
+use chain::locator::HeaderChain;
+use consensus::params::Params;
+use util::hash::BlockchainHash;
+use util::uint::Uint256;
+
+/// A header stored in the tree
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HeaderEntry {
+    /// The header, with its `height` field set
+    pub header: BlockHeader,
+    /// Hash of the header
+    pub hash: sha256d::Hash,
+    /// Total work of the chain ending with this header
+    pub chainwork: Uint256,
+    /// Hash of an ancestor further back, used to speed up ancestor lookup
+    skip: Option<sha256d::Hash>,
+}
+
+impl HeaderEntry {
+    /// Height of the header
+    pub fn height(&self) -> u64 {
+        self.header.height
+    }
+}
+
+/// Change of the best chain after a header has been added; `disconnected`
+/// lists the removed headers from the old tip down, `connected` the new ones
+/// from the fork point up. `disconnected` is empty when the tip was extended.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Reorg {
+    /// Hashes removed from the best chain, old tip first
+    pub disconnected: Vec<sha256d::Hash>,
+    /// Hashes added to the best chain, new tip last
+    pub connected: Vec<sha256d::Hash>,
+}
+
+/// Turn the lowest set bit off
+fn invert_lowest_one(n: u64) -> u64 {
+    n & n.wrapping_sub(1)
+}
+
+/// Height the skip pointer of a header at the given height points to
+fn skip_height(height: u64) -> u64 {
+    if height < 2 {
+        return 0;
+    }
+    // Jumps are chosen so that any ancestor is reachable in O(log n) steps
+    if height & 1 == 1 {
+        invert_lowest_one(invert_lowest_one(height - 1)) + 1
+    } else {
+        invert_lowest_one(height)
+    }
+}
+
+/// Tree of block headers with total-work fork choice
+pub struct HeaderTree {
+    params: Params,
+    /// Checkpoints of the network, ordered by height
+    checkpoints: Vec<(u64, sha256d::Hash)>,
+    entries: HashMap<sha256d::Hash, HeaderEntry>,
+    /// Hashes of the best chain indexed by height
+    best_chain: Vec<sha256d::Hash>,
+}
+
+impl HeaderTree {
+    /// Create a tree holding only the genesis header of the network
+    pub fn new(params: Params) -> HeaderTree {
+        let genesis = genesis_header(params.network);
+        let hash = genesis.blockchain_hash();
+        let mut entries = HashMap::new();
+        entries.insert(hash, HeaderEntry {
+            header:    genesis,
+            hash:      hash,
+            chainwork: genesis.total_work(),
+            skip:      None,
+        });
+        HeaderTree {
+            checkpoints: checkpoints::checkpoints(params.network),
+            params:      params,
+            entries:     entries,
+            best_chain:  vec![hash],
+        }
+    }
+
+    /// Consensus parameters the tree validates headers against
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    /// Checkpoints of the network, ordered by height
+    pub fn checkpoints(&self) -> &[(u64, sha256d::Hash)] {
+        &self.checkpoints
+    }
+
+    /// Check if a header is known
+    pub fn contains(&self, hash: &sha256d::Hash) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    /// Return a known header
+    pub fn get(&self, hash: &sha256d::Hash) -> Option<&HeaderEntry> {
+        self.entries.get(hash)
+    }
+
+    /// Return the entry of the best tip
+    pub fn best_tip(&self) -> &HeaderEntry {
+        &self.entries[self.best_chain.last().unwrap()]
+    }
+
+    /// Check if a header is part of the best chain
+    pub fn is_on_best_chain(&self, hash: &sha256d::Hash) -> bool {
+        match self.entries.get(hash) {
+            Some(entry) => self.best_chain.get(entry.height() as usize) == Some(hash),
+            None        => false,
+        }
+    }
+
+    /// Return the ancestor at the given height of a known header,
+    /// following skip pointers where they do not overshoot
+    pub fn ancestor(&self, hash: &sha256d::Hash, height: u64) -> Option<&HeaderEntry> {
+        let mut entry = self.entries.get(hash)?;
+        if height > entry.height() {
+            return None;
+        }
+        while entry.height() > height {
+            let walk_height = entry.height();
+            let skip = skip_height(walk_height);
+            let skip_prev = skip_height(walk_height - 1);
+            let use_skip = entry.skip.is_some() &&
+                (skip == height || (skip > height && !(skip_prev + 2 < skip && skip_prev >= height)));
+            entry = if use_skip {
+                &self.entries[entry.skip.as_ref().unwrap()]
+            } else {
+                &self.entries[&entry.header.prev_blockhash]
+            };
+        }
+        Some(entry)
+    }
+
+    /// Return the last header two headers have in common
+    pub fn fork_point(&self, a: &sha256d::Hash, b: &sha256d::Hash) -> Option<&HeaderEntry> {
+        let (mut a, mut b) = (self.entries.get(a)?, self.entries.get(b)?);
+        if a.height() > b.height() {
+            a = self.ancestor(&a.hash, b.height())?;
+        } else if b.height() > a.height() {
+            b = self.ancestor(&b.hash, a.height())?;
+        }
+        while a.hash != b.hash {
+            a = &self.entries[&a.header.prev_blockhash];
+            b = &self.entries[&b.header.prev_blockhash];
+        }
+        Some(a)
+    }
+
+    /// Validate a header against its parent and the network-adjusted time
+    fn check_header(&self, header: &BlockHeader, prev: &HeaderEntry, adjusted_time: i64) -> Result<(), Error> {
+        let required = next_target_required(self, prev, header.timestamp);
+        header.validate_pow(&BlockHeader::compute_target_from_compact(required))?;
+
+        if header.timestamp <= median_time_past(self, prev) {
+            return Err(Error::ErrorInvalidHeader(BlockTimeTooOldError));
+        }
+        if header.timestamp as i64 > adjusted_time + MAX_FUTURE_BLOCK_TIME {
+            return Err(Error::ErrorInvalidHeader(BlockTimeTooNewError));
+        }
+
+        // History up to the last checkpoint we have is final
+        let height = prev.height() + 1;
+        if last_checkpoint_height(self).map_or(false, |checkpoint| height < checkpoint) {
+            return Err(Error::ErrorInvalidHeader(BlockCheckpointError));
+        }
+        let hash = header.blockchain_hash();
+        if self.checkpoints.iter().any(|&(checkpoint, ref expected)| checkpoint == height && *expected != hash) {
+            return Err(Error::ErrorInvalidHeader(BlockCheckpointError));
+        }
+        Ok(())
+    }
+
+    /// Add a header to the tree, returning the change of the best chain if
+    /// the header became the new best tip. Known headers are ignored.
+    /// `adjusted_time` is the network-adjusted time in seconds since the epoch.
+    pub fn insert(&mut self, mut header: BlockHeader, adjusted_time: i64) -> Result<Option<Reorg>, Error> {
+        let hash = header.blockchain_hash();
+        if self.entries.contains_key(&hash) {
+            return Ok(None);
+        }
+
+        let (height, chainwork, skip) = {
+            let prev = self.entries.get(&header.prev_blockhash).ok_or(Error::ErrorUnconnectedHeader)?;
+            self.check_header(&header, prev, adjusted_time)?;
+            let height = prev.height() + 1;
+            let skip = self.ancestor(&prev.hash, skip_height(height)).map(|entry| entry.hash);
+            (height, prev.chainwork + header.total_work(), skip)
+        };
+
+        header.height = height;
+        self.entries.insert(hash, HeaderEntry {
+            header:    header,
+            hash:      hash,
+            chainwork: chainwork,
+            skip:      skip,
+        });
+
+        if chainwork <= self.best_tip().chainwork {
+            return Ok(None);
+        }
+        Ok(Some(self.set_best_tip(hash)))
+    }
+
+    /// Switch the best chain to end with the given header
+    fn set_best_tip(&mut self, hash: sha256d::Hash) -> Reorg {
+        let old_tip = *self.best_chain.last().unwrap();
+        let fork_height = self.fork_point(&old_tip, &hash).unwrap().height();
+
+        let disconnected = self.best_chain.split_off(fork_height as usize + 1);
+        let mut connected = Vec::new();
+        let mut entry = &self.entries[&hash];
+        while entry.height() > fork_height {
+            connected.push(entry.hash);
+            entry = &self.entries[&entry.header.prev_blockhash];
+        }
+        connected.reverse();
+        self.best_chain.extend(connected.iter().cloned());
+
+        Reorg {
+            disconnected: disconnected.into_iter().rev().collect(),
+            connected:    connected,
+        }
+    }
+}
+
+impl HeaderChain for HeaderTree {
+    fn best_height(&self) -> u64 {
+        (self.best_chain.len() - 1) as u64
+    }
+
+    fn header_at_height(&self, height: u64) -> Option<BlockHeader> {
+        self.best_chain.get(height as usize).map(|hash| self.entries[hash].header)
+    }
+
+    fn height_of(&self, hash: &sha256d::Hash) -> Option<u64> {
+        if self.is_on_best_chain(hash) {
+            self.entries.get(hash).map(|entry| entry.height())
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,126 @@
+/// Block locators and the replies to `getheaders` and `getblocks`
+///
+/// A locator is a list of block hashes from our best tip back to genesis,
+/// dense for the most recent blocks and sparse further back, that lets a
+/// peer find the last block we have in common with it
+
+use hashes::sha256d;
+
+use blockdata::block::BlockHeader;
+use util::hash::BlockchainHash;
+use util::constants::{MAX_BLOCK_HEADERS_PER_MSG, MAX_BLOCKS_PER_MSG, MAX_LOCATOR_HASHES};
+use wire::msgblock::{GetHeadersMessage, GetBlocksMessage, HeadersMessage};
+use wire::msginv::{Inventory, InvType, InvMessage};
+
+/// Number of most recent blocks included one by one in a locator
+const LOCATOR_DENSE_BLOCKS: usize = 10;
+
+/// A chain of block headers as seen along our best chain
+pub trait HeaderChain {
+    /// Height of the best tip
+    fn best_height(&self) -> u64;
+
+    /// Header at the given height on the best chain
+    fn header_at_height(&self, height: u64) -> Option<BlockHeader>;
+
+    /// Height of the header with the given hash if it is on the best chain
+    fn height_of(&self, hash: &sha256d::Hash) -> Option<u64>;
+}
+
+/// Build a locator starting from the best tip of the chain
+pub fn build_locator<C: HeaderChain>(chain: &C) -> Vec<sha256d::Hash> {
+    build_locator_from(chain, chain.best_height())
+}
+
+/// Build a locator starting at the given height on the best chain
+pub fn build_locator_from<C: HeaderChain>(chain: &C, height: u64) -> Vec<sha256d::Hash> {
+    let mut locator = Vec::new();
+    for height in locator_heights(height) {
+        match chain.header_at_height(height) {
+            Some(header) => locator.push(header.blockchain_hash()),
+            None         => break,
+        }
+    }
+    locator
+}
+
+/// Heights of the blocks of a locator starting at the given height:
+/// the last 10 blocks one by one, then doubling the step back to genesis
+pub fn locator_heights(height: u64) -> Vec<u64> {
+    let mut heights = Vec::new();
+    let mut height = height;
+    let mut step = 1u64;
+
+    loop {
+        heights.push(height);
+        if height == 0 {
+            return heights;
+        }
+        if heights.len() >= LOCATOR_DENSE_BLOCKS {
+            step *= 2;
+        }
+        height = height.saturating_sub(step);
+    }
+}
+
+/// Find the height of the last block we have in common with the
+/// peer that sent the locator; genesis if none of the hashes is known
+pub fn find_fork_point<C: HeaderChain>(chain: &C, locator: &[sha256d::Hash]) -> u64 {
+    locator.iter()
+        .take(MAX_LOCATOR_HASHES)
+        .filter_map(|hash| chain.height_of(hash))
+        .next()
+        .unwrap_or(0)
+}
+
+/// Reply to a `getheaders` message with up to 2000 headers following the
+/// fork point; the header matching `stop_hash` is the last one included
+pub fn respond_get_headers<C: HeaderChain>(chain: &C, msg: &GetHeadersMessage) -> HeadersMessage {
+    let mut headers = Vec::new();
+    let zero_hash: sha256d::Hash = Default::default();
+
+    // Without a locator the peer is asking for the single header
+    // identified by the stop hash
+    if msg.block_location_hashes.is_empty() {
+        if let Some(height) = chain.height_of(&msg.stop_hash) {
+            headers.extend(chain.header_at_height(height));
+        }
+        return HeadersMessage::new_headers_msg(headers);
+    }
+
+    let mut height = find_fork_point(chain, &msg.block_location_hashes) + 1;
+    while headers.len() < MAX_BLOCK_HEADERS_PER_MSG as usize {
+        let header = match chain.header_at_height(height) {
+            Some(header) => header,
+            None         => break,
+        };
+        let hash = header.blockchain_hash();
+        headers.push(header);
+        if msg.stop_hash != zero_hash && hash == msg.stop_hash {
+            break;
+        }
+        height += 1;
+    }
+    HeadersMessage::new_headers_msg(headers)
+}
+
+/// Reply to a `getblocks` message with up to 500 block inventories following
+/// the fork point; the block matching `hash_stop` is not included
+pub fn respond_get_blocks<C: HeaderChain>(chain: &C, msg: &GetBlocksMessage) -> InvMessage {
+    let mut inventory = Vec::new();
+    let zero_hash: sha256d::Hash = Default::default();
+
+    let mut height = find_fork_point(chain, &msg.block_locator_hashes) + 1;
+    while inventory.len() < MAX_BLOCKS_PER_MSG as usize {
+        let hash = match chain.header_at_height(height) {
+            Some(header) => header.blockchain_hash(),
+            None         => break,
+        };
+        if msg.hash_stop != zero_hash && hash == msg.hash_stop {
+            break;
+        }
+        inventory.push(Inventory::new_inventory(InvType::Block, hash));
+        height += 1;
+    }
+    InvMessage::new_inv_msg(inventory)
+}
@@ -0,0 +1,102 @@
+/// Timestamp rules for block headers
+///
+/// A header must be timestamped after the median of the 11 blocks before it
+/// and no more than two hours after the network-adjusted time. The
+/// network-adjusted time is our clock corrected by the median offset of the
+/// clocks peers report in their `version` messages.
+
+use std::collections::HashSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chain::headersync::PeerId;
+use chain::headertree::{HeaderTree, HeaderEntry};
+
+/// Number of previous blocks the median time past is computed over
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Maximum time in seconds a header may be ahead of the network-adjusted time
+pub const MAX_FUTURE_BLOCK_TIME: i64 = 2 * 60 * 60;
+
+/// Maximum number of peer clock samples kept
+const MAX_TIME_SAMPLES: usize = 200;
+
+/// Minimum number of samples before our clock gets adjusted
+const MIN_TIME_SAMPLES: usize = 5;
+
+/// Largest adjustment in seconds applied to our clock
+const MAX_TIME_ADJUSTMENT: i64 = 70 * 60;
+
+/// Return the median timestamp of a header and the 10 headers before it
+pub fn median_time_past(tree: &HeaderTree, entry: &HeaderEntry) -> u32 {
+    let mut timestamps = Vec::with_capacity(MEDIAN_TIME_SPAN);
+    let mut current = Some(entry);
+    while let Some(entry) = current {
+        if timestamps.len() == MEDIAN_TIME_SPAN {
+            break;
+        }
+        timestamps.push(entry.header.timestamp);
+        current = if entry.height() > 0 { tree.get(&entry.header.prev_blockhash) } else { None };
+    }
+    timestamps.sort();
+    timestamps[timestamps.len() / 2]
+}
+
+/// Return the local clock in seconds since the epoch
+pub fn local_time() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+/// Tracker of the offset between our clock and the clocks of our peers
+pub struct AdjustedTime {
+    /// Peers that already provided a sample
+    sampled: HashSet<PeerId>,
+    /// Offsets of the peer clocks to ours in seconds, in arrival order
+    offsets: Vec<i64>,
+    /// Offset currently applied to our clock
+    offset: i64,
+}
+
+impl AdjustedTime {
+    pub fn new() -> AdjustedTime {
+        AdjustedTime {
+            sampled: HashSet::new(),
+            offsets: Vec::new(),
+            offset:  0,
+        }
+    }
+
+    /// Record the timestamp a peer sent in its `version` message,
+    /// received when our clock showed `local`; one sample per peer is used
+    pub fn add_sample(&mut self, peer: PeerId, peer_time: i64, local: i64) {
+        if self.offsets.len() >= MAX_TIME_SAMPLES || !self.sampled.insert(peer) {
+            return;
+        }
+        self.offsets.push(peer_time - local);
+
+        // Only an odd number of samples gives an unambiguous median
+        if self.offsets.len() < MIN_TIME_SAMPLES || self.offsets.len() % 2 == 0 {
+            return;
+        }
+        let mut sorted = self.offsets.clone();
+        sorted.sort();
+        let median = sorted[sorted.len() / 2];
+        // Peers far off from us are more likely to be wrong or lying than our clock
+        self.offset = if median.abs() <= MAX_TIME_ADJUSTMENT { median } else { 0 };
+    }
+
+    /// Offset in seconds applied to our clock
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    /// Return the network-adjusted time in seconds since the epoch
+    pub fn now(&self) -> i64 {
+        local_time() + self.offset
+    }
+}
+
+impl Default for AdjustedTime {
+    fn default() -> AdjustedTime {
+        AdjustedTime::new()
+    }
+}
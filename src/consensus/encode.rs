@@ -1,6 +1,6 @@
 use std::{u32, mem};
 
-use std::io::error;
+use std::error;
 use std::fmt;
 use std::io;
 use std::io::{Read, Write};
@@ -8,7 +8,9 @@ use std::io::{Read, Write};
 use byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
 use hashes::{sha256d, Hash as HashTrait};
 
-use util::constants{MAX_VECTOR_SIZE};
+use blockdata::block::BlockHeader;
+use blockdata::transaction::{Transaction, TxIn, TxOut};
+use util::constants::MAX_VECTOR_SIZE;
 
 #[derive(Debug)]
 pub enum Error {
@@ -18,24 +20,28 @@ pub enum Error {
     ErrorVarintDecode(io::Error),
     /// Error received from parsing data
     ErrorParse(&'static str),
+    /// Vector length exceeds the maximum allowed by the protocol
+    ErrorOversizedVector { requested: usize, max: usize },
 }
 
 impl fmt::Display for Error {
-    fn fmt(&self, format: fmt::Formatter) -> fmt::Result {
+    fn fmt(&self, format: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::ErrorEncodeDecode(ref err) => fmt::Display::fmt(err, format),
             Error::ErrorVarintDecode(ref err) => fmt::Display::fmt(err, format),
             Error::ErrorParse(ref err)        => write!(format, "{}: {}", error::Error::description(self), err),
+            Error::ErrorOversizedVector { requested, max } => write!(format, "{}: requested {}, max {}", error::Error::description(self), requested, max),
         }
     }
 }
 
-impl error:Error for Error {
-    fn reason(&self) -> Option<&error::Error> {
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
         match *self {
             Error::ErrorEncodeDecode(ref err) => Some(err),
             Error::ErrorVarintDecode(ref err) => Some(err),
             Error::ErrorParse(..)             => None,
+            Error::ErrorOversizedVector { .. } => None,
         }
     }
 
@@ -43,11 +49,19 @@ impl error:Error for Error {
         match *self {
             Error::ErrorEncodeDecode(ref err) => err.description(),
             Error::ErrorVarintDecode(ref err) => err.description(),
-            Error::ErrorParse(ref err)        => err.description(),
+            Error::ErrorParse(..)             => "parse error",
+            Error::ErrorOversizedVector { .. } => "vector exceeds the maximum allowed length",
         }
     }
 }
 
+#[doc(hidden)]
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::ErrorEncodeDecode(err)
+    }
+}
+
 /// WriteExtend extends std::io::Write in order to achieve Bitcoin consensus data encoding
 pub trait WriteExtend {
     /// Output 64-bit uint
@@ -90,7 +104,7 @@ pub trait ReadExtend {
     fn read_i8(&mut self) -> Result<i8, Error>;
 
     /// Read a byte slice
-    fn read_byte_slice(&mut self, slice: [u8]) -> Result<(), Error>;
+    fn read_byte_slice(&mut self, slice: &mut [u8]) -> Result<(), Error>;
 }
 
 macro_rules! encoder_cns {
@@ -111,7 +125,7 @@ macro_rules! decoder_cns {
     }
 }
 
-impl <W: Write>WriteExtend for Writer {
+impl <W: Write>WriteExtend for W {
     encoder_cns!(output_u64, u64, write_u64);
     encoder_cns!(output_u32, u32, write_u32);
     encoder_cns!(output_u16, u16, write_u16);
@@ -125,7 +139,7 @@ impl <W: Write>WriteExtend for Writer {
     }
     #[inline]
     fn output_i8(&mut self, value: i8) -> Result<(), Error> {
-        self.output_i8(value).map_err(Error::ErrorEncodeDecode)
+        self.write_i8(value).map_err(Error::ErrorEncodeDecode)
     }
 
     #[inline]
@@ -144,12 +158,12 @@ impl <R: Read>ReadExtend for R {
 
     #[inline]
     fn read_u8(&mut self) -> Result<u8, Error> {
-        ReadExtend::read_u8(self).map_err(Error::ErrorEncodeDecode)
+        ReadBytesExt::read_u8(self).map_err(Error::ErrorEncodeDecode)
     }
     
     #[inline]
     fn read_i8(&mut self) -> Result<i8, Error> {
-        ReadExtend::read_i8(self).map_err(Error::ErrorEncodeDecode)
+        ReadBytesExt::read_i8(self).map_err(Error::ErrorEncodeDecode)
     }
 
     #[inline]
@@ -176,9 +190,9 @@ pub struct VarInt(pub u64);
 
 /// Checked data that is to be preceded by a checksum - part of Base58 encode algorithm
 #[derive(PartialEq, Eq, Clone, Debug)]
-pub stuct CheckSumData(pub Vec<u8>);
+pub struct CheckSumData(pub Vec<u8>);
 
-///
+/// Implement consensus encoding for the little-endian integer types
 macro_rules! int_encodable{
     ($type:ident, $decode_method:ident, $encode_method:ident) => (
         impl Decodable for $type {
@@ -190,7 +204,7 @@ macro_rules! int_encodable{
 
         impl Encodable for $type {
             #[inline]
-            fn encode<S: WriteExtend>(
+            fn encode<S: io::Write>(
                 &self,
                 mut s: S,
             ) -> Result<usize, self::Error> {
@@ -255,13 +269,13 @@ impl Encodable for VarInt {
 
 }
 
-impl Decodable for Varint {
+impl Decodable for VarInt {
     #[inline]
     fn decode<Decoder: io::Read>(mut dec: Decoder) -> Result<Self, Error> {
         let n = ReadExtend::read_u8(&mut dec)?;
         match n {
             0xFF => {
-                let x = ReadExtend::read_u8(&mut dec)?;
+                let x = ReadExtend::read_u64(&mut dec)?;
                 if x < 0x100000000 {
                     Err(self::Error::ErrorParse("non-minimal varint"))
                 } else {
@@ -277,7 +291,7 @@ impl Decodable for Varint {
                 }
             }
             0xFD => {
-                let x = ReadExtend::read_u16(&mut d)?;
+                let x = ReadExtend::read_u16(&mut dec)?;
                 if x < 0xFD {
                     Err(self::Error::ErrorParse("non-minimal varint"))
                 } else {
@@ -296,8 +310,184 @@ impl Encodable for sha256d::Hash {
 }
 
 impl Decodable for sha256d::Hash {
-    fn decode<Dec: io.Read>(dec: Dec) -> Result<Self, Error> {
+    fn decode<Dec: io::Read>(dec: Dec) -> Result<Self, Error> {
         let inner = <[u8; 32]>::decode(dec)?;
         Ok(sha256d::Hash::from_slice(&inner).unwrap())
     }
-}
\ No newline at end of file
+}
+impl Encodable for bool {
+    #[inline]
+    fn encode<Wr: io::Write>(&self, mut writer: Wr) -> Result<usize, Error> {
+        writer.output_u8(if *self { 1 } else { 0 })?;
+        Ok(1)
+    }
+}
+
+impl Decodable for bool {
+    #[inline]
+    fn decode<Decoder: io::Read>(mut dec: Decoder) -> Result<Self, Error> {
+        ReadExtend::read_u8(&mut dec).map(|value| value != 0)
+    }
+}
+
+impl Encodable for String {
+    #[inline]
+    fn encode<Wr: io::Write>(&self, mut writer: Wr) -> Result<usize, Error> {
+        let bytes = self.as_bytes();
+        let length = VarInt(bytes.len() as u64).encode(&mut writer)?;
+        writer.output_byte_slice(bytes)?;
+        Ok(length + bytes.len())
+    }
+}
+
+impl Decodable for String {
+    #[inline]
+    fn decode<Decoder: io::Read>(dec: Decoder) -> Result<Self, Error> {
+        String::from_utf8(Decodable::decode(dec)?)
+            .map_err(|_| self::Error::ErrorParse("string is not valid UTF8"))
+    }
+}
+
+/// Implement consensus encoding for fixed-size byte arrays
+macro_rules! byte_array_encodable {
+    ($size:expr) => (
+        impl Encodable for [u8; $size] {
+            #[inline]
+            fn encode<Wr: io::Write>(&self, mut writer: Wr) -> Result<usize, Error> {
+                writer.output_byte_slice(&self[..])?;
+                Ok(self.len())
+            }
+        }
+
+        impl Decodable for [u8; $size] {
+            #[inline]
+            fn decode<Decoder: io::Read>(mut dec: Decoder) -> Result<Self, Error> {
+                let mut ret = [0; $size];
+                dec.read_byte_slice(&mut ret)?;
+                Ok(ret)
+            }
+        }
+    )
+}
+
+byte_array_encodable!(2);
+byte_array_encodable!(4);
+byte_array_encodable!(6);
+byte_array_encodable!(8);
+byte_array_encodable!(12);
+byte_array_encodable!(16);
+byte_array_encodable!(32);
+
+impl Encodable for [u16; 8] {
+    #[inline]
+    fn encode<Wr: io::Write>(&self, mut writer: Wr) -> Result<usize, Error> {
+        for segment in self.iter() {
+            segment.encode(&mut writer)?;
+        }
+        Ok(16)
+    }
+}
+
+impl Decodable for [u16; 8] {
+    #[inline]
+    fn decode<Decoder: io::Read>(mut dec: Decoder) -> Result<Self, Error> {
+        let mut ret = [0u16; 8];
+        for segment in ret.iter_mut() {
+            *segment = Decodable::decode(&mut dec)?;
+        }
+        Ok(ret)
+    }
+}
+
+/// Implement consensus encoding for vectors of encodable items,
+/// prefixed by their `VarInt` length
+macro_rules! vec_encodable {
+    ($type:ty) => (
+        impl Encodable for Vec<$type> {
+            #[inline]
+            fn encode<Wr: io::Write>(&self, mut writer: Wr) -> Result<usize, Error> {
+                let mut length = VarInt(self.len() as u64).encode(&mut writer)?;
+                for item in self.iter() {
+                    length += item.encode(&mut writer)?;
+                }
+                Ok(length)
+            }
+        }
+
+        impl Decodable for Vec<$type> {
+            #[inline]
+            fn decode<Decoder: io::Read>(mut dec: Decoder) -> Result<Self, Error> {
+                let count = VarInt::decode(&mut dec)?.0;
+                let byte_size = (count as usize)
+                    .checked_mul(mem::size_of::<$type>())
+                    .ok_or(self::Error::ErrorParse("invalid length"))?;
+                if byte_size > MAX_VECTOR_SIZE {
+                    return Err(self::Error::ErrorOversizedVector { requested: byte_size, max: MAX_VECTOR_SIZE });
+                }
+                let mut ret = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    ret.push(Decodable::decode(&mut dec)?);
+                }
+                Ok(ret)
+            }
+        }
+    )
+}
+
+vec_encodable!(u64);
+vec_encodable!(sha256d::Hash);
+vec_encodable!(Vec<u8>);
+vec_encodable!(BlockHeader);
+vec_encodable!(TxIn);
+vec_encodable!(TxOut);
+vec_encodable!(Transaction);
+
+impl Encodable for Vec<u8> {
+    #[inline]
+    fn encode<Wr: io::Write>(&self, mut writer: Wr) -> Result<usize, Error> {
+        let length = VarInt(self.len() as u64).encode(&mut writer)?;
+        writer.output_byte_slice(&self)?;
+        Ok(length + self.len())
+    }
+}
+
+impl Decodable for Vec<u8> {
+    #[inline]
+    fn decode<Decoder: io::Read>(mut dec: Decoder) -> Result<Self, Error> {
+        let length = VarInt::decode(&mut dec)?.0 as usize;
+        if length > MAX_VECTOR_SIZE {
+            return Err(self::Error::ErrorOversizedVector { requested: length, max: MAX_VECTOR_SIZE });
+        }
+        let mut ret = vec![0u8; length];
+        dec.read_byte_slice(&mut ret)?;
+        Ok(ret)
+    }
+}
+
+/// Encode an object into a vector of bytes
+pub fn serialize<T: Encodable + ?Sized>(data: &T) -> Vec<u8> {
+    let mut encoder = Vec::new();
+    let length = data.encode(&mut encoder).unwrap();
+    debug_assert_eq!(length, encoder.len());
+    encoder
+}
+
+/// Decode an object from a vector of bytes, failing if the data
+/// is not consumed completely
+pub fn deserialize<T: Decodable>(data: &[u8]) -> Result<T, Error> {
+    let mut decoder = io::Cursor::new(data);
+    let ret = T::decode(&mut decoder)?;
+    if decoder.position() as usize == data.len() {
+        Ok(ret)
+    } else {
+        Err(self::Error::ErrorParse("data not consumed entirely when deserializing"))
+    }
+}
+
+/// Decode an object from the beginning of a vector of bytes,
+/// returning the object and the number of consumed bytes
+pub fn deserialize_partial<T: Decodable>(data: &[u8]) -> Result<(T, usize), Error> {
+    let mut decoder = io::Cursor::new(data);
+    let ret = T::decode(&mut decoder)?;
+    Ok((ret, decoder.position() as usize))
+}
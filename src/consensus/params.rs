@@ -0,0 +1,159 @@
+/// Networks the node can operate on and their consensus parameters
+
+use util::amount::COIN;
+use util::uint::Uint256;
+
+/// Subsidy of the first blocks, before any halving
+const INITIAL_SUBSIDY: u64 = 50 * COIN;
+
+/// The network a node operates on
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum Network {
+    /// Main Bitcoin network
+    Bitcoin,
+    /// Public test network
+    Testnet,
+    /// Local regression testing network
+    Regtest,
+}
+
+impl Network {
+    /// Return the magic bytes prefixing every message sent on the network
+    pub fn magic(&self) -> u32 {
+        match *self {
+            Network::Bitcoin => 0xD9B4BEF9,
+            Network::Testnet => 0x0709110B,
+            Network::Regtest => 0xDAB5BFFA,
+        }
+    }
+
+    /// Return the network using the given magic bytes
+    pub fn from_magic(magic: u32) -> Option<Network> {
+        match magic {
+            0xD9B4BEF9 => Some(Network::Bitcoin),
+            0x0709110B => Some(Network::Testnet),
+            0xDAB5BFFA => Some(Network::Regtest),
+            _          => None,
+        }
+    }
+
+    /// Return the default port peers listen on
+    pub fn default_port(&self) -> u16 {
+        match *self {
+            Network::Bitcoin => 8333,
+            Network::Testnet => 18333,
+            Network::Regtest => 18444,
+        }
+    }
+}
+
+/// Highest target allowed on main and test networks
+const MAX_TARGET_BITCOIN: Uint256 = Uint256([
+    0xFFFFFFFFFFFFFFFF, 0xFFFFFFFFFFFFFFFF, 0xFFFFFFFFFFFFFFFF, 0x00000000FFFFFFFF
+]);
+
+/// Highest target allowed on the regression testing network
+const MAX_TARGET_REGTEST: Uint256 = Uint256([
+    0xFFFFFFFFFFFFFFFF, 0xFFFFFFFFFFFFFFFF, 0xFFFFFFFFFFFFFFFF, 0x7FFFFFFFFFFFFFFF
+]);
+
+/// Consensus parameters of a network
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Params {
+    /// Network the parameters belong to
+    pub network: Network,
+    /// Highest target a block hash may be below
+    pub pow_limit: Uint256,
+    /// Expected time between blocks in seconds
+    pub pow_target_spacing: u64,
+    /// Expected time of a whole difficulty period in seconds
+    pub pow_target_timespan: u64,
+    /// A block may use the lowest difficulty when it comes more than
+    /// twice the target spacing after its parent
+    pub allow_min_difficulty_blocks: bool,
+    /// Difficulty never changes
+    pub no_pow_retargeting: bool,
+    /// Number of blocks after which the block subsidy halves
+    pub subsidy_halving_interval: u64,
+    /// Height from which coinbases must start with the block height (BIP34)
+    pub bip34_height: u64,
+    /// Height from which signatures must be strict DER (BIP66)
+    pub bip66_height: u64,
+    /// Height from which OP_CHECKLOCKTIMEVERIFY is enforced (BIP65)
+    pub bip65_height: u64,
+    /// Height from which relative lock times and median time past
+    /// lock time checks are enforced (BIP68, BIP112, BIP113)
+    pub csv_height: u64,
+    /// Height from which segregated witness is enforced (BIP141, BIP143, BIP147)
+    pub segwit_height: u64,
+}
+
+impl Params {
+    /// Return the parameters of a network
+    pub fn new(network: Network) -> Params {
+        match network {
+            Network::Bitcoin => Params {
+                network:                     Network::Bitcoin,
+                pow_limit:                   MAX_TARGET_BITCOIN,
+                pow_target_spacing:          10 * 60,
+                pow_target_timespan:         14 * 24 * 60 * 60,
+                allow_min_difficulty_blocks: false,
+                no_pow_retargeting:          false,
+                subsidy_halving_interval:    210_000,
+                bip34_height:                227_931,
+                bip66_height:                363_725,
+                bip65_height:                388_381,
+                csv_height:                  419_328,
+                segwit_height:               481_824,
+            },
+            Network::Testnet => Params {
+                network:                     Network::Testnet,
+                pow_limit:                   MAX_TARGET_BITCOIN,
+                pow_target_spacing:          10 * 60,
+                pow_target_timespan:         14 * 24 * 60 * 60,
+                allow_min_difficulty_blocks: true,
+                no_pow_retargeting:          false,
+                subsidy_halving_interval:    210_000,
+                bip34_height:                21_111,
+                bip66_height:                330_776,
+                bip65_height:                581_885,
+                csv_height:                  770_112,
+                segwit_height:               834_624,
+            },
+            Network::Regtest => Params {
+                network:                     Network::Regtest,
+                pow_limit:                   MAX_TARGET_REGTEST,
+                pow_target_spacing:          10 * 60,
+                pow_target_timespan:         14 * 24 * 60 * 60,
+                allow_min_difficulty_blocks: true,
+                no_pow_retargeting:          true,
+                subsidy_halving_interval:    150,
+                bip34_height:                500,
+                bip66_height:                1_251,
+                bip65_height:                1_351,
+                csv_height:                  432,
+                segwit_height:               0,
+            },
+        }
+    }
+
+    /// Number of blocks between difficulty adjustments
+    pub fn difficulty_adjustment_interval(&self) -> u64 {
+        self.pow_target_timespan / self.pow_target_spacing
+    }
+
+    /// Return the amount a coinbase may create at the given height
+    /// on top of the fees of the block
+    pub fn block_subsidy(&self, height: u64) -> u64 {
+        let halvings = height / self.subsidy_halving_interval;
+        if halvings >= 64 {
+            return 0;
+        }
+        INITIAL_SUBSIDY >> halvings
+    }
+}
+
+/// Return the highest target allowed on a network
+pub fn max_target(network: Network) -> Uint256 {
+    Params::new(network).pow_limit
+}
@@ -8,7 +8,7 @@ macro_rules! consensus_encoding {
             ) -> Result<usize, ::consensus::encode::Error> {
                 let mut length = 0;
                 $(length += self.$key.encode(&mut w)?;)+
-                Ok(len)
+                Ok(length)
             }
         }
 
@@ -18,7 +18,7 @@ macro_rules! consensus_encoding {
                 mut d: Decoder,
             ) -> Result<$entity, ::consensus::encode::Error> {
                 Ok($entity {
-                    $($key ::consensus::Decodable::decode(&mut d)?),+
+                    $($key: ::consensus::Decodable::decode(&mut d)?),+
                 })
             }
         }
@@ -0,0 +1,103 @@
+/// Implementation of errors used in mempool namespace
+///
+use std::{error, fmt};
+
+use blockdata;
+use validation::error::Error as ValidationError;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Transaction breaks consensus rules
+    ErrorValidation(ValidationError),
+    /// Transaction is valid but not relayed by policy
+    ErrorNonStandard(&'static str),
+    /// An output is worth less than the cost of spending it
+    ErrorDust,
+    /// Fee is below the minimum accepted one
+    ErrorFee { fee: u64, required: u64 },
+    /// Transaction is already in the mempool
+    ErrorDuplicate,
+    /// Transaction spends an output already spent by a mempool transaction
+    /// that cannot be replaced
+    ErrorConflict,
+    /// Replacement does not pay enough to evict the transactions it conflicts with
+    ErrorReplacementFee { fee: u64, required: u64 },
+    /// Replacement breaks another rule of BIP125
+    ErrorReplacementRejected(&'static str),
+    /// Some spent outputs are neither in the unspent set nor in the mempool
+    ErrorMissingInputs,
+    /// Transaction would exceed the limits on unconfirmed ancestors or descendants
+    ErrorTooLongChain,
+    /// Transaction was evicted right away to keep the mempool within its size
+    ErrorMempoolFull,
+}
+
+impl Error {
+    /// Return the error reported to peers; `None` when the transaction
+    /// may still turn out valid, as an orphan does
+    pub fn blockdata_error(&self) -> Option<blockdata::Error> {
+        match *self {
+            Error::ErrorValidation(ref e)       => e.blockdata_error(),
+            Error::ErrorNonStandard(..)         => Some(blockdata::Error::TransactionNonStandardError),
+            Error::ErrorDust                    => Some(blockdata::Error::TransactionDustError),
+            Error::ErrorFee { .. }              => Some(blockdata::Error::TransactionFeeError),
+            Error::ErrorDuplicate               => Some(blockdata::Error::TransactionDuplicateError),
+            Error::ErrorConflict                => Some(blockdata::Error::TransactionDuplicateError),
+            Error::ErrorReplacementFee { .. }   => Some(blockdata::Error::TransactionFeeError),
+            Error::ErrorReplacementRejected(..) => Some(blockdata::Error::TransactionNonStandardError),
+            Error::ErrorMissingInputs           => None,
+            Error::ErrorTooLongChain            => Some(blockdata::Error::TransactionNonStandardError),
+            Error::ErrorMempoolFull             => Some(blockdata::Error::TransactionFeeError),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::ErrorValidation(ref e)       => write!(f, "{}: {}", error::Error::description(self), e),
+            Error::ErrorNonStandard(ref reason) => write!(f, "{}: {}", error::Error::description(self), reason),
+            Error::ErrorFee { fee, required }   => write!(f, "{}: {} < {}", error::Error::description(self), fee, required),
+            Error::ErrorReplacementFee { fee, required } =>
+                write!(f, "{}: {} < {}", error::Error::description(self), fee, required),
+            Error::ErrorReplacementRejected(ref reason) =>
+                write!(f, "{}: {}", error::Error::description(self), reason),
+            _                                   => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::ErrorValidation(ref e) => Some(e),
+            _                             => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::ErrorValidation(..)          => "invalid transaction",
+            Error::ErrorNonStandard(..)         => "non-standard transaction",
+            Error::ErrorDust                    => "dust output",
+            Error::ErrorFee { .. }              => "insufficient fee",
+            Error::ErrorDuplicate               => "transaction already in mempool",
+            Error::ErrorConflict                => "transaction conflicts with the mempool",
+            Error::ErrorReplacementFee { .. }   => "insufficient fee for replacement",
+            Error::ErrorReplacementRejected(..) => "replacement rejected",
+            Error::ErrorMissingInputs           => "missing inputs",
+            Error::ErrorTooLongChain            => "too many unconfirmed ancestors or descendants",
+            Error::ErrorMempoolFull             => "mempool full",
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<ValidationError> for Error {
+    fn from(e: ValidationError) -> Error {
+        match e {
+            ValidationError::ErrorMissingInput(..) => Error::ErrorMissingInputs,
+            e                                      => Error::ErrorValidation(e),
+        }
+    }
+}
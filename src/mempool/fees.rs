@@ -0,0 +1,433 @@
+/// Fee estimation
+///
+/// Transactions entering the pool while it is in sync with the best block are
+/// tracked in the bucket of their fee rate, buckets growing exponentially from
+/// the minimum relay fee. Once a tracked transaction confirms, every target of
+/// at least the number of blocks it waited counts it as confirmed in time; a
+/// transaction dropped from the pool, or still waiting past a target, counts as
+/// a failure for that target. Transactions still waiting past the highest
+/// target are counted as failed for every target and no longer tracked. All
+/// counts decay with every block so estimates follow the recent history.
+///
+/// The estimate for a target walks the buckets from the most expensive one,
+/// grouping buckets until they hold enough data, and returns the average fee
+/// rate of the cheapest group still confirming enough of its transactions in
+/// time.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use hashes::sha256d;
+
+use blockdata::block::Block;
+use consensus::encode::{serialize, Decodable};
+use mempool::pool::MempoolEntry;
+use mempool::policy::DEFAULT_MIN_RELAY_FEE;
+use store::error::Error;
+use store::file::write_atomic;
+use util::amount::FeeRate;
+
+/// Highest confirmation target in blocks that can be estimated
+pub const MAX_CONFIRM_TARGET: usize = 48;
+
+/// Fee rate of the most expensive bucket; higher rates land into it
+const MAX_BUCKET_FEE: u64 = 10_000_000;
+
+/// Ratio between the upper bounds of consecutive buckets
+const BUCKET_SPACING: f64 = 1.1;
+
+/// Factor applied to every count on each new block
+const DECAY: f64 = 0.998;
+
+/// Decayed number of transactions a group of buckets needs to be trusted
+const SUFFICIENT_TXS: f64 = 0.1 / (1.0 - DECAY);
+
+/// Share of transactions confirmed in time for an economical estimate
+const ECONOMICAL_THRESHOLD: f64 = 0.85;
+
+/// Share of transactions confirmed in time for a conservative estimate
+const CONSERVATIVE_THRESHOLD: f64 = 0.95;
+
+/// Version of the persisted estimator state
+const FILE_VERSION: u32 = 1;
+
+/// How likely an estimate has to get a transaction confirmed in time
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum EstimateMode {
+    /// Succeed for most transactions, also over twice the target, at a higher fee
+    Conservative,
+    /// Succeed for fewer transactions at a lower fee
+    Economical,
+}
+
+/// Tracked pool transaction
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct TrackedTx {
+    /// Height of the best block when it entered the pool
+    height: u64,
+    fee_rate: FeeRate,
+    bucket: usize,
+}
+
+/// Fee estimator fed with pool transactions and connected blocks
+pub struct FeeEstimator {
+    /// Upper fee rate bound of every bucket
+    buckets: Vec<u64>,
+    /// Confirmed transactions in every bucket, whatever their delay
+    tx_counts: Vec<f64>,
+    /// Sum of the fee rates of confirmed transactions in every bucket
+    fee_sums: Vec<f64>,
+    /// Transactions confirmed within `target + 1` blocks, per target and bucket
+    confirmed: Vec<Vec<f64>>,
+    /// Transactions dropped after waiting more than `target + 1` blocks, per target and bucket
+    failed: Vec<Vec<f64>>,
+    tracked: HashMap<sha256d::Hash, TrackedTx>,
+    best_height: u64,
+}
+
+/// Return the upper bounds of the fee rate buckets
+fn bucket_bounds() -> Vec<u64> {
+    let mut buckets = Vec::new();
+    let mut bound = DEFAULT_MIN_RELAY_FEE as f64;
+    while bound < MAX_BUCKET_FEE as f64 {
+        buckets.push(bound as u64);
+        bound *= BUCKET_SPACING;
+    }
+    buckets.push(MAX_BUCKET_FEE);
+    buckets
+}
+
+impl FeeEstimator {
+    /// Create an estimator without any history
+    pub fn new() -> FeeEstimator {
+        let buckets = bucket_bounds();
+        let count = buckets.len();
+        FeeEstimator {
+            buckets:     buckets,
+            tx_counts:   vec![0.0; count],
+            fee_sums:    vec![0.0; count],
+            confirmed:   vec![vec![0.0; count]; MAX_CONFIRM_TARGET],
+            failed:      vec![vec![0.0; count]; MAX_CONFIRM_TARGET],
+            tracked:     HashMap::new(),
+            best_height: 0,
+        }
+    }
+
+    /// Height of the last processed block
+    pub fn best_height(&self) -> u64 {
+        self.best_height
+    }
+
+    /// Return the bucket of a fee rate
+    fn bucket(&self, fee_rate: FeeRate) -> usize {
+        let rate = fee_rate.sat_per_kvb();
+        self.buckets.iter().position(|bound| rate <= *bound).unwrap_or(self.buckets.len() - 1)
+    }
+
+    /// Start tracking a transaction accepted into the pool. Transactions
+    /// entering while the pool lags behind the best block are ignored, their
+    /// delay being meaningless.
+    pub fn process_transaction(&mut self, entry: &MempoolEntry) {
+        self.track(entry.txid, entry.height, entry.fee_rate());
+    }
+
+    fn track(&mut self, txid: sha256d::Hash, height: u64, fee_rate: FeeRate) {
+        if height != self.best_height || self.tracked.contains_key(&txid) {
+            return;
+        }
+        let bucket = self.bucket(fee_rate);
+        self.tracked.insert(txid, TrackedTx { height: height, fee_rate: fee_rate, bucket: bucket });
+    }
+
+    /// Stop tracking a transaction that left the pool without confirming,
+    /// counting it as failed for every target it waited longer than
+    pub fn remove_transaction(&mut self, txid: &sha256d::Hash) {
+        if let Some(tracked) = self.tracked.remove(txid) {
+            let waited = (self.best_height - tracked.height) as usize;
+            for target in 0..cmp::min(waited, MAX_CONFIRM_TARGET) {
+                self.failed[target][tracked.bucket] += 1.0;
+            }
+        }
+    }
+
+    /// Record the confirmation of tracked transactions by a newly connected
+    /// block. Blocks at or below the last processed height, as seen during a
+    /// reorganization, only stop the tracking of their transactions.
+    pub fn process_block(&mut self, block: &Block, height: u64) {
+        self.process_confirmed(block.txdata.iter().map(|tx| tx.txid()), height);
+    }
+
+    fn process_confirmed<I: Iterator<Item = sha256d::Hash>>(&mut self, txids: I, height: u64) {
+        if height <= self.best_height {
+            for txid in txids {
+                self.tracked.remove(&txid);
+            }
+            return;
+        }
+        self.best_height = height;
+
+        for value in self.tx_counts.iter_mut().chain(self.fee_sums.iter_mut()) {
+            *value *= DECAY;
+        }
+        for value in self.confirmed.iter_mut().chain(self.failed.iter_mut()).flat_map(|row| row.iter_mut()) {
+            *value *= DECAY;
+        }
+
+        for txid in txids {
+            let tracked = match self.tracked.remove(&txid) {
+                Some(tracked) => tracked,
+                None          => continue,
+            };
+            let blocks = (height - tracked.height) as usize;
+            self.tx_counts[tracked.bucket] += 1.0;
+            self.fee_sums[tracked.bucket] += tracked.fee_rate.sat_per_kvb() as f64;
+            for target in blocks.saturating_sub(1)..MAX_CONFIRM_TARGET {
+                self.confirmed[target][tracked.bucket] += 1.0;
+            }
+        }
+
+        // Whenever they confirm, these transactions came too late for every target
+        let expired: Vec<sha256d::Hash> = self.tracked.iter()
+            .filter(|&(_, tracked)| height - tracked.height > MAX_CONFIRM_TARGET as u64)
+            .map(|(txid, _)| *txid)
+            .collect();
+        for txid in expired.iter() {
+            self.remove_transaction(txid);
+        }
+    }
+
+    /// Return the lowest fee rate confirming at least the given share of
+    /// transactions within the target
+    fn estimate_threshold(&self, target: usize, threshold: f64) -> Option<FeeRate> {
+        let row = target - 1;
+        let mut waiting = vec![0.0; self.buckets.len()];
+        for tracked in self.tracked.values() {
+            if self.best_height - tracked.height >= target as u64 {
+                waiting[tracked.bucket] += 1.0;
+            }
+        }
+
+        let mut estimate = None;
+        let (mut confirmed, mut total, mut count, mut fees) = (0.0, 0.0, 0.0, 0.0);
+        for bucket in (0..self.buckets.len()).rev() {
+            confirmed += self.confirmed[row][bucket];
+            total += self.tx_counts[bucket] + self.failed[row][bucket] + waiting[bucket];
+            count += self.tx_counts[bucket];
+            fees += self.fee_sums[bucket];
+            if total < SUFFICIENT_TXS {
+                continue;
+            }
+            if confirmed / total < threshold {
+                break;
+            }
+            if count > 0.0 {
+                estimate = Some(FeeRate::from_sat_per_kvb((fees / count) as u64));
+            }
+            confirmed = 0.0;
+            total = 0.0;
+            count = 0.0;
+            fees = 0.0;
+        }
+        estimate
+    }
+
+    /// Estimate the fee rate getting a transaction confirmed within the given
+    /// number of blocks, `None` when there is not enough history
+    pub fn estimate(&self, target_blocks: usize, mode: EstimateMode) -> Option<FeeRate> {
+        if target_blocks == 0 {
+            return None;
+        }
+        let target = cmp::min(target_blocks, MAX_CONFIRM_TARGET);
+        match mode {
+            EstimateMode::Economical   => self.estimate_threshold(target, ECONOMICAL_THRESHOLD),
+            EstimateMode::Conservative => {
+                let longer = cmp::min(target * 2, MAX_CONFIRM_TARGET);
+                let estimate = self.estimate_threshold(target, CONSERVATIVE_THRESHOLD)?;
+                Some(match self.estimate_threshold(longer, CONSERVATIVE_THRESHOLD) {
+                    Some(longer) if longer > estimate => longer,
+                    _                                 => estimate,
+                })
+            },
+        }
+    }
+
+    /// Write the estimator history to a file, replacing it atomically.
+    /// Tracked transactions are not kept, the pool being rebuilt on restart.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut payload = serialize(&FILE_VERSION);
+        payload.extend(serialize(&self.best_height));
+        payload.extend(serialize(&self.buckets));
+        payload.extend(serialize(&float_bits(&self.tx_counts)));
+        payload.extend(serialize(&float_bits(&self.fee_sums)));
+        for row in self.confirmed.iter().chain(self.failed.iter()) {
+            payload.extend(serialize(&float_bits(row)));
+        }
+
+        write_atomic(path, &payload)
+    }
+
+    /// Read an estimator history written by `write`
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<FeeEstimator, Error> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        let mut decoder = io::Cursor::new(&data[..]);
+
+        let version: u32 = Decodable::decode(&mut decoder)?;
+        if version != FILE_VERSION {
+            return Err(Error::ErrorCorrupted("unknown fee estimates version"));
+        }
+        let mut estimator = FeeEstimator::new();
+        estimator.best_height = Decodable::decode(&mut decoder)?;
+        let buckets: Vec<u64> = Decodable::decode(&mut decoder)?;
+        if buckets != estimator.buckets {
+            return Err(Error::ErrorCorrupted("fee estimates buckets mismatch"));
+        }
+
+        let count = buckets.len();
+        let mut read_row = || -> Result<Vec<f64>, Error> {
+            let row = floats(Decodable::decode(&mut decoder)?);
+            if row.len() != count {
+                return Err(Error::ErrorCorrupted("fee estimates length mismatch"));
+            }
+            Ok(row)
+        };
+        estimator.tx_counts = read_row()?;
+        estimator.fee_sums = read_row()?;
+        for target in 0..MAX_CONFIRM_TARGET {
+            estimator.confirmed[target] = read_row()?;
+        }
+        for target in 0..MAX_CONFIRM_TARGET {
+            estimator.failed[target] = read_row()?;
+        }
+        if decoder.position() as usize != data.len() {
+            return Err(Error::ErrorCorrupted("trailing data after fee estimates"));
+        }
+        Ok(estimator)
+    }
+}
+
+impl Default for FeeEstimator {
+    fn default() -> FeeEstimator {
+        FeeEstimator::new()
+    }
+}
+
+/// Encode decayed counts by their bit patterns
+fn float_bits(values: &[f64]) -> Vec<u64> {
+    values.iter().map(|value| value.to_bits()).collect()
+}
+
+fn floats(bits: Vec<u64>) -> Vec<f64> {
+    bits.into_iter().map(f64::from_bits).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use hashes::{sha256d, Hash};
+
+    use consensus::encode::serialize;
+    use util::amount::FeeRate;
+    use super::{EstimateMode, FeeEstimator, MAX_CONFIRM_TARGET};
+
+    /// Fee rates of the simulated transactions, and the blocks they wait
+    const HIGH_FEE: u64 = 50_000;
+    const MEDIUM_FEE: u64 = 10_000;
+    const LOW_FEE: u64 = 2_000;
+    const MEDIUM_DELAY: u64 = 3;
+
+    /// Feed the estimator with 10 transactions of every fee rate per block:
+    /// high fee ones confirm in the next block, medium fee ones a few blocks
+    /// later and low fee ones never
+    fn simulate(estimator: &mut FeeEstimator, blocks: u64) {
+        let txid = |height: u64, kind: u8, index: u8| {
+            let mut data = serialize(&height);
+            data.extend(&[kind, index]);
+            sha256d::Hash::hash(&data)
+        };
+        for height in 0..blocks {
+            for index in 0..10 {
+                estimator.track(txid(height, 0, index), height, FeeRate::from_sat_per_kvb(HIGH_FEE));
+                estimator.track(txid(height, 1, index), height, FeeRate::from_sat_per_kvb(MEDIUM_FEE));
+                estimator.track(txid(height, 2, index), height, FeeRate::from_sat_per_kvb(LOW_FEE));
+            }
+            let mut confirmed: Vec<sha256d::Hash> = (0..10).map(|index| txid(height, 0, index)).collect();
+            if height + 1 >= MEDIUM_DELAY {
+                confirmed.extend((0..10).map(|index| txid(height + 1 - MEDIUM_DELAY, 1, index)));
+            }
+            estimator.process_confirmed(confirmed.into_iter(), height + 1);
+        }
+    }
+
+    fn assert_close(estimate: Option<FeeRate>, expected: u64) {
+        let estimate = estimate.unwrap().sat_per_kvb();
+        assert!(estimate + 1 >= expected && estimate <= expected, "{} instead of {}", estimate, expected);
+    }
+
+    #[test]
+    fn no_history() {
+        let estimator = FeeEstimator::new();
+        assert_eq!(estimator.estimate(0, EstimateMode::Economical), None);
+        assert_eq!(estimator.estimate(1, EstimateMode::Economical), None);
+        assert_eq!(estimator.estimate(MAX_CONFIRM_TARGET, EstimateMode::Conservative), None);
+    }
+
+    #[test]
+    fn estimates() {
+        let mut estimator = FeeEstimator::new();
+        simulate(&mut estimator, 300);
+        assert_eq!(estimator.best_height(), 300);
+
+        assert_close(estimator.estimate(1, EstimateMode::Economical), HIGH_FEE);
+        assert_close(estimator.estimate(2, EstimateMode::Conservative), HIGH_FEE);
+        assert_close(estimator.estimate(MEDIUM_DELAY as usize, EstimateMode::Economical), MEDIUM_FEE);
+        assert_close(estimator.estimate(MAX_CONFIRM_TARGET, EstimateMode::Economical), MEDIUM_FEE);
+        assert_close(estimator.estimate(10 * MAX_CONFIRM_TARGET, EstimateMode::Economical), MEDIUM_FEE);
+
+        // Transactions that never confirm stop being tracked past the highest target
+        assert!(estimator.tracked.len() <= 10 * (MAX_CONFIRM_TARGET + MEDIUM_DELAY as usize + 1));
+        assert!(estimator.tracked.values().all(|tracked| estimator.best_height - tracked.height <= MAX_CONFIRM_TARGET as u64));
+    }
+
+    #[test]
+    fn transactions_behind_the_best_block() {
+        let mut estimator = FeeEstimator::new();
+        simulate(&mut estimator, 10);
+        estimator.track(sha256d::Hash::hash(&[1]), 9, FeeRate::from_sat_per_kvb(HIGH_FEE));
+        assert!(!estimator.tracked.contains_key(&sha256d::Hash::hash(&[1])));
+
+        // A block seen again during a reorganization only drops its transactions
+        estimator.track(sha256d::Hash::hash(&[2]), 10, FeeRate::from_sat_per_kvb(HIGH_FEE));
+        estimator.process_confirmed(Some(sha256d::Hash::hash(&[2])).into_iter(), 10);
+        assert!(!estimator.tracked.contains_key(&sha256d::Hash::hash(&[2])));
+        assert_eq!(estimator.best_height(), 10);
+    }
+
+    #[test]
+    fn write_read() {
+        let mut estimator = FeeEstimator::new();
+        simulate(&mut estimator, 100);
+        let path = env::temp_dir().join(format!("fee_estimates_{}.dat", ::std::process::id()));
+        estimator.write(&path).unwrap();
+        let read = FeeEstimator::read(&path).unwrap();
+
+        assert_eq!(read.best_height(), estimator.best_height());
+        assert!(read.tracked.is_empty());
+        assert_eq!(read.tx_counts, estimator.tx_counts);
+        assert_eq!(read.fee_sums, estimator.fee_sums);
+        assert_eq!(read.confirmed, estimator.confirmed);
+        assert_eq!(read.failed, estimator.failed);
+
+        // Trailing data is refused
+        let mut data = fs::read(&path).unwrap();
+        data.push(0);
+        fs::write(&path, &data).unwrap();
+        assert!(FeeEstimator::read(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
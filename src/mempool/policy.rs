@@ -0,0 +1,145 @@
+/// Relay policy
+///
+/// Rules a transaction has to follow, beyond consensus, to be accepted into
+/// the mempool and relayed: known output types, push-only input scripts,
+/// bounded sizes and outputs worth more than the cost of spending them.
+
+use blockdata::script::{Script, OP_1, OP_16, OP_CHECKSIG, OP_CHECKMULTISIG};
+use blockdata::transaction::{Transaction, TxOut};
+use consensus::encode::{serialize, VarInt};
+use mempool::error::Error;
+use util::amount::FeeRate;
+use validation::script::{VERIFY_P2SH, VERIFY_DERSIG, VERIFY_NULLDUMMY, VERIFY_CHECKLOCKTIMEVERIFY,
+                         VERIFY_CHECKSEQUENCEVERIFY, VERIFY_WITNESS};
+
+/// Maximum weight of a relayed transaction
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+
+/// Maximum signature operations cost of a relayed transaction
+pub const MAX_STANDARD_TX_SIGOPS_COST: usize = 16_000;
+
+/// Maximum size of a relayed input script
+pub const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1_650;
+
+/// Maximum size of a relayed data carrier output script
+pub const MAX_OP_RETURN_RELAY: usize = 83;
+
+/// Default minimum fee rate of relayed transactions
+pub const DEFAULT_MIN_RELAY_FEE: u64 = 1_000;
+
+/// Default fee rate outputs are valued against to decide they are dust
+pub const DUST_RELAY_FEE: u64 = 3_000;
+
+/// Script flags enforced on relayed transactions
+pub const STANDARD_SCRIPT_FLAGS: u32 = VERIFY_P2SH | VERIFY_DERSIG | VERIFY_NULLDUMMY
+    | VERIFY_CHECKLOCKTIMEVERIFY | VERIFY_CHECKSEQUENCEVERIFY | VERIFY_WITNESS;
+
+/// Kinds of output scripts relayed by policy
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum OutputType {
+    PubKey,
+    PubKeyHash,
+    ScriptHash,
+    MultiSig,
+    NullData,
+    WitnessV0KeyHash,
+    WitnessV0ScriptHash,
+    WitnessUnknown,
+}
+
+/// Return the type of a standard output script
+pub fn output_type(script: &Script) -> Option<OutputType> {
+    let bytes = script.as_bytes();
+    if let Some((version, program)) = script.witness_program() {
+        return match (version, program.len()) {
+            (0, 20) => Some(OutputType::WitnessV0KeyHash),
+            (0, 32) => Some(OutputType::WitnessV0ScriptHash),
+            (0, _)  => None,
+            _       => Some(OutputType::WitnessUnknown),
+        };
+    }
+    if script.is_p2pkh() {
+        return Some(OutputType::PubKeyHash);
+    }
+    if script.is_p2sh() {
+        return Some(OutputType::ScriptHash);
+    }
+    if script.is_op_return() {
+        let data = Script::from(bytes[1..].to_vec());
+        return if bytes.len() <= MAX_OP_RETURN_RELAY && data.is_push_only() { Some(OutputType::NullData) } else { None };
+    }
+
+    let instructions: Vec<_> = script.instructions().collect();
+    // <pubkey> OP_CHECKSIG
+    if instructions.len() == 2 && instructions[1].opcode == OP_CHECKSIG
+        && (instructions[0].data.len() == 33 || instructions[0].data.len() == 65) {
+        return Some(OutputType::PubKey);
+    }
+    // m <pubkey>... n OP_CHECKMULTISIG, with at most 3 keys
+    if instructions.len() >= 4 && instructions.last().unwrap().opcode == OP_CHECKMULTISIG {
+        let count = instructions.len();
+        let (m, n) = (instructions[0].opcode, instructions[count - 2].opcode);
+        let keys = &instructions[1..count - 2];
+        let small = |op: u8| op >= OP_1 && op <= OP_16;
+        if small(m) && small(n) && m <= n && (n - OP_1 + 1) as usize == keys.len() && keys.len() <= 3
+            && keys.iter().all(|key| key.data.len() == 33 || key.data.len() == 65) {
+            return Some(OutputType::MultiSig);
+        }
+    }
+    None
+}
+
+/// Return the value below which an output costs more to spend than it is worth
+pub fn dust_threshold(txout: &TxOut, dust_relay_fee: FeeRate) -> u64 {
+    if txout.pkscript.is_provably_unspendable() {
+        return 0;
+    }
+    let output_size = 8 + serialize(&VarInt(txout.pkscript.len() as u64)).len() + txout.pkscript.len();
+    // Outpoint, script length, sequence and a typical signature, discounted for witness spends
+    let input_size = if txout.pkscript.witness_program().is_some() {
+        32 + 4 + 1 + 107 / 4 + 4
+    } else {
+        32 + 4 + 1 + 107 + 4
+    };
+    dust_relay_fee.fee(output_size + input_size)
+}
+
+/// Check if an output is dust
+pub fn is_dust(txout: &TxOut, dust_relay_fee: FeeRate) -> bool {
+    txout.value_out < dust_threshold(txout, dust_relay_fee)
+}
+
+/// Check a transaction follows the relay policy
+pub fn check_standard(tx: &Transaction) -> Result<(), Error> {
+    if tx.version < 1 || tx.version > 2 {
+        return Err(Error::ErrorNonStandard("version"));
+    }
+    if tx.weight() > MAX_STANDARD_TX_WEIGHT {
+        return Err(Error::ErrorNonStandard("tx-size"));
+    }
+    for txin in tx.input_ent.iter() {
+        if txin.sig_script.len() > MAX_STANDARD_SCRIPTSIG_SIZE {
+            return Err(Error::ErrorNonStandard("scriptsig-size"));
+        }
+        if !txin.sig_script.is_push_only() {
+            return Err(Error::ErrorNonStandard("scriptsig-not-pushonly"));
+        }
+    }
+
+    let dust_relay_fee = FeeRate::from_sat_per_kvb(DUST_RELAY_FEE);
+    let mut data_outputs = 0;
+    for txout in tx.output_ent.iter() {
+        match output_type(&txout.pkscript) {
+            Some(OutputType::NullData) => data_outputs += 1,
+            Some(_)                    => {},
+            None                       => return Err(Error::ErrorNonStandard("scriptpubkey")),
+        }
+        if is_dust(txout, dust_relay_fee) {
+            return Err(Error::ErrorDust);
+        }
+    }
+    if data_outputs > 1 {
+        return Err(Error::ErrorNonStandard("multi-op-return"));
+    }
+    Ok(())
+}
@@ -0,0 +1,810 @@
+/// Pool of unconfirmed transactions
+///
+/// Transactions are accepted when they are valid on top of the unspent set,
+/// possibly spending outputs of other pool transactions, and follow the relay
+/// policy. Every entry keeps links to the pool transactions it spends from and
+/// to those spending it, which bound the length of unconfirmed chains and let
+/// packages be evicted together. When the pool grows above its size limit the
+/// package with the lowest fee rate is evicted and the minimum fee raised
+/// above it; that minimum decays back over time.
+///
+/// A transaction spending outputs already spent inside the pool replaces the
+/// conflicting transactions and their descendants if they signal it (BIP125)
+/// and it pays for the bandwidth of relaying everything it evicts.
+///
+/// The pool feeds its fee estimator with the transactions it accepts, those
+/// leaving it unconfirmed and the blocks confirming them.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+
+use hashes::sha256d;
+
+use blockdata::block::Block;
+use blockdata::transaction::{OutPoint, Transaction};
+use chain::headertree::HeaderTree;
+use chain::time::median_time_past;
+use mempool::error::Error;
+use mempool::fees::FeeEstimator;
+use mempool::policy::{check_standard, DEFAULT_MIN_RELAY_FEE, MAX_STANDARD_TX_SIGOPS_COST, STANDARD_SCRIPT_FLAGS};
+use mining::template::TemplateTx;
+use store::utxo::{Coin, UtxoSet};
+use util::amount::FeeRate;
+use validation::block::verify_tx_scripts;
+use validation::error::Error as ValidationError;
+use validation::script::ScriptVerifier;
+use validation::transaction::{check_transaction, check_tx_inputs, sigop_cost,
+                              calculate_sequence_locks, check_sequence_locks};
+
+/// Default maximum total virtual size of the pool
+pub const DEFAULT_MAX_MEMPOOL_SIZE: usize = 300_000_000;
+
+/// Default time in seconds after which a transaction is dropped
+pub const DEFAULT_MEMPOOL_EXPIRY: i64 = 14 * 24 * 60 * 60;
+
+/// Maximum number of pool transactions a transaction and its ancestors may form
+pub const DEFAULT_ANCESTOR_LIMIT: usize = 25;
+
+/// Maximum virtual size of a transaction and its pool ancestors
+pub const DEFAULT_ANCESTOR_SIZE_LIMIT: usize = 101_000;
+
+/// Maximum number of pool transactions a transaction and its descendants may form
+pub const DEFAULT_DESCENDANT_LIMIT: usize = 25;
+
+/// Maximum virtual size of a transaction and its pool descendants
+pub const DEFAULT_DESCENDANT_SIZE_LIMIT: usize = 101_000;
+
+/// Fee rate added to the rate of an evicted package to get the new minimum fee
+pub const INCREMENTAL_RELAY_FEE: u64 = 1_000;
+
+/// Time in seconds after which the minimum fee raised by evictions halves
+const ROLLING_FEE_HALFLIFE: i64 = 12 * 60 * 60;
+
+/// Maximum number of transactions a replacement may evict
+pub const MAX_REPLACEMENT_CANDIDATES: usize = 100;
+
+/// Transaction inside the pool
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MempoolEntry {
+    pub tx: Transaction,
+    pub txid: sha256d::Hash,
+    /// Witness transaction id, computed once for compact block short IDs
+    pub wtxid: sha256d::Hash,
+    /// Fee paid by the transaction
+    pub fee: u64,
+    /// Virtual size of the transaction
+    pub vsize: usize,
+    /// Signature operations cost of the transaction
+    pub sigop_cost: usize,
+    /// Time the transaction entered the pool
+    pub time: i64,
+    /// Height of the best block when the transaction entered the pool
+    pub height: u64,
+    /// Pool transactions spent by this one
+    parents: HashSet<sha256d::Hash>,
+    /// Pool transactions spending this one
+    children: HashSet<sha256d::Hash>,
+    /// Fee of the transaction and of its pool descendants
+    descendant_fee: u64,
+    /// Virtual size of the transaction and of its pool descendants
+    descendant_vsize: usize,
+}
+
+impl MempoolEntry {
+    /// Fee rate of the transaction alone
+    pub fn fee_rate(&self) -> FeeRate {
+        FeeRate::from_fee(self.fee, self.vsize)
+    }
+
+    /// Fee rate of the transaction together with its pool descendants
+    pub fn descendant_fee_rate(&self) -> FeeRate {
+        FeeRate::from_fee(self.descendant_fee, self.descendant_vsize)
+    }
+
+    /// Pool transactions spent by this one
+    pub fn parents(&self) -> &HashSet<sha256d::Hash> {
+        &self.parents
+    }
+
+    /// Pool transactions spending this one
+    pub fn children(&self) -> &HashSet<sha256d::Hash> {
+        &self.children
+    }
+}
+
+/// Pool of unconfirmed transactions
+pub struct Mempool {
+    entries: HashMap<sha256d::Hash, MempoolEntry>,
+    /// Pool transaction spending every spent outpoint
+    spent: HashMap<OutPoint, sha256d::Hash>,
+    total_vsize: usize,
+    max_size: usize,
+    expiry: i64,
+    min_relay_fee: FeeRate,
+    /// Minimum fee raised by evictions, and when it was last raised
+    rolling_min_fee: FeeRate,
+    last_rolling_update: i64,
+    fee_estimator: FeeEstimator,
+}
+
+impl Mempool {
+    /// Create an empty pool limited to the given total virtual size
+    pub fn new(max_size: usize) -> Mempool {
+        Mempool {
+            entries:             HashMap::new(),
+            spent:               HashMap::new(),
+            total_vsize:         0,
+            max_size:            max_size,
+            expiry:              DEFAULT_MEMPOOL_EXPIRY,
+            min_relay_fee:       FeeRate::from_sat_per_kvb(DEFAULT_MIN_RELAY_FEE),
+            rolling_min_fee:     FeeRate::default(),
+            last_rolling_update: 0,
+            fee_estimator:       FeeEstimator::new(),
+        }
+    }
+
+    /// Fee estimator fed by the pool
+    pub fn fee_estimator(&self) -> &FeeEstimator {
+        &self.fee_estimator
+    }
+
+    /// Replace the fee estimator, such as with one read back from disk
+    pub fn set_fee_estimator(&mut self, fee_estimator: FeeEstimator) {
+        self.fee_estimator = fee_estimator;
+    }
+
+    /// Set the minimum fee rate of accepted transactions
+    pub fn set_min_relay_fee(&mut self, fee_rate: FeeRate) {
+        self.min_relay_fee = fee_rate;
+    }
+
+    /// Set the time in seconds after which transactions are dropped
+    pub fn set_expiry(&mut self, expiry: i64) {
+        self.expiry = expiry;
+    }
+
+    /// Number of transactions in the pool
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if the pool is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total virtual size of the pool transactions
+    pub fn total_vsize(&self) -> usize {
+        self.total_vsize
+    }
+
+    /// Check if a transaction is in the pool
+    pub fn contains(&self, txid: &sha256d::Hash) -> bool {
+        self.entries.contains_key(txid)
+    }
+
+    /// Return a pool entry
+    pub fn get(&self, txid: &sha256d::Hash) -> Option<&MempoolEntry> {
+        self.entries.get(txid)
+    }
+
+    /// Iterate over all entries, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &MempoolEntry> {
+        self.entries.values()
+    }
+
+    /// Return the pool transaction spending an outpoint
+    pub fn spender(&self, outpoint: &OutPoint) -> Option<&sha256d::Hash> {
+        self.spent.get(outpoint)
+    }
+
+    /// Return the pool transactions a transaction depends on, directly or not
+    pub fn ancestors(&self, txid: &sha256d::Hash) -> HashSet<sha256d::Hash> {
+        self.walk(txid, |entry| &entry.parents)
+    }
+
+    /// Return the pool transactions depending on a transaction, directly or not
+    pub fn descendants(&self, txid: &sha256d::Hash) -> HashSet<sha256d::Hash> {
+        self.walk(txid, |entry| &entry.children)
+    }
+
+    fn walk<F>(&self, txid: &sha256d::Hash, next: F) -> HashSet<sha256d::Hash>
+        where F: Fn(&MempoolEntry) -> &HashSet<sha256d::Hash> {
+        let mut found = HashSet::new();
+        let mut pending = vec![*txid];
+        while let Some(current) = pending.pop() {
+            if let Some(entry) = self.entries.get(&current) {
+                for linked in next(entry).iter() {
+                    if found.insert(*linked) {
+                        pending.push(*linked);
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// Return the fee and virtual size of a set of pool transactions
+    fn package_stats<'a, I: Iterator<Item = &'a sha256d::Hash>>(&self, txids: I) -> (u64, usize) {
+        txids.filter_map(|txid| self.entries.get(txid))
+            .fold((0, 0), |(fee, vsize), entry| (fee + entry.fee, vsize + entry.vsize))
+    }
+
+    /// Minimum fee rate of accepted transactions at the given time
+    pub fn min_fee(&self, now: i64) -> FeeRate {
+        let halvings = cmp::max(now - self.last_rolling_update, 0) / ROLLING_FEE_HALFLIFE;
+        let mut rolling = if halvings >= 64 { 0 } else { self.rolling_min_fee.sat_per_kvb() >> halvings };
+        if rolling < INCREMENTAL_RELAY_FEE / 2 {
+            rolling = 0;
+        }
+        cmp::max(FeeRate::from_sat_per_kvb(rolling), self.min_relay_fee)
+    }
+
+    /// Return the coins spent by a transaction, from the pool or the unspent set,
+    /// along with the pool transactions it spends from. Outputs of pool
+    /// transactions count as created at `height`.
+    fn find_coins(&self, tx: &Transaction, utxo: &UtxoSet, height: u64) -> Result<(Vec<Coin>, HashSet<sha256d::Hash>), Error> {
+        let mut coins = Vec::with_capacity(tx.input_ent.len());
+        let mut parents = HashSet::new();
+        for txin in tx.input_ent.iter() {
+            let outpoint = &txin.previous_out;
+            let coin = match self.entries.get(&outpoint.txid) {
+                Some(parent) => {
+                    parents.insert(parent.txid);
+                    let txout = parent.tx.output_ent.get(outpoint.vout as usize).ok_or(Error::ErrorMissingInputs)?;
+                    Coin { txout: txout.clone(), height: height as u32, is_coinbase: false }
+                },
+                None => utxo.get(outpoint).map_err(ValidationError::from)?.ok_or(Error::ErrorMissingInputs)?,
+            };
+            coins.push(coin);
+        }
+        Ok((coins, parents))
+    }
+
+    /// Validate a transaction against the unspent set and the pool and add it
+    pub fn accept(&mut self, tx: Transaction, tree: &HeaderTree, utxo: &UtxoSet,
+                  verifier: &ScriptVerifier, now: i64) -> Result<sha256d::Hash, Error> {
+        let txid = tx.txid();
+        if self.entries.contains_key(&txid) {
+            return Err(Error::ErrorDuplicate);
+        }
+        check_transaction(&tx)?;
+        if tx.is_coin_base() {
+            return Err(Error::ErrorNonStandard("coinbase"));
+        }
+        check_standard(&tx)?;
+
+        // The transaction has to fit into the block following the best one
+        let tip = tree.get(&utxo.best_block()).ok_or(ValidationError::ErrorUnknownBlock)?;
+        let height = tip.height() + 1;
+        let median_time = median_time_past(tree, tip);
+        if !tx.is_final(height, median_time) {
+            return Err(Error::ErrorNonStandard("non-final"));
+        }
+
+        let conflicts: HashSet<sha256d::Hash> = tx.input_ent.iter()
+            .filter_map(|txin| self.spent.get(&txin.previous_out).cloned())
+            .collect();
+        if conflicts.iter().any(|conflict| !self.is_replaceable(conflict)) {
+            return Err(Error::ErrorConflict);
+        }
+
+        let (coins, parents) = self.find_coins(&tx, utxo, height)?;
+        let fee = check_tx_inputs(&tx, &coins, height)?;
+
+        let median_time_at = |height: u64| {
+            tree.ancestor(&tip.hash, height).map_or(median_time, |ancestor| median_time_past(tree, ancestor))
+        };
+        let locks = calculate_sequence_locks(&tx, &coins, &median_time_at);
+        if !check_sequence_locks(&locks, height, median_time) {
+            return Err(Error::ErrorNonStandard("non-BIP68-final"));
+        }
+
+        let cost = sigop_cost(&tx, &coins, STANDARD_SCRIPT_FLAGS);
+        if cost > MAX_STANDARD_TX_SIGOPS_COST {
+            return Err(Error::ErrorNonStandard("bad-txns-too-many-sigops"));
+        }
+
+        let vsize = tx.vsize();
+        let required = cmp::max(self.min_fee(now).fee(vsize), self.min_relay_fee.fee(vsize));
+        if fee < required {
+            return Err(Error::ErrorFee { fee: fee, required: required });
+        }
+
+        let mut replaced = conflicts.clone();
+        for conflict in conflicts.iter() {
+            replaced.extend(self.descendants(conflict));
+        }
+        if !conflicts.is_empty() {
+            self.check_replacement(&conflicts, &replaced, &parents, fee, vsize)?;
+        }
+
+        self.check_chain_limits(&parents, &replaced, vsize)?;
+        verify_tx_scripts(&tx, &coins, STANDARD_SCRIPT_FLAGS, verifier)?;
+
+        for conflict in conflicts.iter() {
+            self.remove_recursive(conflict);
+        }
+
+        let wtxid = tx.wtxid();
+        self.insert(MempoolEntry {
+            tx:               tx,
+            txid:             txid,
+            wtxid:            wtxid,
+            fee:              fee,
+            vsize:            vsize,
+            sigop_cost:       cost,
+            time:             now,
+            height:           tip.height(),
+            parents:          parents,
+            children:         HashSet::new(),
+            descendant_fee:   fee,
+            descendant_vsize: vsize,
+        });
+
+        self.trim(now);
+        match self.entries.get(&txid) {
+            Some(entry) => self.fee_estimator.process_transaction(entry),
+            None        => return Err(Error::ErrorMempoolFull),
+        }
+        Ok(txid)
+    }
+
+    /// Check if a pool transaction can be replaced: it or one of its
+    /// unconfirmed ancestors has to signal replaceability
+    pub fn is_replaceable(&self, txid: &sha256d::Hash) -> bool {
+        match self.entries.get(txid) {
+            Some(entry) if entry.tx.signals_rbf() => true,
+            Some(_)                               => self.ancestors(txid).iter()
+                .filter_map(|ancestor| self.entries.get(ancestor))
+                .any(|ancestor| ancestor.tx.signals_rbf()),
+            None                                  => false,
+        }
+    }
+
+    /// Check a transaction with the given fee, virtual size and pool parents
+    /// may replace the conflicting transactions, `replaced` holding them and
+    /// their descendants (BIP125)
+    fn check_replacement(&self, conflicts: &HashSet<sha256d::Hash>, replaced: &HashSet<sha256d::Hash>,
+                         parents: &HashSet<sha256d::Hash>, fee: u64, vsize: usize) -> Result<(), Error> {
+        if replaced.len() > MAX_REPLACEMENT_CANDIDATES {
+            return Err(Error::ErrorReplacementRejected("too many potential replacements"));
+        }
+        if parents.iter().any(|parent| replaced.contains(parent)) {
+            return Err(Error::ErrorReplacementRejected("spends conflicting transaction"));
+        }
+
+        // No new unconfirmed inputs besides those the replaced transactions spent
+        let allowed: HashSet<&sha256d::Hash> = conflicts.iter()
+            .filter_map(|conflict| self.entries.get(conflict))
+            .flat_map(|entry| entry.parents.iter())
+            .collect();
+        if parents.iter().any(|parent| !allowed.contains(parent)) {
+            return Err(Error::ErrorReplacementRejected("replacement adds unconfirmed inputs"));
+        }
+
+        // The replacement has to be more attractive to mine than what it directly replaces
+        let replaced_rate = conflicts.iter()
+            .filter_map(|conflict| self.entries.get(conflict))
+            .map(|entry| entry.fee_rate())
+            .max().unwrap_or_default();
+        if FeeRate::from_fee(fee, vsize) <= replaced_rate {
+            return Err(Error::ErrorReplacementFee { fee: fee, required: replaced_rate.fee(vsize) + 1 });
+        }
+
+        // and pay for the relay of everything it evicts, and of itself
+        let (replaced_fees, _) = self.package_stats(replaced.iter());
+        let required = replaced_fees + FeeRate::from_sat_per_kvb(INCREMENTAL_RELAY_FEE).fee(vsize);
+        if fee < required {
+            return Err(Error::ErrorReplacementFee { fee: fee, required: required });
+        }
+        Ok(())
+    }
+
+    /// Check that a transaction spending the given pool transactions keeps
+    /// every unconfirmed chain within the ancestor and descendant limits,
+    /// once the transactions it replaces are gone
+    fn check_chain_limits(&self, parents: &HashSet<sha256d::Hash>, replaced: &HashSet<sha256d::Hash>,
+                          vsize: usize) -> Result<(), Error> {
+        let mut ancestors = parents.clone();
+        for parent in parents.iter() {
+            ancestors.extend(self.ancestors(parent));
+        }
+        let (_, ancestors_vsize) = self.package_stats(ancestors.iter());
+        if ancestors.len() + 1 > DEFAULT_ANCESTOR_LIMIT || ancestors_vsize + vsize > DEFAULT_ANCESTOR_SIZE_LIMIT {
+            return Err(Error::ErrorTooLongChain);
+        }
+
+        // Replaced transactions are never ancestors, a replacement
+        // spending one of them being refused
+        for ancestor in ancestors.iter() {
+            let descendants: HashSet<sha256d::Hash> = self.descendants(ancestor).into_iter()
+                .filter(|descendant| !replaced.contains(descendant))
+                .collect();
+            let (_, descendants_vsize) = self.package_stats(descendants.iter().chain(Some(ancestor)));
+            if descendants.len() + 2 > DEFAULT_DESCENDANT_LIMIT
+                || descendants_vsize + vsize > DEFAULT_DESCENDANT_SIZE_LIMIT {
+                return Err(Error::ErrorTooLongChain);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add an entry, linking it to the pool transactions it spends and to those spending it
+    fn insert(&mut self, mut entry: MempoolEntry) {
+        let txid = entry.txid;
+        for parent in entry.parents.iter() {
+            if let Some(parent) = self.entries.get_mut(parent) {
+                parent.children.insert(txid);
+            }
+        }
+        // Pool transactions may already spend it when it comes back from a disconnected block
+        for vout in 0..entry.tx.output_ent.len() {
+            if let Some(child) = self.spent.get(&OutPoint::new(txid, vout as u32)).cloned() {
+                entry.children.insert(child);
+                if let Some(child) = self.entries.get_mut(&child) {
+                    child.parents.insert(txid);
+                }
+            }
+        }
+        for txin in entry.tx.input_ent.iter() {
+            self.spent.insert(txin.previous_out, txid);
+        }
+        self.total_vsize += entry.vsize;
+        self.entries.insert(txid, entry);
+
+        let mut changed = self.ancestors(&txid);
+        changed.insert(txid);
+        self.update_descendant_stats(changed.iter());
+    }
+
+    /// Recompute the descendant fee and size of pool transactions
+    fn update_descendant_stats<'a, I: Iterator<Item = &'a sha256d::Hash>>(&mut self, txids: I) {
+        for txid in txids {
+            let (fee, vsize) = {
+                let descendants = self.descendants(txid);
+                self.package_stats(descendants.iter().chain(Some(txid)))
+            };
+            if let Some(entry) = self.entries.get_mut(txid) {
+                entry.descendant_fee = fee;
+                entry.descendant_vsize = vsize;
+            }
+        }
+    }
+
+    /// Remove a single entry, unlinking it from the remaining ones
+    fn remove_entry(&mut self, txid: &sha256d::Hash) -> Option<MempoolEntry> {
+        let ancestors = self.ancestors(txid);
+        let entry = self.entries.remove(txid)?;
+        for txin in entry.tx.input_ent.iter() {
+            if self.spent.get(&txin.previous_out) == Some(txid) {
+                self.spent.remove(&txin.previous_out);
+            }
+        }
+        for parent in entry.parents.iter() {
+            if let Some(parent) = self.entries.get_mut(parent) {
+                parent.children.remove(txid);
+            }
+        }
+        for child in entry.children.iter() {
+            if let Some(child) = self.entries.get_mut(child) {
+                child.parents.remove(txid);
+            }
+        }
+        self.total_vsize -= entry.vsize;
+        self.update_descendant_stats(ancestors.iter());
+        Some(entry)
+    }
+
+    /// Remove a transaction together with everything depending on it,
+    /// none of them getting confirmed
+    pub fn remove_recursive(&mut self, txid: &sha256d::Hash) -> Vec<Transaction> {
+        let mut removed = Vec::new();
+        let descendants = self.descendants(txid);
+        for remove in Some(*txid).iter().chain(descendants.iter()) {
+            if let Some(entry) = self.remove_entry(remove) {
+                self.fee_estimator.remove_transaction(remove);
+                removed.push(entry.tx);
+            }
+        }
+        removed
+    }
+
+    /// Evict the packages with the lowest fee rate until the pool fits its
+    /// size limit, raising the minimum fee above the evicted rates
+    pub fn trim(&mut self, now: i64) -> Vec<Transaction> {
+        let mut removed = Vec::new();
+        while self.total_vsize > self.max_size {
+            // A transaction is evicted with its descendants, so the
+            // rate of that whole package is what counts
+            let worst = self.entries.values()
+                .map(|entry| (entry.descendant_fee_rate(), entry.txid))
+                .min();
+            let (fee_rate, txid) = match worst {
+                Some(worst) => worst,
+                None        => break,
+            };
+
+            let raised = FeeRate::from_sat_per_kvb(fee_rate.sat_per_kvb() + INCREMENTAL_RELAY_FEE);
+            self.rolling_min_fee = cmp::max(self.min_fee(now), raised);
+            self.last_rolling_update = now;
+            removed.extend(self.remove_recursive(&txid));
+        }
+        removed
+    }
+
+    /// Drop transactions that entered the pool too long ago, with their descendants
+    pub fn expire(&mut self, now: i64) -> Vec<Transaction> {
+        let expired: Vec<sha256d::Hash> = self.entries.values()
+            .filter(|entry| entry.time < now - self.expiry)
+            .map(|entry| entry.txid)
+            .collect();
+        let mut removed = Vec::new();
+        for txid in expired.iter() {
+            removed.extend(self.remove_recursive(txid));
+        }
+        removed
+    }
+
+    /// Remove the transactions of a newly connected block at the given
+    /// height, and every pool transaction conflicting with them; the
+    /// conflicts are returned
+    pub fn remove_for_block(&mut self, block: &Block, height: u64) -> Vec<Transaction> {
+        self.fee_estimator.process_block(block, height);
+        let mut conflicts = Vec::new();
+        for tx in block.txdata.iter() {
+            let txid = tx.txid();
+            // Children of a confirmed transaction stay, now spending confirmed outputs
+            self.remove_entry(&txid);
+            for txin in tx.input_ent.iter() {
+                if let Some(spender) = self.spent.get(&txin.previous_out).cloned() {
+                    conflicts.extend(self.remove_recursive(&spender));
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Re-add the transactions of a disconnected block once the unspent set
+    /// has been reverted, dropping pool transactions left without inputs
+    pub fn readd_for_disconnect(&mut self, block: &Block, tree: &HeaderTree, utxo: &UtxoSet,
+                                verifier: &ScriptVerifier, now: i64) -> Vec<Transaction> {
+        for tx in block.txdata.iter().filter(|tx| !tx.is_coin_base()) {
+            let _ = self.accept(tx.clone(), tree, utxo, verifier, now);
+        }
+
+        let orphaned: Vec<sha256d::Hash> = self.entries.values()
+            .filter(|entry| entry.tx.input_ent.iter().any(|txin| {
+                !self.entries.contains_key(&txin.previous_out.txid)
+                    && !utxo.contains(&txin.previous_out).unwrap_or(false)
+            }))
+            .map(|entry| entry.txid)
+            .collect();
+        let mut removed = Vec::new();
+        for txid in orphaned.iter() {
+            removed.extend(self.remove_recursive(txid));
+        }
+        removed
+    }
+
+    /// Return the pool transactions as candidates for a block template
+    pub fn template_candidates(&self) -> Vec<TemplateTx> {
+        self.entries.values()
+            .map(|entry| TemplateTx {
+                tx:         entry.tx.clone(),
+                fee:        entry.fee,
+                sigop_cost: entry.sigop_cost,
+                parents:    entry.parents.iter().cloned().collect(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    use hashes::{sha256d, Hash};
+
+    use blockdata::block::Block;
+    use blockdata::constants::genesis_header;
+    use blockdata::script::Script;
+    use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut, MAX_BIP125_RBF_SEQUENCE, SEQUENCE_FINAL};
+    use chain::headertree::HeaderTree;
+    use consensus::params::{Network, Params};
+    use mempool::error::Error;
+    use store::utxo::{BlockUndo, UtxoSet};
+    use validation::script::NoScriptVerification;
+    use super::{Mempool, DEFAULT_DESCENDANT_LIMIT, DEFAULT_MAX_MEMPOOL_SIZE};
+
+    /// Value of every confirmed output
+    const COIN: u64 = 100_000_000;
+
+    /// Pool on top of a regtest genesis block holding confirmed outputs
+    struct Fixture {
+        tree: HeaderTree,
+        utxo: UtxoSet,
+        pool: Mempool,
+        coins: Vec<OutPoint>,
+        path: PathBuf,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Fixture {
+            let path = env::temp_dir().join(format!("mempool_{}_{}.dat", name, process::id()));
+            let _ = fs::remove_file(&path);
+            let mut utxo = UtxoSet::open(&path, 1_000).unwrap();
+
+            // The set only checks the block extends its best block, so the
+            // genesis header can carry outputs spendable right away
+            let funding = Transaction {
+                version:    1,
+                lock_time:  0,
+                input_ent:  vec![TxIn {
+                    previous_out: OutPoint::new(sha256d::Hash::hash(b"funding"), 0),
+                    sig_script:   Script::new(),
+                    sequence:     SEQUENCE_FINAL,
+                    witness:      vec![],
+                }],
+                output_ent: (0..4).map(|_| TxOut { value_out: COIN, pkscript: p2wpkh() }).collect(),
+            };
+            let coins = (0..4).map(|vout| OutPoint::new(funding.txid(), vout)).collect();
+            let block = Block { header: genesis_header(Network::Regtest), txdata: vec![funding] };
+            utxo.apply_block(&block, 0, &BlockUndo::default()).unwrap();
+
+            Fixture {
+                tree:  HeaderTree::new(Params::new(Network::Regtest)),
+                utxo:  utxo,
+                pool:  Mempool::new(DEFAULT_MAX_MEMPOOL_SIZE),
+                coins: coins,
+                path:  path,
+            }
+        }
+
+        fn accept(&mut self, tx: &Transaction) -> Result<sha256d::Hash, Error> {
+            let now = self.tree.best_tip().header.timestamp as i64 + 60;
+            self.pool.accept(tx.clone(), &self.tree, &self.utxo, &NoScriptVerification, now)
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    fn p2wpkh() -> Script {
+        let mut script = vec![0x00, 0x14];
+        script.extend(&[0x42; 20]);
+        Script::from(script)
+    }
+
+    /// Transaction spending the given outputs into outputs of the given values
+    fn spend(inputs: &[OutPoint], values: &[u64], sequence: u32) -> Transaction {
+        Transaction {
+            version:    1,
+            lock_time:  0,
+            input_ent:  inputs.iter().map(|input| TxIn {
+                previous_out: *input,
+                sig_script:   Script::new(),
+                sequence:     sequence,
+                witness:      vec![],
+            }).collect(),
+            output_ent: values.iter().map(|value| TxOut { value_out: *value, pkscript: p2wpkh() }).collect(),
+        }
+    }
+
+    fn output(tx: &Transaction, vout: u32) -> OutPoint {
+        OutPoint::new(tx.txid(), vout)
+    }
+
+    #[test]
+    fn replace_signalling_transaction() {
+        let mut fixture = Fixture::new("replace");
+        let coin = fixture.coins[0];
+        let original = spend(&[coin], &[COIN - 1_000], MAX_BIP125_RBF_SEQUENCE);
+        fixture.accept(&original).unwrap();
+        assert!(fixture.pool.is_replaceable(&original.txid()));
+
+        let replacement = spend(&[coin], &[COIN - 2_000], SEQUENCE_FINAL);
+        fixture.accept(&replacement).unwrap();
+        assert!(!fixture.pool.contains(&original.txid()));
+        assert!(fixture.pool.contains(&replacement.txid()));
+        assert_eq!(fixture.pool.spender(&coin), Some(&replacement.txid()));
+    }
+
+    #[test]
+    fn keep_non_signalling_transaction() {
+        let mut fixture = Fixture::new("non_signalling");
+        let coin = fixture.coins[0];
+        let original = spend(&[coin], &[COIN - 1_000], MAX_BIP125_RBF_SEQUENCE + 1);
+        fixture.accept(&original).unwrap();
+
+        let replacement = spend(&[coin], &[COIN - 50_000], SEQUENCE_FINAL);
+        assert!(match fixture.accept(&replacement) { Err(Error::ErrorConflict) => true, _ => false });
+        assert!(fixture.pool.contains(&original.txid()));
+    }
+
+    #[test]
+    fn inherited_signalling() {
+        let mut fixture = Fixture::new("inherited");
+        let parent = spend(&[fixture.coins[0]], &[COIN - 1_000], MAX_BIP125_RBF_SEQUENCE);
+        let child = spend(&[output(&parent, 0)], &[COIN - 2_000], SEQUENCE_FINAL);
+        fixture.accept(&parent).unwrap();
+        fixture.accept(&child).unwrap();
+        assert!(fixture.pool.is_replaceable(&child.txid()));
+
+        let replacement = spend(&[output(&parent, 0)], &[COIN - 3_000], SEQUENCE_FINAL);
+        fixture.accept(&replacement).unwrap();
+        assert!(fixture.pool.contains(&parent.txid()));
+        assert!(!fixture.pool.contains(&child.txid()));
+    }
+
+    #[test]
+    fn replacement_pays_for_descendants() {
+        let mut fixture = Fixture::new("descendants");
+        let coin = fixture.coins[0];
+        let original = spend(&[coin], &[COIN - 1_000], MAX_BIP125_RBF_SEQUENCE);
+        let child = spend(&[output(&original, 0)], &[COIN - 2_000], SEQUENCE_FINAL);
+        fixture.accept(&original).unwrap();
+        fixture.accept(&child).unwrap();
+
+        // A better fee rate than the original is not enough without paying for the child
+        let cheap = spend(&[coin], &[COIN - 1_500], SEQUENCE_FINAL);
+        assert!(match fixture.accept(&cheap) { Err(Error::ErrorReplacementFee { .. }) => true, _ => false });
+        assert_eq!(fixture.pool.len(), 2);
+
+        let replacement = spend(&[coin], &[COIN - 3_000], SEQUENCE_FINAL);
+        fixture.accept(&replacement).unwrap();
+        assert_eq!(fixture.pool.len(), 1);
+        assert!(!fixture.pool.contains(&original.txid()));
+        assert!(!fixture.pool.contains(&child.txid()));
+    }
+
+    #[test]
+    fn replacement_inputs() {
+        let mut fixture = Fixture::new("inputs");
+        let coin = fixture.coins[0];
+        let original = spend(&[coin], &[COIN / 2, COIN / 2 - 1_000], MAX_BIP125_RBF_SEQUENCE);
+        let unrelated = spend(&[fixture.coins[1]], &[COIN - 1_000], SEQUENCE_FINAL);
+        fixture.accept(&original).unwrap();
+        fixture.accept(&unrelated).unwrap();
+
+        let new_unconfirmed = spend(&[coin, output(&unrelated, 0)], &[2 * COIN - 10_000], SEQUENCE_FINAL);
+        assert!(match fixture.accept(&new_unconfirmed) { Err(Error::ErrorReplacementRejected(..)) => true, _ => false });
+
+        let spends_conflict = spend(&[coin, output(&original, 0)], &[COIN + COIN / 2 - 10_000], SEQUENCE_FINAL);
+        assert!(match fixture.accept(&spends_conflict) { Err(Error::ErrorReplacementRejected(..)) => true, _ => false });
+        assert!(fixture.pool.contains(&original.txid()));
+    }
+
+    #[test]
+    fn chain_limits_without_replaced_transactions() {
+        let mut fixture = Fixture::new("chain_limits");
+        let parent = spend(&[fixture.coins[0]], &[COIN / 2, COIN / 2 - 1_000], MAX_BIP125_RBF_SEQUENCE);
+        fixture.accept(&parent).unwrap();
+
+        // Fill the descendant limit of the parent with a chain from its first output
+        let mut previous = output(&parent, 0);
+        let mut value = COIN / 2;
+        for _ in 0..DEFAULT_DESCENDANT_LIMIT - 1 {
+            value -= 1_000;
+            let tx = spend(&[previous], &[value], MAX_BIP125_RBF_SEQUENCE);
+            fixture.accept(&tx).unwrap();
+            previous = output(&tx, 0);
+        }
+        assert_eq!(fixture.pool.len(), DEFAULT_DESCENDANT_LIMIT);
+
+        let sibling = spend(&[output(&parent, 1)], &[COIN / 2 - 2_000], SEQUENCE_FINAL);
+        assert!(match fixture.accept(&sibling) { Err(Error::ErrorTooLongChain) => true, _ => false });
+
+        // Replacing the whole chain leaves room for the replacement
+        let replacement = spend(&[output(&parent, 0)], &[COIN / 2 - 30_000], SEQUENCE_FINAL);
+        fixture.accept(&replacement).unwrap();
+        assert_eq!(fixture.pool.len(), 2);
+        assert!(fixture.pool.contains(&replacement.txid()));
+    }
+}
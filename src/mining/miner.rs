@@ -0,0 +1,114 @@
+/// CPU miner
+///
+/// The nonce range of a header is split between threads, thread `i` trying
+/// every nonce equal to `i` modulo the number of threads. Once the whole range
+/// is exhausted the extra nonce inside the coinbase is rolled, which changes
+/// the merkle root and gives a fresh range. Only practical on regtest.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use blockdata;
+use blockdata::block::{Block, BlockHeader};
+use blockdata::script::Script;
+use chain::headertree::HeaderTree;
+use mining::template::{BlockTemplate, TemplateTx, build_template};
+use util::hash::BlockchainHash;
+use util::uint::Uint256;
+
+/// Multithreaded nonce grinder
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Miner {
+    threads: usize,
+}
+
+/// Check if the hash of the header is below the target
+fn meets_target(header: &BlockHeader, target: &Uint256) -> bool {
+    let hash = Uint256::from_le_bytes(&header.blockchain_hash().into_inner());
+    hash <= *target
+}
+
+impl Miner {
+    /// Create a miner using the given number of threads, at least one
+    pub fn new(threads: usize) -> Miner {
+        Miner { threads: if threads == 0 { 1 } else { threads } }
+    }
+
+    /// Search the whole nonce range of a header for a hash below its target
+    pub fn solve_header(&self, header: &BlockHeader) -> Option<u32> {
+        let target = header.compute_target();
+        let found = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+
+        let workers: Vec<_> = (0..self.threads).map(|start| {
+            let mut header = *header;
+            let found = found.clone();
+            let sender = sender.clone();
+            let step = self.threads as u32;
+            thread::spawn(move || {
+                let mut nonce = start as u32;
+                while !found.load(Ordering::Relaxed) {
+                    header.nonce = nonce;
+                    if meets_target(&header, &target) {
+                        found.store(true, Ordering::Relaxed);
+                        let _ = sender.send(nonce);
+                        return;
+                    }
+                    nonce = match nonce.checked_add(step) {
+                        Some(next) => next,
+                        None       => return,
+                    };
+                }
+            })
+        }).collect();
+        drop(sender);
+
+        for worker in workers {
+            let _ = worker.join();
+        }
+        receiver.try_recv().ok()
+    }
+
+    /// Mine a template, rolling the extra nonce until a solution is found
+    pub fn mine(&self, template: &BlockTemplate) -> Block {
+        let mut extra_nonce = 0;
+        loop {
+            let mut block = template.with_extra_nonce(extra_nonce);
+            if let Some(nonce) = self.solve_header(&block.header) {
+                block.header.nonce = nonce;
+                return block;
+            }
+            extra_nonce += 1;
+        }
+    }
+
+    /// Build a template on the best header of the tree and mine it
+    pub fn generate_block(&self, tree: &HeaderTree, candidates: &[TemplateTx], payout: Script, now: i64) -> Result<Block, blockdata::Error> {
+        let template = build_template(tree, candidates, payout, now)?;
+        Ok(self.mine(&template))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blockdata::script::Script;
+    use chain::headertree::HeaderTree;
+    use consensus::params::{Network, Params};
+    use util::hash::BlockchainHash;
+    use validation::block::check_block;
+    use super::Miner;
+
+    #[test]
+    fn mine_regtest_block() {
+        let mut tree = HeaderTree::new(Params::new(Network::Regtest));
+        let now = tree.best_tip().header.timestamp as i64 + 10 * 60;
+        let block = Miner::new(2).generate_block(&tree, &[], Script::new(), now).unwrap();
+
+        assert!(check_block(&block).is_ok());
+        tree.insert(block.header, now).unwrap();
+        assert_eq!(tree.best_tip().hash, block.blockchain_hash());
+        assert_eq!(tree.best_tip().height(), 1);
+    }
+}
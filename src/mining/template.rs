@@ -0,0 +1,294 @@
+/// Block templates
+///
+/// Transactions are selected by the fee rate of the package they form with
+/// their unconfirmed ancestors, which have to be included before them.
+/// Packages are added greedily from the highest ancestor fee rate down as long
+/// as they fit into the weight and signature operations limits left after
+/// reserving room for the coinbase.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+
+use hashes::sha256d;
+
+use blockdata;
+use blockdata::block::Block;
+use blockdata::coinbase::CoinbaseBuilder;
+use blockdata::script::Script;
+use blockdata::transaction::Transaction;
+use chain::difficulty::next_target_required;
+use chain::headertree::HeaderTree;
+use chain::time::median_time_past;
+use util::hash::MerkleRoot;
+use validation::block::{MAX_BLOCK_WEIGHT, MAX_BLOCK_SIGOPS_COST, WITNESS_SCALE_FACTOR, block_weight};
+use validation::transaction::legacy_sigops;
+
+/// Weight kept free for the coinbase
+pub const COINBASE_RESERVED_WEIGHT: usize = 4_000;
+
+/// Signature operations cost kept free for the coinbase
+pub const COINBASE_RESERVED_SIGOPS: usize = 400;
+
+/// Version of the blocks we build, signalling no soft fork (BIP9)
+pub const TEMPLATE_BLOCK_VERSION: u32 = 0x20000000;
+
+/// Unconfirmed transaction offered for inclusion in a block
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TemplateTx {
+    pub tx: Transaction,
+    /// Fee paid by the transaction
+    pub fee: u64,
+    /// Signature operations cost of the transaction
+    pub sigop_cost: usize,
+    /// Ids of the unconfirmed transactions it spends from
+    pub parents: Vec<sha256d::Hash>,
+}
+
+/// Block ready to be mined, with the data needed to roll the extra nonce
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlockTemplate {
+    /// Block with a zero nonce and extra nonce
+    pub block: Block,
+    /// Builder of the coinbase of the block
+    pub coinbase: CoinbaseBuilder,
+    /// Fees of the selected transactions
+    pub fees: u64,
+    /// Weight of the block
+    pub weight: usize,
+    /// Signature operations cost of the block
+    pub sigop_cost: usize,
+}
+
+impl BlockTemplate {
+    /// Height of the block
+    pub fn height(&self) -> u64 {
+        self.block.header.height
+    }
+
+    /// Return the block with its coinbase using the given extra nonce
+    pub fn with_extra_nonce(&self, extra_nonce: u64) -> Block {
+        let mut block = self.block.clone();
+        // The extra nonce has a fixed size, so the coinbase stays valid
+        block.txdata[0] = self.coinbase.clone().extra_nonce(extra_nonce).build()
+            .expect("coinbase of the template is valid");
+        block.header.merkle_root = block.merkle_root();
+        block
+    }
+}
+
+/// Return every candidate the given one depends on, directly or not
+fn ancestors(index: usize, candidates: &[TemplateTx], by_txid: &HashMap<sha256d::Hash, usize>) -> HashSet<usize> {
+    let mut found = HashSet::new();
+    let mut pending = vec![index];
+    while let Some(current) = pending.pop() {
+        for parent in candidates[current].parents.iter() {
+            if let Some(&parent) = by_txid.get(parent) {
+                if found.insert(parent) {
+                    pending.push(parent);
+                }
+            }
+        }
+    }
+    found
+}
+
+/// Select candidates by ancestor fee rate within the given limits, returning
+/// their indexes in an order where parents come before their children.
+/// Candidates marked in `excluded` are never selected, nor anything depending on them.
+///
+/// The fee, weight and signature operations cost of every package are summed
+/// once; including a transaction then only subtracts its share from the
+/// packages of its descendants.
+pub fn select_transactions(candidates: &[TemplateTx], excluded: &HashSet<usize>,
+                           max_weight: usize, max_sigops: usize) -> Vec<usize> {
+    let by_txid: HashMap<sha256d::Hash, usize> = candidates.iter().enumerate()
+        .map(|(index, candidate)| (candidate.tx.txid(), index))
+        .collect();
+    let all_ancestors: Vec<HashSet<usize>> = (0..candidates.len())
+        .map(|index| ancestors(index, candidates, &by_txid))
+        .collect();
+    let mut descendants: Vec<Vec<usize>> = vec![Vec::new(); candidates.len()];
+    for (index, ancestors) in all_ancestors.iter().enumerate() {
+        for &ancestor in ancestors.iter() {
+            descendants[ancestor].push(index);
+        }
+    }
+    let weights: Vec<usize> = candidates.iter().map(|candidate| candidate.tx.weight()).collect();
+
+    // Fee, weight and signature operations cost of every package, without
+    // the ancestors already included
+    let mut packages: Vec<(u64, usize, usize)> = (0..candidates.len())
+        .map(|index| {
+            all_ancestors[index].iter().chain(Some(&index)).fold((0, 0, 0), |(fee, weight, sigops), &member| {
+                (fee + candidates[member].fee, weight + weights[member], sigops + candidates[member].sigop_cost)
+            })
+        })
+        .collect();
+
+    let mut selected = Vec::new();
+    let mut included = vec![false; candidates.len()];
+    let mut failed = vec![false; candidates.len()];
+    for index in 0..candidates.len() {
+        failed[index] = excluded.contains(&index) || all_ancestors[index].iter().any(|a| excluded.contains(a));
+    }
+    let (mut weight, mut sigops) = (0, 0);
+
+    loop {
+        // Find the package with the highest fee rate among the remaining ones
+        let mut best: Option<usize> = None;
+        for index in 0..candidates.len() {
+            if included[index] || failed[index] {
+                continue;
+            }
+            let (fee, package_weight, _) = packages[index];
+            let better = match best {
+                Some(best) => {
+                    let (best_fee, best_weight, _) = packages[best];
+                    (fee as u128) * (best_weight as u128) > (best_fee as u128) * (package_weight as u128)
+                },
+                None => true,
+            };
+            if better {
+                best = Some(index);
+            }
+        }
+
+        let index = match best {
+            Some(best) => best,
+            None       => break,
+        };
+        let (_, package_weight, package_sigops) = packages[index];
+        if weight + package_weight > max_weight || sigops + package_sigops > max_sigops {
+            failed[index] = true;
+            continue;
+        }
+
+        // Ancestors have fewer ancestors than their descendants, so this is a valid order
+        let mut package: Vec<usize> = all_ancestors[index].iter().cloned().filter(|&a| !included[a]).collect();
+        package.sort_by_key(|&member| all_ancestors[member].len());
+        package.push(index);
+        for member in package {
+            included[member] = true;
+            selected.push(member);
+            for &descendant in descendants[member].iter().filter(|&&descendant| !included[descendant]) {
+                let package = &mut packages[descendant];
+                package.0 -= candidates[member].fee;
+                package.1 -= weights[member];
+                package.2 -= candidates[member].sigop_cost;
+            }
+        }
+        weight += package_weight;
+        sigops += package_sigops;
+    }
+    selected
+}
+
+/// Build a template extending the best header of the tree, paying the
+/// subsidy and fees to `payout`
+pub fn build_template(tree: &HeaderTree, candidates: &[TemplateTx], payout: Script, now: i64) -> Result<BlockTemplate, blockdata::Error> {
+    let params = tree.params();
+    let tip = tree.best_tip();
+    let height = tip.height() + 1;
+    let median_time = median_time_past(tree, tip);
+    let timestamp = cmp::max(median_time as i64 + 1, now) as u32;
+    let target = next_target_required(tree, tip, timestamp);
+
+    let lock_time_cutoff = if height >= params.csv_height { median_time } else { timestamp };
+    let excluded: HashSet<usize> = candidates.iter().enumerate()
+        .filter(|&(_, candidate)| !candidate.tx.is_final(height, lock_time_cutoff))
+        .map(|(index, _)| index)
+        .collect();
+    let selected = select_transactions(candidates, &excluded,
+                                       MAX_BLOCK_WEIGHT - COINBASE_RESERVED_WEIGHT,
+                                       MAX_BLOCK_SIGOPS_COST - COINBASE_RESERVED_SIGOPS);
+
+    let fees: u64 = selected.iter().map(|&index| candidates[index].fee).sum();
+    let mut coinbase = CoinbaseBuilder::new(height).pay_to(payout, params.block_subsidy(height) + fees);
+
+    let mut txdata = vec![coinbase.build()?];
+    txdata.extend(selected.iter().map(|&index| candidates[index].tx.clone()));
+    let mut block = tip.header.next(txdata, timestamp, target);
+    block.header.block_version = TEMPLATE_BLOCK_VERSION;
+
+    if height >= params.segwit_height {
+        coinbase = coinbase.witness_root(block.witness_root());
+        block.txdata[0] = coinbase.build()?;
+        block.header.merkle_root = block.merkle_root();
+    }
+
+    let sigop_cost = legacy_sigops(&block.txdata[0]) * WITNESS_SCALE_FACTOR
+        + selected.iter().map(|&index| candidates[index].sigop_cost).sum::<usize>();
+    Ok(BlockTemplate {
+        weight:     block_weight(&block),
+        block:      block,
+        coinbase:   coinbase,
+        fees:       fees,
+        sigop_cost: sigop_cost,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use hashes::sha256d;
+
+    use blockdata::script::Script;
+    use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+    use super::{TemplateTx, select_transactions};
+
+    /// Candidate spending the first output of a parent, or of a confirmed
+    /// transaction told apart by the lock time
+    fn candidate(parent: Option<&TemplateTx>, lock_time: u32, fee: u64) -> TemplateTx {
+        let previous_out = match parent {
+            Some(parent) => OutPoint::new(parent.tx.txid(), 0),
+            None         => OutPoint::new(sha256d::Hash::default(), lock_time),
+        };
+        let tx = Transaction {
+            version:    2,
+            lock_time:  lock_time,
+            input_ent:  vec![TxIn { previous_out: previous_out, sig_script: Script::new(), sequence: 0xffffffff, witness: vec![] }],
+            output_ent: vec![TxOut { value_out: 1_000, pkscript: Script::new() }],
+        };
+        TemplateTx {
+            tx:         tx,
+            fee:        fee,
+            sigop_cost: 0,
+            parents:    parent.map(|parent| parent.tx.txid()).into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn child_pays_for_parent() {
+        let parent = candidate(None, 0, 100);
+        let child = candidate(Some(&parent), 1, 10_000);
+        let other = candidate(None, 2, 3_000);
+        let candidates = vec![parent, child, other];
+        assert_eq!(select_transactions(&candidates, &HashSet::new(), 4_000_000, 80_000), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn included_ancestors_leave_packages() {
+        // Once the parent is in, the child is left alone with its zero fee
+        let parent = candidate(None, 0, 5_000);
+        let child = candidate(Some(&parent), 1, 0);
+        let other = candidate(None, 2, 3_000);
+        let candidates = vec![parent, child, other];
+        assert_eq!(select_transactions(&candidates, &HashSet::new(), 4_000_000, 80_000), vec![0, 2, 1]);
+    }
+
+    #[test]
+    fn limits_and_exclusions() {
+        let parent = candidate(None, 0, 5_000);
+        let child = candidate(Some(&parent), 1, 10_000);
+        let other = candidate(None, 2, 3_000);
+        let weight = parent.tx.weight();
+        let candidates = vec![parent, child, other];
+
+        // Only a single transaction fits, the package of two is skipped
+        assert_eq!(select_transactions(&candidates, &HashSet::new(), weight, 80_000), vec![0]);
+
+        let excluded: HashSet<usize> = Some(0).into_iter().collect();
+        assert_eq!(select_transactions(&candidates, &excluded, 4_000_000, 80_000), vec![2]);
+    }
+}
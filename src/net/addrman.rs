@@ -0,0 +1,555 @@
+/// Address manager
+///
+/// Addresses learnt from `addr` gossip are stored in the "new" table, those
+/// we managed to connect to in the "tried" table. Both tables are split into
+/// buckets of fixed size, the bucket and the slot of an address being picked
+/// by hashing it with a secret key:
+///
+/// - a new address lands in one of 64 buckets chosen by the network group of
+///   the peer it was learnt from, so a single peer cannot fill the table;
+/// - a tried address lands in one of 8 buckets chosen by its own group.
+///
+/// An address taking a slot already used is dropped, unless the occupant looks
+/// useless. An address moving to a used slot of the tried table is recorded as
+/// a collision instead: the occupant is tested with a connection attempt, and
+/// only pushed back to the new table if the attempt fails or never happens.
+///
+/// Selection favours addresses that were not tried recently and did not fail
+/// many times. There is no randomness source in the crate, callers pass the
+/// secret key and random values in.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use hashes::{sha256d, Hash};
+
+use consensus::encode::{serialize, Decodable};
+use store::error::Error;
+use store::file::write_atomic;
+use util::constants::MAX_ADDR_PER_MSG;
+use wire::msgaddr::AddrMessage;
+use wire::netaddress::NetAddress;
+
+/// Number of buckets of the new table
+pub const NEW_BUCKET_COUNT: usize = 1024;
+
+/// Number of buckets of the tried table
+pub const TRIED_BUCKET_COUNT: usize = 256;
+
+/// Number of slots of a bucket
+pub const BUCKET_SIZE: usize = 64;
+
+/// Number of new buckets the addresses learnt from a group may land in
+const NEW_BUCKETS_PER_SOURCE_GROUP: u64 = 64;
+
+/// Number of tried buckets the addresses of a group may land in
+const TRIED_BUCKETS_PER_GROUP: u64 = 8;
+
+/// Age in seconds after which an address is considered useless
+const HORIZON: i64 = 30 * 24 * 60 * 60;
+
+/// Failed attempts after which a never successful address is useless
+const RETRIES: u32 = 3;
+
+/// Failed attempts after which an address failing for a week is useless
+const MAX_FAILURES: u32 = 10;
+
+/// Time in seconds without success after which `MAX_FAILURES` applies
+const MIN_FAIL: i64 = 7 * 24 * 60 * 60;
+
+/// Time in seconds during which a tried address is not picked again as often
+const RECENT_TRY: i64 = 10 * 60;
+
+/// Penalty in seconds on the timestamps of gossiped addresses
+const GOSSIP_TIME_PENALTY: i64 = 2 * 60 * 60;
+
+/// Time in seconds after which the timestamp of a connected address is refreshed
+const CONNECTED_UPDATE_INTERVAL: i64 = 20 * 60;
+
+/// Maximum number of tried table collisions waiting for a test
+const MAX_TRIED_COLLISIONS: usize = 10;
+
+/// Time in seconds a tried address succeeding within keeps its slot
+const TRIED_REPLACEMENT_TIME: i64 = 4 * 60 * 60;
+
+/// Time in seconds a collision waits for the occupant to be tested
+const TEST_WINDOW: i64 = 40 * 60;
+
+/// Percentage of the known addresses returned for a `getaddr` request
+const MAX_GETADDR_PCT: usize = 23;
+
+/// Version of the addresses file format
+const FILE_VERSION: u32 = 1;
+
+/// Small generator expanding a random value from the caller
+struct SplitMix(u64);
+
+impl SplitMix {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Identifies an address regardless of its timestamp and services
+type AddrKey = ([u16; 8], u16);
+
+fn addr_key(address: &NetAddress) -> AddrKey {
+    (address.ip, address.port)
+}
+
+/// What we know of an address
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct AddrInfo {
+    address: NetAddress,
+    /// Address of the peer it was learnt from
+    source: NetAddress,
+    /// Last connection attempt
+    last_try: i64,
+    /// Last successful connection
+    last_success: i64,
+    /// Failed attempts since the last success
+    attempts: u32,
+    /// Whether the address is in the tried table
+    tried: bool,
+}
+
+impl AddrInfo {
+    /// Check if the address is not worth keeping
+    fn is_terrible(&self, now: i64) -> bool {
+        if self.last_try >= now - 60 {
+            return false;
+        }
+        self.address.timestamp > now + 10 * 60
+            || self.address.timestamp == 0
+            || now - self.address.timestamp > HORIZON
+            || (self.last_success == 0 && self.attempts >= RETRIES)
+            || (now - self.last_success > MIN_FAIL && self.attempts >= MAX_FAILURES)
+    }
+
+    /// Relative chance of picking the address
+    fn chance(&self, now: i64) -> f64 {
+        let mut chance = 1.0;
+        if now - self.last_try < RECENT_TRY {
+            chance *= 0.01;
+        }
+        chance * 0.66f64.powi(cmp::min(self.attempts, 8) as i32)
+    }
+}
+
+/// Known peer addresses
+pub struct AddrMan {
+    /// Secret key of the bucket hashes
+    key: [u8; 32],
+    entries: HashMap<usize, AddrInfo>,
+    ids: HashMap<AddrKey, usize>,
+    next_id: usize,
+    /// Slots of the new table, bucket after bucket
+    new_table: Vec<Option<usize>>,
+    /// Slots of the tried table, bucket after bucket
+    tried_table: Vec<Option<usize>>,
+    /// New entries that succeeded but whose tried slot is taken
+    tried_collisions: Vec<usize>,
+}
+
+impl AddrMan {
+    /// Create an empty manager with a random secret key
+    pub fn new(key: [u8; 32]) -> AddrMan {
+        AddrMan {
+            key:              key,
+            entries:          HashMap::new(),
+            ids:              HashMap::new(),
+            next_id:          0,
+            new_table:        vec![None; NEW_BUCKET_COUNT * BUCKET_SIZE],
+            tried_table:      vec![None; TRIED_BUCKET_COUNT * BUCKET_SIZE],
+            tried_collisions: Vec::new(),
+        }
+    }
+
+    /// Number of known addresses
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if no address is known
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of addresses in the tried table
+    pub fn tried_count(&self) -> usize {
+        self.entries.values().filter(|info| info.tried).count()
+    }
+
+    /// Hash data with the secret key
+    fn keyed_hash(&self, parts: &[&[u8]]) -> u64 {
+        let mut data = self.key.to_vec();
+        for part in parts.iter() {
+            data.extend_from_slice(part);
+        }
+        let hash = sha256d::Hash::hash(&data).into_inner();
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&hash[0..8]);
+        u64::from_le_bytes(bytes)
+    }
+
+    /// Slot of an address in the new table
+    fn new_slot(&self, address: &NetAddress, source: &NetAddress) -> usize {
+        let source_group = source.group();
+        let hash = self.keyed_hash(&[&address.group(), &source_group]) % NEW_BUCKETS_PER_SOURCE_GROUP;
+        let bucket = self.keyed_hash(&[&source_group, &hash.to_le_bytes()]) % NEW_BUCKET_COUNT as u64;
+        let position = self.keyed_hash(&[b"N", &bucket.to_le_bytes(), &serialize(address)[12..]]) % BUCKET_SIZE as u64;
+        bucket as usize * BUCKET_SIZE + position as usize
+    }
+
+    /// Slot of an address in the tried table
+    fn tried_slot(&self, address: &NetAddress) -> usize {
+        let hash = self.keyed_hash(&[&serialize(address)[12..]]) % TRIED_BUCKETS_PER_GROUP;
+        let bucket = self.keyed_hash(&[&address.group(), &hash.to_le_bytes()]) % TRIED_BUCKET_COUNT as u64;
+        let position = self.keyed_hash(&[b"K", &bucket.to_le_bytes(), &serialize(address)[12..]]) % BUCKET_SIZE as u64;
+        bucket as usize * BUCKET_SIZE + position as usize
+    }
+
+    fn delete(&mut self, id: usize) {
+        if let Some(info) = self.entries.remove(&id) {
+            self.ids.remove(&addr_key(&info.address));
+        }
+        self.tried_collisions.retain(|collision| *collision != id);
+    }
+
+    /// Put an entry into its new slot, evicting a useless occupant; the entry
+    /// is deleted if the slot stays taken
+    fn place_new(&mut self, id: usize, now: i64) -> bool {
+        let slot = {
+            let info = &self.entries[&id];
+            self.new_slot(&info.address, &info.source)
+        };
+        if let Some(occupant) = self.new_table[slot] {
+            if occupant == id {
+                return true;
+            }
+            if !self.entries[&occupant].is_terrible(now) {
+                self.delete(id);
+                return false;
+            }
+            self.delete(occupant);
+        }
+        self.new_table[slot] = Some(id);
+        true
+    }
+
+    /// Add an address learnt from a peer, with its timestamp moved back by a
+    /// penalty; return whether it was not known yet
+    pub fn add(&mut self, address: &NetAddress, source: &NetAddress, now: i64, time_penalty: i64) -> bool {
+        if !address.is_routable() {
+            return false;
+        }
+        let timestamp = cmp::max(0, address.timestamp - time_penalty);
+
+        if let Some(id) = self.ids.get(&addr_key(address)).cloned() {
+            let info = self.entries.get_mut(&id).unwrap();
+            // Refresh the timestamp less often for addresses seen recently
+            let online = now - address.timestamp < 24 * 60 * 60;
+            let interval = if online { 60 * 60 } else { 24 * 60 * 60 };
+            if info.address.timestamp < timestamp - interval {
+                info.address.timestamp = timestamp;
+            }
+            info.address.add_service(address.services);
+            return false;
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut stored = *address;
+        stored.timestamp = timestamp;
+        self.entries.insert(id, AddrInfo {
+            address:      stored,
+            source:       *source,
+            last_try:     0,
+            last_success: 0,
+            attempts:     0,
+            tried:        false,
+        });
+        self.ids.insert(addr_key(address), id);
+        self.place_new(id, now)
+    }
+
+    /// Add the addresses of an `addr` message; timestamps in the future or
+    /// far in the past are replaced by a five days old one
+    pub fn add_gossip(&mut self, msg: &AddrMessage, source: &NetAddress, now: i64) -> usize {
+        let mut added = 0;
+        for address in msg.addresses.iter() {
+            let mut address = *address;
+            if address.timestamp <= 100_000_000 || address.timestamp > now + 10 * 60 {
+                address.timestamp = now - 5 * 24 * 60 * 60;
+            }
+            if self.add(&address, source, now, GOSSIP_TIME_PENALTY) {
+                added += 1;
+            }
+        }
+        added
+    }
+
+    /// Record a connection attempt to an address
+    pub fn attempt(&mut self, address: &NetAddress, now: i64) {
+        if let Some(id) = self.ids.get(&addr_key(address)) {
+            let info = self.entries.get_mut(id).unwrap();
+            info.last_try = now;
+            info.attempts += 1;
+        }
+    }
+
+    /// Check if an address was tried within the last minutes
+    pub fn tried_recently(&self, address: &NetAddress, now: i64) -> bool {
+        self.ids.get(&addr_key(address)).map_or(false, |id| now - self.entries[id].last_try < RECENT_TRY)
+    }
+
+    /// Record a successful connection to an address, moving it to the tried
+    /// table; when its slot there is taken, the collision is recorded for
+    /// `resolve_collisions` and the address stays new
+    pub fn good(&mut self, address: &NetAddress, now: i64) {
+        let id = match self.ids.get(&addr_key(address)) {
+            Some(id) => *id,
+            None     => return,
+        };
+        {
+            let info = self.entries.get_mut(&id).unwrap();
+            info.last_success = now;
+            info.last_try = now;
+            info.attempts = 0;
+            if info.tried {
+                return;
+            }
+        }
+
+        let tried_slot = self.tried_slot(&self.entries[&id].address);
+        match self.tried_table[tried_slot] {
+            Some(occupant) if occupant != id => {
+                if self.tried_collisions.len() < MAX_TRIED_COLLISIONS && !self.tried_collisions.contains(&id) {
+                    self.tried_collisions.push(id);
+                }
+            },
+            _ => self.make_tried(id),
+        }
+    }
+
+    /// Move a new entry to its tried slot; the address it replaces there goes
+    /// back to the new table
+    fn make_tried(&mut self, id: usize) {
+        let (new_slot, tried_slot) = {
+            let info = &self.entries[&id];
+            (self.new_slot(&info.address, &info.source), self.tried_slot(&info.address))
+        };
+        if self.new_table[new_slot] == Some(id) {
+            self.new_table[new_slot] = None;
+        }
+        if let Some(evicted) = self.tried_table[tried_slot] {
+            self.entries.get_mut(&evicted).unwrap().tried = false;
+            let slot = {
+                let info = &self.entries[&evicted];
+                self.new_slot(&info.address, &info.source)
+            };
+            // The evicted address has proven itself, it takes over its new slot
+            if let Some(occupant) = self.new_table[slot] {
+                self.delete(occupant);
+            }
+            self.new_table[slot] = Some(evicted);
+        }
+        self.tried_table[tried_slot] = Some(id);
+        self.entries.get_mut(&id).unwrap().tried = true;
+    }
+
+    /// Settle the recorded tried table collisions: an occupant that succeeded
+    /// recently keeps its slot, one that failed a recent attempt, or was not
+    /// tried within `TEST_WINDOW` of the collision, is evicted
+    pub fn resolve_collisions(&mut self, now: i64) {
+        for id in self.tried_collisions.clone() {
+            let info = match self.entries.get(&id) {
+                Some(info) if !info.tried => info.clone(),
+                _                         => {
+                    self.tried_collisions.retain(|collision| *collision != id);
+                    continue;
+                },
+            };
+            let occupant = match self.tried_table[self.tried_slot(&info.address)] {
+                Some(occupant) => occupant,
+                None           => {
+                    self.tried_collisions.retain(|collision| *collision != id);
+                    self.make_tried(id);
+                    continue;
+                },
+            };
+
+            let occupant_info = &self.entries[&occupant];
+            let keep = now - occupant_info.last_success < TRIED_REPLACEMENT_TIME;
+            let evict = if keep {
+                false
+            } else if now - occupant_info.last_try < TRIED_REPLACEMENT_TIME {
+                // An attempt older than a minute without success failed
+                now - occupant_info.last_try > 60
+            } else {
+                now - info.last_success > TEST_WINDOW
+            };
+            if keep || evict {
+                self.tried_collisions.retain(|collision| *collision != id);
+            }
+            if evict {
+                self.make_tried(id);
+            }
+        }
+    }
+
+    /// Pick the occupant of a collided tried slot, to test it with a
+    /// connection attempt
+    pub fn select_tried_collision(&self, random: u64) -> Option<NetAddress> {
+        if self.tried_collisions.is_empty() {
+            return None;
+        }
+        let id = self.tried_collisions[(random % self.tried_collisions.len() as u64) as usize];
+        let slot = self.tried_slot(&self.entries.get(&id)?.address);
+        self.tried_table[slot].map(|occupant| self.entries[&occupant].address)
+    }
+
+    /// Record that we are connected to an address
+    pub fn connected(&mut self, address: &NetAddress, now: i64) {
+        if let Some(id) = self.ids.get(&addr_key(address)) {
+            let info = self.entries.get_mut(id).unwrap();
+            if now - info.address.timestamp > CONNECTED_UPDATE_INTERVAL {
+                info.address.timestamp = now;
+            }
+        }
+    }
+
+    /// Update the services of an address after its `version` message
+    pub fn set_services(&mut self, address: &NetAddress, services: u64) {
+        if let Some(id) = self.ids.get(&addr_key(address)) {
+            self.entries.get_mut(id).unwrap().address.services = services;
+        }
+    }
+
+    /// Pick an address to connect to, from either table with equal odds or
+    /// from the new table only, favouring addresses that did not fail
+    pub fn select(&self, new_only: bool, now: i64, random: u64) -> Option<NetAddress> {
+        let mut rng = SplitMix(random);
+        let tried_count = self.tried_count();
+        let new_count = self.entries.len() - tried_count;
+        let use_tried = !new_only && tried_count > 0 && (new_count == 0 || rng.next() % 2 == 0);
+        if !use_tried && new_count == 0 {
+            return None;
+        }
+
+        let candidates: Vec<&AddrInfo> = self.entries.values().filter(|info| info.tried == use_tried).collect();
+        let mut chance_factor = 1.0;
+        loop {
+            let info = candidates[(rng.next() % candidates.len() as u64) as usize];
+            if rng.next_f64() < chance_factor * info.chance(now) {
+                return Some(info.address);
+            }
+            chance_factor *= 1.2;
+        }
+    }
+
+    /// Return a random sample of the addresses worth sharing, to answer a
+    /// `getaddr` request
+    pub fn get_addr(&self, now: i64, random: u64) -> Vec<NetAddress> {
+        let mut rng = SplitMix(random);
+        let mut addresses: Vec<NetAddress> = self.entries.values()
+            .filter(|info| !info.is_terrible(now))
+            .map(|info| info.address)
+            .collect();
+        let count = cmp::min(self.entries.len() * MAX_GETADDR_PCT / 100, MAX_ADDR_PER_MSG);
+        let count = cmp::min(count, addresses.len());
+        // Partial Fisher-Yates shuffle
+        for index in 0..count {
+            let other = index + (rng.next() % (addresses.len() - index) as u64) as usize;
+            addresses.swap(index, other);
+        }
+        addresses.truncate(count);
+        addresses
+    }
+
+    /// Write the addresses to a file, atomically replacing it
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut payload = serialize(&FILE_VERSION);
+        payload.extend(serialize(&self.key));
+        payload.extend(serialize(&(self.entries.len() as u64)));
+        for info in self.entries.values() {
+            payload.extend(serialize(&info.address));
+            payload.extend(serialize(&info.source));
+            payload.extend(serialize(&info.last_try));
+            payload.extend(serialize(&info.last_success));
+            payload.extend(serialize(&info.attempts));
+            payload.extend(serialize(&info.tried));
+        }
+
+        write_atomic(path, &payload)
+    }
+
+    /// Read addresses written by `write`, placing them back into the tables
+    pub fn read<P: AsRef<Path>>(path: P, now: i64) -> Result<AddrMan, Error> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        let mut decoder = io::Cursor::new(&data[..]);
+
+        let version: u32 = Decodable::decode(&mut decoder)?;
+        if version != FILE_VERSION {
+            return Err(Error::ErrorCorrupted("unknown addresses version"));
+        }
+        let mut addrman = AddrMan::new(Decodable::decode(&mut decoder)?);
+        let count: u64 = Decodable::decode(&mut decoder)?;
+        if count > ((NEW_BUCKET_COUNT + TRIED_BUCKET_COUNT) * BUCKET_SIZE) as u64 {
+            return Err(Error::ErrorCorrupted("too many addresses"));
+        }
+        let mut tried = Vec::new();
+        for _ in 0..count {
+            let info = AddrInfo {
+                address:      Decodable::decode(&mut decoder)?,
+                source:       Decodable::decode(&mut decoder)?,
+                last_try:     Decodable::decode(&mut decoder)?,
+                last_success: Decodable::decode(&mut decoder)?,
+                attempts:     Decodable::decode(&mut decoder)?,
+                tried:        false,
+            };
+            let was_tried: bool = Decodable::decode(&mut decoder)?;
+            let address = info.address;
+            if addrman.ids.contains_key(&addr_key(&address)) {
+                return Err(Error::ErrorCorrupted("duplicate address"));
+            }
+            let id = addrman.next_id;
+            addrman.next_id += 1;
+            addrman.entries.insert(id, info);
+            addrman.ids.insert(addr_key(&address), id);
+            if was_tried {
+                tried.push(id);
+            } else {
+                addrman.place_new(id, now);
+            }
+        }
+        if decoder.position() as usize != data.len() {
+            return Err(Error::ErrorCorrupted("trailing data after addresses"));
+        }
+
+        // Tried addresses losing their slot to another one go back to new
+        for id in tried {
+            let slot = addrman.tried_slot(&addrman.entries[&id].address);
+            if addrman.tried_table[slot].is_none() {
+                addrman.tried_table[slot] = Some(id);
+                addrman.entries.get_mut(&id).unwrap().tried = true;
+            } else {
+                addrman.place_new(id, now);
+            }
+        }
+        Ok(addrman)
+    }
+}
@@ -0,0 +1,194 @@
+/// Ban list
+///
+/// Addresses and subnets we refuse to connect to or accept connections from,
+/// each until an expiry time. The list is kept on disk so bans survive a
+/// restart.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv6Addr};
+use std::path::Path;
+
+use consensus::encode::{serialize, Decodable};
+use store::error::Error;
+use store::file::write_atomic;
+use wire::netaddress::NetAddress;
+
+/// Default time in seconds an address stays banned
+pub const DEFAULT_BAN_DURATION: i64 = 24 * 60 * 60;
+
+/// Prefix length of ipv4-mapped addresses before the IPv4 part
+const IPV4_MAPPED_PREFIX: u8 = 96;
+
+/// Version of the ban list file format
+const FILE_VERSION: u32 = 1;
+
+/// Range of IP addresses sharing a prefix
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Subnet {
+    /// Address with the bits past the prefix cleared
+    network: [u16; 8],
+    /// Prefix length in bits of the IPv6, or ipv4-mapped, address
+    prefix: u8,
+}
+
+impl Subnet {
+    /// Subnet of the addresses sharing `prefix` bits with an address; the
+    /// prefix of an IPv4 address counts its IPv4 bits only
+    pub fn new(address: &NetAddress, prefix: u8) -> Option<Subnet> {
+        let prefix = if address.ipv4().is_some() {
+            if prefix > 32 {
+                return None;
+            }
+            IPV4_MAPPED_PREFIX + prefix
+        } else {
+            if prefix > 128 {
+                return None;
+            }
+            prefix
+        };
+        let mut network = address.ip;
+        for (index, segment) in network.iter_mut().enumerate() {
+            *segment &= segment_mask(prefix, index);
+        }
+        Some(Subnet { network: network, prefix: prefix })
+    }
+
+    /// Subnet holding a single address
+    pub fn single(address: &NetAddress) -> Subnet {
+        Subnet { network: address.ip, prefix: 128 }
+    }
+
+    /// Parse an address, or an address and a prefix length separated by `/`
+    pub fn parse(subnet: &str) -> Option<Subnet> {
+        let mut parts = subnet.splitn(2, '/');
+        let ip: IpAddr = parts.next()?.parse().ok()?;
+        let ip = match ip {
+            IpAddr::V4(ipv4) => ipv4.to_ipv6_mapped(),
+            IpAddr::V6(ipv6) => ipv6,
+        };
+        let address = NetAddress { timestamp: 0, services: 0, ip: ip.segments(), port: 0 };
+        match parts.next() {
+            Some(prefix) => Subnet::new(&address, prefix.parse().ok()?),
+            None         => Some(Subnet::single(&address)),
+        }
+    }
+
+    /// Check if an address is inside the subnet
+    pub fn contains(&self, address: &NetAddress) -> bool {
+        self.network.iter().zip(address.ip.iter()).enumerate()
+            .all(|(index, (network, segment))| segment & segment_mask(self.prefix, index) == *network)
+    }
+}
+
+/// Mask of the bits of a prefix falling into a 16-bit segment of an address
+fn segment_mask(prefix: u8, segment: usize) -> u16 {
+    let bits = (prefix as u32).saturating_sub(segment as u32 * 16).min(16);
+    if bits == 0 { 0 } else { !0u16 << (16 - bits) }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ip = Ipv6Addr::from(self.network);
+        match ip.to_ipv4() {
+            Some(ipv4) if self.prefix >= IPV4_MAPPED_PREFIX && self.network[0..6] == [0, 0, 0, 0, 0, 0xffff] => {
+                write!(f, "{}/{}", ipv4, self.prefix - IPV4_MAPPED_PREFIX)
+            },
+            _ => write!(f, "{}/{}", ip, self.prefix),
+        }
+    }
+}
+
+consensus_encoding!(Subnet, network, prefix);
+
+/// Ban of a subnet
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BanEntry {
+    /// Time the ban was created
+    pub created: i64,
+    /// Time the ban expires
+    pub until: i64,
+}
+
+consensus_encoding!(BanEntry, created, until);
+
+/// Banned subnets
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct BanList {
+    entries: HashMap<Subnet, BanEntry>,
+}
+
+impl BanList {
+    /// Create an empty ban list
+    pub fn new() -> BanList {
+        BanList { entries: HashMap::new() }
+    }
+
+    /// Ban a subnet for a duration in seconds, extending an existing ban
+    pub fn ban(&mut self, subnet: Subnet, now: i64, duration: i64) {
+        let entry = self.entries.entry(subnet).or_insert(BanEntry { created: now, until: now });
+        if entry.until < now + duration {
+            entry.until = now + duration;
+        }
+    }
+
+    /// Lift the ban of a subnet, returning whether it was banned
+    pub fn unban(&mut self, subnet: &Subnet) -> bool {
+        self.entries.remove(subnet).is_some()
+    }
+
+    /// Check if an address is inside a banned subnet
+    pub fn is_banned(&self, address: &NetAddress, now: i64) -> bool {
+        self.entries.iter().any(|(subnet, entry)| entry.until > now && subnet.contains(address))
+    }
+
+    /// Iterate over the bans
+    pub fn iter(&self) -> impl Iterator<Item = (&Subnet, &BanEntry)> {
+        self.entries.iter()
+    }
+
+    /// Remove the expired bans
+    pub fn sweep(&mut self, now: i64) {
+        self.entries.retain(|_, entry| entry.until > now);
+    }
+
+    /// Write the ban list to a file, atomically replacing it
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut payload = serialize(&FILE_VERSION);
+        payload.extend(serialize(&(self.entries.len() as u64)));
+        for (subnet, entry) in self.entries.iter() {
+            payload.extend(serialize(subnet));
+            payload.extend(serialize(entry));
+        }
+
+        write_atomic(path, &payload)
+    }
+
+    /// Read a ban list written by `write`, dropping the expired bans
+    pub fn read<P: AsRef<Path>>(path: P, now: i64) -> Result<BanList, Error> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        let mut decoder = io::Cursor::new(&data[..]);
+
+        let version: u32 = Decodable::decode(&mut decoder)?;
+        if version != FILE_VERSION {
+            return Err(Error::ErrorCorrupted("unknown ban list version"));
+        }
+        let count: u64 = Decodable::decode(&mut decoder)?;
+        let mut banlist = BanList::new();
+        for _ in 0..count {
+            let subnet: Subnet = Decodable::decode(&mut decoder)?;
+            if subnet.prefix > 128 {
+                return Err(Error::ErrorCorrupted("invalid banned subnet"));
+            }
+            banlist.entries.insert(subnet, Decodable::decode(&mut decoder)?);
+        }
+        if decoder.position() as usize != data.len() {
+            return Err(Error::ErrorCorrupted("trailing data after ban list"));
+        }
+        banlist.sweep(now);
+        Ok(banlist)
+    }
+}
@@ -0,0 +1,197 @@
+/// Bloom filters loaded by peers (BIP37)
+///
+/// A peer loads a filter over the transaction ids, outpoints and script data
+/// pushes it is interested in; we then only relay the transactions matching it
+/// and serve blocks to it as `merkleblock` messages holding the branches of the
+/// matching transactions. Depending on its flags the filter grows with the
+/// outpoints of matched outputs, so that their spending matches as well.
+
+use std::cmp;
+
+use blockdata::block::Block;
+use blockdata::merkleblock::PartialMerkleTree;
+use blockdata::script::{Script, MAX_SCRIPT_ELEMENT_SIZE};
+use blockdata::transaction::{OutPoint, Transaction};
+use consensus::encode::serialize;
+use mempool::policy::{output_type, OutputType};
+use net::error::Error;
+use util::hash::murmur3_32;
+use wire::msgbloom::{FilterLoadMessage, MerkleBlockMessage};
+
+/// Maximum size in bytes of a filter
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000;
+
+/// Maximum number of hash functions of a filter
+pub const MAX_HASH_FUNCS: u32 = 50;
+
+/// Multiplier of the hash function number in the seed
+const SEED_MULTIPLIER: u32 = 0xfba4c795;
+
+/// How matching outputs update a filter
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum BloomFlags {
+    /// Never update the filter
+    None,
+    /// Add the outpoint of every matching output
+    All,
+    /// Add the outpoint of matching pay-to-pubkey and multisig outputs only
+    PubKeyOnly,
+}
+
+impl BloomFlags {
+    /// Return the protocol code of the flags
+    pub fn code(&self) -> u8 {
+        match *self {
+            BloomFlags::None       => 0,
+            BloomFlags::All        => 1,
+            BloomFlags::PubKeyOnly => 2,
+        }
+    }
+
+    /// Return the flags corresponding to a protocol code
+    pub fn from_code(code: u8) -> Option<BloomFlags> {
+        match code {
+            0 => Some(BloomFlags::None),
+            1 => Some(BloomFlags::All),
+            2 => Some(BloomFlags::PubKeyOnly),
+            _ => None,
+        }
+    }
+}
+
+/// Probabilistic set of byte strings
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BloomFilter {
+    data: Vec<u8>,
+    hash_funcs: u32,
+    tweak: u32,
+    flags: BloomFlags,
+}
+
+impl BloomFilter {
+    /// Create a filter sized for the given number of elements and false
+    /// positive rate, within the protocol limits
+    pub fn new(elements: usize, fp_rate: f64, tweak: u32, flags: BloomFlags) -> BloomFilter {
+        let ln2 = ::std::f64::consts::LN_2;
+        let bits = -1.0 / (ln2 * ln2) * elements.max(1) as f64 * fp_rate.ln();
+        let size = cmp::max(1, cmp::min((bits / 8.0) as usize, MAX_BLOOM_FILTER_SIZE));
+        let hash_funcs = cmp::max(1, cmp::min((size as f64 * 8.0 / elements.max(1) as f64 * ln2) as u32, MAX_HASH_FUNCS));
+        BloomFilter {
+            data:       vec![0; size],
+            hash_funcs: hash_funcs,
+            tweak:      tweak,
+            flags:      flags,
+        }
+    }
+
+    /// Create the filter loaded by a `filterload` message
+    pub fn from_msg(msg: &FilterLoadMessage) -> Result<BloomFilter, Error> {
+        if msg.filter.len() > MAX_BLOOM_FILTER_SIZE || msg.hash_funcs > MAX_HASH_FUNCS {
+            return Err(Error::ErrorBloomFilter("filter too large"));
+        }
+        let flags = BloomFlags::from_code(msg.flags).ok_or(Error::ErrorBloomFilter("unknown flags"))?;
+        Ok(BloomFilter {
+            data:       msg.filter.clone(),
+            hash_funcs: msg.hash_funcs,
+            tweak:      msg.tweak,
+            flags:      flags,
+        })
+    }
+
+    /// Return the `filterload` message loading this filter
+    pub fn to_msg(&self) -> FilterLoadMessage {
+        FilterLoadMessage::new_filter_load_msg(self.data.clone(), self.hash_funcs, self.tweak, self.flags.code())
+    }
+
+    /// Bit of the filter selected by a hash function for an element
+    fn bit_index(&self, hash_num: u32, element: &[u8]) -> usize {
+        let seed = hash_num.wrapping_mul(SEED_MULTIPLIER).wrapping_add(self.tweak);
+        murmur3_32(seed, element) as usize % (self.data.len() * 8)
+    }
+
+    /// Add an element to the filter
+    pub fn insert(&mut self, element: &[u8]) {
+        if self.data.is_empty() {
+            return;
+        }
+        for hash_num in 0..self.hash_funcs {
+            let index = self.bit_index(hash_num, element);
+            self.data[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    /// Check if an element may be in the filter
+    pub fn contains(&self, element: &[u8]) -> bool {
+        if self.data.is_empty() {
+            return false;
+        }
+        (0..self.hash_funcs).all(|hash_num| {
+            let index = self.bit_index(hash_num, element);
+            self.data[index / 8] & (1 << (index % 8)) != 0
+        })
+    }
+
+    /// Add an element received in a `filteradd` message
+    pub fn add(&mut self, element: &[u8]) -> Result<(), Error> {
+        if element.len() > MAX_SCRIPT_ELEMENT_SIZE {
+            return Err(Error::ErrorBloomFilter("element too large"));
+        }
+        self.insert(element);
+        Ok(())
+    }
+
+    /// Check if any data push of a script is in the filter
+    fn contains_push(&self, script: &Script) -> bool {
+        script.instructions().any(|instruction| !instruction.data.is_empty() && self.contains(instruction.data))
+    }
+
+    /// Check if a transaction matches the filter, adding the outpoints of its
+    /// matching outputs as the flags require
+    pub fn is_relevant_and_update(&mut self, tx: &Transaction) -> bool {
+        let txid = tx.txid();
+        let mut found = self.contains(&txid.into_inner());
+
+        for (vout, txout) in tx.output_ent.iter().enumerate() {
+            if !self.contains_push(&txout.pkscript) {
+                continue;
+            }
+            found = true;
+            let update = match self.flags {
+                BloomFlags::None       => false,
+                BloomFlags::All        => true,
+                BloomFlags::PubKeyOnly => match output_type(&txout.pkscript) {
+                    Some(OutputType::PubKey) | Some(OutputType::MultiSig) => true,
+                    _                                                     => false,
+                },
+            };
+            if update {
+                self.insert(&serialize(&OutPoint::new(txid, vout as u32)));
+            }
+        }
+        if found {
+            return true;
+        }
+
+        tx.input_ent.iter().any(|txin| {
+            self.contains(&serialize(&txin.previous_out)) || self.contains_push(&txin.sig_script)
+        })
+    }
+}
+
+/// Filter a block for a peer, returning the `merkleblock` and the matching
+/// transactions to send after it
+pub fn filtered_block(block: &Block, filter: &mut BloomFilter) -> (MerkleBlockMessage, Vec<Transaction>) {
+    let mut txids = Vec::with_capacity(block.txdata.len());
+    let mut matches = Vec::with_capacity(block.txdata.len());
+    let mut matched = Vec::new();
+    for tx in block.txdata.iter() {
+        let relevant = filter.is_relevant_and_update(tx);
+        txids.push(tx.txid());
+        matches.push(relevant);
+        if relevant {
+            matched.push(tx.clone());
+        }
+    }
+    let txn = PartialMerkleTree::from_txids(&txids, &matches);
+    (MerkleBlockMessage::new_merkle_block_msg(block.header, txn), matched)
+}
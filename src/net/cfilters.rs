@@ -0,0 +1,100 @@
+/// Serving compact block filters (BIP157)
+///
+/// Requests name a range of the chain by the height of its first block and the
+/// hash of its last one, which has to be a known block. Blocks of the range are
+/// found by walking back from the stop block, so ranges on a chain that lost
+/// the best chain status are served as well.
+
+use hashes::sha256d;
+
+use blockdata::filter::BASIC_FILTER_TYPE;
+use chain::headertree::HeaderTree;
+use net::error::Error;
+use store::filterindex::FilterIndex;
+use wire::msgcfilter::{GetCFiltersMessage, CFilterMessage, GetCFHeadersMessage, CFHeadersMessage,
+                       GetCFCheckptMessage, CFCheckptMessage};
+
+/// Maximum number of filters requested by a single `getcfilters`
+pub const MAX_GETCFILTERS_SIZE: u64 = 1_000;
+
+/// Maximum number of filter headers requested by a single `getcfheaders`
+pub const MAX_GETCFHEADERS_SIZE: u64 = 2_000;
+
+/// Distance between the filter headers of a `cfcheckpt`
+pub const CFCHECKPT_INTERVAL: u64 = 1_000;
+
+/// Return the hashes of the blocks from `start_height` up to the stop block
+fn block_range(tree: &HeaderTree, filter_type: u8, start_height: u32, stop_hash: &sha256d::Hash,
+               max_size: u64) -> Result<Vec<sha256d::Hash>, Error> {
+    if filter_type != BASIC_FILTER_TYPE {
+        return Err(Error::ErrorFilterRequest("unknown filter type"));
+    }
+    let stop = tree.get(stop_hash).ok_or(Error::ErrorFilterUnavailable("unknown stop hash"))?;
+    let start_height = start_height as u64;
+    if start_height > stop.height() {
+        return Err(Error::ErrorFilterRequest("start height above stop hash"));
+    }
+    if stop.height() - start_height >= max_size {
+        return Err(Error::ErrorFilterRequest("too many filters requested"));
+    }
+
+    let mut hashes = Vec::with_capacity((stop.height() - start_height + 1) as usize);
+    let mut current = stop;
+    loop {
+        hashes.push(current.hash);
+        if current.height() == start_height {
+            break;
+        }
+        current = tree.get(&current.header.prev_blockhash).ok_or(Error::ErrorFilterUnavailable("unknown block"))?;
+    }
+    hashes.reverse();
+    Ok(hashes)
+}
+
+/// Answer a `getcfilters` request with one `cfilter` per block
+pub fn get_cfilters(index: &FilterIndex, tree: &HeaderTree, msg: &GetCFiltersMessage) -> Result<Vec<CFilterMessage>, Error> {
+    let hashes = block_range(tree, msg.filter_type, msg.start_height, &msg.stop_hash, MAX_GETCFILTERS_SIZE)?;
+    let mut filters = Vec::with_capacity(hashes.len());
+    for hash in hashes.iter() {
+        let filter = index.get_filter(hash)?.ok_or(Error::ErrorFilterUnavailable("filter not indexed"))?;
+        filters.push(CFilterMessage::new_cfilter_msg(filter));
+    }
+    Ok(filters)
+}
+
+/// Answer a `getcfheaders` request
+pub fn get_cfheaders(index: &FilterIndex, tree: &HeaderTree, msg: &GetCFHeadersMessage) -> Result<CFHeadersMessage, Error> {
+    let hashes = block_range(tree, msg.filter_type, msg.start_height, &msg.stop_hash, MAX_GETCFHEADERS_SIZE)?;
+    let previous_filter_header = if msg.start_height == 0 {
+        sha256d::Hash::default()
+    } else {
+        let first = tree.get(&hashes[0]).ok_or(Error::ErrorFilterUnavailable("unknown block"))?;
+        index.get_filter_header(&first.header.prev_blockhash)?.ok_or(Error::ErrorFilterUnavailable("filter not indexed"))?
+    };
+
+    let mut filter_hashes = Vec::with_capacity(hashes.len());
+    for hash in hashes.iter() {
+        let filter = index.get_filter(hash)?.ok_or(Error::ErrorFilterUnavailable("filter not indexed"))?;
+        filter_hashes.push(filter.filter_hash());
+    }
+    Ok(CFHeadersMessage::new_cfheaders_msg(msg.filter_type, msg.stop_hash, previous_filter_header, filter_hashes))
+}
+
+/// Answer a `getcfcheckpt` request with the filter headers at every
+/// checkpoint interval of the chain ending with the stop block
+pub fn get_cfcheckpt(index: &FilterIndex, tree: &HeaderTree, msg: &GetCFCheckptMessage) -> Result<CFCheckptMessage, Error> {
+    if msg.filter_type != BASIC_FILTER_TYPE {
+        return Err(Error::ErrorFilterRequest("unknown filter type"));
+    }
+    let stop = tree.get(&msg.stop_hash).ok_or(Error::ErrorFilterUnavailable("unknown stop hash"))?;
+
+    let mut filter_headers = Vec::new();
+    let mut height = CFCHECKPT_INTERVAL;
+    while height <= stop.height() {
+        let block = tree.ancestor(&msg.stop_hash, height).ok_or(Error::ErrorFilterUnavailable("unknown block"))?;
+        let header = index.get_filter_header(&block.hash)?.ok_or(Error::ErrorFilterUnavailable("filter not indexed"))?;
+        filter_headers.push(header);
+        height += CFCHECKPT_INTERVAL;
+    }
+    Ok(CFCheckptMessage::new_cfcheckpt_msg(msg.filter_type, msg.stop_hash, filter_headers))
+}
@@ -0,0 +1,144 @@
+/// Compact block reconstruction (BIP152)
+///
+/// A received compact block is turned into a partial block: prefilled
+/// transactions are placed at their positions and the others are looked up
+/// in the pool by short ID. Transactions that are missing, or whose short ID
+/// matches several pool transactions, are requested with `getblocktxn`. The
+/// completed block is checked against the merkle root of its header and its
+/// witness commitment, since a short ID collision may have put a wrong
+/// transaction in; in that case the full block has to be requested instead.
+
+use std::collections::HashMap;
+
+use hashes::sha256d;
+
+use blockdata::block::Block;
+use blockdata::transaction::Transaction;
+use mempool::pool::Mempool;
+use net::error::Error;
+use util::constants::SHORT_IDS_BLOCKS_VERSION;
+use util::hash::BlockchainHash;
+use wire::msgcmpct::{CmpctBlockMessage, GetBlockTxnMessage, BlockTxnMessage, SendCmpctMessage, SHORT_ID_SIZE};
+
+/// Block being reconstructed from a compact block
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PartialBlock {
+    block_hash: sha256d::Hash,
+    cmpct: CmpctBlockMessage,
+    /// Transactions found so far, by position
+    slots: Vec<Option<Transaction>>,
+}
+
+impl PartialBlock {
+    /// Start reconstructing a compact block, filling what the pool has
+    pub fn new(cmpct: CmpctBlockMessage, mempool: &Mempool) -> Result<PartialBlock, Error> {
+        let count = cmpct.tx_count();
+        if cmpct.short_ids.is_empty() && cmpct.prefilled.is_empty() {
+            return Err(Error::ErrorCompactBlock("empty block"));
+        }
+        let mut slots: Vec<Option<Transaction>> = vec![None; count];
+        for prefilled in cmpct.prefilled.iter() {
+            if prefilled.index >= count {
+                return Err(Error::ErrorCompactBlock("prefilled index out of range"));
+            }
+            slots[prefilled.index] = Some(prefilled.tx.clone());
+        }
+
+        // Positions left to the short IDs, in order
+        let mut positions: HashMap<[u8; SHORT_ID_SIZE], usize> = HashMap::with_capacity(cmpct.short_ids.len());
+        let mut free = (0..count).filter(|index| slots[*index].is_none());
+        for short_id in cmpct.short_ids.iter() {
+            let index = free.next().ok_or(Error::ErrorCompactBlock("short IDs do not fit the block"))?;
+            if positions.insert(*short_id, index).is_some() {
+                return Err(Error::ErrorCompactBlock("duplicate short ID"));
+            }
+        }
+
+        // Pool transactions matching a short ID; an ambiguous match stays missing
+        let key = cmpct.short_id_key();
+        let mut matches: HashMap<usize, usize> = HashMap::new();
+        for entry in mempool.iter() {
+            if let Some(index) = positions.get(&key.short_id(&entry.wtxid)) {
+                *matches.entry(*index).or_insert(0) += 1;
+                slots[*index] = Some(entry.tx.clone());
+            }
+        }
+        for (index, found) in matches.iter() {
+            if *found > 1 {
+                slots[*index] = None;
+            }
+        }
+
+        Ok(PartialBlock {
+            block_hash: cmpct.header.blockchain_hash(),
+            cmpct:      cmpct,
+            slots:      slots,
+        })
+    }
+
+    /// Hash of the block being reconstructed
+    pub fn block_hash(&self) -> sha256d::Hash {
+        self.block_hash
+    }
+
+    /// Positions of the transactions still missing
+    pub fn missing(&self) -> Vec<usize> {
+        (0..self.slots.len()).filter(|index| self.slots[*index].is_none()).collect()
+    }
+
+    /// Request for the missing transactions, `None` when nothing is missing
+    pub fn request(&self) -> Option<GetBlockTxnMessage> {
+        let missing = self.missing();
+        if missing.is_empty() {
+            return None;
+        }
+        Some(GetBlockTxnMessage::new_get_block_txn_msg(self.block_hash, missing))
+    }
+
+    /// Fill the missing transactions with those received in a `blocktxn`
+    /// answering our request
+    pub fn fill(&mut self, msg: BlockTxnMessage) -> Result<(), Error> {
+        let missing = self.missing();
+        if msg.block_hash != self.block_hash || msg.transactions.len() != missing.len() {
+            return Err(Error::ErrorBlockTransactions);
+        }
+        for (index, tx) in missing.into_iter().zip(msg.transactions.into_iter()) {
+            self.slots[index] = Some(tx);
+        }
+        Ok(())
+    }
+
+    /// Return the complete block, `None` while transactions are missing
+    pub fn block(&self) -> Result<Option<Block>, Error> {
+        if self.slots.iter().any(|slot| slot.is_none()) {
+            return Ok(None);
+        }
+        let block = Block {
+            header: self.cmpct.header,
+            txdata: self.slots.iter().map(|slot| slot.clone().unwrap()).collect(),
+        };
+        if !block.check_merkle_root() || !block.check_witness_commit() {
+            return Err(Error::ErrorReconstructedBlock);
+        }
+        Ok(Some(block))
+    }
+}
+
+/// Answer a `getblocktxn` request from a block we have
+pub fn block_transactions(block: &Block, request: &GetBlockTxnMessage) -> Result<BlockTxnMessage, Error> {
+    let mut transactions = Vec::with_capacity(request.indexes.len());
+    for index in request.indexes.iter() {
+        let tx = block.txdata.get(*index).ok_or(Error::ErrorBlockTransactions)?;
+        transactions.push(tx.clone());
+    }
+    Ok(BlockTxnMessage::new_block_txn_msg(request.block_hash, transactions))
+}
+
+/// `sendcmpct` message to send a peer after its `version` message; peers
+/// older than compact blocks would take it for an unknown message
+pub fn send_cmpct_message(protocol: u32, announce: bool) -> Option<SendCmpctMessage> {
+    if protocol < SHORT_IDS_BLOCKS_VERSION {
+        return None;
+    }
+    Some(SendCmpctMessage::new_send_cmpct_msg(announce))
+}
@@ -0,0 +1,74 @@
+/// Implementation of errors used in net namespace
+///
+use std::{error, fmt};
+
+use store::error::Error as StoreError;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Compact block is inconsistent and cannot be reconstructed
+    ErrorCompactBlock(&'static str),
+    /// Transactions received for a compact block do not match the request
+    ErrorBlockTransactions,
+    /// Reconstructed block does not match its header, short IDs collided
+    ErrorReconstructedBlock,
+    /// Request for filters that is invalid
+    ErrorFilterRequest(&'static str),
+    /// Request for filters of blocks we do not know or did not index yet
+    ErrorFilterUnavailable(&'static str),
+    /// Filters or filter headers received without being requested
+    ErrorUnexpectedFilters,
+    /// Filter or filter header does not match the agreed filter header chain
+    ErrorFilterHeaderMismatch,
+    /// Bloom filter loaded by a peer exceeds the protocol limits
+    ErrorBloomFilter(&'static str),
+    /// Connection refused by the peer manager
+    ErrorConnectionRefused(&'static str),
+    /// Reading a local index failed
+    ErrorStore(StoreError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::ErrorCompactBlock(ref reason)      => write!(f, "{}: {}", error::Error::description(self), reason),
+            Error::ErrorFilterRequest(ref reason)     => write!(f, "{}: {}", error::Error::description(self), reason),
+            Error::ErrorFilterUnavailable(ref reason) => write!(f, "{}: {}", error::Error::description(self), reason),
+            Error::ErrorBloomFilter(ref reason)       => write!(f, "{}: {}", error::Error::description(self), reason),
+            Error::ErrorConnectionRefused(ref reason) => write!(f, "{}: {}", error::Error::description(self), reason),
+            Error::ErrorStore(ref e)                  => write!(f, "{}: {}", error::Error::description(self), e),
+            _                                         => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::ErrorStore(ref e) => Some(e),
+            _                        => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::ErrorCompactBlock(..)      => "invalid compact block",
+            Error::ErrorBlockTransactions     => "block transactions do not match the request",
+            Error::ErrorReconstructedBlock    => "reconstructed block does not match its header",
+            Error::ErrorFilterRequest(..)     => "invalid filter request",
+            Error::ErrorFilterUnavailable(..) => "filters not available",
+            Error::ErrorUnexpectedFilters     => "unexpected filters",
+            Error::ErrorFilterHeaderMismatch  => "filter does not match the filter header chain",
+            Error::ErrorBloomFilter(..)       => "invalid bloom filter",
+            Error::ErrorConnectionRefused(..) => "connection refused",
+            Error::ErrorStore(..)             => "store error",
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<StoreError> for Error {
+    fn from(e: StoreError) -> Error {
+        Error::ErrorStore(e)
+    }
+}
@@ -0,0 +1,451 @@
+/// Light client scanning the chain with compact block filters (BIP157)
+///
+/// Headers are downloaded by `HeaderSync`; this engine follows its best chain
+/// and fetches, for every block, the filter header and then the filter:
+///
+/// 1. every peer is asked for the filter header checkpoints of the best chain
+///    and the checkpoints a majority of at least `MIN_FILTER_PEERS` peers
+///    agree on are trusted,
+/// 2. filter headers are downloaded between checkpoints from a single peer
+///    and checked against the checkpoint closing the range; past the last
+///    checkpoint the same range is asked from `MIN_FILTER_PEERS` peers and
+///    only accepted when their answers agree,
+/// 3. filters are downloaded in order, checked against their filter header
+///    and matched locally against the watched scripts,
+/// 4. matching blocks are requested with `getdata` and scanned for the
+///    transactions paying to a watched script or spending an output that did.
+///
+/// When the answers past the last checkpoint disagree, the first block they
+/// disagree on is downloaded together with the filter of every peer: a filter
+/// not matching the filter hash its peer claimed, or missing an output script
+/// of the block, proves that peer wrong. The spent scripts are not available
+/// to a light client, so when this leaves no single answer the peers involved
+/// stop being used for filter headers.
+///
+/// A peer answering with a filter header or filter that does not match the
+/// agreed chain is reported through an error so the caller can drop it.
+
+use std::collections::{HashMap, HashSet};
+
+use hashes::sha256d;
+
+use blockdata::block::Block;
+use blockdata::filter::{filter_header, BlockFilter, BASIC_FILTER_TYPE};
+use blockdata::script::Script;
+use blockdata::transaction::{OutPoint, Transaction};
+use chain::headersync::PeerId;
+use chain::headertree::{HeaderTree, Reorg};
+use chain::locator::HeaderChain;
+use net::cfilters::{CFCHECKPT_INTERVAL, MAX_GETCFILTERS_SIZE};
+use net::error::Error;
+use util::hash::BlockchainHash;
+use wire::msgcfilter::{GetCFiltersMessage, CFilterMessage, GetCFHeadersMessage, CFHeadersMessage,
+                       GetCFCheckptMessage, CFCheckptMessage};
+use wire::msg::Message;
+use wire::msginv::{GetDataMessage, Inventory, InvType};
+
+/// Minimum number of peers that have to agree on filter headers
+pub const MIN_FILTER_PEERS: usize = 2;
+
+/// Filter data of a block of the best chain
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct FilterEntry {
+    block_hash: sha256d::Hash,
+    filter_hash: sha256d::Hash,
+    filter_header: sha256d::Hash,
+}
+
+/// Disagreement on filter headers, settled with the block the answers first
+/// disagree on
+struct Conflict {
+    /// Height and hash of the block
+    height: u64,
+    block_hash: sha256d::Hash,
+    /// Filter hash every answering peer claimed for the block
+    claims: HashMap<PeerId, sha256d::Hash>,
+    /// Filters received for the block
+    filters: HashMap<PeerId, BlockFilter>,
+    block: Option<Block>,
+    /// Block and filters were requested
+    requested: bool,
+}
+
+/// Compact filter light client engine
+pub struct LightClient {
+    /// Output scripts of the wallet
+    scripts: HashSet<Script>,
+    /// Outputs paying to the wallet, whose spending is relevant too
+    outpoints: HashSet<OutPoint>,
+    peers: HashSet<PeerId>,
+    /// Latest checkpoints received from every peer
+    checkpoints: HashMap<PeerId, CFCheckptMessage>,
+    /// Checkpoints agreed on, the first one at height `CFCHECKPT_INTERVAL`
+    verified_checkpoints: Vec<sha256d::Hash>,
+    /// Filter entries of the best chain by height, as far as verified
+    chain: Vec<FilterEntry>,
+    /// Stop hash of the outstanding `getcfheaders` and the answers received so far
+    headers_request: Option<sha256d::Hash>,
+    headers_answers: HashMap<PeerId, CFHeadersMessage>,
+    /// Disagreement between the answers of the outstanding `getcfheaders`
+    conflict: Option<Conflict>,
+    /// Stop hash of the outstanding `getcfilters`
+    filters_request: Option<sha256d::Hash>,
+    /// Height of the next filter to match
+    scanned_height: u64,
+    /// Matching blocks requested with `getdata`
+    pending_blocks: HashSet<sha256d::Hash>,
+}
+
+impl LightClient {
+    /// Create an engine watching the given output scripts
+    pub fn new(scripts: Vec<Script>) -> LightClient {
+        LightClient {
+            scripts:              scripts.into_iter().collect(),
+            outpoints:            HashSet::new(),
+            peers:                HashSet::new(),
+            checkpoints:          HashMap::new(),
+            verified_checkpoints: Vec::new(),
+            chain:                Vec::new(),
+            headers_request:      None,
+            headers_answers:      HashMap::new(),
+            conflict:             None,
+            filters_request:      None,
+            scanned_height:       0,
+            pending_blocks:       HashSet::new(),
+        }
+    }
+
+    /// Watch an additional output script; only blocks not scanned yet are
+    /// matched against it
+    pub fn add_script(&mut self, script: Script) {
+        self.scripts.insert(script);
+    }
+
+    /// Start using a peer serving compact filters
+    pub fn add_peer(&mut self, peer: PeerId) {
+        self.peers.insert(peer);
+    }
+
+    /// Stop using a disconnected peer, dropping its outstanding requests
+    pub fn remove_peer(&mut self, peer: PeerId) {
+        self.peers.remove(&peer);
+        self.checkpoints.remove(&peer);
+        self.headers_answers.remove(&peer);
+        self.headers_request = None;
+        self.conflict = None;
+        self.filters_request = None;
+    }
+
+    /// Height of the next block whose filter has to be matched
+    pub fn scanned_height(&self) -> u64 {
+        self.scanned_height
+    }
+
+    /// Check if every block of the best chain has been scanned
+    pub fn is_synced(&self, tree: &HeaderTree) -> bool {
+        self.scanned_height > tree.best_height() && self.pending_blocks.is_empty()
+    }
+
+    /// Request for the checkpoints of the best chain, to send to every peer
+    pub fn checkpoint_request(&self, tree: &HeaderTree) -> GetCFCheckptMessage {
+        GetCFCheckptMessage::new_get_cfcheckpt_msg(BASIC_FILTER_TYPE, tree.best_tip().hash)
+    }
+
+    /// Process a `cfcheckpt` message; once enough peers answered for the same
+    /// stop block, the checkpoints of the majority are trusted and the peers
+    /// disagreeing with it are returned
+    pub fn on_cfcheckpt(&mut self, peer: PeerId, msg: CFCheckptMessage) -> Vec<PeerId> {
+        if msg.filter_type != BASIC_FILTER_TYPE {
+            return Vec::new();
+        }
+        self.checkpoints.insert(peer, msg.clone());
+
+        let answers: Vec<(&PeerId, &CFCheckptMessage)> = self.checkpoints.iter()
+            .filter(|&(_, answer)| answer.stop_hash == msg.stop_hash)
+            .collect();
+        if answers.len() < MIN_FILTER_PEERS {
+            return Vec::new();
+        }
+        let majority = answers.iter()
+            .map(|&(_, answer)| &answer.filter_headers)
+            .find(|headers| answers.iter().filter(|&&(_, answer)| answer.filter_headers == **headers).count() * 2 > answers.len())
+            .cloned();
+        let majority = match majority {
+            Some(majority) => majority,
+            None           => return Vec::new(),
+        };
+
+        // Checkpoints below what is already verified can only be extended
+        let agree = majority.iter().zip(self.verified_checkpoints.iter()).all(|(a, b)| a == b);
+        if agree && majority.len() > self.verified_checkpoints.len() {
+            self.verified_checkpoints = majority.clone();
+        }
+        answers.iter()
+            .filter(|&&(_, answer)| answer.filter_headers != majority)
+            .map(|&(peer, _)| *peer)
+            .collect()
+    }
+
+    /// Next `getcfheaders` to send and the peers to send it to, if no request is
+    /// outstanding. Ranges end at checkpoints while there are some.
+    pub fn next_cfheaders_request(&mut self, tree: &HeaderTree) -> Option<(Vec<PeerId>, GetCFHeadersMessage)> {
+        if self.headers_request.is_some() || self.peers.is_empty() {
+            return None;
+        }
+        let start = self.chain.len() as u64;
+        let tip = tree.best_tip();
+        if start > tip.height() {
+            return None;
+        }
+
+        let checkpointed = self.verified_checkpoints.len() as u64 * CFCHECKPT_INTERVAL;
+        let (stop_height, peers) = if start <= checkpointed && checkpointed > 0 {
+            let next_checkpoint = (start / CFCHECKPT_INTERVAL + 1) * CFCHECKPT_INTERVAL;
+            (next_checkpoint.min(checkpointed), vec![*self.peers.iter().next().unwrap()])
+        } else {
+            if self.peers.len() < MIN_FILTER_PEERS {
+                return None;
+            }
+            let stop_height = (start + CFCHECKPT_INTERVAL - 1).min(tip.height());
+            (stop_height, self.peers.iter().take(MIN_FILTER_PEERS).cloned().collect())
+        };
+
+        let stop = tree.ancestor(&tip.hash, stop_height)?;
+        self.headers_request = Some(stop.hash);
+        self.headers_answers.clear();
+        Some((peers, GetCFHeadersMessage::new_get_cfheaders_msg(BASIC_FILTER_TYPE, start as u32, stop.hash)))
+    }
+
+    /// Process a `cfheaders` message answering our request
+    pub fn on_cfheaders(&mut self, peer: PeerId, msg: CFHeadersMessage, tree: &HeaderTree) -> Result<(), Error> {
+        if self.headers_request != Some(msg.stop_hash) || msg.filter_type != BASIC_FILTER_TYPE || self.conflict.is_some() {
+            return Err(Error::ErrorUnexpectedFilters);
+        }
+        let stop = tree.get(&msg.stop_hash).ok_or(Error::ErrorUnexpectedFilters)?;
+        let start = self.chain.len() as u64;
+        if msg.filter_hashes.is_empty() || stop.height() + 1 != start + msg.filter_hashes.len() as u64 {
+            return Err(Error::ErrorUnexpectedFilters);
+        }
+        let previous = self.chain.last().map_or_else(Default::default, |entry| entry.filter_header);
+        if msg.previous_filter_header != previous {
+            return Err(Error::ErrorFilterHeaderMismatch);
+        }
+
+        // Ranges closed by a checkpoint are trusted once they reach it
+        let checkpoint = if stop.height() > 0 && stop.height() % CFCHECKPT_INTERVAL == 0 {
+            self.verified_checkpoints.get((stop.height() / CFCHECKPT_INTERVAL - 1) as usize).cloned()
+        } else {
+            None
+        };
+        let last_header = msg.filter_hashes.iter().fold(previous, |previous, hash| filter_header(hash, &previous));
+        match checkpoint {
+            Some(checkpoint) if checkpoint != last_header => return Err(Error::ErrorFilterHeaderMismatch),
+            Some(_)                                       => {},
+            None                                          => {
+                self.headers_answers.insert(peer, msg.clone());
+                if self.headers_answers.len() < MIN_FILTER_PEERS {
+                    return Ok(());
+                }
+                // Answers cover the same range, the first differing hash
+                // is where the headers start to disagree
+                let differing = (0..msg.filter_hashes.len())
+                    .find(|&offset| self.headers_answers.values().any(|answer| answer.filter_hashes[offset] != msg.filter_hashes[offset]));
+                if let Some(offset) = differing {
+                    let height = start + offset as u64;
+                    let block = tree.ancestor(&stop.hash, height).ok_or(Error::ErrorUnexpectedFilters)?;
+                    self.conflict = Some(Conflict {
+                        height:     height,
+                        block_hash: block.hash,
+                        claims:     self.headers_answers.iter().map(|(peer, answer)| (*peer, answer.filter_hashes[offset])).collect(),
+                        filters:    HashMap::new(),
+                        block:      None,
+                        requested:  false,
+                    });
+                    return Ok(());
+                }
+            },
+        }
+
+        self.extend_chain(&msg.filter_hashes, &stop.hash, tree)
+    }
+
+    /// Append the filter hashes of the blocks following the verified chain
+    /// towards a block, and close the outstanding `getcfheaders`
+    fn extend_chain(&mut self, filter_hashes: &[sha256d::Hash], stop_hash: &sha256d::Hash, tree: &HeaderTree) -> Result<(), Error> {
+        let start = self.chain.len() as u64;
+        let mut header = self.chain.last().map_or_else(Default::default, |entry| entry.filter_header);
+        for (offset, filter_hash) in filter_hashes.iter().enumerate() {
+            let block = tree.ancestor(stop_hash, start + offset as u64).ok_or(Error::ErrorUnexpectedFilters)?;
+            header = filter_header(filter_hash, &header);
+            self.chain.push(FilterEntry {
+                block_hash:    block.hash,
+                filter_hash:   *filter_hash,
+                filter_header: header,
+            });
+        }
+        self.headers_request = None;
+        self.headers_answers.clear();
+        Ok(())
+    }
+
+    /// Requests for the block and the filters settling a disagreement on
+    /// filter headers, if not sent yet
+    pub fn next_conflict_requests(&mut self) -> Vec<(PeerId, Message)> {
+        let conflict = match self.conflict {
+            Some(ref mut conflict) if !conflict.requested => conflict,
+            _                                             => return Vec::new(),
+        };
+        conflict.requested = true;
+
+        let mut requests: Vec<(PeerId, Message)> = conflict.claims.keys()
+            .map(|peer| {
+                let request = GetCFiltersMessage::new_get_cfilters_msg(BASIC_FILTER_TYPE, conflict.height as u32, conflict.block_hash);
+                (*peer, Message::GetCFiltersMessage(request))
+            })
+            .collect();
+        let inventory = Inventory::new_inventory(InvType::WitnessBlock, conflict.block_hash);
+        let peer = *conflict.claims.keys().next().unwrap();
+        requests.push((peer, Message::GetDataMessage(GetDataMessage::new_get_data_msg(vec![inventory]))));
+        requests
+    }
+
+    /// Settle a disagreement on filter headers once the block and the filters
+    /// of every peer involved arrived, returning the peers proven wrong. The
+    /// verified chain is extended up to the block when a single answer is left.
+    pub fn resolve_conflict(&mut self, tree: &HeaderTree) -> Result<Vec<PeerId>, Error> {
+        let ready = match self.conflict {
+            Some(ref conflict) => conflict.block.is_some() && conflict.filters.len() == conflict.claims.len(),
+            None               => false,
+        };
+        if !ready {
+            return Ok(Vec::new());
+        }
+        let mut conflict = self.conflict.take().unwrap();
+        let block = conflict.block.take().unwrap();
+
+        // Every output script of the block has to be in its filter
+        let outputs: HashSet<&[u8]> = block.txdata.iter()
+            .flat_map(|tx| tx.output_ent.iter())
+            .filter(|txout| !txout.pkscript.is_empty() && !txout.pkscript.is_op_return())
+            .map(|txout| txout.pkscript.as_bytes())
+            .collect();
+        let (wrong, right): (Vec<(&PeerId, &sha256d::Hash)>, Vec<(&PeerId, &sha256d::Hash)>) = conflict.claims.iter()
+            .partition(|&(peer, claim)| {
+                let filter = &conflict.filters[peer];
+                filter.filter_hash() != *claim
+                    || !outputs.iter().all(|script| filter.match_any(&[*script]).unwrap_or(false))
+            });
+        let wrong: Vec<PeerId> = wrong.into_iter().map(|(peer, _)| *peer).collect();
+
+        let single = right.first().map_or(false, |&(_, first)| right.iter().all(|&(_, claim)| claim == first));
+        if single {
+            // Hashes past the block are asked again from other peers
+            let answer = self.headers_answers[right[0].0].clone();
+            let count = (conflict.height + 1) as usize - self.chain.len();
+            self.extend_chain(&answer.filter_hashes[..count], &answer.stop_hash, tree)?;
+        } else {
+            for &(peer, _) in right.iter() {
+                self.peers.remove(peer);
+            }
+            self.headers_request = None;
+            self.headers_answers.clear();
+        }
+        Ok(wrong)
+    }
+
+    /// Next `getcfilters` to send for blocks whose filter header is known,
+    /// and the peer to send it to, if no request is outstanding
+    pub fn next_cfilters_request(&mut self) -> Option<(PeerId, GetCFiltersMessage)> {
+        if self.filters_request.is_some() || self.scanned_height >= self.chain.len() as u64 {
+            return None;
+        }
+        let peer = *self.peers.iter().next()?;
+        let stop_height = (self.scanned_height + MAX_GETCFILTERS_SIZE - 1).min(self.chain.len() as u64 - 1);
+        let stop_hash = self.chain[stop_height as usize].block_hash;
+        self.filters_request = Some(stop_hash);
+        Some((peer, GetCFiltersMessage::new_get_cfilters_msg(BASIC_FILTER_TYPE, self.scanned_height as u32, stop_hash)))
+    }
+
+    /// Process a `cfilter` message, the filters of a range arriving in order;
+    /// a matching block is requested
+    pub fn on_cfilter(&mut self, peer: PeerId, msg: &CFilterMessage) -> Result<Option<GetDataMessage>, Error> {
+        if let Some(ref mut conflict) = self.conflict {
+            if conflict.block_hash == msg.block_hash && conflict.claims.contains_key(&peer) {
+                conflict.filters.insert(peer, msg.block_filter());
+                return Ok(None);
+            }
+        }
+        let entry = match self.chain.get(self.scanned_height as usize) {
+            Some(entry) if self.filters_request.is_some() && entry.block_hash == msg.block_hash => *entry,
+            _                                                                                  => return Err(Error::ErrorUnexpectedFilters),
+        };
+        let filter = msg.block_filter();
+        if filter.filter_hash() != entry.filter_hash {
+            return Err(Error::ErrorFilterHeaderMismatch);
+        }
+
+        self.scanned_height += 1;
+        if self.filters_request == Some(entry.block_hash) {
+            self.filters_request = None;
+        }
+        let scripts: Vec<&[u8]> = self.scripts.iter().map(|script| script.as_bytes()).collect();
+        if !filter.match_any(&scripts).map_err(|_| Error::ErrorFilterHeaderMismatch)? {
+            return Ok(None);
+        }
+        self.pending_blocks.insert(entry.block_hash);
+        let inventory = Inventory::new_inventory(InvType::WitnessBlock, entry.block_hash);
+        Ok(Some(GetDataMessage::new_get_data_msg(vec![inventory])))
+    }
+
+    /// Process a block requested after its filter matched, returning the
+    /// transactions relevant to the wallet; `None` for blocks not requested
+    pub fn on_block(&mut self, block: &Block) -> Option<Vec<Transaction>> {
+        if let Some(ref mut conflict) = self.conflict {
+            if conflict.block.is_none() && conflict.block_hash == block.blockchain_hash() && block.check_merkle_root() {
+                conflict.block = Some(block.clone());
+                return None;
+            }
+        }
+        if !self.pending_blocks.remove(&block.blockchain_hash()) || !block.check_merkle_root() {
+            return None;
+        }
+        let mut relevant = Vec::new();
+        for tx in block.txdata.iter() {
+            let spends = tx.input_ent.iter().any(|txin| self.outpoints.remove(&txin.previous_out));
+            let mut pays = false;
+            for (vout, txout) in tx.output_ent.iter().enumerate() {
+                if self.scripts.contains(&txout.pkscript) {
+                    self.outpoints.insert(OutPoint::new(tx.txid(), vout as u32));
+                    pays = true;
+                }
+            }
+            if spends || pays {
+                relevant.push(tx.clone());
+            }
+        }
+        Some(relevant)
+    }
+
+    /// Follow a change of the best chain, dropping the filter data of the
+    /// disconnected blocks so they are fetched again for the new chain
+    pub fn on_reorg(&mut self, reorg: &Reorg, tree: &HeaderTree) {
+        // Entries are kept up to the fork point, below the lowest disconnected block
+        let kept = match reorg.disconnected.last().and_then(|hash| tree.get(hash)) {
+            Some(lowest) => lowest.height(),
+            None         => return,
+        };
+        self.chain.truncate(kept as usize);
+        self.verified_checkpoints.truncate((kept.saturating_sub(1) / CFCHECKPT_INTERVAL) as usize);
+        self.checkpoints.clear();
+        if self.scanned_height > kept {
+            self.scanned_height = kept;
+        }
+        for hash in reorg.disconnected.iter() {
+            self.pending_blocks.remove(hash);
+        }
+        self.headers_request = None;
+        self.headers_answers.clear();
+        self.conflict = None;
+        self.filters_request = None;
+    }
+}
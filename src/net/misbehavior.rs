@@ -0,0 +1,60 @@
+/// Misbehavior scores of protocol violations
+///
+/// Every error caused by data a peer sent us carries a score; the peer
+/// manager adds it to the score of the peer and disconnects and discourages
+/// the peer once it reaches `DISCOURAGEMENT_THRESHOLD`. Errors that honest
+/// peers may trigger, through races or on our side, score nothing.
+
+use blockdata::Error::BlockTimeTooNewError;
+use chain::error::Error as ChainError;
+use consensus::encode;
+use net::error::Error as NetError;
+
+/// Score at which a peer is disconnected and discouraged
+pub const DISCOURAGEMENT_THRESHOLD: u32 = 100;
+
+/// Misbehavior score of an error caused by a peer
+pub trait Misbehavior {
+    /// Return the score the peer sending the offending data earns
+    fn misbehavior_score(&self) -> u32;
+}
+
+/// Every message is checksummed, undecodable payloads are never accidental
+impl Misbehavior for encode::Error {
+    fn misbehavior_score(&self) -> u32 {
+        DISCOURAGEMENT_THRESHOLD
+    }
+}
+
+impl Misbehavior for ChainError {
+    fn misbehavior_score(&self) -> u32 {
+        match *self {
+            // Headers may cross a reorganization of the peer's chain
+            ChainError::ErrorUnconnectedHeader                   => 10,
+            ChainError::ErrorNonContinuousHeaders                => 20,
+            // Our clock may be behind the peer's
+            ChainError::ErrorInvalidHeader(BlockTimeTooNewError) => 0,
+            ChainError::ErrorInvalidHeader(..)                   => DISCOURAGEMENT_THRESHOLD,
+            ChainError::ErrorStore(..)                           => 0,
+        }
+    }
+}
+
+impl Misbehavior for NetError {
+    fn misbehavior_score(&self) -> u32 {
+        match *self {
+            NetError::ErrorCompactBlock(..)      => DISCOURAGEMENT_THRESHOLD,
+            NetError::ErrorBlockTransactions     => DISCOURAGEMENT_THRESHOLD,
+            // Short IDs of distinct transactions may collide
+            NetError::ErrorReconstructedBlock    => 0,
+            NetError::ErrorFilterRequest(..)     => DISCOURAGEMENT_THRESHOLD,
+            // The peer may be ahead of us or requesting during our sync
+            NetError::ErrorFilterUnavailable(..) => 0,
+            NetError::ErrorUnexpectedFilters     => 20,
+            NetError::ErrorFilterHeaderMismatch  => DISCOURAGEMENT_THRESHOLD,
+            NetError::ErrorBloomFilter(..)       => DISCOURAGEMENT_THRESHOLD,
+            NetError::ErrorConnectionRefused(..) => 0,
+            NetError::ErrorStore(..)             => 0,
+        }
+    }
+}
@@ -0,0 +1,398 @@
+/// Peer manager
+///
+/// Keeps a fixed number of outbound connections open, most of them relaying
+/// blocks and transactions, a few relaying blocks only so that our topology
+/// is harder to infer from transaction relay. Outbound addresses come from the
+/// address manager, at most one per network group. An address we failed to
+/// connect to is retried after a delay doubling with every failure.
+///
+/// Inbound connections are accepted up to a limit; past it an inbound peer is
+/// evicted to make room, after protecting the peers an attacker would find the
+/// hardest to imitate: those from distinct network groups, with the lowest
+/// latency, that recently relayed us transactions or blocks, or that have been
+/// connected the longest. The evicted peer comes from the network group with
+/// the most remaining connections.
+///
+/// Peers earn misbehavior scores for protocol violations; reaching the
+/// threshold gets them disconnected and their address discouraged for a day.
+/// Banned subnets are refused altogether.
+///
+/// The manager does no I/O: callers open and close sockets as told and report
+/// the outcome.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+
+use chain::headersync::PeerId;
+use net::addrman::AddrMan;
+use net::banlist::{BanList, Subnet};
+use net::error::Error;
+use net::misbehavior::DISCOURAGEMENT_THRESHOLD;
+use util::hash::siphash24;
+use wire::netaddress::NetAddress;
+
+/// Number of outbound connections relaying blocks and transactions
+pub const MAX_OUTBOUND_FULL_RELAY: usize = 8;
+
+/// Number of outbound connections relaying blocks only
+pub const MAX_BLOCK_RELAY_ONLY: usize = 2;
+
+/// Default number of inbound connections
+pub const DEFAULT_MAX_INBOUND: usize = 115;
+
+/// Time in seconds a misbehaving address stays discouraged
+pub const DISCOURAGEMENT_DURATION: i64 = 24 * 60 * 60;
+
+/// Delay in seconds before retrying an address after a first failure
+const INITIAL_RETRY_DELAY: i64 = 60;
+
+/// Longest delay in seconds before retrying an address
+const MAX_RETRY_DELAY: i64 = 60 * 60;
+
+/// Number of addresses drawn from the address manager for one connection
+const MAX_SELECT_TRIES: usize = 100;
+
+/// Number of draws during which recently tried addresses are skipped
+const RECENT_TRY_SKIP: usize = 30;
+
+/// Kind of connection to a peer
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum ConnectionType {
+    /// Outbound connection relaying blocks, transactions and addresses
+    OutboundFullRelay,
+    /// Outbound connection relaying blocks only
+    BlockRelayOnly,
+    /// Connection opened by the peer
+    Inbound,
+}
+
+/// What the manager knows of a connected peer
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PeerInfo {
+    pub address: NetAddress,
+    pub connection: ConnectionType,
+    /// Time the connection was established
+    pub connected_at: i64,
+    /// Whether the `version` handshake completed
+    pub handshake_done: bool,
+    /// Lowest ping time seen, in milliseconds
+    pub min_ping: Option<u64>,
+    /// Last time the peer sent a new block
+    pub last_block_time: i64,
+    /// Last time the peer sent a new transaction
+    pub last_tx_time: i64,
+    /// Whether the peer wants transactions
+    pub relay_txs: bool,
+    /// Sum of the scores of the protocol violations of the peer
+    pub misbehavior: u32,
+}
+
+/// Retry schedule of an address we failed to connect to
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct Backoff {
+    failures: u32,
+    retry_at: i64,
+}
+
+/// Identifies an address regardless of its timestamp and services
+type AddrKey = ([u16; 8], u16);
+
+fn addr_key(address: &NetAddress) -> AddrKey {
+    (address.ip, address.port)
+}
+
+/// Connections to peers
+pub struct PeerManager {
+    /// Secret key of the network group hashes used for eviction
+    key: (u64, u64),
+    max_inbound: usize,
+    peers: HashMap<PeerId, PeerInfo>,
+    next_id: PeerId,
+    /// Outbound connections being opened
+    pending: HashMap<AddrKey, (NetAddress, ConnectionType)>,
+    backoff: HashMap<AddrKey, Backoff>,
+    bans: BanList,
+    /// Addresses of misbehaving peers, until the end of their discouragement
+    discouraged: HashMap<[u16; 8], i64>,
+}
+
+impl PeerManager {
+    /// Create a manager without any connection, with a random secret key
+    pub fn new(key: (u64, u64)) -> PeerManager {
+        PeerManager {
+            key:         key,
+            max_inbound: DEFAULT_MAX_INBOUND,
+            peers:       HashMap::new(),
+            next_id:     0,
+            pending:     HashMap::new(),
+            backoff:     HashMap::new(),
+            bans:        BanList::new(),
+            discouraged: HashMap::new(),
+        }
+    }
+
+    /// Replace the ban list, e.g. with the one read from disk
+    pub fn set_ban_list(&mut self, bans: BanList) {
+        self.bans = bans;
+    }
+
+    /// Banned subnets
+    pub fn ban_list(&self) -> &BanList {
+        &self.bans
+    }
+
+    /// Ban a subnet for a duration in seconds, returning the connected peers
+    /// inside it to disconnect
+    pub fn ban(&mut self, subnet: Subnet, now: i64, duration: i64) -> Vec<PeerId> {
+        self.bans.ban(subnet, now, duration);
+        self.peers.iter()
+            .filter(|&(_, info)| subnet.contains(&info.address))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Lift the ban of a subnet, returning whether it was banned
+    pub fn unban(&mut self, subnet: &Subnet) -> bool {
+        self.bans.unban(subnet)
+    }
+
+    /// Check if connections with an address are refused
+    pub fn is_refused(&self, address: &NetAddress, now: i64) -> bool {
+        self.bans.is_banned(address, now)
+            || self.discouraged.get(&address.ip).map_or(false, |until| *until > now)
+    }
+
+    /// Add a score to a peer for a protocol violation; return whether the peer
+    /// reached the threshold and must be disconnected, discouraging it
+    pub fn misbehaving(&mut self, peer: PeerId, score: u32, now: i64) -> bool {
+        let info = match self.peers.get_mut(&peer) {
+            Some(info) => info,
+            None       => return false,
+        };
+        info.misbehavior = info.misbehavior.saturating_add(score);
+        if info.misbehavior < DISCOURAGEMENT_THRESHOLD {
+            return false;
+        }
+        self.discouraged.insert(info.address.ip, now + DISCOURAGEMENT_DURATION);
+        true
+    }
+
+    /// Forget the bans and discouragements that expired
+    pub fn sweep_bans(&mut self, now: i64) {
+        self.bans.sweep(now);
+        self.discouraged.retain(|_, until| *until > now);
+    }
+
+    /// Set the number of inbound connections accepted
+    pub fn set_max_inbound(&mut self, max_inbound: usize) {
+        self.max_inbound = max_inbound;
+    }
+
+    /// Information about a connected peer
+    pub fn get(&self, peer: PeerId) -> Option<&PeerInfo> {
+        self.peers.get(&peer)
+    }
+
+    /// Iterate over the connected peers
+    pub fn iter(&self) -> impl Iterator<Item = (&PeerId, &PeerInfo)> {
+        self.peers.iter()
+    }
+
+    /// Number of connections of a kind, including those being opened
+    pub fn count(&self, connection: ConnectionType) -> usize {
+        self.peers.values().filter(|info| info.connection == connection).count()
+            + self.pending.values().filter(|&&(_, kind)| kind == connection).count()
+    }
+
+    /// Check if an address is connected or being connected to
+    fn is_connected(&self, address: &NetAddress) -> bool {
+        self.pending.contains_key(&addr_key(address))
+            || self.peers.values().any(|info| addr_key(&info.address) == addr_key(address))
+    }
+
+    /// Network groups of the outbound connections, including those being opened
+    fn outbound_groups(&self) -> HashSet<Vec<u8>> {
+        self.peers.values()
+            .filter(|info| info.connection != ConnectionType::Inbound)
+            .map(|info| info.address.group())
+            .chain(self.pending.values().map(|&(address, _)| address.group()))
+            .collect()
+    }
+
+    /// Pick the next outbound connection to open, if a slot is free, and
+    /// record it as being opened
+    pub fn next_outbound(&mut self, addrman: &mut AddrMan, now: i64, random: u64) -> Option<(NetAddress, ConnectionType)> {
+        let connection = if self.count(ConnectionType::OutboundFullRelay) < MAX_OUTBOUND_FULL_RELAY {
+            ConnectionType::OutboundFullRelay
+        } else if self.count(ConnectionType::BlockRelayOnly) < MAX_BLOCK_RELAY_ONLY {
+            ConnectionType::BlockRelayOnly
+        } else {
+            return None;
+        };
+
+        let groups = self.outbound_groups();
+        for tries in 0..MAX_SELECT_TRIES {
+            let draw = siphash24(random, tries as u64, b"select");
+            let address = addrman.select(false, now, draw)?;
+            if self.is_connected(&address) || groups.contains(&address.group()) || self.is_refused(&address, now) {
+                continue;
+            }
+            if self.backoff.get(&addr_key(&address)).map_or(false, |backoff| backoff.retry_at > now) {
+                continue;
+            }
+            // Prefer addresses not tried lately while there are other ones
+            if tries < RECENT_TRY_SKIP && addrman.tried_recently(&address, now) {
+                continue;
+            }
+            addrman.attempt(&address, now);
+            self.pending.insert(addr_key(&address), (address, connection));
+            return Some((address, connection));
+        }
+        None
+    }
+
+    /// Record that an outbound connection opened by `next_outbound` succeeded
+    pub fn outbound_connected(&mut self, address: &NetAddress, now: i64) -> Option<PeerId> {
+        let (address, connection) = self.pending.remove(&addr_key(address))?;
+        Some(self.insert_peer(address, connection, now))
+    }
+
+    /// Record that an outbound connection opened by `next_outbound` failed,
+    /// delaying the next attempt to the address
+    pub fn outbound_failed(&mut self, address: &NetAddress, now: i64) {
+        self.pending.remove(&addr_key(address));
+        self.add_failure(address, now);
+    }
+
+    fn add_failure(&mut self, address: &NetAddress, now: i64) {
+        let backoff = self.backoff.entry(addr_key(address)).or_insert(Backoff { failures: 0, retry_at: 0 });
+        let delay = if backoff.failures < 16 { INITIAL_RETRY_DELAY << backoff.failures } else { MAX_RETRY_DELAY };
+        backoff.failures += 1;
+        backoff.retry_at = now + cmp::min(delay, MAX_RETRY_DELAY);
+    }
+
+    fn insert_peer(&mut self, address: NetAddress, connection: ConnectionType, now: i64) -> PeerId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.peers.insert(id, PeerInfo {
+            address:         address,
+            connection:      connection,
+            connected_at:    now,
+            handshake_done:  false,
+            min_ping:        None,
+            last_block_time: 0,
+            last_tx_time:    0,
+            relay_txs:       connection == ConnectionType::OutboundFullRelay,
+            misbehavior:     0,
+        });
+        id
+    }
+
+    /// Accept an inbound connection from an address neither banned nor
+    /// discouraged, returning its identifier and the peer to disconnect to
+    /// make room for it, if any
+    pub fn accept_inbound(&mut self, address: &NetAddress, now: i64) -> Result<(PeerId, Option<PeerId>), Error> {
+        if self.bans.is_banned(address, now) {
+            return Err(Error::ErrorConnectionRefused("banned"));
+        }
+        if self.is_refused(address, now) {
+            return Err(Error::ErrorConnectionRefused("discouraged"));
+        }
+        let mut evicted = None;
+        if self.count(ConnectionType::Inbound) >= self.max_inbound {
+            let peer = self.select_eviction().ok_or(Error::ErrorConnectionRefused("inbound slots full"))?;
+            self.peers.remove(&peer);
+            evicted = Some(peer);
+        }
+        Ok((self.insert_peer(*address, ConnectionType::Inbound, now), evicted))
+    }
+
+    /// Record the completed `version` handshake of a peer; outbound addresses
+    /// are then marked good in the address manager
+    pub fn handshake_done(&mut self, peer: PeerId, services: u64, relay_txs: bool, addrman: &mut AddrMan, now: i64) {
+        let info = match self.peers.get_mut(&peer) {
+            Some(info) => info,
+            None       => return,
+        };
+        info.handshake_done = true;
+        info.address.services = services;
+        info.relay_txs = relay_txs && info.connection != ConnectionType::BlockRelayOnly;
+        if info.connection != ConnectionType::Inbound {
+            self.backoff.remove(&addr_key(&info.address));
+            addrman.set_services(&info.address, services);
+            addrman.good(&info.address, now);
+        }
+    }
+
+    /// Record the disconnection of a peer; an outbound peer dropping before
+    /// its handshake counts as a failed attempt
+    pub fn disconnected(&mut self, peer: PeerId, addrman: &mut AddrMan, now: i64) -> Option<PeerInfo> {
+        let info = self.peers.remove(&peer)?;
+        if info.connection != ConnectionType::Inbound {
+            if info.handshake_done {
+                addrman.connected(&info.address, now);
+            } else {
+                self.add_failure(&info.address, now);
+            }
+        }
+        Some(info)
+    }
+
+    /// Record a ping round trip time of a peer
+    pub fn record_ping(&mut self, peer: PeerId, ping: u64) {
+        if let Some(info) = self.peers.get_mut(&peer) {
+            info.min_ping = Some(info.min_ping.map_or(ping, |min_ping| cmp::min(min_ping, ping)));
+        }
+    }
+
+    /// Record that a peer sent us a new block
+    pub fn record_block(&mut self, peer: PeerId, now: i64) {
+        if let Some(info) = self.peers.get_mut(&peer) {
+            info.last_block_time = now;
+        }
+    }
+
+    /// Record that a peer sent us a new transaction
+    pub fn record_tx(&mut self, peer: PeerId, now: i64) {
+        if let Some(info) = self.peers.get_mut(&peer) {
+            info.last_tx_time = now;
+        }
+    }
+
+    /// Keyed hash of the network group of a peer, unpredictable to peers
+    fn keyed_group(&self, info: &PeerInfo) -> u64 {
+        siphash24(self.key.0, self.key.1, &info.address.group())
+    }
+
+    /// Pick the inbound peer to evict for a new inbound connection, if any
+    /// is not protected
+    pub fn select_eviction(&self) -> Option<PeerId> {
+        let mut candidates: Vec<(PeerId, &PeerInfo)> = self.peers.iter()
+            .filter(|&(_, info)| info.connection == ConnectionType::Inbound)
+            .map(|(id, info)| (*id, info))
+            .collect();
+
+        // Protect the peers with the best values of each criterion in turn
+        fn protect<K: Ord, F: Fn(&PeerInfo) -> K>(candidates: &mut Vec<(PeerId, &PeerInfo)>, count: usize, key: F) {
+            candidates.sort_by_key(|&(id, info)| (key(info), id));
+            let keep = candidates.len().saturating_sub(count);
+            candidates.truncate(keep);
+        }
+        protect(&mut candidates, 4, |info| self.keyed_group(info));
+        protect(&mut candidates, 8, |info| info.min_ping.map_or(0, |ping| u64::max_value() - ping));
+        protect(&mut candidates, 4, |info| info.last_tx_time);
+        protect(&mut candidates, 4, |info| info.last_block_time);
+        let half = candidates.len() / 2;
+        protect(&mut candidates, half, |info| cmp::Reverse(info.connected_at));
+
+        // Evict the newest peer of the group with the most connections
+        let mut groups: HashMap<Vec<u8>, Vec<(PeerId, &PeerInfo)>> = HashMap::new();
+        for (id, info) in candidates {
+            groups.entry(info.address.group()).or_insert_with(Vec::new).push((id, info));
+        }
+        groups.values()
+            .max_by_key(|peers| (peers.len(), peers.iter().map(|&(_, info)| info.connected_at).max()))
+            .and_then(|peers| peers.iter().max_by_key(|&&(id, info)| (info.connected_at, id)))
+            .map(|&(id, _)| id)
+    }
+}
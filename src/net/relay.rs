@@ -0,0 +1,345 @@
+/// Transaction relay to peers
+///
+/// Every peer may ask, with a `feefilter` message (BIP133), not to be told
+/// about transactions paying less than a fee rate; announcements below it are
+/// dropped. In turn we tell each peer the minimum fee rate of our pool. The
+/// value sent is rounded to a coarse bucket, picked at random among the two
+/// closest ones, and sent at random intervals, so that it does not reveal the
+/// exact state of the pool. A large change of the minimum is sent sooner.
+///
+/// A peer may also load a bloom filter (BIP37); only the transactions
+/// matching it are then announced, and blocks are served to it filtered.
+///
+/// New transactions are not announced right away: they are queued per peer
+/// and flushed in batches at Poisson distributed times, less often to inbound
+/// peers, so that the first peer announcing a transaction does not reveal its
+/// origin. Inbound peers share a single timer, so connecting many times does
+/// not give an attacker more samples of it. A `mempool` request (BIP35) is
+/// answered at the next flush as well, with every pool transaction passing the
+/// peer's filters.
+///
+/// There is no randomness source in the crate, callers pass random values in.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+
+use hashes::sha256d;
+
+use blockdata::block::Block;
+use blockdata::transaction::Transaction;
+use chain::headersync::PeerId;
+use mempool::pool::Mempool;
+use mempool::policy::DEFAULT_MIN_RELAY_FEE;
+use net::bloom::{self, BloomFilter};
+use net::error::Error;
+use util::amount::{money_range, FeeRate};
+use util::constants::{FEEFILTER_VERSION, MAX_INV_PER_MSG};
+use wire::msgbloom::{FilterAddMessage, FilterLoadMessage, MerkleBlockMessage};
+use wire::msgfeefilter::FeeFilterMessage;
+use wire::msginv::{Inventory, InvMessage, InvType};
+
+/// Average time in seconds between two `feefilter` messages to a peer
+pub const AVG_FEEFILTER_BROADCAST_INTERVAL: i64 = 10 * 60;
+
+/// Maximum time in seconds before a large change of our minimum fee is sent
+pub const MAX_FEEFILTER_CHANGE_DELAY: i64 = 5 * 60;
+
+/// Average time in seconds between two transaction announcements to an
+/// inbound peer, outbound peers getting them twice as often
+pub const INVENTORY_BROADCAST_INTERVAL: i64 = 5;
+
+/// Maximum number of transactions announced to a peer at once
+pub const INVENTORY_BROADCAST_MAX: usize = 7 * INVENTORY_BROADCAST_INTERVAL as usize;
+
+/// Highest fee rate sent in a `feefilter` message
+const MAX_FILTER_FEERATE: u64 = 10_000_000;
+
+/// Ratio between consecutive rounded fee rates
+const FEE_FILTER_SPACING: f64 = 1.1;
+
+/// Rounds fee rates to a coarse set of values
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FeeFilterRounder {
+    buckets: Vec<u64>,
+}
+
+impl FeeFilterRounder {
+    /// Create a rounder with buckets growing from half the default minimum relay fee
+    pub fn new() -> FeeFilterRounder {
+        let mut buckets = vec![0];
+        let mut bound = (DEFAULT_MIN_RELAY_FEE / 2) as f64;
+        while bound <= MAX_FILTER_FEERATE as f64 {
+            buckets.push(bound as u64);
+            bound *= FEE_FILTER_SPACING;
+        }
+        FeeFilterRounder { buckets: buckets }
+    }
+
+    /// Round a fee rate to the closest bucket above it or, two times out of
+    /// three, to the one below
+    pub fn round(&self, fee_rate: FeeRate, random: u64) -> FeeRate {
+        let rate = fee_rate.sat_per_kvb();
+        let mut index = self.buckets.iter().position(|bucket| *bucket >= rate).unwrap_or(self.buckets.len() - 1);
+        if index > 0 && random % 3 != 0 {
+            index -= 1;
+        }
+        FeeRate::from_sat_per_kvb(self.buckets[index])
+    }
+}
+
+impl Default for FeeFilterRounder {
+    fn default() -> FeeFilterRounder {
+        FeeFilterRounder::new()
+    }
+}
+
+/// Return a delay drawn from an exponential distribution of the given average
+fn poisson_delay(average: i64, random: u64) -> i64 {
+    // Uniform value in (0, 1] from the 53 high bits
+    let uniform = ((random >> 11) + 1) as f64 / (1u64 << 53) as f64;
+    (-uniform.ln() * average as f64) as i64
+}
+
+/// Relay state of a single peer
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct PeerRelay {
+    /// Whether the peer wants transactions at all
+    relay_txs: bool,
+    /// Whether the peer understands `feefilter`
+    fee_filter_support: bool,
+    /// Minimum fee rate of the transactions announced to the peer
+    fee_filter: FeeRate,
+    /// Last fee rate we sent to the peer
+    fee_filter_sent: FeeRate,
+    /// Time our next `feefilter` is due
+    next_fee_filter: i64,
+    /// Bloom filter loaded by the peer
+    bloom: Option<BloomFilter>,
+    /// Whether the peer connected to us
+    inbound: bool,
+    /// Transactions waiting to be announced
+    queue: HashSet<sha256d::Hash>,
+    /// Time the queued transactions are due; the shared inbound time for
+    /// inbound peers
+    next_inv_send: i64,
+    /// Whether the peer sent a `mempool` request not answered yet
+    mempool_requested: bool,
+}
+
+/// Per-peer transaction relay filters
+pub struct TxRelay {
+    peers: HashMap<PeerId, PeerRelay>,
+    rounder: FeeFilterRounder,
+    /// Time the transactions queued for inbound peers are due
+    next_inbound_inv_send: i64,
+}
+
+impl TxRelay {
+    /// Create relay filters without any peer
+    pub fn new() -> TxRelay {
+        TxRelay {
+            peers:                 HashMap::new(),
+            rounder:               FeeFilterRounder::new(),
+            next_inbound_inv_send: 0,
+        }
+    }
+
+    /// Register a peer after its `version` message
+    pub fn add_peer(&mut self, peer: PeerId, protocol: u32, relay_txs: bool, inbound: bool) {
+        self.peers.insert(peer, PeerRelay {
+            relay_txs:          relay_txs,
+            fee_filter_support: protocol >= FEEFILTER_VERSION,
+            fee_filter:         FeeRate::default(),
+            fee_filter_sent:    FeeRate::default(),
+            next_fee_filter:    0,
+            bloom:              None,
+            inbound:            inbound,
+            queue:              HashSet::new(),
+            next_inv_send:      0,
+            mempool_requested:  false,
+        });
+    }
+
+    /// Forget a disconnected peer
+    pub fn remove_peer(&mut self, peer: PeerId) {
+        self.peers.remove(&peer);
+    }
+
+    /// Minimum fee rate a peer asked for
+    pub fn fee_filter(&self, peer: PeerId) -> Option<FeeRate> {
+        self.peers.get(&peer).map(|state| state.fee_filter)
+    }
+
+    /// Process a `feefilter` message; filters above the amount of coins
+    /// that can ever exist are ignored
+    pub fn process_fee_filter(&mut self, peer: PeerId, msg: &FeeFilterMessage) {
+        if !money_range(msg.fee_rate) {
+            return;
+        }
+        if let Some(state) = self.peers.get_mut(&peer) {
+            state.fee_filter = msg.fee_rate();
+        }
+    }
+
+    /// Process a `filterload` message, which also turns transaction relay on
+    pub fn process_filter_load(&mut self, peer: PeerId, msg: &FilterLoadMessage) -> Result<(), Error> {
+        let filter = BloomFilter::from_msg(msg)?;
+        if let Some(state) = self.peers.get_mut(&peer) {
+            state.bloom = Some(filter);
+            state.relay_txs = true;
+        }
+        Ok(())
+    }
+
+    /// Process a `filteradd` message; adding to a peer without a filter is an error
+    pub fn process_filter_add(&mut self, peer: PeerId, msg: &FilterAddMessage) -> Result<(), Error> {
+        match self.peers.get_mut(&peer).and_then(|state| state.bloom.as_mut()) {
+            Some(filter) => filter.add(&msg.data),
+            None         => Err(Error::ErrorBloomFilter("no filter loaded")),
+        }
+    }
+
+    /// Process a `filterclear` message, relaying every transaction again
+    pub fn process_filter_clear(&mut self, peer: PeerId) {
+        if let Some(state) = self.peers.get_mut(&peer) {
+            state.bloom = None;
+            state.relay_txs = true;
+        }
+    }
+
+    /// Check if a peer has loaded a bloom filter
+    pub fn has_bloom_filter(&self, peer: PeerId) -> bool {
+        self.peers.get(&peer).map_or(false, |state| state.bloom.is_some())
+    }
+
+    /// Check if a transaction with the given fee rate may be announced to a peer
+    pub fn should_announce(&self, peer: PeerId, fee_rate: FeeRate) -> bool {
+        match self.peers.get(&peer) {
+            Some(state) => state.relay_txs && fee_rate >= state.fee_filter,
+            None        => false,
+        }
+    }
+
+    /// Check if a transaction matches the bloom filter of a peer, if it loaded
+    /// one, updating the filter
+    fn matches_bloom(&mut self, peer: PeerId, tx: &Transaction) -> bool {
+        match self.peers.get_mut(&peer).and_then(|state| state.bloom.as_mut()) {
+            Some(filter) => filter.is_relevant_and_update(tx),
+            None         => true,
+        }
+    }
+
+    /// Build the announcements of pool transactions for a peer, dropping those
+    /// below its fee filter, those not matching its bloom filter and those no
+    /// longer in the pool
+    pub fn announcements(&mut self, peer: PeerId, txids: &[sha256d::Hash], mempool: &Mempool) -> Vec<Inventory> {
+        let mut inventory = Vec::new();
+        for entry in txids.iter().filter_map(|txid| mempool.get(txid)) {
+            if self.should_announce(peer, entry.fee_rate()) && self.matches_bloom(peer, &entry.tx) {
+                inventory.push(Inventory::new_inventory(InvType::Transaction, entry.txid));
+            }
+        }
+        inventory
+    }
+
+    /// Queue a transaction newly accepted to the pool for announcement to
+    /// every peer relaying transactions
+    pub fn queue_transaction(&mut self, txid: sha256d::Hash) {
+        for state in self.peers.values_mut().filter(|state| state.relay_txs) {
+            state.queue.insert(txid);
+        }
+    }
+
+    /// Process a `mempool` message, answered at the next announcement time
+    pub fn process_mempool(&mut self, peer: PeerId) {
+        if let Some(state) = self.peers.get_mut(&peer) {
+            state.mempool_requested = true;
+        }
+    }
+
+    /// Return the `inv` messages due to a peer at the given time: the answer
+    /// to its `mempool` request, if any, then the queued transactions with the
+    /// highest fee rates, a bounded number at a time
+    pub fn inventory_to_send(&mut self, peer: PeerId, mempool: &Mempool, now: i64, random: u64) -> Vec<InvMessage> {
+        let next_inbound_inv_send = &mut self.next_inbound_inv_send;
+        let (queue, mempool_requested) = match self.peers.get_mut(&peer) {
+            Some(state) if now >= state.next_inv_send => {
+                state.next_inv_send = if state.inbound {
+                    if now >= *next_inbound_inv_send {
+                        *next_inbound_inv_send = now + poisson_delay(INVENTORY_BROADCAST_INTERVAL, random);
+                    }
+                    *next_inbound_inv_send
+                } else {
+                    now + poisson_delay(INVENTORY_BROADCAST_INTERVAL / 2, random)
+                };
+                let requested = state.mempool_requested;
+                state.mempool_requested = false;
+                (state.queue.drain().collect::<Vec<_>>(), requested)
+            },
+            _ => return Vec::new(),
+        };
+
+        let mut inventory = Vec::new();
+        let mut queued = Vec::new();
+        if mempool_requested {
+            // The answer covers the queued transactions as well
+            let txids: Vec<sha256d::Hash> = mempool.iter().map(|entry| entry.txid).collect();
+            inventory = self.announcements(peer, &txids, mempool);
+        } else {
+            queued = queue.iter().filter_map(|txid| mempool.get(txid)).collect();
+            queued.sort_by(|a, b| b.fee_rate().cmp(&a.fee_rate()).then(a.txid.cmp(&b.txid)));
+        }
+        let (due, later) = queued.split_at(cmp::min(queued.len(), INVENTORY_BROADCAST_MAX));
+        let due: Vec<sha256d::Hash> = due.iter().map(|entry| entry.txid).collect();
+        inventory.extend(self.announcements(peer, &due, mempool));
+        if let Some(state) = self.peers.get_mut(&peer) {
+            state.queue.extend(later.iter().map(|entry| entry.txid));
+        }
+
+        inventory.chunks(MAX_INV_PER_MSG).map(|chunk| InvMessage::new_inv_msg(chunk.to_vec())).collect()
+    }
+
+    /// Filter a block requested as `MSG_FILTERED_BLOCK` by a peer, returning
+    /// the `merkleblock` and the matched transactions to send after it, or
+    /// `None` if the peer has no filter loaded
+    pub fn filtered_block(&mut self, peer: PeerId, block: &Block) -> Option<(MerkleBlockMessage, Vec<Transaction>)> {
+        let filter = self.peers.get_mut(&peer).and_then(|state| state.bloom.as_mut())?;
+        Some(bloom::filtered_block(block, filter))
+    }
+
+    /// Return the `feefilter` message due to a peer at the given time, if any,
+    /// advertising our current pool minimum fee rate
+    pub fn fee_filter_to_send(&mut self, peer: PeerId, min_fee: FeeRate, now: i64, random: u64) -> Option<FeeFilterMessage> {
+        let rounder = &self.rounder;
+        let state = match self.peers.get_mut(&peer) {
+            Some(state) if state.fee_filter_support && state.relay_txs => state,
+            _                                                          => return None,
+        };
+
+        if now >= state.next_fee_filter {
+            state.next_fee_filter = now + poisson_delay(AVG_FEEFILTER_BROADCAST_INTERVAL, random);
+            // The delay only uses the high 53 bits
+            let rounded = rounder.round(min_fee, random & 0x7ff);
+            if rounded == state.fee_filter_sent {
+                return None;
+            }
+            state.fee_filter_sent = rounded;
+            return Some(FeeFilterMessage::new_fee_filter_msg(rounded));
+        }
+
+        // A change by more than a third is sent within a bounded delay
+        let sent = state.fee_filter_sent.sat_per_kvb();
+        let current = min_fee.sat_per_kvb();
+        if state.next_fee_filter > now + MAX_FEEFILTER_CHANGE_DELAY
+            && (current * 4 < sent * 3 || current * 3 > sent * 4) {
+            state.next_fee_filter = now + (random % MAX_FEEFILTER_CHANGE_DELAY as u64) as i64;
+        }
+        None
+    }
+}
+
+impl Default for TxRelay {
+    fn default() -> TxRelay {
+        TxRelay::new()
+    }
+}
@@ -0,0 +1,205 @@
+/// Bootstrap from DNS seeds
+///
+/// A node without known addresses asks the DNS seeds of its network for
+/// theirs: every A and AAAA record of a seed is the address of a reachable
+/// node. Seeds supporting it answer with nodes offering given services only
+/// when queried through an `x<services in hex>` subdomain; full nodes with
+/// segregated witness support are always asked for. When no seed
+/// answers, the fixed seeds configured by the caller are used instead.
+///
+/// Resolution goes through the `Resolver` trait, so the lookups can be
+/// replaced, e.g. by a local stub.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+use consensus::params::Network;
+use net::addrman::AddrMan;
+use util::constants::{NODE_NETWORK, NODE_WITNESS};
+use wire::netaddress::NetAddress;
+
+/// Services every seeded node is expected to offer
+const SEED_SERVICES: u64 = NODE_NETWORK | NODE_WITNESS;
+
+/// Age in seconds given to seeded addresses, so gossiped ones win over them
+const SEED_ADDRESS_AGE: i64 = 5 * 24 * 60 * 60;
+
+/// DNS seeds of the main network, and whether they filter by services
+const DNS_SEEDS_BITCOIN: &[(&str, bool)] = &[
+    ("seed.bitcoin.sipa.be",          true),
+    ("dnsseed.bluematt.me",           true),
+    ("dnsseed.bitcoin.dashjr.org",    false),
+    ("seed.bitcoinstats.com",         true),
+    ("seed.bitcoin.jonasschnelli.ch", true),
+    ("seed.btc.petertodd.org",        true),
+    ("seed.bitcoin.sprovoost.nl",     true),
+    ("dnsseed.emzy.de",               true),
+];
+
+/// DNS seeds of the test network, and whether they filter by services
+const DNS_SEEDS_TESTNET: &[(&str, bool)] = &[
+    ("testnet-seed.bitcoin.jonasschnelli.ch", true),
+    ("seed.tbtc.petertodd.org",               true),
+    ("seed.testnet.bitcoin.sprovoost.nl",     true),
+    ("testnet-seed.bluematt.me",              false),
+];
+
+/// Resolves host names to IP addresses
+pub trait Resolver {
+    /// Return the addresses of the A and AAAA records of a host
+    fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>>;
+}
+
+/// Resolver using the resolver of the operating system
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        Ok((host, 0).to_socket_addrs()?.map(|address| address.ip()).collect())
+    }
+}
+
+/// Return the DNS seeds of a network, and whether they filter by services
+pub fn dns_seeds(network: Network) -> &'static [(&'static str, bool)] {
+    match network {
+        Network::Bitcoin => DNS_SEEDS_BITCOIN,
+        Network::Testnet => DNS_SEEDS_TESTNET,
+        Network::Regtest => &[],
+    }
+}
+
+/// Host name to query on a seed for nodes offering the given services
+pub fn seed_host(seed: &str, filters_services: bool, services: u64) -> String {
+    if filters_services && services != 0 {
+        format!("x{:x}.{}", services, seed)
+    } else {
+        seed.to_string()
+    }
+}
+
+/// Query the DNS seeds of a network for nodes offering the given services;
+/// return the addresses of every seed that answered, with the address of the
+/// first node it gave, to be used as their source
+pub fn query_seeds<R: Resolver>(resolver: &R, network: Network, services: u64, now: i64) -> Vec<(NetAddress, Vec<NetAddress>)> {
+    let services = services | SEED_SERVICES;
+    let mut answers = Vec::new();
+    for &(seed, filters_services) in dns_seeds(network).iter() {
+        let ips = match resolver.resolve(&seed_host(seed, filters_services, services)) {
+            Ok(ips) => ips,
+            Err(_)  => continue,
+        };
+        let addresses: Vec<NetAddress> = ips.into_iter()
+            .map(|ip| NetAddress::new_netaddress(&SocketAddr::new(ip, network.default_port()), services, now - SEED_ADDRESS_AGE))
+            .filter(|address| address.is_routable())
+            .collect();
+        if let Some(source) = addresses.first().cloned() {
+            answers.push((source, addresses));
+        }
+    }
+    answers
+}
+
+/// Fill an empty address manager from the DNS seeds or, if none answers,
+/// from the fixed seeds; return the number of addresses added
+pub fn bootstrap<R: Resolver>(addrman: &mut AddrMan, resolver: &R, network: Network, services: u64,
+                              fixed_seeds: &[SocketAddr], now: i64) -> usize {
+    if !addrman.is_empty() {
+        return 0;
+    }
+    let mut added = 0;
+    for (source, addresses) in query_seeds(resolver, network, services, now) {
+        for address in addresses.iter() {
+            if addrman.add(address, &source, now, 0) {
+                added += 1;
+            }
+        }
+    }
+    if added > 0 {
+        return added;
+    }
+
+    // Fixed seeds share a single unroutable source, and so few buckets
+    let source = NetAddress::new_netaddress(&SocketAddr::new(IpAddr::from([0u8, 0, 0, 0]), 0), 0, now);
+    for seed in fixed_seeds.iter() {
+        let address = NetAddress::new_netaddress(seed, services | SEED_SERVICES, now - SEED_ADDRESS_AGE);
+        if addrman.add(&address, &source, now, 0) {
+            added += 1;
+        }
+    }
+    added
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io;
+    use std::net::{IpAddr, SocketAddr};
+
+    use consensus::params::Network;
+    use net::addrman::AddrMan;
+    use util::constants::NODE_COMPACT_FILTERS;
+    use super::{Resolver, bootstrap, seed_host};
+
+    /// Resolver answering from a fixed table
+    struct StubResolver {
+        records: HashMap<String, Vec<IpAddr>>,
+    }
+
+    impl StubResolver {
+        fn new(records: &[(&str, &[&str])]) -> StubResolver {
+            StubResolver {
+                records: records.iter()
+                    .map(|&(host, ips)| (host.to_string(), ips.iter().map(|ip| ip.parse().unwrap()).collect()))
+                    .collect(),
+            }
+        }
+    }
+
+    impl Resolver for StubResolver {
+        fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+            self.records.get(host).cloned().ok_or(io::Error::new(io::ErrorKind::NotFound, "unknown host"))
+        }
+    }
+
+    const NOW: i64 = 1_600_000_000;
+
+    #[test]
+    fn service_subdomain() {
+        assert_eq!(seed_host("seed.example.org", true, 9), "x9.seed.example.org");
+        assert_eq!(seed_host("seed.example.org", true, 0x49), "x49.seed.example.org");
+        assert_eq!(seed_host("seed.example.org", false, 9), "seed.example.org");
+        assert_eq!(seed_host("seed.example.org", true, 0), "seed.example.org");
+    }
+
+    #[test]
+    fn bootstrap_from_dns_seeds() {
+        // Filtering seeds are asked through the subdomain of the services,
+        // the others by their name; unroutable answers are dropped
+        let resolver = StubResolver::new(&[
+            ("x49.seed.bitcoin.sipa.be",   &["1.2.3.4", "5.6.7.8", "10.0.0.1"]),
+            ("dnsseed.bitcoin.dashjr.org", &["9.10.11.12"]),
+            ("seed.bitcoin.sipa.be",       &["13.14.15.16"]),
+        ]);
+        let mut addrman = AddrMan::new([0; 32]);
+        assert_eq!(bootstrap(&mut addrman, &resolver, Network::Bitcoin, NODE_COMPACT_FILTERS, &[], NOW), 3);
+        assert_eq!(addrman.len(), 3);
+
+        // A manager knowing addresses is left alone
+        assert_eq!(bootstrap(&mut addrman, &resolver, Network::Bitcoin, NODE_COMPACT_FILTERS, &[], NOW), 0);
+    }
+
+    #[test]
+    fn bootstrap_from_fixed_seeds() {
+        let fixed_seeds: Vec<SocketAddr> = vec!["1.2.3.4:8333".parse().unwrap(), "5.6.7.8:8333".parse().unwrap()];
+
+        let mut addrman = AddrMan::new([0; 32]);
+        let resolver = StubResolver::new(&[]);
+        assert_eq!(bootstrap(&mut addrman, &resolver, Network::Bitcoin, 0, &fixed_seeds, NOW), 2);
+        assert_eq!(addrman.len(), 2);
+
+        // Regtest has no DNS seeds at all
+        let mut addrman = AddrMan::new([0; 32]);
+        assert_eq!(bootstrap(&mut addrman, &resolver, Network::Regtest, 0, &fixed_seeds, NOW), 2);
+    }
+}
@@ -0,0 +1,351 @@
+/// Flat-file block store
+///
+/// Blocks are appended to rolling `blkNNNNN.dat` files, every record made of
+/// the network magic, the block length and the serialized block; a new file is
+/// started once the current one would grow above 128 MiB. The index from block
+/// hash to file position and validation status lives in `index.dat`, an
+/// append-only log where later records override earlier ones.
+///
+/// A block is synced to its data file before its index record is written, so
+/// after a crash the index never points to missing data; whatever follows the
+/// last indexed block in the last data file, and any data file after it, is
+/// dropped on open. When the index is missing or unreadable it is rebuilt by
+/// rescanning the data files.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use hashes::sha256d;
+
+use blockdata::block::Block;
+use consensus::encode::{self, serialize, deserialize, Encodable, Decodable};
+use consensus::params::Network;
+use store::error::Error;
+use store::file::write_atomic;
+use util::hash::BlockchainHash;
+
+/// Maximum size of a single block file
+pub const MAX_BLOCKFILE_SIZE: u64 = 128 * 1024 * 1024;
+
+/// Magic and length preceding every block inside a block file
+const RECORD_HEADER_SIZE: u64 = 8;
+
+/// Size of a record of the index log: hash, file, offset, length and status
+const INDEX_RECORD_SIZE: usize = 32 + 4 + 8 + 4 + 1;
+
+/// Name of the index log inside the store directory
+const INDEX_FILE_NAME: &str = "index.dat";
+
+/// How far a stored block got through validation
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum BlockStatus {
+    /// Block data is stored but was not fully validated
+    Stored,
+    /// Block passed full validation
+    Valid,
+    /// Block failed validation
+    Invalid,
+}
+
+impl BlockStatus {
+    fn code(&self) -> u8 {
+        match *self {
+            BlockStatus::Stored  => 0,
+            BlockStatus::Valid   => 1,
+            BlockStatus::Invalid => 2,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<BlockStatus> {
+        match code {
+            0 => Some(BlockStatus::Stored),
+            1 => Some(BlockStatus::Valid),
+            2 => Some(BlockStatus::Invalid),
+            _ => None,
+        }
+    }
+}
+
+/// Location of a block inside the block files
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BlockPosition {
+    /// Number of the block file
+    pub file: u32,
+    /// Offset of the serialized block inside the file
+    pub offset: u64,
+    /// Length of the serialized block
+    pub length: u32,
+    /// Validation status of the block
+    pub status: BlockStatus,
+}
+
+/// Block store over a directory of flat files
+pub struct BlockStore {
+    dir: PathBuf,
+    magic: u32,
+    index: HashMap<sha256d::Hash, BlockPosition>,
+    index_file: File,
+    /// Number of the file new blocks are appended to
+    current_file: u32,
+    /// Size of the file new blocks are appended to
+    current_size: u64,
+}
+
+/// Encode an index log record
+fn encode_index_record(hash: &sha256d::Hash, pos: &BlockPosition) -> Vec<u8> {
+    let mut data = serialize(hash);
+    pos.file.encode(&mut data).unwrap();
+    pos.offset.encode(&mut data).unwrap();
+    pos.length.encode(&mut data).unwrap();
+    pos.status.code().encode(&mut data).unwrap();
+    data
+}
+
+/// Decode an index log record
+fn decode_index_record(mut data: &[u8]) -> Result<(sha256d::Hash, BlockPosition), encode::Error> {
+    let hash = Decodable::decode(&mut data)?;
+    let file = Decodable::decode(&mut data)?;
+    let offset = Decodable::decode(&mut data)?;
+    let length = Decodable::decode(&mut data)?;
+    let status = BlockStatus::from_code(Decodable::decode(&mut data)?)
+        .ok_or(encode::Error::ErrorParse("unknown block status"))?;
+    Ok((hash, BlockPosition { file: file, offset: offset, length: length, status: status }))
+}
+
+impl BlockStore {
+    /// Open the store inside the given directory, creating it if needed
+    pub fn open<P: AsRef<Path>>(dir: P, network: Network) -> Result<BlockStore, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let index_path = dir.join(INDEX_FILE_NAME);
+        let index_existed = index_path.exists();
+        let index_file = OpenOptions::new().read(true).write(true).create(true).open(&index_path)?;
+
+        let mut store = BlockStore {
+            dir:          dir,
+            magic:        network.magic(),
+            index:        HashMap::new(),
+            index_file:   index_file,
+            current_file: 0,
+            current_size: 0,
+        };
+
+        let loaded = index_existed && store.load_index().is_ok();
+        if !loaded {
+            store.rebuild_index()?;
+        }
+        store.truncate_unindexed()?;
+        Ok(store)
+    }
+
+    /// Path of a block file
+    fn block_file_path(&self, file: u32) -> PathBuf {
+        self.dir.join(format!("blk{:05}.dat", file))
+    }
+
+    /// Read the index log, dropping a partially written last record
+    fn load_index(&mut self) -> Result<(), Error> {
+        let mut data = Vec::new();
+        self.index_file.seek(SeekFrom::Start(0))?;
+        self.index_file.read_to_end(&mut data)?;
+
+        let complete = data.len() - data.len() % INDEX_RECORD_SIZE;
+        for chunk in data[..complete].chunks(INDEX_RECORD_SIZE) {
+            let (hash, pos) = decode_index_record(chunk)?;
+            self.index.insert(hash, pos);
+        }
+        if complete < data.len() {
+            self.index_file.set_len(complete as u64)?;
+            self.index_file.sync_data()?;
+        }
+        self.index_file.seek(SeekFrom::End(0))?;
+
+        self.current_file = self.index.values().map(|pos| pos.file).max().unwrap_or(0);
+        Ok(())
+    }
+
+    /// Drop anything following the last indexed block of the current file,
+    /// and the files started after it
+    fn truncate_unindexed(&mut self) -> Result<(), Error> {
+        let current = self.current_file;
+        self.current_size = self.index.values()
+            .filter(|pos| pos.file == current)
+            .map(|pos| pos.offset + pos.length as u64)
+            .max()
+            .unwrap_or(0);
+
+        let path = self.block_file_path(current);
+        if path.exists() {
+            let file = OpenOptions::new().write(true).open(&path)?;
+            if file.metadata()?.len() > self.current_size {
+                file.set_len(self.current_size)?;
+                file.sync_data()?;
+            }
+        }
+
+        let mut next = current + 1;
+        while self.block_file_path(next).exists() {
+            fs::remove_file(self.block_file_path(next))?;
+            next += 1;
+        }
+        Ok(())
+    }
+
+    /// Append a record to the index log
+    fn write_index_record(&mut self, hash: &sha256d::Hash, pos: &BlockPosition) -> Result<(), Error> {
+        self.index_file.write_all(&encode_index_record(hash, pos))?;
+        self.index_file.sync_data()?;
+        Ok(())
+    }
+
+    /// Check if a block is stored
+    pub fn contains(&self, hash: &sha256d::Hash) -> bool {
+        self.index.contains_key(hash)
+    }
+
+    /// Return the position and status of a stored block
+    pub fn position(&self, hash: &sha256d::Hash) -> Option<&BlockPosition> {
+        self.index.get(hash)
+    }
+
+    /// Number of stored blocks
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Check if no block is stored
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Append a block to the current block file; storing a known block is a no-op
+    pub fn put_block(&mut self, block: &Block) -> Result<BlockPosition, Error> {
+        let hash = block.blockchain_hash();
+        if let Some(pos) = self.index.get(&hash) {
+            return Ok(*pos);
+        }
+
+        let data = serialize(block);
+        let record_size = RECORD_HEADER_SIZE + data.len() as u64;
+        if self.current_size > 0 && self.current_size + record_size > MAX_BLOCKFILE_SIZE {
+            self.current_file += 1;
+            self.current_size = 0;
+        }
+
+        let mut record = Vec::with_capacity(record_size as usize);
+        self.magic.encode(&mut record)?;
+        (data.len() as u32).encode(&mut record)?;
+        record.extend_from_slice(&data);
+
+        // Write at the recorded size, over whatever a failed write left behind
+        let mut file = OpenOptions::new().write(true).create(true).open(self.block_file_path(self.current_file))?;
+        file.set_len(self.current_size)?;
+        file.seek(SeekFrom::Start(self.current_size))?;
+        file.write_all(&record)?;
+        file.sync_data()?;
+
+        let pos = BlockPosition {
+            file:   self.current_file,
+            offset: self.current_size + RECORD_HEADER_SIZE,
+            length: data.len() as u32,
+            status: BlockStatus::Stored,
+        };
+        self.current_size += record_size;
+        self.write_index_record(&hash, &pos)?;
+        self.index.insert(hash, pos);
+        Ok(pos)
+    }
+
+    /// Read a stored block
+    pub fn get_block(&self, hash: &sha256d::Hash) -> Result<Option<Block>, Error> {
+        let pos = match self.index.get(hash) {
+            Some(pos) => *pos,
+            None      => return Ok(None),
+        };
+        let mut file = File::open(self.block_file_path(pos.file))?;
+        file.seek(SeekFrom::Start(pos.offset))?;
+        let mut data = vec![0u8; pos.length as usize];
+        file.read_exact(&mut data)?;
+
+        let block: Block = deserialize(&data)?;
+        if block.blockchain_hash() != *hash {
+            return Err(Error::ErrorCorrupted("block hash does not match the index"));
+        }
+        Ok(Some(block))
+    }
+
+    /// Record the validation status of a stored block
+    pub fn set_status(&mut self, hash: &sha256d::Hash, status: BlockStatus) -> Result<(), Error> {
+        let mut pos = *self.index.get(hash).ok_or(Error::ErrorCorrupted("block not stored"))?;
+        if pos.status == status {
+            return Ok(());
+        }
+        pos.status = status;
+        self.write_index_record(hash, &pos)?;
+        self.index.insert(*hash, pos);
+        Ok(())
+    }
+
+    /// Rebuild the index by rescanning all block files. Statuses of blocks
+    /// already in the index are kept, other blocks are marked as stored only.
+    pub fn rebuild_index(&mut self) -> Result<(), Error> {
+        let mut index = HashMap::new();
+        let mut file_number = 0;
+        while self.block_file_path(file_number).exists() {
+            let mut data = Vec::new();
+            File::open(self.block_file_path(file_number))?.read_to_end(&mut data)?;
+            self.scan_block_file(file_number, &data, &mut index);
+            file_number += 1;
+        }
+
+        // Write the new index next to the old one and swap them,
+        // so that a crash leaves one of the two intact
+        let mut records = Vec::new();
+        for (hash, pos) in index.iter() {
+            records.extend(encode_index_record(hash, pos));
+        }
+        let index_path = self.dir.join(INDEX_FILE_NAME);
+        write_atomic(&index_path, &records)?;
+        self.index_file = OpenOptions::new().read(true).write(true).open(&index_path)?;
+        self.index_file.seek(SeekFrom::End(0))?;
+
+        self.current_file = if file_number > 0 { file_number - 1 } else { 0 };
+        self.index = index;
+        Ok(())
+    }
+
+    /// Index every complete block record found inside a block file
+    fn scan_block_file(&self, file_number: u32, data: &[u8], index: &mut HashMap<sha256d::Hash, BlockPosition>) {
+        let magic = serialize(&self.magic);
+        let mut position = 0;
+        while position + RECORD_HEADER_SIZE as usize <= data.len() {
+            // Records are searched by their magic, skipping any garbage between them
+            if data[position..position + 4] != magic[..] {
+                position += 1;
+                continue;
+            }
+            let length: u32 = deserialize(&data[position + 4..position + 8]).unwrap();
+            let start = position + RECORD_HEADER_SIZE as usize;
+            let end = start + length as usize;
+            if end > data.len() {
+                break;
+            }
+            if let Ok(block) = deserialize::<Block>(&data[start..end]) {
+                let hash = block.blockchain_hash();
+                let status = self.index.get(&hash).map_or(BlockStatus::Stored, |pos| pos.status);
+                index.insert(hash, BlockPosition {
+                    file:   file_number,
+                    offset: start as u64,
+                    length: length,
+                    status: status,
+                });
+                position = end;
+            } else {
+                position += 1;
+            }
+        }
+    }
+}
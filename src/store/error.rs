@@ -0,0 +1,63 @@
+/// Implementation of errors used in store namespace
+///
+use std::{error, fmt, io};
+
+use blockdata::transaction::OutPoint;
+use consensus::encode;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a store file failed
+    ErrorIo(io::Error),
+    /// Stored data could not be decoded
+    ErrorEncode(encode::Error),
+    /// Stored data is inconsistent
+    ErrorCorrupted(&'static str),
+    /// A spent output is not in the unspent set
+    ErrorMissingCoin(OutPoint),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::ErrorIo(ref e)          => write!(f, "{}: {}", error::Error::description(self), e),
+            Error::ErrorEncode(ref e)      => write!(f, "{}: {}", error::Error::description(self), e),
+            Error::ErrorCorrupted(ref e)   => write!(f, "{}: {}", error::Error::description(self), e),
+            Error::ErrorMissingCoin(ref e) => write!(f, "{}: {}", error::Error::description(self), e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::ErrorIo(ref e)          => Some(e),
+            Error::ErrorEncode(ref e)      => Some(e),
+            Error::ErrorCorrupted(..)      => None,
+            Error::ErrorMissingCoin(..)    => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::ErrorIo(..)             => "store I/O error",
+            Error::ErrorEncode(..)         => "store decoding error",
+            Error::ErrorCorrupted(..)      => "store corrupted",
+            Error::ErrorMissingCoin(..)    => "missing unspent output",
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::ErrorIo(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<encode::Error> for Error {
+    fn from(e: encode::Error) -> Error {
+        Error::ErrorEncode(e)
+    }
+}
@@ -0,0 +1,30 @@
+/// Atomic file replacement
+///
+/// The new content is written to a temporary file next to the target, synced
+/// to disk and only then renamed over the target, so that a crash leaves
+/// either the old file or the new one complete.
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use store::error::Error;
+
+/// Temporary file the content of a file is written to before replacing it
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    PathBuf::from(tmp_path)
+}
+
+/// Replace the content of a file, creating it if needed
+pub fn write_atomic<P: AsRef<Path>>(path: P, data: &[u8]) -> Result<(), Error> {
+    let path = path.as_ref();
+    let tmp_path = tmp_path(path);
+    let mut tmp_file = File::create(&tmp_path)?;
+    tmp_file.write_all(data)?;
+    tmp_file.sync_all()?;
+    drop(tmp_file);
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
@@ -0,0 +1,89 @@
+/// Index of compact block filters
+///
+/// The basic filter and the filter header of every connected block are kept
+/// inside a `KvStore`, keyed by block hash, so filters of blocks that left the
+/// best chain stay available and need no cleanup on reorganization. The filter
+/// of a block is built from the block and its undo data, which holds the
+/// scripts spent by the block.
+
+use std::path::Path;
+
+use hashes::sha256d;
+
+use blockdata::block::Block;
+use blockdata::filter::{BlockFilter, BASIC_FILTER_TYPE};
+use blockdata::script::Script;
+use consensus::encode::{serialize, deserialize, Encodable};
+use store::error::Error;
+use store::kvstore::{KvStore, WriteBatch};
+use store::utxo::BlockUndo;
+use util::hash::BlockchainHash;
+
+/// Key prefix of filters
+const FILTER_PREFIX: u8 = b'F';
+
+/// Key prefix of filter headers
+const HEADER_PREFIX: u8 = b'H';
+
+fn index_key(prefix: u8, hash: &sha256d::Hash) -> Vec<u8> {
+    let mut key = vec![prefix];
+    hash.encode(&mut key).unwrap();
+    key
+}
+
+/// Basic filters and filter headers by block hash
+pub struct FilterIndex {
+    db: KvStore,
+}
+
+impl FilterIndex {
+    /// Open the index stored at the given path
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<FilterIndex, Error> {
+        Ok(FilterIndex { db: KvStore::open(path)? })
+    }
+
+    /// Check if the filter of a block is indexed
+    pub fn contains(&self, block_hash: &sha256d::Hash) -> bool {
+        self.db.get(&index_key(HEADER_PREFIX, block_hash)).is_some()
+    }
+
+    /// Build and store the filter of a block connected with the given undo
+    /// data; the filter of its parent has to be indexed already, unless the
+    /// block is the genesis block. Return the filter header.
+    pub fn connect_block(&mut self, block: &Block, undo: &BlockUndo) -> Result<sha256d::Hash, Error> {
+        let prev_hash = block.header.prev_blockhash;
+        let previous = if prev_hash == sha256d::Hash::default() {
+            sha256d::Hash::default()
+        } else {
+            self.get_filter_header(&prev_hash)?.ok_or(Error::ErrorCorrupted("missing previous filter header"))?
+        };
+
+        let spent: Vec<Script> = undo.spent.iter().map(|&(_, ref coin)| coin.txout.pkscript.clone()).collect();
+        let filter = BlockFilter::new_basic(block, &spent);
+        let header = filter.filter_header(&previous);
+
+        let hash = block.blockchain_hash();
+        let mut batch = WriteBatch::new();
+        batch.put(index_key(FILTER_PREFIX, &hash), filter.content);
+        batch.put(index_key(HEADER_PREFIX, &hash), serialize(&header));
+        self.db.write(batch)?;
+        Ok(header)
+    }
+
+    /// Return the basic filter of a block
+    pub fn get_filter(&self, block_hash: &sha256d::Hash) -> Result<Option<BlockFilter>, Error> {
+        Ok(self.db.get(&index_key(FILTER_PREFIX, block_hash)).map(|content| BlockFilter {
+            filter_type: BASIC_FILTER_TYPE,
+            block_hash:  *block_hash,
+            content:     content.clone(),
+        }))
+    }
+
+    /// Return the filter header of a block
+    pub fn get_filter_header(&self, block_hash: &sha256d::Hash) -> Result<Option<sha256d::Hash>, Error> {
+        match self.db.get(&index_key(HEADER_PREFIX, block_hash)) {
+            Some(data) => Ok(Some(deserialize(data)?)),
+            None       => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,231 @@
+/// Embedded key-value store
+///
+/// All data is kept in memory in key order and persisted to a single log file
+/// of write batches. Every batch is written with its length and a checksum and
+/// synced before `write` returns, so a batch is either applied as a whole or
+/// not at all: a batch cut short by a crash fails its checksum and is dropped
+/// when the log is replayed on open. The log is rewritten as a single batch
+/// once it grows well beyond the size of the live data.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use hashes::{sha256d, Hash};
+
+use consensus::encode::{self, serialize, Encodable, Decodable, VarInt};
+use store::error::Error;
+use store::file::write_atomic;
+
+/// Log size above which compaction is considered
+const COMPACTION_MIN_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Length and checksum around every batch inside the log
+const BATCH_OVERHEAD: usize = 8;
+
+/// Set of changes applied atomically
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct WriteBatch {
+    /// Keys with their new value, `None` deleting the key
+    ops: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> WriteBatch {
+        WriteBatch { ops: Vec::new() }
+    }
+
+    /// Set the value of a key
+    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.ops.push((key, Some(value)));
+    }
+
+    /// Delete a key
+    pub fn delete(&mut self, key: Vec<u8>) {
+        self.ops.push((key, None));
+    }
+
+    /// Number of changes in the batch
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Check if the batch holds no change
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+impl Encodable for WriteBatch {
+    fn encode<Wr: Write>(&self, mut wr: Wr) -> Result<usize, encode::Error> {
+        let mut length = VarInt(self.ops.len() as u64).encode(&mut wr)?;
+        for &(ref key, ref value) in self.ops.iter() {
+            match *value {
+                Some(ref value) => {
+                    length += 1u8.encode(&mut wr)?;
+                    length += key.encode(&mut wr)?;
+                    length += value.encode(&mut wr)?;
+                },
+                None => {
+                    length += 0u8.encode(&mut wr)?;
+                    length += key.encode(&mut wr)?;
+                }
+            }
+        }
+        Ok(length)
+    }
+}
+
+impl Decodable for WriteBatch {
+    fn decode<Decoder: Read>(mut decoder: Decoder) -> Result<Self, encode::Error> {
+        let count = VarInt::decode(&mut decoder)?.0;
+        let mut batch = WriteBatch::new();
+        for _ in 0..count {
+            let op: u8 = Decodable::decode(&mut decoder)?;
+            let key = Decodable::decode(&mut decoder)?;
+            match op {
+                1 => batch.put(key, Decodable::decode(&mut decoder)?),
+                0 => batch.delete(key),
+                _ => return Err(encode::Error::ErrorParse("unknown batch operation")),
+            }
+        }
+        Ok(batch)
+    }
+}
+
+/// Checksum of a batch - the first 4 bytes of its double SHA256
+fn checksum(data: &[u8]) -> [u8; 4] {
+    let hash = sha256d::Hash::hash(data).into_inner();
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Key-value store persisted to a log of atomic batches
+pub struct KvStore {
+    path: PathBuf,
+    file: File,
+    data: BTreeMap<Vec<u8>, Vec<u8>>,
+    /// Current size of the log file
+    log_size: u64,
+    /// Size of the keys and values currently stored
+    live_size: u64,
+}
+
+impl KvStore {
+    /// Open the store at the given path, replaying its log
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<KvStore, Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new().read(true).write(true).create(true).open(&path)?;
+        let mut log = Vec::new();
+        file.read_to_end(&mut log)?;
+
+        let mut store = KvStore {
+            path:      path,
+            file:      file,
+            data:      BTreeMap::new(),
+            log_size:  0,
+            live_size: 0,
+        };
+
+        let mut position = 0;
+        while position + BATCH_OVERHEAD <= log.len() {
+            let length: u32 = encode::deserialize(&log[position..position + 4])?;
+            let start = position + 4;
+            let end = start + length as usize;
+            if end + 4 > log.len() || checksum(&log[start..end]) != log[end..end + 4] {
+                break;
+            }
+            let batch: WriteBatch = encode::deserialize(&log[start..end])?;
+            store.apply(batch);
+            position = end + 4;
+        }
+
+        // Drop a batch partially written before a crash
+        if position < log.len() {
+            store.file.set_len(position as u64)?;
+            store.file.sync_data()?;
+        }
+        store.file.seek(SeekFrom::End(0))?;
+        store.log_size = position as u64;
+        Ok(store)
+    }
+
+    /// Apply a batch to the in-memory data
+    fn apply(&mut self, batch: WriteBatch) {
+        for (key, value) in batch.ops.into_iter() {
+            let key_size = key.len() as u64;
+            let old = match value {
+                Some(value) => {
+                    self.live_size += key_size + value.len() as u64;
+                    self.data.insert(key, value)
+                },
+                None => self.data.remove(&key),
+            };
+            if let Some(old) = old {
+                self.live_size -= key_size + old.len() as u64;
+            }
+        }
+    }
+
+    /// Return the value of a key
+    pub fn get(&self, key: &[u8]) -> Option<&Vec<u8>> {
+        self.data.get(key)
+    }
+
+    /// Iterate over all keys starting with the given prefix, in key order
+    pub fn iter_prefix<'a>(&'a self, prefix: &'a [u8]) -> impl Iterator<Item = (&'a Vec<u8>, &'a Vec<u8>)> + 'a {
+        self.data.range::<[u8], _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |&(key, _)| key.starts_with(prefix))
+    }
+
+    /// Number of stored keys
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Check if the store is empty
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Atomically apply and persist a batch of changes
+    pub fn write(&mut self, batch: WriteBatch) -> Result<(), Error> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let payload = serialize(&batch);
+        let mut record = serialize(&(payload.len() as u32));
+        record.extend_from_slice(&payload);
+        record.extend_from_slice(&checksum(&payload));
+
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+        self.log_size += record.len() as u64;
+        self.apply(batch);
+
+        if self.log_size > COMPACTION_MIN_SIZE && self.log_size > 2 * self.live_size {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the log as a single batch holding the live data
+    pub fn compact(&mut self) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        for (key, value) in self.data.iter() {
+            batch.put(key.clone(), value.clone());
+        }
+        let payload = serialize(&batch);
+        let mut record = serialize(&(payload.len() as u32));
+        record.extend(&payload);
+        record.extend(&checksum(&payload));
+
+        // The new log replaces the old one only once it is completely on disk
+        write_atomic(&self.path, &record)?;
+
+        self.file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        self.log_size = self.file.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
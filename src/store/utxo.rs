@@ -0,0 +1,379 @@
+/// Set of unspent transaction outputs
+///
+/// Every unspent output is stored as a `Coin` keyed by its `OutPoint` inside
+/// a `KvStore`. Changes made while connecting and disconnecting blocks stay
+/// in a write-back cache and are flushed together with the undo data of the
+/// blocks and the hash of the best block in a single atomic batch, so after a
+/// crash the set always matches the best block it records.
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+
+use hashes::{sha256d, Hash, HashEngine};
+
+use blockdata::block::Block;
+use blockdata::transaction::{OutPoint, TxOut};
+use consensus::encode::{self, serialize, deserialize, Encodable, Decodable, VarInt};
+use store::error::Error;
+use store::kvstore::{KvStore, WriteBatch};
+use util::hash::BlockchainHash;
+
+/// Key prefix of unspent outputs
+const COIN_PREFIX: u8 = b'C';
+
+/// Key prefix of block undo data
+const UNDO_PREFIX: u8 = b'U';
+
+/// Key of the hash of the block the set is up to date with
+const BEST_BLOCK_KEY: &[u8] = b"B";
+
+/// Default number of cached entries above which the cache is flushed
+pub const DEFAULT_CACHE_SIZE: usize = 200_000;
+
+/// An unspent output together with the data needed to validate its spending
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Coin {
+    /// The output itself
+    pub txout: TxOut,
+    /// Height of the block including the transaction
+    pub height: u32,
+    /// The output belongs to a coinbase transaction
+    pub is_coinbase: bool,
+}
+
+/// Height and coinbase flag are packed together in a single varint
+impl Encodable for Coin {
+    fn encode<Wr: io::Write>(&self, mut wr: Wr) -> Result<usize, encode::Error> {
+        let code = ((self.height as u64) << 1) | (self.is_coinbase as u64);
+        let length = VarInt(code).encode(&mut wr)?
+            + self.txout.encode(&mut wr)?;
+        Ok(length)
+    }
+}
+
+impl Decodable for Coin {
+    fn decode<Decoder: io::Read>(mut decoder: Decoder) -> Result<Self, encode::Error> {
+        let code = VarInt::decode(&mut decoder)?.0;
+        Ok(Coin {
+            txout:       Decodable::decode(&mut decoder)?,
+            height:      (code >> 1) as u32,
+            is_coinbase: code & 1 == 1,
+        })
+    }
+}
+
+/// Outputs spent by a block, in the order of the inputs spending them,
+/// needed to restore the set when the block is disconnected
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct BlockUndo {
+    pub spent: Vec<(OutPoint, Coin)>,
+}
+
+impl Encodable for BlockUndo {
+    fn encode<Wr: io::Write>(&self, mut wr: Wr) -> Result<usize, encode::Error> {
+        let mut length = VarInt(self.spent.len() as u64).encode(&mut wr)?;
+        for &(ref outpoint, ref coin) in self.spent.iter() {
+            length += outpoint.encode(&mut wr)?;
+            length += coin.encode(&mut wr)?;
+        }
+        Ok(length)
+    }
+}
+
+impl Decodable for BlockUndo {
+    fn decode<Decoder: io::Read>(mut decoder: Decoder) -> Result<Self, encode::Error> {
+        let count = VarInt::decode(&mut decoder)?.0;
+        let mut spent = Vec::new();
+        for _ in 0..count {
+            let outpoint = Decodable::decode(&mut decoder)?;
+            let coin = Decodable::decode(&mut decoder)?;
+            spent.push((outpoint, coin));
+        }
+        Ok(BlockUndo { spent: spent })
+    }
+}
+
+/// Statistics over the whole set, as reported by `gettxoutsetinfo`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UtxoStats {
+    /// Block the set is up to date with
+    pub best_block: sha256d::Hash,
+    /// Number of transactions with unspent outputs
+    pub transactions: u64,
+    /// Number of unspent outputs
+    pub txouts: u64,
+    /// Sum of the values of all unspent outputs
+    pub total_amount: u64,
+    /// Hash of the serialized set
+    pub hash_serialized: sha256d::Hash,
+}
+
+/// Unspent outputs set with write-back cache
+pub struct UtxoSet {
+    db: KvStore,
+    /// Changed outputs not flushed yet; `None` when spent
+    cache: HashMap<OutPoint, Option<Coin>>,
+    /// Undo data to write, or to delete when `None`
+    pending_undo: HashMap<sha256d::Hash, Option<BlockUndo>>,
+    best_block: sha256d::Hash,
+    max_cache_size: usize,
+}
+
+fn coin_key(outpoint: &OutPoint) -> Vec<u8> {
+    let mut key = vec![COIN_PREFIX];
+    outpoint.encode(&mut key).unwrap();
+    key
+}
+
+fn undo_key(hash: &sha256d::Hash) -> Vec<u8> {
+    let mut key = vec![UNDO_PREFIX];
+    hash.encode(&mut key).unwrap();
+    key
+}
+
+impl UtxoSet {
+    /// Open the set stored at the given path
+    pub fn open<P: AsRef<Path>>(path: P, max_cache_size: usize) -> Result<UtxoSet, Error> {
+        let db = KvStore::open(path)?;
+        let best_block = match db.get(BEST_BLOCK_KEY) {
+            Some(data) => deserialize(data)?,
+            None       => Default::default(),
+        };
+        Ok(UtxoSet {
+            db:             db,
+            cache:          HashMap::new(),
+            pending_undo:   HashMap::new(),
+            best_block:     best_block,
+            max_cache_size: max_cache_size,
+        })
+    }
+
+    /// Hash of the block the set is up to date with; zero for an empty set
+    pub fn best_block(&self) -> sha256d::Hash {
+        self.best_block
+    }
+
+    /// Return an unspent output
+    pub fn get(&self, outpoint: &OutPoint) -> Result<Option<Coin>, Error> {
+        if let Some(coin) = self.cache.get(outpoint) {
+            return Ok(coin.clone());
+        }
+        match self.db.get(&coin_key(outpoint)) {
+            Some(data) => Ok(Some(deserialize(data)?)),
+            None       => Ok(None),
+        }
+    }
+
+    /// Check if an output is unspent
+    pub fn contains(&self, outpoint: &OutPoint) -> Result<bool, Error> {
+        Ok(self.get(outpoint)?.is_some())
+    }
+
+    /// Return the outputs spent by a block, in the order of its inputs, without
+    /// changing the set. Fails if an input spends an output that is not in the
+    /// set, or that an earlier input of the block already spent.
+    pub fn spent_coins(&self, block: &Block, height: u32) -> Result<BlockUndo, Error> {
+        let mut undo = BlockUndo::default();
+        let mut created: HashMap<OutPoint, Coin> = HashMap::new();
+        let mut spent: HashSet<OutPoint> = HashSet::new();
+        for tx in block.txdata.iter() {
+            let is_coinbase = tx.is_coin_base();
+            if !is_coinbase {
+                for txin in tx.input_ent.iter() {
+                    let outpoint = txin.previous_out;
+                    if !spent.insert(outpoint) {
+                        return Err(Error::ErrorMissingCoin(outpoint));
+                    }
+                    let coin = match created.remove(&outpoint) {
+                        Some(coin) => coin,
+                        None       => self.get(&outpoint)?.ok_or(Error::ErrorMissingCoin(outpoint))?,
+                    };
+                    undo.spent.push((outpoint, coin));
+                }
+            }
+            let txid = tx.txid();
+            for (vout, txout) in tx.output_ent.iter().enumerate() {
+                if txout.pkscript.is_provably_unspendable() {
+                    continue;
+                }
+                created.insert(OutPoint::new(txid, vout as u32), Coin {
+                    txout:       txout.clone(),
+                    height:      height,
+                    is_coinbase: is_coinbase,
+                });
+            }
+        }
+        Ok(undo)
+    }
+
+    /// Apply the outputs spent and created by a block on top of the best block,
+    /// returning the data needed to disconnect it again. The set is left
+    /// untouched if an input spends an output that is not in the set, or that
+    /// an earlier input of the block already spent.
+    pub fn connect_block(&mut self, block: &Block, height: u32) -> Result<BlockUndo, Error> {
+        if block.header.prev_blockhash != self.best_block {
+            return Err(Error::ErrorCorrupted("block does not extend the best block"));
+        }
+        let undo = self.spent_coins(block, height)?;
+        self.apply_block(block, height, &undo)?;
+        Ok(undo)
+    }
+
+    /// Apply a block on top of the best block given the outputs it spends,
+    /// as returned by `spent_coins`
+    pub fn apply_block(&mut self, block: &Block, height: u32, undo: &BlockUndo) -> Result<(), Error> {
+        let hash = block.blockchain_hash();
+        if block.header.prev_blockhash != self.best_block {
+            return Err(Error::ErrorCorrupted("block does not extend the best block"));
+        }
+
+        // Outputs both created and spent inside the block never reach the set
+        let spent: HashSet<OutPoint> = undo.spent.iter().map(|&(outpoint, _)| outpoint).collect();
+        let mut created: HashSet<OutPoint> = HashSet::new();
+        for tx in block.txdata.iter() {
+            let txid = tx.txid();
+            let is_coinbase = tx.is_coin_base();
+            for (vout, txout) in tx.output_ent.iter().enumerate() {
+                if txout.pkscript.is_provably_unspendable() {
+                    continue;
+                }
+                let outpoint = OutPoint::new(txid, vout as u32);
+                created.insert(outpoint);
+                if !spent.contains(&outpoint) {
+                    self.cache.insert(outpoint, Some(Coin {
+                        txout:       txout.clone(),
+                        height:      height,
+                        is_coinbase: is_coinbase,
+                    }));
+                }
+            }
+        }
+        for outpoint in spent.iter() {
+            if !created.contains(outpoint) {
+                self.cache.insert(*outpoint, None);
+            }
+        }
+        self.pending_undo.insert(hash, Some(undo.clone()));
+        self.best_block = hash;
+
+        if self.cache.len() > self.max_cache_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Revert a block connected last, restoring the outputs it spent. The set
+    /// is left untouched if the undo data does not match the block.
+    pub fn disconnect_block(&mut self, block: &Block, undo: &BlockUndo) -> Result<(), Error> {
+        let hash = block.blockchain_hash();
+        if hash != self.best_block {
+            return Err(Error::ErrorCorrupted("block is not the best block"));
+        }
+
+        // Undo data follows the order of the inputs, so it is consumed
+        // from the end while walking the transactions backwards; changes
+        // are applied in the same order once all of it is checked
+        let mut changes: Vec<(OutPoint, Option<Coin>)> = Vec::new();
+        let mut spent = undo.spent.iter().rev();
+        for tx in block.txdata.iter().rev() {
+            let txid = tx.txid();
+            for vout in 0..tx.output_ent.len() {
+                changes.push((OutPoint::new(txid, vout as u32), None));
+            }
+            if tx.is_coin_base() {
+                continue;
+            }
+            for txin in tx.input_ent.iter().rev() {
+                let &(ref outpoint, ref coin) = spent.next()
+                    .ok_or(Error::ErrorCorrupted("undo data does not match the block"))?;
+                if *outpoint != txin.previous_out {
+                    return Err(Error::ErrorCorrupted("undo data does not match the block"));
+                }
+                changes.push((*outpoint, Some(coin.clone())));
+            }
+        }
+        if spent.next().is_some() {
+            return Err(Error::ErrorCorrupted("undo data does not match the block"));
+        }
+
+        for (outpoint, coin) in changes {
+            self.cache.insert(outpoint, coin);
+        }
+        self.pending_undo.insert(hash, None);
+        self.best_block = block.header.prev_blockhash;
+
+        if self.cache.len() > self.max_cache_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Return the undo data of a connected block
+    pub fn get_undo(&self, hash: &sha256d::Hash) -> Result<Option<BlockUndo>, Error> {
+        if let Some(undo) = self.pending_undo.get(hash) {
+            return Ok(undo.clone());
+        }
+        match self.db.get(&undo_key(hash)) {
+            Some(data) => Ok(Some(deserialize(data)?)),
+            None       => Ok(None),
+        }
+    }
+
+    /// Write all cached changes to the store in a single batch and empty the cache
+    pub fn flush(&mut self) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        for (outpoint, coin) in self.cache.iter() {
+            match *coin {
+                Some(ref coin) => batch.put(coin_key(outpoint), serialize(coin)),
+                None           => batch.delete(coin_key(outpoint)),
+            }
+        }
+        for (hash, undo) in self.pending_undo.iter() {
+            match *undo {
+                Some(ref undo) => batch.put(undo_key(hash), serialize(undo)),
+                None           => batch.delete(undo_key(hash)),
+            }
+        }
+        batch.put(BEST_BLOCK_KEY.to_vec(), serialize(&self.best_block));
+
+        self.db.write(batch)?;
+        self.cache.clear();
+        self.pending_undo.clear();
+        Ok(())
+    }
+
+    /// Flush the cache and compute statistics over the whole set
+    pub fn stats(&mut self) -> Result<UtxoStats, Error> {
+        self.flush()?;
+
+        let mut stats = UtxoStats {
+            best_block:      self.best_block,
+            transactions:    0,
+            txouts:          0,
+            total_amount:    0,
+            hash_serialized: Default::default(),
+        };
+        let mut engine = sha256d::Hash::engine();
+        engine.input(&serialize(&self.best_block));
+
+        // Keys are ordered by transaction id, so outputs of the
+        // same transaction follow each other
+        let mut last_txid = None;
+        let prefix = [COIN_PREFIX];
+        for (key, value) in self.db.iter_prefix(&prefix) {
+            let outpoint: OutPoint = deserialize(&key[1..])?;
+            let coin: Coin = deserialize(value)?;
+            if last_txid != Some(outpoint.txid) {
+                stats.transactions += 1;
+                last_txid = Some(outpoint.txid);
+            }
+            stats.txouts += 1;
+            stats.total_amount += coin.txout.value_out;
+            engine.input(&key[1..]);
+            engine.input(value);
+        }
+        stats.hash_serialized = sha256d::Hash::from_engine(engine);
+        Ok(stats)
+    }
+}
@@ -1 +1,51 @@
-/// 
\ No newline at end of file
+/// Amounts of coins, expressed in satoshis, and fee rates
+
+use std::fmt;
+
+/// Number of satoshis in one coin
+pub const COIN: u64 = 100_000_000;
+
+/// Maximum amount of satoshis that can ever exist
+pub const MAX_MONEY: u64 = 21_000_000 * COIN;
+
+/// Check if an amount is within the valid range
+pub fn money_range(value: u64) -> bool {
+    value <= MAX_MONEY
+}
+
+/// Fee rate in satoshis per 1000 virtual bytes
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Default)]
+pub struct FeeRate(u64);
+
+impl FeeRate {
+    /// Create a fee rate from satoshis per 1000 virtual bytes
+    pub fn from_sat_per_kvb(sat_per_kvb: u64) -> FeeRate {
+        FeeRate(sat_per_kvb)
+    }
+
+    /// Return the rate of a fee paid for the given virtual size
+    pub fn from_fee(fee: u64, vsize: usize) -> FeeRate {
+        if vsize == 0 {
+            return FeeRate(0);
+        }
+        FeeRate(fee * 1000 / vsize as u64)
+    }
+
+    /// Return the rate in satoshis per 1000 virtual bytes
+    pub fn sat_per_kvb(&self) -> u64 {
+        self.0
+    }
+
+    /// Return the fee for the given virtual size; a non-zero rate
+    /// never rounds down to a zero fee
+    pub fn fee(&self, vsize: usize) -> u64 {
+        let fee = self.0 * vsize as u64 / 1000;
+        if fee == 0 && vsize > 0 && self.0 > 0 { 1 } else { fee }
+    }
+}
+
+impl fmt::Display for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} sat/kvB", self.0)
+    }
+}
@@ -1,6 +1,15 @@
-pub const PROTOCOL_VERSION: u32 = 70001;
+pub const PROTOCOL_VERSION: u32 = 70011;
+pub const FEEFILTER_VERSION: u32 = 70013;
+pub const SHORT_IDS_BLOCKS_VERSION: u32 = 70014;
 pub const MAX_BLOCK_HEADERS_PER_MSG: u32 = 2000;
 pub const MAX_BLOCKS_PER_MSG: u32 = 500;
 pub const MAX_VECTOR_SIZE: usize = 4_000_000;
+pub const MAX_INV_PER_MSG: usize = 50_000;
+pub const MAX_ADDR_PER_MSG: usize = 1_000;
+pub const MAX_LOCATOR_HASHES: usize = 101;
 
-pub const SERVICES: u64 = 0;
\ No newline at end of file
+pub const SERVICES: u64 = 0;
+pub const NODE_NETWORK: u64 = 1 << 0;
+pub const NODE_BLOOM: u64 = 1 << 2;
+pub const NODE_WITNESS: u64 = 1 << 3;
+pub const NODE_COMPACT_FILTERS: u64 = 1 << 6;
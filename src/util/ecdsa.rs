@@ -0,0 +1,479 @@
+/// ECDSA signature verification over the secp256k1 curve
+///
+/// Only what script validation needs: parsing public keys and DER signatures
+/// and checking a signature against a message hash. No secret ever goes
+/// through this code, so none of it tries to run in constant time.
+
+use std::cmp::Ordering;
+
+/// A 256-bit number as four 64-bit words, least significant first
+type Limbs = [u64; 4];
+
+const ZERO: Limbs = [0, 0, 0, 0];
+const ONE: Limbs = [1, 0, 0, 0];
+
+/// A prime just below 2^256 and the arithmetic modulo it
+struct Modulus {
+    /// The prime
+    m: Limbs,
+    /// 2^256 minus the prime
+    c: Limbs,
+}
+
+/// Prime of the field the curve is defined over
+const P: Modulus = Modulus {
+    m: [0xfffffffefffffc2f, 0xffffffffffffffff, 0xffffffffffffffff, 0xffffffffffffffff],
+    c: [0x00000001000003d1, 0x0000000000000000, 0x0000000000000000, 0x0000000000000000],
+};
+
+/// Order of the group generated by G
+const N: Modulus = Modulus {
+    m: [0xbfd25e8cd0364141, 0xbaaedce6af48a03b, 0xfffffffffffffffe, 0xffffffffffffffff],
+    c: [0x402da1732fc9bebf, 0x4551231950b75fc4, 0x0000000000000001, 0x0000000000000000],
+};
+
+/// (P + 1) / 4, the exponent giving square roots modulo P
+const P_SQRT_EXP: Limbs = [0xffffffffbfffff0c, 0xffffffffffffffff, 0xffffffffffffffff, 0x3fffffffffffffff];
+
+/// Coordinates of the generator G
+const GX: Limbs = [0x59f2815b16f81798, 0x029bfcdb2dce28d9, 0x55a06295ce870b07, 0x79be667ef9dcbbac];
+const GY: Limbs = [0x9c47d08ffb10d4b8, 0xfd17b448a6855419, 0x5da4fbfc0e1108a8, 0x483ada7726a3c465];
+
+/// Compare two numbers
+fn compare(a: &Limbs, b: &Limbs) -> Ordering {
+    for i in (0..4).rev() {
+        match a[i].cmp(&b[i]) {
+            Ordering::Equal => {},
+            ordering        => return ordering,
+        }
+    }
+    Ordering::Equal
+}
+
+/// Add two numbers, returning the sum modulo 2^256 and the carry
+fn add(a: &Limbs, b: &Limbs) -> (Limbs, bool) {
+    let mut result = ZERO;
+    let mut carry = 0u128;
+    for i in 0..4 {
+        let sum = a[i] as u128 + b[i] as u128 + carry;
+        result[i] = sum as u64;
+        carry = sum >> 64;
+    }
+    (result, carry != 0)
+}
+
+/// Subtract two numbers, returning the difference modulo 2^256 and the borrow
+fn sub(a: &Limbs, b: &Limbs) -> (Limbs, bool) {
+    let mut result = ZERO;
+    let mut borrow = false;
+    for i in 0..4 {
+        let (diff, borrow1) = a[i].overflowing_sub(b[i]);
+        let (diff, borrow2) = diff.overflowing_sub(borrow as u64);
+        result[i] = diff;
+        borrow = borrow1 || borrow2;
+    }
+    (result, borrow)
+}
+
+/// Read a big-endian number of at most 32 bytes
+fn from_be_bytes(bytes: &[u8]) -> Limbs {
+    let mut result = ZERO;
+    for (i, byte) in bytes.iter().rev().enumerate() {
+        result[i / 8] |= (*byte as u64) << (8 * (i % 8));
+    }
+    result
+}
+
+impl Modulus {
+    /// Check if a number is a reduced value modulo the prime
+    fn contains(&self, a: &Limbs) -> bool {
+        compare(a, &self.m) == Ordering::Less
+    }
+
+    fn add(&self, a: &Limbs, b: &Limbs) -> Limbs {
+        let (sum, carry) = add(a, b);
+        if carry || !self.contains(&sum) { sub(&sum, &self.m).0 } else { sum }
+    }
+
+    fn sub(&self, a: &Limbs, b: &Limbs) -> Limbs {
+        let (diff, borrow) = sub(a, b);
+        if borrow { add(&diff, &self.m).0 } else { diff }
+    }
+
+    fn neg(&self, a: &Limbs) -> Limbs {
+        self.sub(&ZERO, a)
+    }
+
+    fn mul(&self, a: &Limbs, b: &Limbs) -> Limbs {
+        let mut wide = [0u64; 8];
+        for i in 0..4 {
+            let mut carry = 0u128;
+            for j in 0..4 {
+                let product = wide[i + j] as u128 + a[i] as u128 * b[j] as u128 + carry;
+                wide[i + j] = product as u64;
+                carry = product >> 64;
+            }
+            wide[i + 4] = carry as u64;
+        }
+        self.reduce(wide)
+    }
+
+    /// Reduce a 512-bit number. As 2^256 is congruent to `c`, the high half
+    /// folds into the low half multiplied by `c`, until nothing is left of it.
+    fn reduce(&self, mut wide: [u64; 8]) -> Limbs {
+        while wide[4..].iter().any(|&word| word != 0) {
+            let mut folded = [0u64; 8];
+            folded[..4].copy_from_slice(&wide[..4]);
+            for i in 0..4 {
+                let mut carry = 0u128;
+                for j in 0..4 {
+                    let product = folded[i + j] as u128 + wide[i + 4] as u128 * self.c[j] as u128 + carry;
+                    folded[i + j] = product as u64;
+                    carry = product >> 64;
+                }
+                let mut k = i + 4;
+                while carry != 0 {
+                    let sum = folded[k] as u128 + carry;
+                    folded[k] = sum as u64;
+                    carry = sum >> 64;
+                    k += 1;
+                }
+            }
+            wide = folded;
+        }
+        let mut result = [wide[0], wide[1], wide[2], wide[3]];
+        while !self.contains(&result) {
+            result = sub(&result, &self.m).0;
+        }
+        result
+    }
+
+    fn pow(&self, base: &Limbs, exponent: &Limbs) -> Limbs {
+        let mut result = ONE;
+        for i in (0..256).rev() {
+            result = self.mul(&result, &result);
+            if (exponent[i / 64] >> (i % 64)) & 1 == 1 {
+                result = self.mul(&result, base);
+            }
+        }
+        result
+    }
+
+    /// Inverse of a non-zero value, by Fermat's little theorem
+    fn inv(&self, a: &Limbs) -> Limbs {
+        let exponent = sub(&self.m, &[2, 0, 0, 0]).0;
+        self.pow(a, &exponent)
+    }
+}
+
+/// Right-hand side of the curve equation y^2 = x^3 + 7
+fn curve_rhs(x: &Limbs) -> Limbs {
+    P.add(&P.mul(&P.mul(x, x), x), &[7, 0, 0, 0])
+}
+
+/// Curve point in Jacobian coordinates (X / Z^2, Y / Z^3); Z is zero
+/// for the point at infinity
+#[derive(Copy, Clone)]
+struct Point {
+    x: Limbs,
+    y: Limbs,
+    z: Limbs,
+}
+
+impl Point {
+    fn infinity() -> Point {
+        Point { x: ONE, y: ONE, z: ZERO }
+    }
+
+    fn affine(x: Limbs, y: Limbs) -> Point {
+        Point { x: x, y: y, z: ONE }
+    }
+
+    fn is_infinity(&self) -> bool {
+        self.z == ZERO
+    }
+
+    fn double(&self) -> Point {
+        if self.is_infinity() || self.y == ZERO {
+            return Point::infinity();
+        }
+        let a = P.mul(&self.x, &self.x);
+        let b = P.mul(&self.y, &self.y);
+        let c = P.mul(&b, &b);
+        let xb = P.add(&self.x, &b);
+        let d = P.sub(&P.sub(&P.mul(&xb, &xb), &a), &c);
+        let d = P.add(&d, &d);
+        let e = P.add(&P.add(&a, &a), &a);
+        let f = P.mul(&e, &e);
+        let x = P.sub(&f, &P.add(&d, &d));
+        let c8 = P.add(&c, &c);
+        let c8 = P.add(&c8, &c8);
+        let c8 = P.add(&c8, &c8);
+        let y = P.sub(&P.mul(&e, &P.sub(&d, &x)), &c8);
+        let z = P.mul(&self.y, &self.z);
+        Point { x: x, y: y, z: P.add(&z, &z) }
+    }
+
+    fn add(&self, other: &Point) -> Point {
+        if self.is_infinity() {
+            return *other;
+        }
+        if other.is_infinity() {
+            return *self;
+        }
+        let z1z1 = P.mul(&self.z, &self.z);
+        let z2z2 = P.mul(&other.z, &other.z);
+        let u1 = P.mul(&self.x, &z2z2);
+        let u2 = P.mul(&other.x, &z1z1);
+        let s1 = P.mul(&P.mul(&self.y, &other.z), &z2z2);
+        let s2 = P.mul(&P.mul(&other.y, &self.z), &z1z1);
+        if u1 == u2 {
+            return if s1 == s2 { self.double() } else { Point::infinity() };
+        }
+        let h = P.sub(&u2, &u1);
+        let r = P.sub(&s2, &s1);
+        let hh = P.mul(&h, &h);
+        let hhh = P.mul(&hh, &h);
+        let v = P.mul(&u1, &hh);
+        let x = P.sub(&P.sub(&P.mul(&r, &r), &hhh), &P.add(&v, &v));
+        let y = P.sub(&P.mul(&r, &P.sub(&v, &x)), &P.mul(&s1, &hhh));
+        let z = P.mul(&P.mul(&h, &self.z), &other.z);
+        Point { x: x, y: y, z: z }
+    }
+}
+
+/// A point of the curve other than the point at infinity
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct PublicKey {
+    x: Limbs,
+    y: Limbs,
+}
+
+impl PublicKey {
+    /// Parse a compressed (33 bytes), uncompressed or hybrid (65 bytes) key
+    pub fn from_slice(data: &[u8]) -> Option<PublicKey> {
+        match (data.len(), data.first()) {
+            (33, Some(&0x02)) | (33, Some(&0x03)) => {
+                let x = from_be_bytes(&data[1..33]);
+                if !P.contains(&x) {
+                    return None;
+                }
+                let rhs = curve_rhs(&x);
+                let y = P.pow(&rhs, &P_SQRT_EXP);
+                if P.mul(&y, &y) != rhs {
+                    return None;
+                }
+                let y = if (y[0] & 1) as u8 == data[0] & 1 { y } else { P.neg(&y) };
+                Some(PublicKey { x: x, y: y })
+            },
+            (65, Some(&0x04)) | (65, Some(&0x06)) | (65, Some(&0x07)) => {
+                let x = from_be_bytes(&data[1..33]);
+                let y = from_be_bytes(&data[33..65]);
+                if !P.contains(&x) || !P.contains(&y) || P.mul(&y, &y) != curve_rhs(&x) {
+                    return None;
+                }
+                // Hybrid keys repeat the parity of y in their prefix
+                if data[0] != 0x04 && (y[0] & 1) as u8 != data[0] & 1 {
+                    return None;
+                }
+                Some(PublicKey { x: x, y: y })
+            },
+            _ => None,
+        }
+    }
+}
+
+/// A signature, with both values in [1, n - 1]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Signature {
+    r: Limbs,
+    s: Limbs,
+}
+
+impl Signature {
+    /// Parse a DER signature as leniently as OpenSSL used to, which is what
+    /// blocks before BIP66 were checked with: lengths may use more bytes than
+    /// needed, integers may carry leading zeros, and trailing bytes are ignored
+    pub fn from_der_lax(data: &[u8]) -> Option<Signature> {
+        let mut pos = 0;
+        if data.get(pos) != Some(&0x30) {
+            return None;
+        }
+        pos += 1;
+        let length = *data.get(pos)?;
+        pos += 1;
+        if length & 0x80 != 0 {
+            let skip = (length - 0x80) as usize;
+            if skip > data.len() - pos {
+                return None;
+            }
+            pos += skip;
+        }
+        let r = Signature::read_integer(data, &mut pos)?;
+        let s = Signature::read_integer(data, &mut pos)?;
+        let r = Signature::scalar(r)?;
+        let s = Signature::scalar(s)?;
+        Some(Signature { r: r, s: s })
+    }
+
+    /// Read a DER integer at `pos`, returning its bytes
+    fn read_integer<'a>(data: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+        if data.get(*pos) != Some(&0x02) {
+            return None;
+        }
+        *pos += 1;
+        let mut length = *data.get(*pos)? as usize;
+        *pos += 1;
+        if length & 0x80 != 0 {
+            let mut size = length - 0x80;
+            if size > data.len() - *pos {
+                return None;
+            }
+            while size > 0 && data[*pos] == 0 {
+                *pos += 1;
+                size -= 1;
+            }
+            if size >= 8 {
+                return None;
+            }
+            length = 0;
+            for _ in 0..size {
+                length = (length << 8) + data[*pos] as usize;
+                *pos += 1;
+            }
+        }
+        if length > data.len() - *pos {
+            return None;
+        }
+        let integer = &data[*pos..*pos + length];
+        *pos += length;
+        Some(integer)
+    }
+
+    /// Convert the bytes of an integer to a value in [1, n - 1]
+    fn scalar(mut bytes: &[u8]) -> Option<Limbs> {
+        while !bytes.is_empty() && bytes[0] == 0 {
+            bytes = &bytes[1..];
+        }
+        if bytes.len() > 32 {
+            return None;
+        }
+        let value = from_be_bytes(bytes);
+        if value == ZERO || !N.contains(&value) {
+            return None;
+        }
+        Some(value)
+    }
+}
+
+/// Check a signature of the 32-byte message hash `msg` under `key`. Both
+/// (r, s) and (r, n - s) are accepted, as the network always did.
+pub fn verify(msg: &[u8; 32], sig: &Signature, key: &PublicKey) -> bool {
+    let mut z = from_be_bytes(msg);
+    if !N.contains(&z) {
+        z = sub(&z, &N.m).0;
+    }
+    let w = N.inv(&sig.s);
+    let u1 = N.mul(&z, &w);
+    let u2 = N.mul(&sig.r, &w);
+
+    // Compute u1 * G + u2 * Q with a single chain of doublings
+    let g = Point::affine(GX, GY);
+    let q = Point::affine(key.x, key.y);
+    let gq = g.add(&q);
+    let mut point = Point::infinity();
+    for i in (0..256).rev() {
+        point = point.double();
+        let bits = ((u1[i / 64] >> (i % 64)) & 1, (u2[i / 64] >> (i % 64)) & 1);
+        point = match bits {
+            (1, 1) => point.add(&gq),
+            (1, 0) => point.add(&g),
+            (0, 1) => point.add(&q),
+            _      => point,
+        };
+    }
+    if point.is_infinity() {
+        return false;
+    }
+
+    let zinv = P.inv(&point.z);
+    let mut x = P.mul(&point.x, &P.mul(&zinv, &zinv));
+    if !N.contains(&x) {
+        x = sub(&x, &N.m).0;
+    }
+    x == sig.r
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::hex::FromHex;
+
+    use super::{verify, PublicKey, Signature};
+
+    // Key 0x1234567 signing sha256("abc")
+    const MSG: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    const COMPRESSED: &str = "02d47644539acec3da5e3ecf5fe8863c628a9c97e8b71e9ea9167a6f4f83c03c32";
+    const UNCOMPRESSED: &str = "04d47644539acec3da5e3ecf5fe8863c628a9c97e8b71e9ea9167a6f4f83c03c32\
+                                133d725f647850113deead6cf8dfa22043d74b81433c445d07092c2ed9751c9e";
+    const SIG: &str = "3044022076e042d0bdd1d965f91f2d7bce77f247b9fc3b0faed8a2e47f2b118088e2ddcd\
+                       0220197c3372f0eb292751df71d52deabce4a49505c5bee41a92c81e2b2db83fcde4";
+    const HIGH_S_SIG: &str = "3045022076e042d0bdd1d965f91f2d7bce77f247b9fc3b0faed8a2e47f2b118088e2ddcd\
+                              022100e683cc8d0f14d6d8ae208e2ad215431a1619d720f06485a8f7b4335f17f6735d";
+
+    fn msg(hex: &str) -> [u8; 32] {
+        let mut msg = [0u8; 32];
+        msg.copy_from_slice(&Vec::<u8>::from_hex(hex).unwrap());
+        msg
+    }
+
+    fn key(hex: &str) -> PublicKey {
+        PublicKey::from_slice(&Vec::<u8>::from_hex(hex).unwrap()).unwrap()
+    }
+
+    fn sig(hex: &str) -> Signature {
+        Signature::from_der_lax(&Vec::<u8>::from_hex(hex).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn verify_signature() {
+        assert_eq!(key(COMPRESSED), key(UNCOMPRESSED));
+        assert!(verify(&msg(MSG), &sig(SIG), &key(COMPRESSED)));
+        assert!(verify(&msg(MSG), &sig(HIGH_S_SIG), &key(UNCOMPRESSED)));
+
+        let mut other = msg(MSG);
+        other[31] ^= 1;
+        assert!(!verify(&other, &sig(SIG), &key(COMPRESSED)));
+    }
+
+    #[test]
+    fn parse_public_key() {
+        let mut data = Vec::<u8>::from_hex(COMPRESSED).unwrap();
+        // The other prefix selects the negated point
+        data[0] = 0x03;
+        let negated = PublicKey::from_slice(&data).unwrap();
+        assert!(negated != key(COMPRESSED));
+        assert!(!verify(&msg(MSG), &sig(SIG), &negated));
+
+        let mut data = Vec::<u8>::from_hex(UNCOMPRESSED).unwrap();
+        data[0] = 0x06;
+        assert_eq!(PublicKey::from_slice(&data), Some(key(COMPRESSED)));
+        data[0] = 0x07;
+        assert_eq!(PublicKey::from_slice(&data), None);
+        data[0] = 0x04;
+        data[64] ^= 1;
+        assert_eq!(PublicKey::from_slice(&data), None);
+        assert_eq!(PublicKey::from_slice(&data[..33]), None);
+    }
+
+    #[test]
+    fn parse_lax_der() {
+        let der = Vec::<u8>::from_hex(SIG).unwrap();
+        // Padded integers and long-form lengths are tolerated
+        let mut padded = vec![0x30, 0x81, 0x47, 0x02, 0x82, 0x00, 0x21, 0x00];
+        padded.extend_from_slice(&der[4..]);
+        padded.extend_from_slice(&[0x00, 0x01]);
+        assert_eq!(Signature::from_der_lax(&padded), Some(sig(SIG)));
+        assert_eq!(Signature::from_der_lax(&der[..der.len() - 1]), None);
+        assert_eq!(Signature::from_der_lax(&[0x30, 0x06, 0x02, 0x01, 0x00, 0x02, 0x01, 0x01]), None);
+    }
+}
@@ -1,9 +1,12 @@
 /// Hash functions
 
+use std::cmp::min;
 use std::default::Default;
 
 use hashes::{sha256d, Hash};
 
+use consensus::encode::Encodable;
+
 /// Any collection of objects for which a merkle root makes sense to be calculated
 pub trait MerkleRoot {
     /// Construct a merkle tree from a collection of elements, keeping their initial order
@@ -35,4 +38,122 @@ pub fn blockchain_merkle_root(data: Vec<sha256d::Hash>) -> sha256d::Hash {
 pub trait BlockchainHash {
     /// Return 256-bit hash from the object
     fn blockchain_hash(&self) -> sha256d::Hash;
-}
\ No newline at end of file
+}
+/// One SipHash round over the state
+fn sip_round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(13) ^ v[0];
+    v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(16) ^ v[2];
+    v[0] = v[0].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(21) ^ v[0];
+    v[2] = v[2].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(17) ^ v[2];
+    v[2] = v[2].rotate_left(32);
+}
+
+/// Return the SipHash-2-4 of the data under the 128-bit key `(k0, k1)`
+pub fn siphash24(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut v = [
+        k0 ^ 0x736f6d6570736575,
+        k1 ^ 0x646f72616e646f6d,
+        k0 ^ 0x6c7967656e657261,
+        k1 ^ 0x7465646279746573,
+    ];
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in chunks.by_ref() {
+        let mut word = [0u8; 8];
+        word.copy_from_slice(chunk);
+        let m = u64::from_le_bytes(word);
+        v[3] ^= m;
+        sip_round(&mut v);
+        sip_round(&mut v);
+        v[0] ^= m;
+    }
+
+    // Last block: remaining bytes and the length in the top byte
+    let mut last = (data.len() as u64) << 56;
+    for (i, byte) in chunks.remainder().iter().enumerate() {
+        last |= (*byte as u64) << (8 * i);
+    }
+    v[3] ^= last;
+    sip_round(&mut v);
+    sip_round(&mut v);
+    v[0] ^= last;
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        sip_round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+/// Return the 32-bit MurmurHash3 of the data with the given seed
+pub fn murmur3_32(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+
+    let mut h1 = seed;
+    let mut blocks = data.chunks_exact(4);
+    for block in blocks.by_ref() {
+        let mut k1 = u32::from_le_bytes([block[0], block[1], block[2], block[3]]);
+        k1 = k1.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        h1 ^= k1;
+        h1 = h1.rotate_left(13).wrapping_mul(5).wrapping_add(0xe6546b64);
+    }
+
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        let mut k1 = 0u32;
+        for (i, byte) in tail.iter().enumerate() {
+            k1 |= (*byte as u32) << (8 * i);
+        }
+        k1 = k1.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        h1 ^= k1;
+    }
+
+    // Finalization mix
+    h1 ^= data.len() as u32;
+    h1 ^= h1 >> 16;
+    h1 = h1.wrapping_mul(0x85ebca6b);
+    h1 ^= h1 >> 13;
+    h1 = h1.wrapping_mul(0xc2b2ae35);
+    h1 ^= h1 >> 16;
+    h1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{siphash24, murmur3_32};
+
+    #[test]
+    fn siphash24_vectors() {
+        // Reference vectors of the SipHash paper, key 00..0f and message 00..(n-1)
+        let (k0, k1) = (0x0706050403020100, 0x0f0e0d0c0b0a0908);
+        let data: Vec<u8> = (0..15).collect();
+        assert_eq!(siphash24(k0, k1, &[]), 0x726fdb47dd0e0e31);
+        assert_eq!(siphash24(k0, k1, &data[..1]), 0x74f839c593dc67fd);
+        assert_eq!(siphash24(k0, k1, &data[..8]), 0x93f5f5799a932462);
+        assert_eq!(siphash24(k0, k1, &data[..15]), 0xa129ca6149be45e5);
+    }
+
+    #[test]
+    fn murmur3_32_vectors() {
+        assert_eq!(murmur3_32(0x00000000, &[]), 0x00000000);
+        assert_eq!(murmur3_32(0xfba4c795, &[]), 0x6a396f08);
+        assert_eq!(murmur3_32(0xffffffff, &[]), 0x81f16f39);
+        assert_eq!(murmur3_32(0x00000000, &[0x00]), 0x514e28b7);
+        assert_eq!(murmur3_32(0xfba4c795, &[0x00]), 0xea3f0b17);
+        assert_eq!(murmur3_32(0x00000000, &[0xff]), 0xfd6cf10d);
+        assert_eq!(murmur3_32(0x00000000, &[0x00, 0x11]), 0x16c6b7ab);
+        assert_eq!(murmur3_32(0x00000000, &[0x00, 0x11, 0x22]), 0x8eb51c3d);
+        assert_eq!(murmur3_32(0x00000000, &[0x00, 0x11, 0x22, 0x33]), 0xb4471bf8);
+        assert_eq!(murmur3_32(0x00000000, &[0x00, 0x11, 0x22, 0x33, 0x44]), 0xe2301fa8);
+        assert_eq!(murmur3_32(0x00000000, &[0x00, 0x11, 0x22, 0x33, 0x44, 0x55]), 0xfc2e4a15);
+        assert_eq!(murmur3_32(0x00000000, &[0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66]), 0xb074502c);
+        assert_eq!(murmur3_32(0x00000000, &[0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]), 0x8034d2a0);
+        assert_eq!(murmur3_32(0x00000000, &[0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88]), 0xb4698def);
+    }
+}
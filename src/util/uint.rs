@@ -0,0 +1,233 @@
+/// 256-bit unsigned integer used for proof of work targets and chainwork
+///
+/// Stored as four 64-bit words, least significant first
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Sub, Mul, Div, Not, Shl, Shr};
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Uint256(pub [u64; 4]);
+
+impl Uint256 {
+    /// Create a value from a 64-bit uint
+    pub fn from_u64(value: u64) -> Option<Uint256> {
+        Some(Uint256([value, 0, 0, 0]))
+    }
+
+    /// Create a value from 32 little-endian bytes
+    pub fn from_le_bytes(bytes: &[u8; 32]) -> Uint256 {
+        let mut ret = [0u64; 4];
+        for (i, word) in ret.iter_mut().enumerate() {
+            for j in 0..8 {
+                *word |= (bytes[i * 8 + j] as u64) << (8 * j);
+            }
+        }
+        Uint256(ret)
+    }
+
+    /// Return the value as 32 little-endian bytes
+    pub fn to_le_bytes(&self) -> [u8; 32] {
+        let mut ret = [0u8; 32];
+        for (i, word) in self.0.iter().enumerate() {
+            for j in 0..8 {
+                ret[i * 8 + j] = (word >> (8 * j)) as u8;
+            }
+        }
+        ret
+    }
+
+    /// Return the lowest 32 bits
+    pub fn low_u32(&self) -> u32 {
+        self.0[0] as u32
+    }
+
+    /// Return the lowest 64 bits
+    pub fn low_u64(&self) -> u64 {
+        self.0[0]
+    }
+
+    /// Return the number of bits needed to represent the value
+    pub fn bits(&self) -> usize {
+        for i in (0..4).rev() {
+            if self.0[i] != 0 {
+                return 64 * i + 64 - self.0[i].leading_zeros() as usize;
+            }
+        }
+        0
+    }
+
+    /// Check if the value is zero
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 4]
+    }
+
+    /// Add one, wrapping around on overflow
+    pub fn increment(&mut self) {
+        for word in self.0.iter_mut() {
+            let (value, overflow) = word.overflowing_add(1);
+            *word = value;
+            if !overflow {
+                break;
+            }
+        }
+    }
+
+    /// Multiply by a 32-bit uint, dropping bits above the 256th
+    pub fn mul_u32(self, other: u32) -> Uint256 {
+        let mut carry = 0u64;
+        let mut ret = [0u64; 4];
+        for i in 0..4 {
+            let product = (self.0[i] as u128) * (other as u128) + carry as u128;
+            ret[i] = product as u64;
+            carry = (product >> 64) as u64;
+        }
+        Uint256(ret)
+    }
+
+    fn bit(&self, index: usize) -> bool {
+        (self.0[index / 64] >> (index % 64)) & 1 == 1
+    }
+}
+
+impl Ord for Uint256 {
+    fn cmp(&self, other: &Uint256) -> Ordering {
+        for i in (0..4).rev() {
+            match self.0[i].cmp(&other.0[i]) {
+                Ordering::Equal => continue,
+                ordering        => return ordering,
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for Uint256 {
+    fn partial_cmp(&self, other: &Uint256) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for Uint256 {
+    type Output = Uint256;
+
+    fn add(self, other: Uint256) -> Uint256 {
+        let mut carry = false;
+        let mut ret = [0u64; 4];
+        for i in 0..4 {
+            let (value, overflow1) = self.0[i].overflowing_add(other.0[i]);
+            let (value, overflow2) = value.overflowing_add(carry as u64);
+            ret[i] = value;
+            carry = overflow1 || overflow2;
+        }
+        Uint256(ret)
+    }
+}
+
+impl Sub for Uint256 {
+    type Output = Uint256;
+
+    fn sub(self, other: Uint256) -> Uint256 {
+        self + (!other + Uint256([1, 0, 0, 0]))
+    }
+}
+
+impl Mul for Uint256 {
+    type Output = Uint256;
+
+    fn mul(self, other: Uint256) -> Uint256 {
+        let mut ret = Uint256::default();
+        for i in 0..4 {
+            let low = other.0[i] as u32;
+            let high = (other.0[i] >> 32) as u32;
+            ret = ret + (self.mul_u32(low) << (64 * i));
+            ret = ret + (self.mul_u32(high) << (64 * i + 32));
+        }
+        ret
+    }
+}
+
+impl Div for Uint256 {
+    type Output = Uint256;
+
+    /// Binary long division; panics when dividing by zero
+    fn div(self, other: Uint256) -> Uint256 {
+        assert!(!other.is_zero(), "division by zero");
+        let mut quotient = Uint256::default();
+        let mut remainder = Uint256::default();
+        for index in (0..self.bits()).rev() {
+            remainder = remainder << 1;
+            if self.bit(index) {
+                remainder.0[0] |= 1;
+            }
+            if remainder >= other {
+                remainder = remainder - other;
+                quotient.0[index / 64] |= 1 << (index % 64);
+            }
+        }
+        quotient
+    }
+}
+
+impl Not for Uint256 {
+    type Output = Uint256;
+
+    fn not(self) -> Uint256 {
+        let Uint256(ref arr) = self;
+        Uint256([!arr[0], !arr[1], !arr[2], !arr[3]])
+    }
+}
+
+impl Shl<usize> for Uint256 {
+    type Output = Uint256;
+
+    fn shl(self, shift: usize) -> Uint256 {
+        let mut ret = [0u64; 4];
+        let word_shift = shift / 64;
+        let bit_shift = shift % 64;
+        for i in 0..4 {
+            if i + word_shift >= 4 {
+                break;
+            }
+            ret[i + word_shift] |= self.0[i] << bit_shift;
+            if bit_shift > 0 && i + word_shift + 1 < 4 {
+                ret[i + word_shift + 1] |= self.0[i] >> (64 - bit_shift);
+            }
+        }
+        Uint256(ret)
+    }
+}
+
+impl Shr<usize> for Uint256 {
+    type Output = Uint256;
+
+    fn shr(self, shift: usize) -> Uint256 {
+        let mut ret = [0u64; 4];
+        let word_shift = shift / 64;
+        let bit_shift = shift % 64;
+        for i in word_shift..4 {
+            ret[i - word_shift] |= self.0[i] >> bit_shift;
+            if bit_shift > 0 && i + 1 < 4 {
+                ret[i - word_shift] |= self.0[i + 1] << (64 - bit_shift);
+            }
+        }
+        Uint256(ret)
+    }
+}
+
+impl fmt::Debug for Uint256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Uint256(ref data) = *self;
+        write!(f, "0x")?;
+        for word in data.iter().rev() {
+            write!(f, "{:016x}", word)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Uint256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
@@ -0,0 +1,196 @@
+/// Full validation of blocks
+///
+/// `check_block` covers the rules that need nothing but the block,
+/// `check_block_contextual` those depending on its position in the chain,
+/// and `connect_block` runs both, then checks values, lock times, signature
+/// operations and scripts of the inputs against the coins they spend before
+/// applying the block to the unspent set. A block failing any check leaves the
+/// set untouched.
+
+use std::collections::HashSet;
+
+use blockdata::block::Block;
+use blockdata::coinbase::height_prefix;
+use blockdata::transaction::Transaction;
+use chain::checkpoints::skip_script_verification;
+use chain::headertree::{HeaderTree, HeaderEntry};
+use chain::time::median_time_past;
+use consensus::encode::{serialize, VarInt};
+use consensus::params::Params;
+use store::utxo::{BlockUndo, Coin, UtxoSet};
+use util::amount::money_range;
+use util::hash::BlockchainHash;
+use validation::error::Error;
+use validation::script::{ScriptInterpreter, ScriptVerifier, block_script_flags};
+use validation::transaction::{check_transaction, check_tx_inputs, legacy_sigops, sigop_cost,
+                              calculate_sequence_locks, check_sequence_locks};
+
+/// Maximum weight of a block
+pub const MAX_BLOCK_WEIGHT: usize = 4_000_000;
+
+/// Weight of a non-witness byte
+pub const WITNESS_SCALE_FACTOR: usize = 4;
+
+/// Maximum signature operations cost of a block
+pub const MAX_BLOCK_SIGOPS_COST: usize = 80_000;
+
+/// Return the weight of a block
+pub fn block_weight(block: &Block) -> usize {
+    let stripped_size = 80 + serialize(&VarInt(block.txdata.len() as u64)).len()
+        + block.txdata.iter().map(|tx| tx.base_size()).sum::<usize>();
+    stripped_size * (WITNESS_SCALE_FACTOR - 1) + serialize(block).len()
+}
+
+/// Check the rules that do not depend on the chain
+pub fn check_block(block: &Block) -> Result<(), Error> {
+    if block.txdata.is_empty() {
+        return Err(Error::ErrorMissingCoinbase);
+    }
+    if !block.check_merkle_root() {
+        return Err(Error::ErrorMerkleRoot);
+    }
+    // Duplicated transactions leave the merkle root unchanged,
+    // so they have to be caught separately
+    let mut txids = HashSet::new();
+    if !block.txdata.iter().all(|tx| txids.insert(tx.txid())) {
+        return Err(Error::ErrorDuplicateTransaction);
+    }
+
+    if block.txdata.len() * WITNESS_SCALE_FACTOR > MAX_BLOCK_WEIGHT || block_weight(block) > MAX_BLOCK_WEIGHT {
+        return Err(Error::ErrorBlockSize);
+    }
+
+    if !block.txdata[0].is_coin_base() {
+        return Err(Error::ErrorMissingCoinbase);
+    }
+    if block.txdata[1..].iter().any(|tx| tx.is_coin_base()) {
+        return Err(Error::ErrorMultipleCoinbase);
+    }
+    for tx in block.txdata.iter() {
+        check_transaction(tx)?;
+    }
+
+    let sigops: usize = block.txdata.iter().map(legacy_sigops).sum();
+    if sigops * WITNESS_SCALE_FACTOR > MAX_BLOCK_SIGOPS_COST {
+        return Err(Error::ErrorTooManySigops);
+    }
+    Ok(())
+}
+
+/// Check the rules depending on the height of the block and on the
+/// median time past of its parent
+pub fn check_block_contextual(block: &Block, height: u64, prev_median_time: u32, params: &Params) -> Result<(), Error> {
+    // Lock times are compared to the median time past once BIP113 is active
+    let lock_time_cutoff = if height >= params.csv_height { prev_median_time } else { block.header.timestamp };
+    if !block.txdata.iter().all(|tx| tx.is_final(height, lock_time_cutoff)) {
+        return Err(Error::ErrorNonFinal);
+    }
+
+    if height >= params.bip34_height {
+        let prefix = height_prefix(height);
+        if !block.txdata[0].input_ent[0].sig_script.as_bytes().starts_with(&prefix) {
+            return Err(Error::ErrorCoinbaseHeight);
+        }
+    }
+
+    if height >= params.segwit_height {
+        if !block.check_witness_commit() {
+            return Err(Error::ErrorWitnessCommitment);
+        }
+    } else if block.txdata.iter().any(|tx| tx.has_witness()) {
+        return Err(Error::ErrorWitnessCommitment);
+    }
+    Ok(())
+}
+
+/// Fully validate a block extending the best block of the unspent set and
+/// spend its inputs, returning the data needed to disconnect it. The header
+/// of the block must already be in the tree.
+pub fn connect_block(block: &Block, tree: &HeaderTree, utxo: &mut UtxoSet) -> Result<BlockUndo, Error> {
+    let entry = tree.get(&block.blockchain_hash()).ok_or(Error::ErrorUnknownBlock)?;
+    let height = entry.height();
+    let prev_median_time = match tree.get(&block.header.prev_blockhash) {
+        Some(prev) if height > 0 => median_time_past(tree, prev),
+        _                        => block.header.timestamp,
+    };
+
+    check_block(block)?;
+    check_block_contextual(block, height, prev_median_time, tree.params())?;
+
+    // Inputs are checked against a read-only view of the set, so a failing
+    // block never reaches the cache nor a flush
+    let undo = utxo.spent_coins(block, height as u32)?;
+    check_block_inputs(block, &undo, tree, entry, prev_median_time)?;
+    utxo.apply_block(block, height as u32, &undo)?;
+    Ok(undo)
+}
+
+/// Check the inputs of every transaction against the coins they spend
+fn check_block_inputs(block: &Block, undo: &BlockUndo, tree: &HeaderTree, entry: &HeaderEntry,
+                      prev_median_time: u32) -> Result<(), Error> {
+    let params = tree.params();
+    let height = entry.height();
+    let flags = block_script_flags(params, height);
+    let verify_scripts = !skip_script_verification(tree, entry);
+    let median_time_at = |height: u64| {
+        tree.ancestor(&entry.hash, height).map_or(0, |ancestor| median_time_past(tree, ancestor))
+    };
+
+    // Undo data lists the spent coins in the order of the inputs
+    let mut spent = undo.spent.iter().map(|&(_, ref coin)| coin);
+    let mut fees = 0u64;
+    let mut cost = 0usize;
+    for tx in block.txdata.iter() {
+        let coins: Vec<Coin> = if tx.is_coin_base() {
+            Vec::new()
+        } else {
+            spent.by_ref().take(tx.input_ent.len()).cloned().collect()
+        };
+
+        cost += sigop_cost(tx, &coins, flags);
+        if cost > MAX_BLOCK_SIGOPS_COST {
+            return Err(Error::ErrorTooManySigops);
+        }
+        if tx.is_coin_base() {
+            continue;
+        }
+
+        fees += check_tx_inputs(tx, &coins, height)?;
+        if !money_range(fees) {
+            return Err(Error::ErrorValueOutOfRange);
+        }
+
+        if height >= params.csv_height {
+            let locks = calculate_sequence_locks(tx, &coins, &median_time_at);
+            if !check_sequence_locks(&locks, height, prev_median_time) {
+                return Err(Error::ErrorSequenceLock);
+            }
+        }
+
+        if verify_scripts {
+            verify_tx_scripts(tx, &coins, flags, &ScriptInterpreter)?;
+        }
+    }
+
+    check_coinbase_value(&block.txdata[0], height, fees, params)
+}
+
+/// Check a coinbase does not pay more than the subsidy and the fees of its block
+pub fn check_coinbase_value(coinbase: &Transaction, height: u64, fees: u64, params: &Params) -> Result<(), Error> {
+    let value = coinbase.output_value();
+    let limit = params.block_subsidy(height) + fees;
+    if value > limit {
+        return Err(Error::ErrorCoinbaseValue { value: value, limit: limit });
+    }
+    Ok(())
+}
+
+/// Verify the script of every input of a transaction
+pub fn verify_tx_scripts(tx: &Transaction, coins: &[Coin], flags: u32, verifier: &ScriptVerifier) -> Result<(), Error> {
+    for (input, coin) in coins.iter().enumerate() {
+        if !verifier.verify_input(tx, input, &coin.txout, flags) {
+            return Err(Error::ErrorScript { txid: tx.txid(), input: input });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,257 @@
+/// Implementation of errors used in validation namespace
+///
+use std::{error, fmt};
+
+use hashes::sha256d;
+
+use blockdata;
+use blockdata::transaction::OutPoint;
+use store::error::Error as StoreError;
+
+#[derive(Debug)]
+pub enum Error {
+    // Block errors
+    /// Block is not known to the header tree
+    ErrorUnknownBlock,
+    /// Block exceeds the size or weight limit
+    ErrorBlockSize,
+    /// Merkle root does not match the transactions
+    ErrorMerkleRoot,
+    /// Witness commitment is missing or does not match the transactions
+    ErrorWitnessCommitment,
+    /// Block contains the same transaction twice
+    ErrorDuplicateTransaction,
+    /// First transaction is not a coinbase
+    ErrorMissingCoinbase,
+    /// A transaction other than the first one is a coinbase
+    ErrorMultipleCoinbase,
+    /// Coinbase script is shorter than 2 or longer than 100 bytes
+    ErrorCoinbaseScriptSize,
+    /// Coinbase does not start with the block height (BIP34)
+    ErrorCoinbaseHeight,
+    /// Coinbase claims more than the subsidy and the fees
+    ErrorCoinbaseValue { value: u64, limit: u64 },
+    /// Block exceeds the signature operations limit
+    ErrorTooManySigops,
+
+    // Transaction errors
+    /// Transaction has no inputs or no outputs
+    ErrorTransactionEmpty,
+    /// Transaction exceeds the block weight on its own
+    ErrorTransactionSize,
+    /// Transaction spends the same output twice
+    ErrorDuplicateInput,
+    /// Non-coinbase transaction spends the null outpoint
+    ErrorNullPrevout,
+    /// Output value or a sum of values is above the maximum amount
+    ErrorValueOutOfRange,
+    /// Spent output is not in the unspent set
+    ErrorMissingInput(OutPoint),
+    /// Coinbase output is spent before reaching maturity
+    ErrorPrematureCoinbaseSpend(OutPoint),
+    /// Outputs are worth more than the spent inputs
+    ErrorInputsBelowOutputs,
+    /// Lock time of the transaction is not reached
+    ErrorNonFinal,
+    /// Relative lock time of an input is not reached (BIP68)
+    ErrorSequenceLock,
+    /// Script of an input failed verification
+    ErrorScript { txid: sha256d::Hash, input: usize },
+
+    /// Unspent set could not be read or written
+    ErrorStore(StoreError),
+}
+
+impl Error {
+    /// Return the error reported to peers for invalid data; `None` when
+    /// the failure is local and says nothing about the validity of the block
+    pub fn blockdata_error(&self) -> Option<blockdata::Error> {
+        match *self {
+            Error::ErrorUnknownBlock               => None,
+            Error::ErrorStore(..)                  => None,
+            Error::ErrorMerkleRoot                 => Some(blockdata::Error::BlockMerkleRootError),
+            Error::ErrorWitnessCommitment          => Some(blockdata::Error::BlockMerkleRootError),
+            Error::ErrorDuplicateTransaction       => Some(blockdata::Error::BlockInvalidError),
+            Error::ErrorBlockSize                  => Some(blockdata::Error::BlockInvalidError),
+            Error::ErrorMissingCoinbase            => Some(blockdata::Error::BlockInvalidError),
+            Error::ErrorMultipleCoinbase           => Some(blockdata::Error::BlockInvalidError),
+            Error::ErrorCoinbaseScriptSize         => Some(blockdata::Error::BlockInvalidError),
+            Error::ErrorCoinbaseHeight             => Some(blockdata::Error::BlockInvalidError),
+            Error::ErrorCoinbaseValue { .. }       => Some(blockdata::Error::BlockInvalidError),
+            Error::ErrorTooManySigops              => Some(blockdata::Error::BlockInvalidError),
+            Error::ErrorTransactionEmpty           => Some(blockdata::Error::TransactionInvalidError),
+            Error::ErrorTransactionSize            => Some(blockdata::Error::TransactionInvalidError),
+            Error::ErrorDuplicateInput             => Some(blockdata::Error::TransactionInvalidError),
+            Error::ErrorNullPrevout                => Some(blockdata::Error::TransactionInvalidError),
+            Error::ErrorValueOutOfRange            => Some(blockdata::Error::TransactionInvalidError),
+            Error::ErrorMissingInput(..)           => Some(blockdata::Error::TransactionInvalidError),
+            Error::ErrorPrematureCoinbaseSpend(..) => Some(blockdata::Error::TransactionInvalidError),
+            Error::ErrorInputsBelowOutputs         => Some(blockdata::Error::TransactionInvalidError),
+            Error::ErrorNonFinal                   => Some(blockdata::Error::TransactionInvalidError),
+            Error::ErrorSequenceLock               => Some(blockdata::Error::TransactionInvalidError),
+            Error::ErrorScript { .. }              => Some(blockdata::Error::TransactionInvalidError),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::ErrorCoinbaseValue { value, limit } =>
+                write!(f, "{}: {} > {}", error::Error::description(self), value, limit),
+            Error::ErrorMissingInput(ref outpoint) =>
+                write!(f, "{}: {}", error::Error::description(self), outpoint),
+            Error::ErrorPrematureCoinbaseSpend(ref outpoint) =>
+                write!(f, "{}: {}", error::Error::description(self), outpoint),
+            Error::ErrorScript { ref txid, input } =>
+                write!(f, "{}: {}:{}", error::Error::description(self), txid, input),
+            Error::ErrorStore(ref e) =>
+                write!(f, "{}: {}", error::Error::description(self), e),
+            _ => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::ErrorStore(ref e) => Some(e),
+            _                        => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::ErrorUnknownBlock               => "block header is unknown",
+            Error::ErrorBlockSize                  => "block size or weight above the limit",
+            Error::ErrorMerkleRoot                 => "block merkle root mismatch",
+            Error::ErrorWitnessCommitment          => "witness commitment mismatch",
+            Error::ErrorDuplicateTransaction       => "duplicate transaction in block",
+            Error::ErrorMissingCoinbase            => "first transaction is not a coinbase",
+            Error::ErrorMultipleCoinbase           => "more than one coinbase",
+            Error::ErrorCoinbaseScriptSize         => "coinbase script size out of range",
+            Error::ErrorCoinbaseHeight             => "coinbase does not start with the block height",
+            Error::ErrorCoinbaseValue { .. }       => "coinbase pays too much",
+            Error::ErrorTooManySigops              => "too many signature operations",
+            Error::ErrorTransactionEmpty           => "transaction without inputs or outputs",
+            Error::ErrorTransactionSize            => "transaction size above the limit",
+            Error::ErrorDuplicateInput             => "transaction spends an output twice",
+            Error::ErrorNullPrevout                => "transaction spends the null outpoint",
+            Error::ErrorValueOutOfRange            => "transaction value out of range",
+            Error::ErrorMissingInput(..)           => "spent output missing or already spent",
+            Error::ErrorPrematureCoinbaseSpend(..) => "premature spend of coinbase",
+            Error::ErrorInputsBelowOutputs         => "transaction inputs below outputs",
+            Error::ErrorNonFinal                   => "non-final transaction",
+            Error::ErrorSequenceLock               => "relative lock time not reached",
+            Error::ErrorScript { .. }              => "script verification failed",
+            Error::ErrorStore(..)                  => "unspent set error",
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<StoreError> for Error {
+    fn from(e: StoreError) -> Error {
+        match e {
+            StoreError::ErrorMissingCoin(outpoint) => Error::ErrorMissingInput(outpoint),
+            e                                      => Error::ErrorStore(e),
+        }
+    }
+}
+
+/// Reasons for a script to fail verification
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ScriptError {
+    /// Script left a false or empty stack
+    ErrorEvalFalse,
+    /// OP_RETURN was executed
+    ErrorOpReturn,
+    /// Script is above the size limit
+    ErrorScriptSize,
+    /// Pushed element is above the size limit
+    ErrorPushSize,
+    /// Script runs too many operations
+    ErrorOpCount,
+    /// Stacks hold too many elements
+    ErrorStackSize,
+    /// Multisig signature count out of range
+    ErrorSigCount,
+    /// Multisig public key count out of range
+    ErrorPubkeyCount,
+    /// A verifying operation failed
+    ErrorVerify,
+    /// Unknown or reserved opcode, or push past the end of the script
+    ErrorBadOpcode,
+    /// Disabled opcode, failing even when not executed
+    ErrorDisabledOpcode,
+    /// Operation on more elements than the stack holds
+    ErrorInvalidStackOperation,
+    /// Conditional without a matching end or beginning
+    ErrorUnbalancedConditional,
+    /// Number operand longer than allowed
+    ErrorNumberSize,
+    /// Lock time operand is negative
+    ErrorNegativeLockTime,
+    /// Transaction does not meet the lock time required by the script
+    ErrorUnsatisfiedLockTime,
+    /// Signature is not strict DER (BIP66)
+    ErrorSigDer,
+    /// Extra element of a multisig is not empty (BIP147)
+    ErrorSigNullDummy,
+    /// Signature script of a pay-to-script-hash spend runs opcodes
+    ErrorSigPushOnly,
+    /// Version 0 witness program of a wrong length
+    ErrorWitnessProgramLength,
+    /// Witness of a script hash program is empty
+    ErrorWitnessProgramEmpty,
+    /// Witness does not match the program
+    ErrorWitnessProgramMismatch,
+    /// Native witness spend with a signature script
+    ErrorWitnessMalleated,
+    /// Nested witness spend with more than the redeem script push
+    ErrorWitnessMalleatedP2sh,
+    /// Witness given for an output without witness program
+    ErrorWitnessUnexpected,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(error::Error::description(self))
+    }
+}
+
+impl error::Error for ScriptError {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            ScriptError::ErrorEvalFalse              => "script evaluated to false",
+            ScriptError::ErrorOpReturn               => "OP_RETURN executed",
+            ScriptError::ErrorScriptSize             => "script size above the limit",
+            ScriptError::ErrorPushSize               => "push size above the limit",
+            ScriptError::ErrorOpCount                => "too many operations",
+            ScriptError::ErrorStackSize              => "stack size above the limit",
+            ScriptError::ErrorSigCount               => "signature count out of range",
+            ScriptError::ErrorPubkeyCount            => "public key count out of range",
+            ScriptError::ErrorVerify                 => "verify operation failed",
+            ScriptError::ErrorBadOpcode              => "invalid opcode",
+            ScriptError::ErrorDisabledOpcode         => "disabled opcode",
+            ScriptError::ErrorInvalidStackOperation  => "operation on too few stack elements",
+            ScriptError::ErrorUnbalancedConditional  => "unbalanced conditional",
+            ScriptError::ErrorNumberSize             => "script number too long",
+            ScriptError::ErrorNegativeLockTime       => "negative lock time",
+            ScriptError::ErrorUnsatisfiedLockTime    => "lock time not satisfied",
+            ScriptError::ErrorSigDer                 => "signature is not strict DER",
+            ScriptError::ErrorSigNullDummy           => "multisig dummy element not empty",
+            ScriptError::ErrorSigPushOnly            => "signature script is not push only",
+            ScriptError::ErrorWitnessProgramLength   => "witness program has a wrong length",
+            ScriptError::ErrorWitnessProgramEmpty    => "witness is empty",
+            ScriptError::ErrorWitnessProgramMismatch => "witness does not match the program",
+            ScriptError::ErrorWitnessMalleated       => "witness spend with a signature script",
+            ScriptError::ErrorWitnessMalleatedP2sh   => "nested witness spend with extra data",
+            ScriptError::ErrorWitnessUnexpected      => "unexpected witness",
+        }
+    }
+}
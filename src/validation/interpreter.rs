@@ -0,0 +1,834 @@
+/// Script interpreter
+///
+/// Runs the signature script of an input, then the locking script of the
+/// output it spends, then the redeem script or witness program they commit
+/// to, following the consensus rules selected by the `VERIFY_*` flags.
+/// Standardness rules of the reference client are not enforced here.
+
+use hashes::{hash160, ripemd160, sha1, sha256, sha256d, Hash};
+
+use blockdata::script::*;
+use blockdata::transaction::{Transaction, TxOut, LOCKTIME_THRESHOLD, SEQUENCE_FINAL,
+                             SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_TYPE_FLAG,
+                             SEQUENCE_LOCKTIME_MASK};
+use util::ecdsa::{self, PublicKey, Signature};
+use validation::error::ScriptError;
+use validation::script::{VERIFY_P2SH, VERIFY_DERSIG, VERIFY_NULLDUMMY, VERIFY_CHECKLOCKTIMEVERIFY,
+                         VERIFY_CHECKSEQUENCEVERIFY, VERIFY_WITNESS};
+
+/// Maximum number of non-push operations in a script
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+
+/// Maximum number of elements on the stack and the alternate stack
+pub const MAX_STACK_SIZE: usize = 1000;
+
+/// Rules a script runs under: the original ones, or those of version 0
+/// witness programs (BIP143)
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum SigVersion {
+    Base,
+    WitnessV0,
+}
+
+/// The input whose scripts are run, for signature and lock time checks
+struct Checker<'a> {
+    tx: &'a Transaction,
+    input: usize,
+    value: u64,
+}
+
+impl<'a> Checker<'a> {
+    /// Check a signature with its sighash type byte against a public key
+    fn check_sig(&self, sig: &[u8], pubkey: &[u8], script_code: &[u8], version: SigVersion) -> bool {
+        let key = match PublicKey::from_slice(pubkey) {
+            Some(key) => key,
+            None      => return false,
+        };
+        let (sighash_type, der) = match sig.split_last() {
+            Some((&sighash_type, der)) => (sighash_type as u32, der),
+            None                       => return false,
+        };
+        let signature = match Signature::from_der_lax(der) {
+            Some(signature) => signature,
+            None            => return false,
+        };
+        let hash = match version {
+            SigVersion::Base      => self.tx.signature_hash(self.input, script_code, sighash_type),
+            SigVersion::WitnessV0 => self.tx.segwit_signature_hash(self.input, script_code, self.value, sighash_type),
+        };
+        let mut msg = [0u8; 32];
+        msg.copy_from_slice(&hash[..]);
+        ecdsa::verify(&msg, &signature, &key)
+    }
+
+    /// Check the transaction lock time is at least `lock_time`, of the same kind (BIP65)
+    fn check_lock_time(&self, lock_time: i64) -> bool {
+        let tx_lock_time = self.tx.lock_time as i64;
+        let threshold = LOCKTIME_THRESHOLD as i64;
+        if (tx_lock_time < threshold) != (lock_time < threshold) || lock_time > tx_lock_time {
+            return false;
+        }
+        // A final input would let the transaction ignore its lock time
+        self.tx.input_ent[self.input].sequence != SEQUENCE_FINAL
+    }
+
+    /// Check the relative lock time of the input is at least `sequence`, of the same kind (BIP112)
+    fn check_sequence(&self, sequence: i64) -> bool {
+        let tx_sequence = self.tx.input_ent[self.input].sequence;
+        if (self.tx.version as i32) < 2 || tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return false;
+        }
+        let mask = SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK;
+        let tx_sequence = tx_sequence & mask;
+        let sequence = sequence as u32 & mask;
+        if (tx_sequence < SEQUENCE_LOCKTIME_TYPE_FLAG) != (sequence < SEQUENCE_LOCKTIME_TYPE_FLAG) {
+            return false;
+        }
+        sequence <= tx_sequence
+    }
+}
+
+/// Read a script number of at most `max_size` bytes
+fn read_num(data: &[u8], max_size: usize) -> Result<i64, ScriptError> {
+    if data.len() > max_size {
+        return Err(ScriptError::ErrorNumberSize);
+    }
+    let mut value = 0i64;
+    for (i, byte) in data.iter().enumerate() {
+        value |= (*byte as i64) << (8 * i);
+    }
+    // The highest bit of the last byte is the sign
+    match data.last() {
+        Some(last) if last & 0x80 != 0 => Ok(-(value & !(0x80i64 << (8 * (data.len() - 1))))),
+        _                               => Ok(value),
+    }
+}
+
+/// Interpret stack data as a boolean: false is any encoding of zero,
+/// including negative zero
+fn cast_to_bool(data: &[u8]) -> bool {
+    for (i, byte) in data.iter().enumerate() {
+        if *byte != 0 {
+            return !(i == data.len() - 1 && *byte == 0x80);
+        }
+    }
+    false
+}
+
+fn push_bool(stack: &mut Vec<Vec<u8>>, value: bool) {
+    stack.push(if value { vec![1] } else { vec![] });
+}
+
+/// Fail unless the stack holds at least `count` elements
+fn require(stack: &[Vec<u8>], count: usize) -> Result<(), ScriptError> {
+    if stack.len() < count {
+        return Err(ScriptError::ErrorInvalidStackOperation);
+    }
+    Ok(())
+}
+
+/// Return the element `depth` positions from the top, the top being 1
+fn top(stack: &[Vec<u8>], depth: usize) -> &[u8] {
+    &stack[stack.len() - depth]
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, ScriptError> {
+    stack.pop().ok_or(ScriptError::ErrorInvalidStackOperation)
+}
+
+/// Check a signature is strict DER followed by a sighash type byte (BIP66)
+fn is_valid_signature_encoding(sig: &[u8]) -> bool {
+    let len = sig.len();
+    if len < 9 || len > 73 || sig[0] != 0x30 || sig[1] as usize != len - 3 {
+        return false;
+    }
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= len {
+        return false;
+    }
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != len {
+        return false;
+    }
+    // Both integers are positive and not padded with a needless zero
+    if sig[2] != 0x02 || len_r == 0 || sig[4] & 0x80 != 0 || (len_r > 1 && sig[4] == 0 && sig[5] & 0x80 == 0) {
+        return false;
+    }
+    if sig[len_r + 4] != 0x02 || len_s == 0 || sig[len_r + 6] & 0x80 != 0
+        || (len_s > 1 && sig[len_r + 6] == 0 && sig[len_r + 7] & 0x80 == 0) {
+        return false;
+    }
+    true
+}
+
+fn check_signature_encoding(sig: &[u8], flags: u32) -> Result<(), ScriptError> {
+    if !sig.is_empty() && flags & VERIFY_DERSIG != 0 && !is_valid_signature_encoding(sig) {
+        return Err(ScriptError::ErrorSigDer);
+    }
+    Ok(())
+}
+
+/// Remove every push of `sig` found at an instruction boundary of the script,
+/// as signatures of legacy scripts cannot sign themselves
+fn find_and_delete(script: &[u8], sig: &[u8]) -> Vec<u8> {
+    let pattern = Builder::new().push_slice(sig).into_script();
+    let pattern = pattern.as_bytes();
+    let mut result = Vec::with_capacity(script.len());
+    let mut position = 0;
+    let mut copied = 0;
+    let mut found = false;
+    loop {
+        result.extend_from_slice(&script[copied..position]);
+        while script.len() - position >= pattern.len() && &script[position..position + pattern.len()] == pattern {
+            position += pattern.len();
+            found = true;
+        }
+        copied = position;
+        let mut instructions = Instructions::new(&script[position..]);
+        match instructions.next() {
+            Some(_) => position += instructions.position(),
+            None    => break,
+        }
+    }
+    if !found {
+        return script.to_vec();
+    }
+    result.extend_from_slice(&script[copied..]);
+    result
+}
+
+/// Run a script on the given stack
+fn eval_script(stack: &mut Vec<Vec<u8>>, script: &[u8], flags: u32, checker: &Checker,
+               version: SigVersion) -> Result<(), ScriptError> {
+    if script.len() > MAX_SCRIPT_SIZE {
+        return Err(ScriptError::ErrorScriptSize);
+    }
+    // Whether each enclosing conditional branch runs
+    let mut exec: Vec<bool> = Vec::new();
+    let mut altstack: Vec<Vec<u8>> = Vec::new();
+    let mut op_count = 0;
+    // Signatures sign the script from the last code separator on
+    let mut code_start = 0;
+
+    let mut instructions = Instructions::new(script);
+    while let Some(ins) = instructions.next() {
+        let executing = exec.iter().all(|&branch| branch);
+        let opcode = ins.opcode;
+
+        if ins.data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+            return Err(ScriptError::ErrorPushSize);
+        }
+        if opcode > OP_16 {
+            op_count += 1;
+            if op_count > MAX_OPS_PER_SCRIPT {
+                return Err(ScriptError::ErrorOpCount);
+            }
+        }
+        match opcode {
+            OP_CAT | OP_SUBSTR | OP_LEFT | OP_RIGHT | OP_INVERT | OP_AND | OP_OR | OP_XOR
+            | OP_2MUL | OP_2DIV | OP_MUL | OP_DIV | OP_MOD | OP_LSHIFT | OP_RSHIFT => {
+                return Err(ScriptError::ErrorDisabledOpcode);
+            },
+            _ => {}
+        }
+
+        if executing && opcode <= OP_PUSHDATA4 {
+            stack.push(ins.data.to_vec());
+        } else if executing || (opcode >= OP_IF && opcode <= OP_ENDIF) {
+            match opcode {
+                OP_1NEGATE | OP_1..=OP_16 => {
+                    stack.push(serialize_script_num(opcode as i64 - (OP_1 as i64 - 1)));
+                },
+
+                OP_NOP | OP_NOP1 | OP_NOP4..=OP_NOP10 => {},
+
+                OP_CHECKLOCKTIMEVERIFY => {
+                    if flags & VERIFY_CHECKLOCKTIMEVERIFY != 0 {
+                        require(stack, 1)?;
+                        // Five bytes, as lock times reach 2^32 - 1
+                        let lock_time = read_num(top(stack, 1), 5)?;
+                        if lock_time < 0 {
+                            return Err(ScriptError::ErrorNegativeLockTime);
+                        }
+                        if !checker.check_lock_time(lock_time) {
+                            return Err(ScriptError::ErrorUnsatisfiedLockTime);
+                        }
+                    }
+                },
+
+                OP_CHECKSEQUENCEVERIFY => {
+                    if flags & VERIFY_CHECKSEQUENCEVERIFY != 0 {
+                        require(stack, 1)?;
+                        let sequence = read_num(top(stack, 1), 5)?;
+                        if sequence < 0 {
+                            return Err(ScriptError::ErrorNegativeLockTime);
+                        }
+                        // The disable flag keeps the opcode a NOP for future uses
+                        if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 == 0 && !checker.check_sequence(sequence) {
+                            return Err(ScriptError::ErrorUnsatisfiedLockTime);
+                        }
+                    }
+                },
+
+                OP_IF | OP_NOTIF => {
+                    let mut value = false;
+                    if executing {
+                        let condition = stack.pop().ok_or(ScriptError::ErrorUnbalancedConditional)?;
+                        value = cast_to_bool(&condition) != (opcode == OP_NOTIF);
+                    }
+                    exec.push(value);
+                },
+
+                OP_ELSE => {
+                    let branch = exec.last_mut().ok_or(ScriptError::ErrorUnbalancedConditional)?;
+                    *branch = !*branch;
+                },
+
+                OP_ENDIF => {
+                    exec.pop().ok_or(ScriptError::ErrorUnbalancedConditional)?;
+                },
+
+                OP_VERIFY => {
+                    if !cast_to_bool(&pop(stack)?) {
+                        return Err(ScriptError::ErrorVerify);
+                    }
+                },
+
+                OP_RETURN => return Err(ScriptError::ErrorOpReturn),
+
+                OP_TOALTSTACK => {
+                    let item = pop(stack)?;
+                    altstack.push(item);
+                },
+
+                OP_FROMALTSTACK => {
+                    let item = pop(&mut altstack)?;
+                    stack.push(item);
+                },
+
+                OP_2DROP => {
+                    require(stack, 2)?;
+                    let len = stack.len();
+                    stack.truncate(len - 2);
+                },
+
+                OP_2DUP | OP_3DUP => {
+                    let count = if opcode == OP_2DUP { 2 } else { 3 };
+                    require(stack, count)?;
+                    let items = stack[stack.len() - count..].to_vec();
+                    stack.extend(items);
+                },
+
+                OP_2OVER => {
+                    require(stack, 4)?;
+                    let len = stack.len();
+                    let items = stack[len - 4..len - 2].to_vec();
+                    stack.extend(items);
+                },
+
+                OP_2ROT => {
+                    require(stack, 6)?;
+                    let len = stack.len();
+                    let items: Vec<Vec<u8>> = stack.drain(len - 6..len - 4).collect();
+                    stack.extend(items);
+                },
+
+                OP_2SWAP => {
+                    require(stack, 4)?;
+                    let len = stack.len();
+                    stack.swap(len - 4, len - 2);
+                    stack.swap(len - 3, len - 1);
+                },
+
+                OP_IFDUP => {
+                    require(stack, 1)?;
+                    if cast_to_bool(top(stack, 1)) {
+                        let item = top(stack, 1).to_vec();
+                        stack.push(item);
+                    }
+                },
+
+                OP_DEPTH => {
+                    let depth = stack.len() as i64;
+                    stack.push(serialize_script_num(depth));
+                },
+
+                OP_DROP => {
+                    pop(stack)?;
+                },
+
+                OP_DUP => {
+                    require(stack, 1)?;
+                    let item = top(stack, 1).to_vec();
+                    stack.push(item);
+                },
+
+                OP_NIP => {
+                    require(stack, 2)?;
+                    let len = stack.len();
+                    stack.remove(len - 2);
+                },
+
+                OP_OVER => {
+                    require(stack, 2)?;
+                    let item = top(stack, 2).to_vec();
+                    stack.push(item);
+                },
+
+                OP_PICK | OP_ROLL => {
+                    require(stack, 2)?;
+                    let depth = read_num(&pop(stack)?, 4)?;
+                    if depth < 0 || depth as usize >= stack.len() {
+                        return Err(ScriptError::ErrorInvalidStackOperation);
+                    }
+                    let index = stack.len() - 1 - depth as usize;
+                    let item = if opcode == OP_ROLL { stack.remove(index) } else { stack[index].clone() };
+                    stack.push(item);
+                },
+
+                OP_ROT => {
+                    require(stack, 3)?;
+                    let len = stack.len();
+                    stack.swap(len - 3, len - 2);
+                    stack.swap(len - 2, len - 1);
+                },
+
+                OP_SWAP => {
+                    require(stack, 2)?;
+                    let len = stack.len();
+                    stack.swap(len - 2, len - 1);
+                },
+
+                OP_TUCK => {
+                    require(stack, 2)?;
+                    let len = stack.len();
+                    let item = top(stack, 1).to_vec();
+                    stack.insert(len - 2, item);
+                },
+
+                OP_SIZE => {
+                    require(stack, 1)?;
+                    let size = top(stack, 1).len() as i64;
+                    stack.push(serialize_script_num(size));
+                },
+
+                OP_EQUAL | OP_EQUALVERIFY => {
+                    require(stack, 2)?;
+                    let equal = pop(stack)? == pop(stack)?;
+                    if opcode == OP_EQUAL {
+                        push_bool(stack, equal);
+                    } else if !equal {
+                        return Err(ScriptError::ErrorVerify);
+                    }
+                },
+
+                OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+                    let value = read_num(&pop(stack)?, 4)?;
+                    let result = match opcode {
+                        OP_1ADD   => value + 1,
+                        OP_1SUB   => value - 1,
+                        OP_NEGATE => -value,
+                        OP_ABS    => value.abs(),
+                        OP_NOT    => (value == 0) as i64,
+                        _         => (value != 0) as i64,
+                    };
+                    stack.push(serialize_script_num(result));
+                },
+
+                OP_ADD | OP_SUB | OP_BOOLAND | OP_BOOLOR | OP_NUMEQUAL | OP_NUMEQUALVERIFY | OP_NUMNOTEQUAL
+                | OP_LESSTHAN | OP_GREATERTHAN | OP_LESSTHANOREQUAL | OP_GREATERTHANOREQUAL | OP_MIN | OP_MAX => {
+                    require(stack, 2)?;
+                    let b = read_num(&pop(stack)?, 4)?;
+                    let a = read_num(&pop(stack)?, 4)?;
+                    let result = match opcode {
+                        OP_ADD                => a + b,
+                        OP_SUB                => a - b,
+                        OP_BOOLAND            => (a != 0 && b != 0) as i64,
+                        OP_BOOLOR             => (a != 0 || b != 0) as i64,
+                        OP_NUMEQUAL           => (a == b) as i64,
+                        OP_NUMEQUALVERIFY     => (a == b) as i64,
+                        OP_NUMNOTEQUAL        => (a != b) as i64,
+                        OP_LESSTHAN           => (a < b) as i64,
+                        OP_GREATERTHAN        => (a > b) as i64,
+                        OP_LESSTHANOREQUAL    => (a <= b) as i64,
+                        OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                        OP_MIN                => a.min(b),
+                        _                     => a.max(b),
+                    };
+                    if opcode != OP_NUMEQUALVERIFY {
+                        stack.push(serialize_script_num(result));
+                    } else if result == 0 {
+                        return Err(ScriptError::ErrorVerify);
+                    }
+                },
+
+                OP_WITHIN => {
+                    require(stack, 3)?;
+                    let max = read_num(&pop(stack)?, 4)?;
+                    let min = read_num(&pop(stack)?, 4)?;
+                    let value = read_num(&pop(stack)?, 4)?;
+                    push_bool(stack, min <= value && value < max);
+                },
+
+                OP_RIPEMD160 | OP_SHA1 | OP_SHA256 | OP_HASH160 | OP_HASH256 => {
+                    let data = pop(stack)?;
+                    let hash = match opcode {
+                        OP_RIPEMD160 => ripemd160::Hash::hash(&data)[..].to_vec(),
+                        OP_SHA1      => sha1::Hash::hash(&data)[..].to_vec(),
+                        OP_SHA256    => sha256::Hash::hash(&data)[..].to_vec(),
+                        OP_HASH160   => hash160::Hash::hash(&data)[..].to_vec(),
+                        _            => sha256d::Hash::hash(&data)[..].to_vec(),
+                    };
+                    stack.push(hash);
+                },
+
+                OP_CODESEPARATOR => code_start = instructions.position(),
+
+                OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                    require(stack, 2)?;
+                    let success = {
+                        let sig = top(stack, 2);
+                        let pubkey = top(stack, 1);
+                        let mut script_code = script[code_start..].to_vec();
+                        if version == SigVersion::Base {
+                            script_code = find_and_delete(&script_code, sig);
+                        }
+                        check_signature_encoding(sig, flags)?;
+                        checker.check_sig(sig, pubkey, &script_code, version)
+                    };
+                    let len = stack.len();
+                    stack.truncate(len - 2);
+                    if opcode == OP_CHECKSIG {
+                        push_bool(stack, success);
+                    } else if !success {
+                        return Err(ScriptError::ErrorVerify);
+                    }
+                },
+
+                OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                    // Stack from the top: key count, keys, signature count,
+                    // signatures and one extra element
+                    let mut depth = 1;
+                    require(stack, depth)?;
+                    let mut keys = read_num(top(stack, depth), 4)?;
+                    if keys < 0 || keys > MAX_PUBKEYS_PER_MULTISIG as i64 {
+                        return Err(ScriptError::ErrorPubkeyCount);
+                    }
+                    op_count += keys as usize;
+                    if op_count > MAX_OPS_PER_SCRIPT {
+                        return Err(ScriptError::ErrorOpCount);
+                    }
+                    let mut key_depth = depth + 1;
+                    depth += 1 + keys as usize;
+                    require(stack, depth)?;
+
+                    let mut sigs = read_num(top(stack, depth), 4)?;
+                    if sigs < 0 || sigs > keys {
+                        return Err(ScriptError::ErrorSigCount);
+                    }
+                    let mut sig_depth = depth + 1;
+                    depth += 1 + sigs as usize;
+                    require(stack, depth)?;
+
+                    let mut script_code = script[code_start..].to_vec();
+                    if version == SigVersion::Base {
+                        for k in 0..sigs as usize {
+                            script_code = find_and_delete(&script_code, top(stack, sig_depth + k));
+                        }
+                    }
+
+                    // Signatures must match the keys in order, so give up as
+                    // soon as too few keys remain for the signatures left
+                    let mut success = true;
+                    while success && sigs > 0 {
+                        let sig = top(stack, sig_depth);
+                        let pubkey = top(stack, key_depth);
+                        check_signature_encoding(sig, flags)?;
+                        if checker.check_sig(sig, pubkey, &script_code, version) {
+                            sig_depth += 1;
+                            sigs -= 1;
+                        }
+                        key_depth += 1;
+                        keys -= 1;
+                        if sigs > keys {
+                            success = false;
+                        }
+                    }
+
+                    let len = stack.len();
+                    stack.truncate(len + 1 - depth);
+                    // The extra element, popped by mistake in the original client
+                    let dummy = pop(stack)?;
+                    if flags & VERIFY_NULLDUMMY != 0 && !dummy.is_empty() {
+                        return Err(ScriptError::ErrorSigNullDummy);
+                    }
+                    if opcode == OP_CHECKMULTISIG {
+                        push_bool(stack, success);
+                    } else if !success {
+                        return Err(ScriptError::ErrorVerify);
+                    }
+                },
+
+                _ => return Err(ScriptError::ErrorBadOpcode),
+            }
+        }
+
+        if stack.len() + altstack.len() > MAX_STACK_SIZE {
+            return Err(ScriptError::ErrorStackSize);
+        }
+    }
+
+    if instructions.failed() {
+        return Err(ScriptError::ErrorBadOpcode);
+    }
+    if !exec.is_empty() {
+        return Err(ScriptError::ErrorUnbalancedConditional);
+    }
+    Ok(())
+}
+
+/// Fail unless the top of the stack is true
+fn check_true(stack: &[Vec<u8>]) -> Result<(), ScriptError> {
+    match stack.last() {
+        Some(item) if cast_to_bool(item) => Ok(()),
+        _                                => Err(ScriptError::ErrorEvalFalse),
+    }
+}
+
+/// Run a witness program against the witness of the input (BIP141)
+fn verify_witness_program(witness: &[Vec<u8>], version: u8, program: &[u8], flags: u32,
+                          checker: &Checker) -> Result<(), ScriptError> {
+    // Unknown versions are left for future soft forks
+    if version != 0 {
+        return Ok(());
+    }
+    let (script, mut stack) = match program.len() {
+        32 => {
+            let (script, stack) = witness.split_last().ok_or(ScriptError::ErrorWitnessProgramEmpty)?;
+            if sha256::Hash::hash(script)[..] != *program {
+                return Err(ScriptError::ErrorWitnessProgramMismatch);
+            }
+            (Script::from(script.clone()), stack.to_vec())
+        },
+        20 => {
+            if witness.len() != 2 {
+                return Err(ScriptError::ErrorWitnessProgramMismatch);
+            }
+            let script = Builder::new()
+                .push_opcode(OP_DUP)
+                .push_opcode(OP_HASH160)
+                .push_slice(program)
+                .push_opcode(OP_EQUALVERIFY)
+                .push_opcode(OP_CHECKSIG)
+                .into_script();
+            (script, witness.to_vec())
+        },
+        _ => return Err(ScriptError::ErrorWitnessProgramLength),
+    };
+    if stack.iter().any(|item| item.len() > MAX_SCRIPT_ELEMENT_SIZE) {
+        return Err(ScriptError::ErrorPushSize);
+    }
+
+    eval_script(&mut stack, script.as_bytes(), flags, checker, SigVersion::WitnessV0)?;
+    // Witness scripts must leave a single true element
+    if stack.len() != 1 {
+        return Err(ScriptError::ErrorEvalFalse);
+    }
+    check_true(&stack)
+}
+
+/// Verify that input `input` of `tx` may spend `spent` under the given flags
+pub fn verify_script(tx: &Transaction, input: usize, spent: &TxOut, flags: u32) -> Result<(), ScriptError> {
+    let txin = &tx.input_ent[input];
+    let checker = Checker { tx: tx, input: input, value: spent.value_out };
+    let sig_script = &txin.sig_script;
+    let pkscript = &spent.pkscript;
+
+    let mut stack = Vec::new();
+    eval_script(&mut stack, sig_script.as_bytes(), flags, &checker, SigVersion::Base)?;
+    let p2sh_stack = if flags & VERIFY_P2SH != 0 { stack.clone() } else { Vec::new() };
+    eval_script(&mut stack, pkscript.as_bytes(), flags, &checker, SigVersion::Base)?;
+    check_true(&stack)?;
+
+    let mut had_witness = false;
+    if flags & VERIFY_WITNESS != 0 {
+        if let Some((version, program)) = pkscript.witness_program() {
+            had_witness = true;
+            if !sig_script.is_empty() {
+                return Err(ScriptError::ErrorWitnessMalleated);
+            }
+            verify_witness_program(&txin.witness, version, program, flags, &checker)?;
+        }
+    }
+
+    if flags & VERIFY_P2SH != 0 && pkscript.is_p2sh() {
+        if !sig_script.is_push_only() {
+            return Err(ScriptError::ErrorSigPushOnly);
+        }
+        // Run the redeem script, the last push of the signature script,
+        // on what the signature script left below it
+        let mut stack = p2sh_stack;
+        let redeem_script = Script::from(pop(&mut stack)?);
+        eval_script(&mut stack, redeem_script.as_bytes(), flags, &checker, SigVersion::Base)?;
+        check_true(&stack)?;
+
+        if flags & VERIFY_WITNESS != 0 {
+            if let Some((version, program)) = redeem_script.witness_program() {
+                had_witness = true;
+                if sig_script.as_bytes() != Builder::new().push_slice(redeem_script.as_bytes()).as_bytes() {
+                    return Err(ScriptError::ErrorWitnessMalleatedP2sh);
+                }
+                verify_witness_program(&txin.witness, version, program, flags, &checker)?;
+            }
+        }
+    }
+
+    if flags & VERIFY_WITNESS != 0 && !had_witness && !txin.witness.is_empty() {
+        return Err(ScriptError::ErrorWitnessUnexpected);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::hex::FromHex;
+
+    use blockdata::script::Script;
+    use blockdata::transaction::{Transaction, TxIn, TxOut};
+    use consensus::encode::deserialize;
+    use validation::error::ScriptError;
+    use validation::script::{VERIFY_P2SH, VERIFY_DERSIG, VERIFY_NULLDUMMY, VERIFY_CHECKLOCKTIMEVERIFY,
+                             VERIFY_CHECKSEQUENCEVERIFY, VERIFY_WITNESS};
+    use super::verify_script;
+
+    const ALL_FLAGS: u32 = VERIFY_P2SH | VERIFY_DERSIG | VERIFY_NULLDUMMY | VERIFY_CHECKLOCKTIMEVERIFY
+        | VERIFY_CHECKSEQUENCEVERIFY | VERIFY_WITNESS;
+
+    /// Spends of a pay-to-pubkey-hash output with SIGHASH_ALL, an uncompressed
+    /// pay-to-pubkey output with SIGHASH_NONE, and a pay-to-pubkey-hash output
+    /// with SIGHASH_SINGLE on an input without matching output
+    const LEGACY_TX: &str = "02000000034bf5122f344554c53bde2ebb8cd2b7e3d1600ad631c385a5d7cce23c7785459a0000000069463043021f3397540bbe03b701b23ee0e00432c8d3e67f89f5ec3bf1468809a6aa2a365602204b3ed247cfd07cac583177270ec275ff2b4f3fb7e19e64a8b298845cfee52adf0121027592aab5d43618dda13fba71e3993cd7517a712d3da49664c06ee1bd3d1f70afffffffffdbc1b4c900ffe48d575b5da5c638040125f65db0fe3e24494b76ea986457d9860100000049483045022100e8b11fb52dbd935ac01cecf23032647d81eeb16b084d87387fef988dfaea7d8302204293270f93eab2c3a77e26f6edc03f9df73ce9076c6bd099dbc0cbabeb445dde02feffffff084fed08b978af4d7d196a7446a86b58009e636b611db16211b65a9aadff29c5020000006a473044022063a43b480606995b350eaa02aca25b83f2e3c118e2511f2a99f7e3d772301ace02205c83affc520241f48565ce56c3fdad22c3c4391b6b1b2ee3b467496d81a3d2b9032102ec6d499aefd540e90357f1004a136049d1f7df5ad99c44c46e3ed4169e40acb6050000000210270000000000001976a914925d4028880bd0c9d68fbc7fc7dfee976698629c88ac204e000000000000016a00000000";
+
+    /// Spends of a 2-of-3 multisig behind pay-to-script-hash and of a
+    /// pay-to-witness-pubkey-hash output worth 50000
+    const SEGWIT_TX: &str = "01000000000102e52d9c508c502347344d8c07ad91cbd6068afc75ff6292f062a09ca381c89e7100000000fdfe000048304502210097cb0989b6a5ac02127f3084a163eecb406660060adb22aa8f918f805d3d6d6a02203b8915a7a4af9df86fc6f6b368080e77a71927f1a8778fe0c60df7b9e52d2b6f01483045022100ff21f53159b17f37489934a7ede2d65c8378b1e222bf52cb0cdbf02fc000f11f022035f89476aa74ec547a703b566cdd81474878144f2a59c9e1450286c435a2cd13014c695221027592aab5d43618dda13fba71e3993cd7517a712d3da49664c06ee1bd3d1f70af2102e5740e63bad28081ed7cf654dd6c19029ca03382fc05ab5f5dda81f2c55b845b2102ec6d499aefd540e90357f1004a136049d1f7df5ad99c44c46e3ed4169e40acb653aeffffffffe77b9a9ae9e30b0dbdb6f510a264ef9de781501d7b6b92ae89eb059c5ab743db0000000000ffffffff0130750000000000001600144446e61cc01c95bc0e6808b88bea7eb1420521ce000247304402206094110744edf2ebe4994cce71f387afbcdeac8e2099f9e3cc27b60e6775fd8c02201789a9fb8b43eae08fdec32d78421b6447e4e10ea5edeac363a6db5c71133d6601210271550e6c83a9381f35c568d1a80e11fa3e0efc97dfd0e0f17492a2edb64c37a900000000";
+
+    /// Spend of a 1-of-2 multisig witness script nested in pay-to-script-hash,
+    /// worth 120000, signed with SIGHASH_SINGLE | SIGHASH_ANYONECANPAY
+    const NESTED_TX: &str = "0200000000010267586e98fad27da0b9968bc039a1ef34c939b9b8e523a8bef89d478608c5ecf60300000023220020fa51f1857dfd5047a100de5f3fc87ae18dfae657f636938972bf0903c59f4091fdffffffca358758f6d27e6cf45272937977a748fd88391db679ceda7dc7bf1f005ee8790000000000ffffffff02e8030000000000000151d0070000000000000152030047304402204e92ebd6a4e0d2c4efe34b861c9dc235d12a87dd215ab55b49ecef89e317be6a02201595f8dfac3c2b2df71534c11621d85185451ef95a156a472c6f2854206790e283475121027592aab5d43618dda13fba71e3993cd7517a712d3da49664c06ee1bd3d1f70af2102e5740e63bad28081ed7cf654dd6c19029ca03382fc05ab5f5dda81f2c55b845b52ae0000000000";
+
+    /// Spends of `<key1> CHECKSIGVERIFY CODESEPARATOR <key2> CHECKSIG`, bare
+    /// and as a witness script worth 7000
+    const CODESEPARATOR_TX: &str = "01000000000102beead77994cf573341ec17b58bbf7eb34d2711c993c1d976b128b3188dc1829a0000000092483045022100af8ae13ff63e14df60624e6efdefd2d49ac1028b26e061e38bf0b4ee192cddd2022076fc682e094af7065601386a733e7f15e0cdefc463574844eb9681c71274ae9401483045022100c492dba0c34f8f7292ebf766717aebea30b74add708cd0edf2951a924e0bf7b9022051f6d852399fac4ad50a40a9a310b4761ee596f7ff4ba2211e6fa48df4341c5701ffffffff2b4c342f5433ebe591a1da77e013d1b72475562d48578dca8b84bac6651c3cb90000000000ffffffff010500000000000000015100034830450221009a7b6ff386580e3c7459f46be6007da052a17ae71724703ba3adc31ef7464e4e02200a3dedb494d932ed472afa1eabf3052db8333579c4effe1a57b763fa431618ff01483045022100c13fc0dfd7e6f361a16dcbaa26aa7b6ebec546c997aae1844cd6f89a3cec0e69022058e569eb286942612d51ff44dd80e41935bc1d957ddcb86334bbf3e5066c6402014721027592aab5d43618dda13fba71e3993cd7517a712d3da49664c06ee1bd3d1f70afadab2102e5740e63bad28081ed7cf654dd6c19029ca03382fc05ab5f5dda81f2c55b845bac00000000";
+
+    fn tx(hex: &str) -> Transaction {
+        deserialize(&Vec::<u8>::from_hex(hex).unwrap()).unwrap()
+    }
+
+    fn output(pkscript: &str, value: u64) -> TxOut {
+        TxOut { value_out: value, pkscript: Script::from(Vec::<u8>::from_hex(pkscript).unwrap()) }
+    }
+
+    #[test]
+    fn signed_inputs() {
+        let spends = [
+            (LEGACY_TX, 0, "76a914925d4028880bd0c9d68fbc7fc7dfee976698629c88ac", 0, true),
+            (LEGACY_TX, 1, "4104e5740e63bad28081ed7cf654dd6c19029ca03382fc05ab5f5dda81f2c55b845b8dc1cf3a30ac29acf910275efd2ed6eb6511cfbeefd753804f664ede1eb2ff80ac", 0, true),
+            (LEGACY_TX, 2, "76a9143203f9559360daea3ed68ea0bdecee39c8a78baa88ac", 0, false),
+            (SEGWIT_TX, 0, "a9142d51d02e7cdf2fb546e533b94c3efac7f3bf6b4987", 0, true),
+            (SEGWIT_TX, 1, "00144446e61cc01c95bc0e6808b88bea7eb1420521ce", 50000, true),
+            (NESTED_TX, 0, "a91464fb9ee3ef313e8c13e88258f6afdd29fdbf665287", 120000, true),
+            (CODESEPARATOR_TX, 0, "21027592aab5d43618dda13fba71e3993cd7517a712d3da49664c06ee1bd3d1f70afadab2102e5740e63bad28081ed7cf654dd6c19029ca03382fc05ab5f5dda81f2c55b845bac", 0, true),
+            (CODESEPARATOR_TX, 1, "002064071228b5c3ca4102e79f50827a26bd5208dfbca867387558a538577b8de2aa", 7000, true),
+        ];
+        // The last field tells if the signature commits to the transaction,
+        // which SIGHASH_SINGLE without matching output does not
+        for &(hex, input, pkscript, value, commits) in spends.iter() {
+            let tx = tx(hex);
+            assert_eq!(verify_script(&tx, input, &output(pkscript, value), ALL_FLAGS), Ok(()));
+
+            // Witness signatures commit to the spent value
+            if value != 0 {
+                assert!(verify_script(&tx, input, &output(pkscript, value + 1), ALL_FLAGS).is_err());
+            }
+            let mut tampered = tx.clone();
+            tampered.lock_time += 1;
+            assert_eq!(verify_script(&tampered, input, &output(pkscript, value), ALL_FLAGS).is_ok(), !commits);
+        }
+    }
+
+    #[test]
+    fn multisig_dummy() {
+        let mut tx = tx(SEGWIT_TX);
+        let pkscript = "a9142d51d02e7cdf2fb546e533b94c3efac7f3bf6b4987";
+        let mut sig_script = tx.input_ent[0].sig_script.as_bytes().to_vec();
+        sig_script[0] = 0x51;
+        tx.input_ent[0].sig_script = Script::from(sig_script);
+        assert_eq!(verify_script(&tx, 0, &output(pkscript, 0), VERIFY_P2SH), Ok(()));
+        assert_eq!(verify_script(&tx, 0, &output(pkscript, 0), VERIFY_P2SH | VERIFY_NULLDUMMY),
+                   Err(ScriptError::ErrorSigNullDummy));
+    }
+
+    #[test]
+    fn unexpected_witness() {
+        let mut tx = tx(LEGACY_TX);
+        let pkscript = "76a914925d4028880bd0c9d68fbc7fc7dfee976698629c88ac";
+        tx.input_ent[0].witness = vec![vec![1]];
+        assert_eq!(verify_script(&tx, 0, &output(pkscript, 0), VERIFY_P2SH), Ok(()));
+        assert_eq!(verify_script(&tx, 0, &output(pkscript, 0), ALL_FLAGS), Err(ScriptError::ErrorWitnessUnexpected));
+    }
+
+    /// Run `pkscript` after `sig_script` in a transaction with the given
+    /// version, lock time and input sequence
+    fn run(sig_script: &str, pkscript: &str, version: u32, lock_time: u32, sequence: u32) -> Result<(), ScriptError> {
+        let tx = Transaction {
+            version:    version,
+            lock_time:  lock_time,
+            input_ent:  vec![TxIn {
+                sig_script: Script::from(Vec::<u8>::from_hex(sig_script).unwrap()),
+                sequence:   sequence,
+                ..Default::default()
+            }],
+            output_ent: vec![],
+        };
+        verify_script(&tx, 0, &output(pkscript, 0), ALL_FLAGS)
+    }
+
+    #[test]
+    fn operations() {
+        // 2 3 ADD 5 EQUAL
+        assert_eq!(run("5253", "935587", 1, 0, 0), Ok(()));
+        assert_eq!(run("5254", "935587", 1, 0, 0), Err(ScriptError::ErrorEvalFalse));
+        // Negative zero is false
+        assert_eq!(run("51", "0180", 1, 0, 0), Err(ScriptError::ErrorEvalFalse));
+        // IF 0 ELSE 1 ENDIF
+        assert_eq!(run("00", "6300675168", 1, 0, 0), Ok(()));
+        assert_eq!(run("51", "6300675168", 1, 0, 0), Err(ScriptError::ErrorEvalFalse));
+        assert_eq!(run("51", "63", 1, 0, 0), Err(ScriptError::ErrorUnbalancedConditional));
+        // Disabled opcodes and OP_VERIF fail even in branches not taken, OP_VER does not
+        assert_eq!(run("00", "637e6851", 1, 0, 0), Err(ScriptError::ErrorDisabledOpcode));
+        assert_eq!(run("00", "63656851", 1, 0, 0), Err(ScriptError::ErrorBadOpcode));
+        assert_eq!(run("00", "63626851", 1, 0, 0), Ok(()));
+        // 1 2 3 2 ROLL leaves 2 3 1
+        assert_eq!(run("515253", "527a518853885287", 1, 0, 0), Ok(()));
+        // Operands are limited to four bytes
+        assert_eq!(run("050000000080", "8b", 1, 0, 0), Err(ScriptError::ErrorNumberSize));
+        // RIPEMD160 of "abc"
+        assert_eq!(run("03616263", "a6148eb208f7e05d987a9b044a8e98c6b087f15a0bfc87", 1, 0, 0), Ok(()));
+    }
+
+    #[test]
+    fn lock_times() {
+        // 100 CHECKLOCKTIMEVERIFY DROP 1
+        assert_eq!(run("", "0164b17551", 1, 100, 0), Ok(()));
+        assert_eq!(run("", "0165b17551", 1, 100, 0), Err(ScriptError::ErrorUnsatisfiedLockTime));
+        assert_eq!(run("", "0164b17551", 1, 100, 0xffffffff), Err(ScriptError::ErrorUnsatisfiedLockTime));
+        assert_eq!(run("", "0181b17551", 1, 100, 0), Err(ScriptError::ErrorNegativeLockTime));
+        assert_eq!(run("", "040065cd1db17551", 1, 100, 0), Err(ScriptError::ErrorUnsatisfiedLockTime));
+        // 10 CHECKSEQUENCEVERIFY DROP 1
+        assert_eq!(run("", "5ab27551", 2, 0, 10), Ok(()));
+        assert_eq!(run("", "5ab27551", 2, 0, 9), Err(ScriptError::ErrorUnsatisfiedLockTime));
+        assert_eq!(run("", "5ab27551", 1, 0, 10), Err(ScriptError::ErrorUnsatisfiedLockTime));
+        assert_eq!(run("", "5ab27551", 2, 0, 10 | (1 << 22)), Err(ScriptError::ErrorUnsatisfiedLockTime));
+    }
+}
@@ -0,0 +1,70 @@
+/// Script verification hooks used by block and transaction validation
+///
+/// The validation code decides which rules apply to a block and passes them
+/// on as flags to a `ScriptVerifier`; `ScriptInterpreter` runs the scripts
+/// under those rules.
+
+use blockdata::transaction::{Transaction, TxOut};
+use consensus::params::Params;
+use validation::interpreter::verify_script;
+
+/// Evaluate pay-to-script-hash redeem scripts (BIP16)
+pub const VERIFY_P2SH: u32 = 1 << 0;
+/// Require strict DER signatures (BIP66)
+pub const VERIFY_DERSIG: u32 = 1 << 2;
+/// Require the dummy element of multisig to be empty (BIP147)
+pub const VERIFY_NULLDUMMY: u32 = 1 << 4;
+/// Enforce OP_CHECKLOCKTIMEVERIFY (BIP65)
+pub const VERIFY_CHECKLOCKTIMEVERIFY: u32 = 1 << 9;
+/// Enforce OP_CHECKSEQUENCEVERIFY (BIP112)
+pub const VERIFY_CHECKSEQUENCEVERIFY: u32 = 1 << 10;
+/// Evaluate witness programs (BIP141, BIP143)
+pub const VERIFY_WITNESS: u32 = 1 << 11;
+
+/// Verifier of the scripts spending outputs
+pub trait ScriptVerifier {
+    /// Check that input `input` of `tx` satisfies the locking script of
+    /// `spent`, the output it spends, under the given flags
+    fn verify_input(&self, tx: &Transaction, input: usize, spent: &TxOut, flags: u32) -> bool;
+}
+
+/// Verifier running the scripts through the interpreter
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ScriptInterpreter;
+
+impl ScriptVerifier for ScriptInterpreter {
+    fn verify_input(&self, tx: &Transaction, input: usize, spent: &TxOut, flags: u32) -> bool {
+        verify_script(tx, input, spent, flags).is_ok()
+    }
+}
+
+/// Verifier accepting every script, for tests built on transactions
+/// without valid signatures
+#[cfg(test)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoScriptVerification;
+
+#[cfg(test)]
+impl ScriptVerifier for NoScriptVerification {
+    fn verify_input(&self, _tx: &Transaction, _input: usize, _spent: &TxOut, _flags: u32) -> bool {
+        true
+    }
+}
+
+/// Return the script flags enforced for a block at the given height
+pub fn block_script_flags(params: &Params, height: u64) -> u32 {
+    let mut flags = VERIFY_P2SH;
+    if height >= params.bip66_height {
+        flags |= VERIFY_DERSIG;
+    }
+    if height >= params.bip65_height {
+        flags |= VERIFY_CHECKLOCKTIMEVERIFY;
+    }
+    if height >= params.csv_height {
+        flags |= VERIFY_CHECKSEQUENCEVERIFY;
+    }
+    if height >= params.segwit_height {
+        flags |= VERIFY_WITNESS | VERIFY_NULLDUMMY;
+    }
+    flags
+}
@@ -0,0 +1,174 @@
+/// Consensus checks of single transactions
+///
+/// `check_transaction` covers the rules that need nothing but the
+/// transaction; the other checks need the coins its inputs spend, in
+/// the order of the inputs.
+
+use std::collections::HashSet;
+
+use blockdata::coinbase::{MIN_COINBASE_SCRIPT_SIZE, MAX_COINBASE_SCRIPT_SIZE};
+use blockdata::script::Script;
+use blockdata::transaction::{Transaction, TxIn,
+                             SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_TYPE_FLAG,
+                             SEQUENCE_LOCKTIME_MASK, SEQUENCE_LOCKTIME_GRANULARITY};
+use store::utxo::Coin;
+use util::amount::money_range;
+use validation::block::{MAX_BLOCK_WEIGHT, WITNESS_SCALE_FACTOR};
+use validation::error::Error;
+use validation::script::{VERIFY_P2SH, VERIFY_WITNESS};
+
+/// Number of blocks before a coinbase output can be spent
+pub const COINBASE_MATURITY: u64 = 100;
+
+/// Check the rules that do not depend on the chain
+pub fn check_transaction(tx: &Transaction) -> Result<(), Error> {
+    if tx.input_ent.is_empty() || tx.output_ent.is_empty() {
+        return Err(Error::ErrorTransactionEmpty);
+    }
+    if tx.base_size() * WITNESS_SCALE_FACTOR > MAX_BLOCK_WEIGHT {
+        return Err(Error::ErrorTransactionSize);
+    }
+
+    let mut value_out = 0u64;
+    for txout in tx.output_ent.iter() {
+        if !money_range(txout.value_out) {
+            return Err(Error::ErrorValueOutOfRange);
+        }
+        value_out += txout.value_out;
+        if !money_range(value_out) {
+            return Err(Error::ErrorValueOutOfRange);
+        }
+    }
+
+    let mut spent = HashSet::new();
+    if !tx.input_ent.iter().all(|txin| spent.insert(txin.previous_out)) {
+        return Err(Error::ErrorDuplicateInput);
+    }
+
+    if tx.is_coin_base() {
+        let script_len = tx.input_ent[0].sig_script.len();
+        if script_len < MIN_COINBASE_SCRIPT_SIZE || script_len > MAX_COINBASE_SCRIPT_SIZE {
+            return Err(Error::ErrorCoinbaseScriptSize);
+        }
+    } else if tx.input_ent.iter().any(|txin| txin.previous_out.is_null()) {
+        return Err(Error::ErrorNullPrevout);
+    }
+    Ok(())
+}
+
+/// Check the spent coins cover the outputs and are mature when spent
+/// inside a block at the given height, returning the fee
+pub fn check_tx_inputs(tx: &Transaction, coins: &[Coin], height: u64) -> Result<u64, Error> {
+    let mut value_in = 0u64;
+    for (txin, coin) in tx.input_ent.iter().zip(coins.iter()) {
+        if coin.is_coinbase && height - (coin.height as u64) < COINBASE_MATURITY {
+            return Err(Error::ErrorPrematureCoinbaseSpend(txin.previous_out));
+        }
+        value_in += coin.txout.value_out;
+        if !money_range(coin.txout.value_out) || !money_range(value_in) {
+            return Err(Error::ErrorValueOutOfRange);
+        }
+    }
+
+    let value_out = tx.output_value();
+    if value_in < value_out {
+        return Err(Error::ErrorInputsBelowOutputs);
+    }
+    Ok(value_in - value_out)
+}
+
+/// Lowest height and time at which a transaction may be included in a
+/// block; both are exclusive and -1 when there is no such constraint
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SequenceLocks {
+    pub min_height: i64,
+    pub min_time: i64,
+}
+
+/// Compute the relative lock times of a transaction (BIP68). `median_time_at`
+/// returns the median time past of the block at a height of the chain
+pub fn calculate_sequence_locks(tx: &Transaction, coins: &[Coin], median_time_at: &Fn(u64) -> u32) -> SequenceLocks {
+    let mut locks = SequenceLocks { min_height: -1, min_time: -1 };
+    if tx.version < 2 {
+        return locks;
+    }
+    for (txin, coin) in tx.input_ent.iter().zip(coins.iter()) {
+        if txin.sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            continue;
+        }
+        let value = (txin.sequence & SEQUENCE_LOCKTIME_MASK) as i64;
+        if txin.sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+            // Time locks count from the median time past of the block before the coin
+            let coin_time = median_time_at((coin.height as u64).saturating_sub(1)) as i64;
+            let min_time = coin_time + (value << SEQUENCE_LOCKTIME_GRANULARITY) - 1;
+            locks.min_time = locks.min_time.max(min_time);
+        } else {
+            locks.min_height = locks.min_height.max(coin.height as i64 + value - 1);
+        }
+    }
+    locks
+}
+
+/// Check relative lock times allow inclusion in a block at the given height
+/// whose parent has the given median time past
+pub fn check_sequence_locks(locks: &SequenceLocks, height: u64, prev_median_time: u32) -> bool {
+    locks.min_height < height as i64 && locks.min_time < prev_median_time as i64
+}
+
+/// Count the signature operations of the scripts of a transaction
+/// without looking at the spent outputs
+pub fn legacy_sigops(tx: &Transaction) -> usize {
+    let inputs: usize = tx.input_ent.iter().map(|txin| txin.sig_script.count_sigops(false)).sum();
+    let outputs: usize = tx.output_ent.iter().map(|txout| txout.pkscript.count_sigops(false)).sum();
+    inputs + outputs
+}
+
+/// Count the signature operations of the redeem scripts of pay-to-script-hash inputs
+pub fn p2sh_sigops(tx: &Transaction, coins: &[Coin]) -> usize {
+    if tx.is_coin_base() {
+        return 0;
+    }
+    tx.input_ent.iter().zip(coins.iter())
+        .filter(|&(_, coin)| coin.txout.pkscript.is_p2sh())
+        .filter_map(|(txin, _)| txin.sig_script.last_push())
+        .map(|redeem| Script::from(redeem.to_vec()).count_sigops(true))
+        .sum()
+}
+
+/// Count the signature operations of the witness program an input spends
+fn witness_sigops(txin: &TxIn, spent: &Script) -> usize {
+    let redeem;
+    let program = if spent.witness_program().is_some() {
+        spent
+    } else if spent.is_p2sh() && txin.sig_script.is_push_only() {
+        redeem = Script::from(txin.sig_script.last_push().unwrap_or(&[]).to_vec());
+        &redeem
+    } else {
+        return 0;
+    };
+    match program.witness_program() {
+        Some((0, program)) if program.len() == 20 => 1,
+        Some((0, program)) if program.len() == 32 => {
+            txin.witness.last().map_or(0, |script| Script::from(script.clone()).count_sigops(true))
+        },
+        _ => 0,
+    }
+}
+
+/// Total signature operations cost of a transaction, legacy operations
+/// counting as much as four witness ones
+pub fn sigop_cost(tx: &Transaction, coins: &[Coin], flags: u32) -> usize {
+    let mut cost = legacy_sigops(tx) * WITNESS_SCALE_FACTOR;
+    if tx.is_coin_base() {
+        return cost;
+    }
+    if flags & VERIFY_P2SH != 0 {
+        cost += p2sh_sigops(tx, coins) * WITNESS_SCALE_FACTOR;
+    }
+    if flags & VERIFY_WITNESS != 0 {
+        cost += tx.input_ent.iter().zip(coins.iter())
+            .map(|(txin, coin)| witness_sigops(txin, &coin.txout.pkscript))
+            .sum::<usize>();
+    }
+    cost
+}
@@ -24,12 +24,8 @@ impl fmt::Display for Error {
 }
 
 impl error::Error for Error {
-    fn reason(&self) -> Option<&error::Error> {
-        match *self {
-            Error::ErrorTORAddress               => Some(e),
-            Error::ErrorAddressLength            => Some(e),
-            Error::ErrorBlockHeaderInvalidLength => Some(e),
-        }
+    fn cause(&self) -> Option<&error::Error> {
+        None
     }
 
     fn description(&self) -> &str {
@@ -2,7 +2,13 @@
 /// 
 use wire::msgver;
 use wire::msgblock;
+use wire::msginv;
 use wire::msgreject;
+use wire::msgfeefilter;
+use wire::msgcmpct;
+use wire::msgcfilter;
+use wire::msgbloom;
+use wire::msgaddr;
 
 use consensus::encode::{self, Encodable, Decodable, VarInt, Error};
 
@@ -11,18 +17,43 @@ pub enum Message {
     VersionMessage(msgver::VersionMessage),
 
     GetHeadersMessage(msgblock::GetHeadersMessage),
+    HeadersMessage(msgblock::HeadersMessage),
     GetBlocksMessage(msgblock::GetBlocksMessage),
 
+    InvMessage(msginv::InvMessage),
+    GetDataMessage(msginv::GetDataMessage),
+    NotFoundMessage(msginv::NotFoundMessage),
+    MempoolMessage,
+
     RejectMessage(msgreject::RejectMessage),
 
+    FeeFilterMessage(msgfeefilter::FeeFilterMessage),
+
+    SendCmpctMessage(msgcmpct::SendCmpctMessage),
+    CmpctBlockMessage(msgcmpct::CmpctBlockMessage),
+    GetBlockTxnMessage(msgcmpct::GetBlockTxnMessage),
+    BlockTxnMessage(msgcmpct::BlockTxnMessage),
+
+    GetCFiltersMessage(msgcfilter::GetCFiltersMessage),
+    CFilterMessage(msgcfilter::CFilterMessage),
+    GetCFHeadersMessage(msgcfilter::GetCFHeadersMessage),
+    CFHeadersMessage(msgcfilter::CFHeadersMessage),
+    GetCFCheckptMessage(msgcfilter::GetCFCheckptMessage),
+    CFCheckptMessage(msgcfilter::CFCheckptMessage),
+
+    FilterLoadMessage(msgbloom::FilterLoadMessage),
+    FilterAddMessage(msgbloom::FilterAddMessage),
+    FilterClearMessage,
+    MerkleBlockMessage(msgbloom::MerkleBlockMessage),
+
+    AddrMessage(msgaddr::AddrMessage),
+    GetAddrMessage,
+
     /// Mesages types to be implemented
     VersionAcknoledgeMessage,
-    NotFoundMessage,
 
     PingMessage,
     PongMessage,
-    GetCFiltersMessage,
-    SendCFilterMessage,
-    
+
     // ...
 }
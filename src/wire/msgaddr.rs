@@ -0,0 +1,57 @@
+/// Implementations for messages related to address gossip
+/// MessageAddr
+///
+/// `getaddr` carries no payload
+
+use std::io;
+
+use consensus::{Encodable, Decodable};
+use consensus::encode::{self, VarInt};
+use util::constants::MAX_ADDR_PER_MSG;
+use wire::netaddress::NetAddress;
+
+/// `addr` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct AddrMessage {
+    /// Addresses of peers known to the sender
+    pub addresses: Vec<NetAddress>,
+}
+
+impl AddrMessage {
+    pub fn new_addr_msg(addresses: Vec<NetAddress>) -> AddrMessage {
+        AddrMessage {
+            addresses: addresses
+        }
+    }
+}
+
+impl Encodable for AddrMessage {
+    #[inline]
+    fn encode<Wr: io::Write>(&self, mut wr: Wr) -> Result<usize, encode::Error> {
+        if self.addresses.len() > MAX_ADDR_PER_MSG {
+            return Err(encode::Error::ErrorOversizedVector { requested: self.addresses.len(), max: MAX_ADDR_PER_MSG });
+        }
+        let mut length = VarInt(self.addresses.len() as u64).encode(&mut wr)?;
+        for address in self.addresses.iter() {
+            length += address.encode(&mut wr)?;
+        }
+        Ok(length)
+    }
+}
+
+impl Decodable for AddrMessage {
+    #[inline]
+    fn decode<Decoder: io::Read>(mut decoder: Decoder) -> Result<Self, encode::Error> {
+        let count = VarInt::decode(&mut decoder)?.0 as usize;
+        if count > MAX_ADDR_PER_MSG {
+            return Err(encode::Error::ErrorOversizedVector { requested: count, max: MAX_ADDR_PER_MSG });
+        }
+        let mut addresses = Vec::with_capacity(count);
+        for _ in 0..count {
+            addresses.push(Decodable::decode(&mut decoder)?);
+        }
+        Ok(AddrMessage {
+            addresses: addresses
+        })
+    }
+}
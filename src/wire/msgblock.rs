@@ -9,19 +9,21 @@
 /// MessageHeader
 /// MessageGetBlocks
 /// MessageBlock
-/// 
+///
 
-use std::{io, iter};
+use std::io;
 use hashes::sha256d;
 use util::constants::{PROTOCOL_VERSION, MAX_BLOCK_HEADERS_PER_MSG};
 
 use blockdata::block::{BlockHeader, Block};
+use consensus::{Encodable, Decodable};
+use consensus::encode::{self, VarInt};
 
 /// `getheaders` message
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub struct GetHeadersMessage {
     /// Protocol version
-    pub protocol: u32
+    pub protocol: u32,
 
     /// List of hashes from newest to oldest (to block 1 is possible)
     /// that the remote peer with reply with - it will send the data
@@ -33,9 +35,9 @@ pub struct GetHeadersMessage {
 }
 
 impl GetHeadersMessage {
-    pub fn new_get_headers_msg(loc_hashes: Vec<sha256d::Hash>, stop_hash: sha256d:Hash) -> GetHeadersMessage {
+    pub fn new_get_headers_msg(loc_hashes: Vec<sha256d::Hash>, stop_hash: sha256d::Hash) -> GetHeadersMessage {
         GetHeadersMessage {
-            protocol:              constants::PROTOCOL_VERSION,
+            protocol:              PROTOCOL_VERSION,
             block_location_hashes: loc_hashes,
             stop_hash:             stop_hash
         }
@@ -49,8 +51,8 @@ consensus_encoding!(GetHeadersMessage, protocol, block_location_hashes, stop_has
 pub struct SendHeadersMessage {
     /// Protocol version
     pub protocol: u32,
- 
-    /// List of headers hashes from newest to oldest form the 
+
+    /// List of headers hashes from newest to oldest form the
     /// sender viewpoint
     pub headers_hashes: Vec<sha256d::Hash>,
     /// Stop_hash from sende viewpoint
@@ -59,11 +61,65 @@ pub struct SendHeadersMessage {
 
 
 impl SendHeadersMessage {
-    pub fn new_send_headers_msg(block_location_hashes: Vec<sha256d::Hash>, stop_hash: sha256d::Hash) -> SendHeadersMessage {
-        if block_location_hashes.len() > MAX_BLOCK_HEADERS_PER_MSG {
-            //
+    pub fn new_send_headers_msg(mut block_location_hashes: Vec<sha256d::Hash>, stop_hash: sha256d::Hash) -> SendHeadersMessage {
+        block_location_hashes.truncate(MAX_BLOCK_HEADERS_PER_MSG as usize);
+        SendHeadersMessage {
+            protocol:       PROTOCOL_VERSION,
+            headers_hashes: block_location_hashes,
+            stop_hash:      stop_hash
+        }
+    }
+}
+
+/// `headers` message - the reply to `getheaders`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct HeadersMessage {
+    /// Headers following the fork point, oldest first
+    pub headers: Vec<BlockHeader>,
+}
+
+impl HeadersMessage {
+    pub fn new_headers_msg(mut headers: Vec<BlockHeader>) -> HeadersMessage {
+        headers.truncate(MAX_BLOCK_HEADERS_PER_MSG as usize);
+        HeadersMessage {
+            headers: headers
+        }
+    }
+}
+
+/// Every header is followed by a transactions count which is always zero
+impl Encodable for HeadersMessage {
+    #[inline]
+    fn encode<Wr: io::Write>(&self, mut wr: Wr) -> Result<usize, encode::Error> {
+        let mut length = VarInt(self.headers.len() as u64).encode(&mut wr)?;
+        for header in self.headers.iter() {
+            length += header.encode(&mut wr)?;
+            length += VarInt(0).encode(&mut wr)?;
+        }
+        Ok(length)
+    }
+}
+
+impl Decodable for HeadersMessage {
+    #[inline]
+    fn decode<Decoder: io::Read>(mut decoder: Decoder) -> Result<Self, encode::Error> {
+        let count = VarInt::decode(&mut decoder)?.0;
+        if count > MAX_BLOCK_HEADERS_PER_MSG as u64 {
+            return Err(encode::Error::ErrorOversizedVector {
+                requested: count as usize,
+                max:       MAX_BLOCK_HEADERS_PER_MSG as usize
+            });
+        }
+        let mut headers = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            headers.push(Decodable::decode(&mut decoder)?);
+            if VarInt::decode(&mut decoder)?.0 != 0 {
+                return Err(encode::Error::ErrorParse("headers message contains transactions"));
+            }
         }
-        //
+        Ok(HeadersMessage {
+            headers: headers
+        })
     }
 }
 
@@ -84,11 +140,11 @@ pub struct GetBlocksMessage {
 impl GetBlocksMessage {
     pub fn new_get_blocks_msg(block_location_hashes: Vec<sha256d::Hash>, hash_stop: sha256d::Hash) -> GetBlocksMessage {
         GetBlocksMessage {
-            protocol:             constants::PROTOCOL_VERSION,
+            protocol:             PROTOCOL_VERSION,
             block_locator_hashes: block_location_hashes,
             hash_stop:            hash_stop
         }
     }
 }
 
-consensus_encoding!(GetBlocksMessage, protocol, block_location_hashes, hash_stop);
\ No newline at end of file
+consensus_encoding!(GetBlocksMessage, protocol, block_locator_hashes, hash_stop);
@@ -0,0 +1,72 @@
+/// Implementations for messages related to bloom filtering (BIP37)
+/// MessageFilterLoad
+/// MessageFilterAdd
+/// MessageMerkleBlock
+///
+/// `filterclear` carries no payload
+
+use blockdata::block::BlockHeader;
+use blockdata::merkleblock::PartialMerkleTree;
+
+/// `filterload` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct FilterLoadMessage {
+    /// Bit field of the filter
+    pub filter: Vec<u8>,
+    /// Number of hash functions
+    pub hash_funcs: u32,
+    /// Random value added to the seed of the hash functions
+    pub tweak: u32,
+    /// How matched outputs update the filter
+    pub flags: u8,
+}
+
+impl FilterLoadMessage {
+    pub fn new_filter_load_msg(filter: Vec<u8>, hash_funcs: u32, tweak: u32, flags: u8) -> FilterLoadMessage {
+        FilterLoadMessage {
+            filter:     filter,
+            hash_funcs: hash_funcs,
+            tweak:      tweak,
+            flags:      flags
+        }
+    }
+}
+
+consensus_encoding!(FilterLoadMessage, filter, hash_funcs, tweak, flags);
+
+/// `filteradd` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct FilterAddMessage {
+    /// Element to add to the loaded filter
+    pub data: Vec<u8>,
+}
+
+impl FilterAddMessage {
+    pub fn new_filter_add_msg(data: Vec<u8>) -> FilterAddMessage {
+        FilterAddMessage {
+            data: data
+        }
+    }
+}
+
+consensus_encoding!(FilterAddMessage, data);
+
+/// `merkleblock` message - a block filtered by the loaded filter, followed
+/// by a `tx` message for every matched transaction
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct MerkleBlockMessage {
+    pub header: BlockHeader,
+    /// Branches of the matched transactions
+    pub txn: PartialMerkleTree,
+}
+
+impl MerkleBlockMessage {
+    pub fn new_merkle_block_msg(header: BlockHeader, txn: PartialMerkleTree) -> MerkleBlockMessage {
+        MerkleBlockMessage {
+            header: header,
+            txn:    txn
+        }
+    }
+}
+
+consensus_encoding!(MerkleBlockMessage, header, txn);
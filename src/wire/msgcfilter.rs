@@ -0,0 +1,164 @@
+/// Implementations for messages related to compact block filters (BIP157)
+/// MessageGetCFilters
+/// MessageCFilter
+/// MessageGetCFHeaders
+/// MessageCFHeaders
+/// MessageGetCFCheckpt
+/// MessageCFCheckpt
+///
+
+use hashes::sha256d;
+
+use blockdata::filter::BlockFilter;
+
+/// `getcfilters` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GetCFiltersMessage {
+    /// Type of the requested filters
+    pub filter_type: u8,
+    /// Height of the first block of the range
+    pub start_height: u32,
+    /// Hash of the last block of the range
+    pub stop_hash: sha256d::Hash,
+}
+
+impl GetCFiltersMessage {
+    pub fn new_get_cfilters_msg(filter_type: u8, start_height: u32, stop_hash: sha256d::Hash) -> GetCFiltersMessage {
+        GetCFiltersMessage {
+            filter_type:  filter_type,
+            start_height: start_height,
+            stop_hash:    stop_hash
+        }
+    }
+}
+
+consensus_encoding!(GetCFiltersMessage, filter_type, start_height, stop_hash);
+
+/// `cfilter` message - one for every block of a `getcfilters` range
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct CFilterMessage {
+    /// Type of the filter
+    pub filter_type: u8,
+    /// Hash of the filtered block
+    pub block_hash: sha256d::Hash,
+    /// Encoded filter
+    pub filter: Vec<u8>,
+}
+
+impl CFilterMessage {
+    pub fn new_cfilter_msg(filter: BlockFilter) -> CFilterMessage {
+        CFilterMessage {
+            filter_type: filter.filter_type,
+            block_hash:  filter.block_hash,
+            filter:      filter.content
+        }
+    }
+
+    /// Return the received filter
+    pub fn block_filter(&self) -> BlockFilter {
+        BlockFilter {
+            filter_type: self.filter_type,
+            block_hash:  self.block_hash,
+            content:     self.filter.clone(),
+        }
+    }
+}
+
+consensus_encoding!(CFilterMessage, filter_type, block_hash, filter);
+
+/// `getcfheaders` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GetCFHeadersMessage {
+    /// Type of the requested filter headers
+    pub filter_type: u8,
+    /// Height of the first block of the range
+    pub start_height: u32,
+    /// Hash of the last block of the range
+    pub stop_hash: sha256d::Hash,
+}
+
+impl GetCFHeadersMessage {
+    pub fn new_get_cfheaders_msg(filter_type: u8, start_height: u32, stop_hash: sha256d::Hash) -> GetCFHeadersMessage {
+        GetCFHeadersMessage {
+            filter_type:  filter_type,
+            start_height: start_height,
+            stop_hash:    stop_hash
+        }
+    }
+}
+
+consensus_encoding!(GetCFHeadersMessage, filter_type, start_height, stop_hash);
+
+/// `cfheaders` message - the reply to `getcfheaders`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct CFHeadersMessage {
+    /// Type of the filters
+    pub filter_type: u8,
+    /// Hash of the last block of the range
+    pub stop_hash: sha256d::Hash,
+    /// Filter header of the block preceding the range
+    pub previous_filter_header: sha256d::Hash,
+    /// Filter hashes of the blocks of the range, in order
+    pub filter_hashes: Vec<sha256d::Hash>,
+}
+
+impl CFHeadersMessage {
+    pub fn new_cfheaders_msg(
+        filter_type:            u8,
+        stop_hash:              sha256d::Hash,
+        previous_filter_header: sha256d::Hash,
+        filter_hashes:          Vec<sha256d::Hash>
+    ) -> CFHeadersMessage {
+        CFHeadersMessage {
+            filter_type:            filter_type,
+            stop_hash:              stop_hash,
+            previous_filter_header: previous_filter_header,
+            filter_hashes:          filter_hashes
+        }
+    }
+}
+
+consensus_encoding!(CFHeadersMessage, filter_type, stop_hash, previous_filter_header, filter_hashes);
+
+/// `getcfcheckpt` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GetCFCheckptMessage {
+    /// Type of the requested filter headers
+    pub filter_type: u8,
+    /// Hash of the last block of the chain
+    pub stop_hash: sha256d::Hash,
+}
+
+impl GetCFCheckptMessage {
+    pub fn new_get_cfcheckpt_msg(filter_type: u8, stop_hash: sha256d::Hash) -> GetCFCheckptMessage {
+        GetCFCheckptMessage {
+            filter_type: filter_type,
+            stop_hash:   stop_hash
+        }
+    }
+}
+
+consensus_encoding!(GetCFCheckptMessage, filter_type, stop_hash);
+
+/// `cfcheckpt` message - the reply to `getcfcheckpt`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct CFCheckptMessage {
+    /// Type of the filters
+    pub filter_type: u8,
+    /// Hash of the last block of the chain
+    pub stop_hash: sha256d::Hash,
+    /// Filter headers at every checkpoint interval up to the stop block
+    pub filter_headers: Vec<sha256d::Hash>,
+}
+
+impl CFCheckptMessage {
+    pub fn new_cfcheckpt_msg(filter_type: u8, stop_hash: sha256d::Hash, filter_headers: Vec<sha256d::Hash>) -> CFCheckptMessage {
+        CFCheckptMessage {
+            filter_type:    filter_type,
+            stop_hash:      stop_hash,
+            filter_headers: filter_headers
+        }
+    }
+}
+
+consensus_encoding!(CFCheckptMessage, filter_type, stop_hash, filter_headers);
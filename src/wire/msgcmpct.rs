@@ -0,0 +1,289 @@
+/// Implementations for messages related to compact block relay (BIP152)
+/// MessageSendCmpct
+/// MessageCmpctBlock
+/// MessageGetBlockTxn
+/// MessageBlockTxn
+///
+/// A compact block carries the header and, for every transaction, a 6-byte
+/// short ID: the SipHash-2-4 of its witness transaction id, keyed with the
+/// single SHA256 of the header and a nonce. Transactions the receiver is not
+/// expected to have, at least the coinbase, are sent in full.
+
+use std::{io, mem};
+use hashes::{sha256, sha256d, Hash};
+
+use blockdata::block::{Block, BlockHeader};
+use blockdata::transaction::Transaction;
+use consensus::{Encodable, Decodable};
+use consensus::encode::{self, serialize, VarInt};
+use util::constants::MAX_VECTOR_SIZE;
+use util::hash::siphash24;
+
+/// Compact block version relaying short IDs of witness transaction ids
+pub const CMPCT_BLOCK_VERSION: u64 = 2;
+
+/// Size of a short transaction ID
+pub const SHORT_ID_SIZE: usize = 6;
+
+/// `sendcmpct` message
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct SendCmpctMessage {
+    /// Announce new blocks with `cmpctblock` instead of `inv` or `headers`
+    pub announce: bool,
+    /// Compact block version
+    pub version: u64,
+}
+
+impl SendCmpctMessage {
+    pub fn new_send_cmpct_msg(announce: bool) -> SendCmpctMessage {
+        SendCmpctMessage {
+            announce: announce,
+            version:  CMPCT_BLOCK_VERSION
+        }
+    }
+}
+
+consensus_encoding!(SendCmpctMessage, announce, version);
+
+/// Key of the short transaction IDs of a compact block
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct ShortIdKey {
+    k0: u64,
+    k1: u64,
+}
+
+impl ShortIdKey {
+    /// Derive the key from the header and the nonce of a compact block
+    pub fn new(header: &BlockHeader, nonce: u64) -> ShortIdKey {
+        let mut data = serialize(header);
+        data.extend(serialize(&nonce));
+        let hash = sha256::Hash::hash(&data).into_inner();
+        let mut k0 = [0u8; 8];
+        let mut k1 = [0u8; 8];
+        k0.copy_from_slice(&hash[0..8]);
+        k1.copy_from_slice(&hash[8..16]);
+        ShortIdKey {
+            k0: u64::from_le_bytes(k0),
+            k1: u64::from_le_bytes(k1),
+        }
+    }
+
+    /// Return the short ID of a transaction from its witness transaction id
+    pub fn short_id(&self, wtxid: &sha256d::Hash) -> [u8; SHORT_ID_SIZE] {
+        let hash = siphash24(self.k0, self.k1, &wtxid.into_inner()).to_le_bytes();
+        let mut short_id = [0u8; SHORT_ID_SIZE];
+        short_id.copy_from_slice(&hash[..SHORT_ID_SIZE]);
+        short_id
+    }
+}
+
+/// Transaction sent in full inside a compact block
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct PrefilledTransaction {
+    /// Position of the transaction in the block
+    pub index: usize,
+    pub tx: Transaction,
+}
+
+/// Encode increasing indexes as differences from the previous index plus one
+fn encode_indexes<Wr: io::Write>(indexes: &[usize], mut wr: Wr) -> Result<usize, encode::Error> {
+    let mut length = VarInt(indexes.len() as u64).encode(&mut wr)?;
+    let mut next = 0;
+    for index in indexes.iter() {
+        if *index < next {
+            return Err(encode::Error::ErrorParse("indexes not increasing"));
+        }
+        length += VarInt((*index - next) as u64).encode(&mut wr)?;
+        next = *index + 1;
+    }
+    Ok(length)
+}
+
+/// Decode a vector length, refusing vectors whose items would take more
+/// than `MAX_VECTOR_SIZE` bytes in memory
+fn decode_count<T, Decoder: io::Read>(mut decoder: Decoder) -> Result<usize, encode::Error> {
+    let count = VarInt::decode(&mut decoder)?.0 as usize;
+    let byte_size = count.checked_mul(mem::size_of::<T>())
+        .ok_or(encode::Error::ErrorParse("invalid length"))?;
+    if byte_size > MAX_VECTOR_SIZE {
+        return Err(encode::Error::ErrorOversizedVector { requested: byte_size, max: MAX_VECTOR_SIZE });
+    }
+    Ok(count)
+}
+
+/// Decode an index stored as its offset from `next`, refusing any above
+/// 16 bits; the offset is checked first as it may be as large as 2^64 - 1
+fn decode_index<Decoder: io::Read>(decoder: Decoder, next: u64) -> Result<u64, encode::Error> {
+    let offset = VarInt::decode(decoder)?.0;
+    if offset > u16::max_value() as u64 || next + offset > u16::max_value() as u64 {
+        return Err(encode::Error::ErrorParse("transaction index overflow"));
+    }
+    Ok(next + offset)
+}
+
+/// Decode indexes encoded by `encode_indexes`, refusing any above 16 bits
+fn decode_indexes<Decoder: io::Read>(mut decoder: Decoder) -> Result<Vec<usize>, encode::Error> {
+    let count = decode_count::<usize, _>(&mut decoder)?;
+    let mut indexes = Vec::with_capacity(count);
+    let mut next = 0u64;
+    for _ in 0..count {
+        let index = decode_index(&mut decoder, next)?;
+        indexes.push(index as usize);
+        next = index + 1;
+    }
+    Ok(indexes)
+}
+
+/// `cmpctblock` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct CmpctBlockMessage {
+    pub header: BlockHeader,
+    /// Nonce keying the short IDs
+    pub nonce: u64,
+    /// Short IDs of the transactions not prefilled, in block order
+    pub short_ids: Vec<[u8; SHORT_ID_SIZE]>,
+    /// Transactions sent in full, by increasing index
+    pub prefilled: Vec<PrefilledTransaction>,
+}
+
+impl CmpctBlockMessage {
+    /// Build the compact block of a block, prefilling its coinbase
+    pub fn new_cmpct_block_msg(block: &Block, nonce: u64) -> CmpctBlockMessage {
+        let key = ShortIdKey::new(&block.header, nonce);
+        let mut prefilled = Vec::new();
+        let mut short_ids = Vec::with_capacity(block.txdata.len());
+        for (index, tx) in block.txdata.iter().enumerate() {
+            if tx.is_coin_base() {
+                prefilled.push(PrefilledTransaction { index: index, tx: tx.clone() });
+            } else {
+                short_ids.push(key.short_id(&tx.wtxid()));
+            }
+        }
+        CmpctBlockMessage {
+            header:    block.header,
+            nonce:     nonce,
+            short_ids: short_ids,
+            prefilled: prefilled
+        }
+    }
+
+    /// Key of the short IDs of the block
+    pub fn short_id_key(&self) -> ShortIdKey {
+        ShortIdKey::new(&self.header, self.nonce)
+    }
+
+    /// Number of transactions in the block
+    pub fn tx_count(&self) -> usize {
+        self.short_ids.len() + self.prefilled.len()
+    }
+}
+
+impl Encodable for CmpctBlockMessage {
+    #[inline]
+    fn encode<Wr: io::Write>(&self, mut wr: Wr) -> Result<usize, encode::Error> {
+        let mut length = self.header.encode(&mut wr)?
+            + self.nonce.encode(&mut wr)?
+            + VarInt(self.short_ids.len() as u64).encode(&mut wr)?;
+        for short_id in self.short_ids.iter() {
+            length += short_id.encode(&mut wr)?;
+        }
+        length += VarInt(self.prefilled.len() as u64).encode(&mut wr)?;
+        let mut next = 0;
+        for prefilled in self.prefilled.iter() {
+            if prefilled.index < next {
+                return Err(encode::Error::ErrorParse("prefilled indexes not increasing"));
+            }
+            length += VarInt((prefilled.index - next) as u64).encode(&mut wr)?;
+            length += prefilled.tx.encode(&mut wr)?;
+            next = prefilled.index + 1;
+        }
+        Ok(length)
+    }
+}
+
+impl Decodable for CmpctBlockMessage {
+    #[inline]
+    fn decode<Decoder: io::Read>(mut decoder: Decoder) -> Result<Self, encode::Error> {
+        let header = Decodable::decode(&mut decoder)?;
+        let nonce = Decodable::decode(&mut decoder)?;
+
+        let count = decode_count::<[u8; SHORT_ID_SIZE], _>(&mut decoder)?;
+        let mut short_ids = Vec::with_capacity(count);
+        for _ in 0..count {
+            short_ids.push(Decodable::decode(&mut decoder)?);
+        }
+
+        let count = decode_count::<PrefilledTransaction, _>(&mut decoder)?;
+        let mut prefilled = Vec::with_capacity(count);
+        let mut next = 0u64;
+        for _ in 0..count {
+            let index = decode_index(&mut decoder, next)?;
+            prefilled.push(PrefilledTransaction { index: index as usize, tx: Decodable::decode(&mut decoder)? });
+            next = index + 1;
+        }
+
+        Ok(CmpctBlockMessage {
+            header:    header,
+            nonce:     nonce,
+            short_ids: short_ids,
+            prefilled: prefilled
+        })
+    }
+}
+
+/// `getblocktxn` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GetBlockTxnMessage {
+    /// Hash of the block the transactions belong to
+    pub block_hash: sha256d::Hash,
+    /// Positions of the requested transactions, increasing
+    pub indexes: Vec<usize>,
+}
+
+impl GetBlockTxnMessage {
+    pub fn new_get_block_txn_msg(block_hash: sha256d::Hash, indexes: Vec<usize>) -> GetBlockTxnMessage {
+        GetBlockTxnMessage {
+            block_hash: block_hash,
+            indexes:    indexes
+        }
+    }
+}
+
+impl Encodable for GetBlockTxnMessage {
+    #[inline]
+    fn encode<Wr: io::Write>(&self, mut wr: Wr) -> Result<usize, encode::Error> {
+        let length = self.block_hash.encode(&mut wr)?
+            + encode_indexes(&self.indexes, &mut wr)?;
+        Ok(length)
+    }
+}
+
+impl Decodable for GetBlockTxnMessage {
+    #[inline]
+    fn decode<Decoder: io::Read>(mut decoder: Decoder) -> Result<Self, encode::Error> {
+        Ok(GetBlockTxnMessage {
+            block_hash: Decodable::decode(&mut decoder)?,
+            indexes:    decode_indexes(&mut decoder)?,
+        })
+    }
+}
+
+/// `blocktxn` message - the reply to `getblocktxn`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct BlockTxnMessage {
+    /// Hash of the block the transactions belong to
+    pub block_hash: sha256d::Hash,
+    /// Requested transactions, in the order of the request
+    pub transactions: Vec<Transaction>,
+}
+
+impl BlockTxnMessage {
+    pub fn new_block_txn_msg(block_hash: sha256d::Hash, transactions: Vec<Transaction>) -> BlockTxnMessage {
+        BlockTxnMessage {
+            block_hash:   block_hash,
+            transactions: transactions
+        }
+    }
+}
+
+consensus_encoding!(BlockTxnMessage, block_hash, transactions);
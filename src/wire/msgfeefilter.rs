@@ -0,0 +1,27 @@
+/// Implementation of the `feefilter` message (BIP133)
+///
+
+use util::amount::FeeRate;
+
+/// `feefilter` message
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct FeeFilterMessage {
+    /// Fee rate in satoshis per 1000 virtual bytes below which
+    /// transactions should not be announced to the sender
+    pub fee_rate: u64,
+}
+
+impl FeeFilterMessage {
+    pub fn new_fee_filter_msg(fee_rate: FeeRate) -> FeeFilterMessage {
+        FeeFilterMessage {
+            fee_rate: fee_rate.sat_per_kvb()
+        }
+    }
+
+    /// Return the minimum fee rate requested by the sender
+    pub fn fee_rate(&self) -> FeeRate {
+        FeeRate::from_sat_per_kvb(self.fee_rate)
+    }
+}
+
+consensus_encoding!(FeeFilterMessage, fee_rate);
@@ -0,0 +1,176 @@
+/// Implementations for messages related to inventory announcements
+/// MessageInv
+/// MessageGetData
+/// MessageNotFound
+///
+
+use std::io;
+use hashes::sha256d;
+
+use consensus::{Encodable, Decodable};
+use consensus::encode::{self, VarInt};
+use util::constants::MAX_INV_PER_MSG;
+
+/// Type of the object an inventory entry refers to
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub enum InvType {
+    /// Any data of this type may be ignored
+    Error,
+    /// Hash of a transaction
+    Transaction,
+    /// Hash of a block
+    Block,
+    /// Hash of a block, reply with `merkleblock` instead of `block`
+    FilteredBlock,
+    /// Hash of a block, reply with `cmpctblock` instead of `block`
+    CompactBlock,
+    /// Hash of a transaction, reply including its witness
+    WitnessTransaction,
+    /// Hash of a block, reply including the witness of its transactions
+    WitnessBlock,
+}
+
+impl InvType {
+    /// Return the protocol code of the inventory type
+    pub fn code(&self) -> u32 {
+        match *self {
+            InvType::Error              => 0,
+            InvType::Transaction        => 1,
+            InvType::Block              => 2,
+            InvType::FilteredBlock      => 3,
+            InvType::CompactBlock       => 4,
+            InvType::WitnessTransaction => 0x40000001,
+            InvType::WitnessBlock       => 0x40000002,
+        }
+    }
+
+    /// Return the inventory type corresponding to a protocol code
+    pub fn from_code(code: u32) -> Result<InvType, encode::Error> {
+        Ok(match code {
+            0          => InvType::Error,
+            1          => InvType::Transaction,
+            2          => InvType::Block,
+            3          => InvType::FilteredBlock,
+            4          => InvType::CompactBlock,
+            0x40000001 => InvType::WitnessTransaction,
+            0x40000002 => InvType::WitnessBlock,
+            _          => return Err(encode::Error::ErrorParse("unknown inventory type")),
+        })
+    }
+}
+
+/// Single inventory entry - the type and hash of an object
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub struct Inventory {
+    /// Type of the referenced object
+    pub inv_type: InvType,
+    /// Hash of the referenced object
+    pub hash: sha256d::Hash,
+}
+
+impl Inventory {
+    pub fn new_inventory(inv_type: InvType, hash: sha256d::Hash) -> Inventory {
+        Inventory {
+            inv_type: inv_type,
+            hash:     hash
+        }
+    }
+}
+
+impl Encodable for Inventory {
+    #[inline]
+    fn encode<Wr: io::Write>(&self, mut wr: Wr) -> Result<usize, encode::Error> {
+        let length = self.inv_type.code().encode(&mut wr)?
+            + self.hash.encode(&mut wr)?;
+        Ok(length)
+    }
+}
+
+impl Decodable for Inventory {
+    #[inline]
+    fn decode<Decoder: io::Read>(mut decoder: Decoder) -> Result<Self, encode::Error> {
+        Ok(Inventory {
+            inv_type: InvType::from_code(Decodable::decode(&mut decoder)?)?,
+            hash:     Decodable::decode(&mut decoder)?,
+        })
+    }
+}
+
+/// Encode a list of inventory entries, prefixed by its length
+fn encode_inventory<Wr: io::Write>(inventory: &[Inventory], mut wr: Wr) -> Result<usize, encode::Error> {
+    let mut length = VarInt(inventory.len() as u64).encode(&mut wr)?;
+    for inv in inventory.iter() {
+        length += inv.encode(&mut wr)?;
+    }
+    Ok(length)
+}
+
+/// Decode a list of inventory entries, refusing lists above the protocol limit
+fn decode_inventory<Decoder: io::Read>(mut decoder: Decoder) -> Result<Vec<Inventory>, encode::Error> {
+    let count = VarInt::decode(&mut decoder)?.0 as usize;
+    if count > MAX_INV_PER_MSG {
+        return Err(encode::Error::ErrorOversizedVector { requested: count, max: MAX_INV_PER_MSG });
+    }
+    let mut inventory = Vec::with_capacity(count);
+    for _ in 0..count {
+        inventory.push(Decodable::decode(&mut decoder)?);
+    }
+    Ok(inventory)
+}
+
+/// Implement the constructor and consensus encoding of a message
+/// that is nothing else than a list of inventory entries
+macro_rules! inventory_message {
+    ($entity:ident, $constructor:ident) => (
+        impl $entity {
+            pub fn $constructor(inventory: Vec<Inventory>) -> $entity {
+                $entity {
+                    inventory: inventory
+                }
+            }
+        }
+
+        impl Encodable for $entity {
+            #[inline]
+            fn encode<Wr: io::Write>(&self, wr: Wr) -> Result<usize, encode::Error> {
+                encode_inventory(&self.inventory, wr)
+            }
+        }
+
+        impl Decodable for $entity {
+            #[inline]
+            fn decode<Decoder: io::Read>(decoder: Decoder) -> Result<Self, encode::Error> {
+                Ok($entity {
+                    inventory: decode_inventory(decoder)?
+                })
+            }
+        }
+    )
+}
+
+/// `inv` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct InvMessage {
+    /// Objects announced by the peer
+    pub inventory: Vec<Inventory>,
+}
+
+inventory_message!(InvMessage, new_inv_msg);
+
+/// `getdata` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GetDataMessage {
+    /// Objects requested from the peer
+    pub inventory: Vec<Inventory>,
+}
+
+inventory_message!(GetDataMessage, new_get_data_msg);
+
+/// `notfound` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct NotFoundMessage {
+    /// Requested objects the peer does not have
+    pub inventory: Vec<Inventory>,
+}
+
+inventory_message!(NotFoundMessage, new_not_found_msg);
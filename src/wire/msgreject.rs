@@ -1,34 +1,37 @@
-/// Implementation of rejection message and possible reasons 
+/// Implementation of rejection message and possible reasons
 /// for the rejection
 
 use std::io;
-use consensus::{Encodable, Decodable, ReadExtend};
+use hashes::sha256d;
+
+use blockdata;
+use consensus::{Encodable, Decodable, ReadExtend, WriteExtend};
 use consensus::encode;
 
 /// `reject` message codes corresponding to reasons
-#[derive(PartialEq, Eq, Clone, copy, Debug)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum ReasonRejection {
     /// malformed message
-    MALFORMED = 0x01;
+    MALFORMED = 0x01,
     /// invalid message - not consistent with active protocol
-    INVALID = 0x10;
-    /// 
-    OBSOLETE = 0x11;
+    INVALID = 0x10,
+    /// obsolete message - version or feature no longer supported
+    OBSOLETE = 0x11,
     /// duplicate message
-    DUPLICATE = 0x12;
+    DUPLICATE = 0x12,
     /// nonstandard message - not consistent with the active protocol
-    NONSTANDARD = 0x40;
+    NONSTANDARD = 0x40,
     /// output below dust limit
-    DUST = 0x41;
+    DUST = 0x41,
     /// insufficient fees dedicated in outputs
-    FEE = 0x42;
-    /// 
-    CHECKPOINT = 0x43;
+    FEE = 0x42,
+    /// block does not match a hard-coded checkpoint
+    CHECKPOINT = 0x43,
 }
 
 impl Encodable for ReasonRejection {
-    fn encode<Writer: io::Write>(&self, mut e: W) -> Result<usize, encode::Error> {
-        e.write_u8(*self as u8)?;
+    fn encode<Writer: io::Write>(&self, mut e: Writer) -> Result<usize, encode::Error> {
+        e.output_u8(*self as u8)?;
         Ok(1)
     }
 }
@@ -36,19 +39,45 @@ impl Encodable for ReasonRejection {
 impl Decodable for ReasonRejection {
     fn decode<Dec: io::Read>(mut decoder: Dec) -> Result<Self, encode::Error> {
         Ok(match decoder.read_u8()? {
-            0x01 => MALFORMED,
-            0x10 => INVALID,
-            0x11 => OBSOLETE,
-            0x12 => DUPLICATE,
-            0x40 => NONSTANDARD,
-            0x41 => DUST,
-            0x42 => FEE,
-            0x43 => CHECKPOINT,
+            0x01 => ReasonRejection::MALFORMED,
+            0x10 => ReasonRejection::INVALID,
+            0x11 => ReasonRejection::OBSOLETE,
+            0x12 => ReasonRejection::DUPLICATE,
+            0x40 => ReasonRejection::NONSTANDARD,
+            0x41 => ReasonRejection::DUST,
+            0x42 => ReasonRejection::FEE,
+            0x43 => ReasonRejection::CHECKPOINT,
             _    => return Err(encode::Error::ErrorParse("unknown rejection code"))
         })
     }
 }
 
+/// Map a block or transaction validation error onto the code
+/// we report to the peer that relayed the data
+impl<'a> From<&'a blockdata::Error> for ReasonRejection {
+    fn from(err: &'a blockdata::Error) -> ReasonRejection {
+        match *err {
+            blockdata::Error::BlockProofOfWorkError       => ReasonRejection::INVALID,
+            blockdata::Error::BlockTargetError            => ReasonRejection::INVALID,
+            blockdata::Error::BlockMerkleRootError        => ReasonRejection::INVALID,
+            blockdata::Error::BlockVersionObsoleteError   => ReasonRejection::OBSOLETE,
+            blockdata::Error::BlockDuplicateError         => ReasonRejection::DUPLICATE,
+            blockdata::Error::BlockTimeTooOldError        => ReasonRejection::INVALID,
+            blockdata::Error::BlockTimeTooNewError        => ReasonRejection::INVALID,
+            blockdata::Error::BlockCheckpointError        => ReasonRejection::CHECKPOINT,
+            blockdata::Error::BlockInvalidError           => ReasonRejection::INVALID,
+            blockdata::Error::TransactionMalformedError   => ReasonRejection::MALFORMED,
+            blockdata::Error::TransactionInvalidError     => ReasonRejection::INVALID,
+            blockdata::Error::TransactionDuplicateError   => ReasonRejection::DUPLICATE,
+            blockdata::Error::TransactionNonStandardError => ReasonRejection::NONSTANDARD,
+            blockdata::Error::TransactionDustError        => ReasonRejection::DUST,
+            blockdata::Error::TransactionFeeError         => ReasonRejection::FEE,
+        }
+    }
+}
+
+/// `reject` message
+#[derive(PartialEq, Eq, Clone, Debug)]
 pub struct RejectMessage {
     /// the message type that has been rejected
     pub message: String,
@@ -56,17 +85,17 @@ pub struct RejectMessage {
     pub code: ReasonRejection,
     /// rejection reason as a string
     pub reason: String,
-    /// rejected item hash
-    pub hash: sha256d::Hash
+    /// rejected item hash - only present when rejecting `block` or `tx`
+    pub hash: Option<sha256d::Hash>
 }
 
-impl RejectMessage -> RejectMessage {
+impl RejectMessage {
     pub fn new_reject_msg(
-        message: String, 
-        code:    ReasonRejection, 
-        reason:  String, 
-        hash:    sha256d::Hash
-    ) {
+        message: String,
+        code:    ReasonRejection,
+        reason:  String,
+        hash:    Option<sha256d::Hash>
+    ) -> RejectMessage {
         RejectMessage {
             message: message,
             code:    code,
@@ -74,6 +103,58 @@ impl RejectMessage -> RejectMessage {
             hash:    hash
         }
     }
+
+    /// Build the rejection we send back after a validation error
+    /// on data received in a message of type `message`
+    pub fn new_reject_msg_from_error(
+        message: String,
+        err:     &blockdata::Error,
+        hash:    Option<sha256d::Hash>
+    ) -> RejectMessage {
+        RejectMessage {
+            message: message,
+            code:    ReasonRejection::from(err),
+            reason:  err.to_string(),
+            hash:    hash
+        }
+    }
 }
 
-consensus_encoding!(RejectMessage, message, code, reason, hash);
\ No newline at end of file
+impl Encodable for RejectMessage {
+    fn encode<Wr: io::Write>(&self, mut wr: Wr) -> Result<usize, encode::Error> {
+        let mut length = self.message.encode(&mut wr)?
+            + self.code.encode(&mut wr)?
+            + self.reason.encode(&mut wr)?;
+        // The hash is extra data, peers only expect it when
+        // a block or a transaction has been rejected
+        if let Some(ref hash) = self.hash {
+            length += hash.encode(&mut wr)?;
+        }
+        Ok(length)
+    }
+}
+
+impl Decodable for RejectMessage {
+    fn decode<Dec: io::Read>(mut decoder: Dec) -> Result<Self, encode::Error> {
+        let message = Decodable::decode(&mut decoder)?;
+        let code = Decodable::decode(&mut decoder)?;
+        let reason = Decodable::decode(&mut decoder)?;
+
+        // Read whatever is left of the payload - it is either empty
+        // or holds exactly the hash of the rejected item
+        let mut rest = Vec::new();
+        io::Read::read_to_end(&mut decoder, &mut rest).map_err(encode::Error::ErrorEncodeDecode)?;
+        let hash = match rest.len() {
+            0  => None,
+            32 => Some(encode::deserialize(&rest)?),
+            _  => return Err(encode::Error::ErrorParse("invalid reject message hash length")),
+        };
+
+        Ok(RejectMessage {
+            message: message,
+            code:    code,
+            reason:  reason,
+            hash:    hash
+        })
+    }
+}
@@ -1,84 +1,141 @@
+use std::fmt;
 use std::io;
-use std::net::{SocketAddr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
-use std::time::{Duration, Instant};
-use wire::Error{ErrorTORAddress, ErrorAddressLength};
+use std::net::{SocketAddr, Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+use wire::error::Error::{self, ErrorTORAddress};
 use wire::protocol::{ServiceFlag, IPAddress};
 
 use consensus::encode::{self, Encodable, Decodable};
 
 /// Defines information about a peer in the network including the last time it was seen,
 /// services it supports, its IP and port
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct NetAddress {
     /// Last time address was seen
     pub timestamp: i64,
     /// Services provided by the peer
     pub services: ServiceFlag,
 
-    /// Network byte-order ipv6 address, or ipv4-mapped address
+    /// IPv6 address segments, or ipv4-mapped address
     pub ip: IPAddress,
     /// Network port the peer is using
-    // encode as big endian?
     pub port: u16
 }
 
 const ONION : [u16; 3] = [0xFD87, 0xD87E, 0xEB43];
 
 impl NetAddress {
-    pub fn new_netaddress(socket: &SocketAddr) -> NetAddress {
-        let (address, port) match socket {
+    pub fn new_netaddress(socket: &SocketAddr, services: ServiceFlag, timestamp: i64) -> NetAddress {
+        let (address, port) = match socket {
             &SocketAddr::V4(ref addr) => (addr.ip().to_ipv6_mapped().segments(), addr.port()),
             &SocketAddr::V6(ref addr) => (addr.ip().segments(), addr.port())
         };
-        NetAddress { 
-            timestamp: Instant::now(),
-            services:  ServiceFlag,
+        NetAddress {
+            timestamp: timestamp,
+            services:  services,
             ip:        address,
             port:      port,
         }
     }
 
-    pub fn socket_addr(&self) -> Result<SocketAddr, util::Error> {
-        let addr = &self.ip;
-        if addr[0..3] == ONION {
+    pub fn socket_addr(&self) -> Result<SocketAddr, Error> {
+        if self.is_onion() {
             return Err(ErrorTORAddress);
         }
-        let ipv6 = Ipv6Addr::new(
-            addr[0], addr[1], addr[2], addr[3], addr[4], addr[5], addr[6], addr[7]
-        );
-        if let Some(ipv4) = ipv6.to_ipv4() {
-            Ok(SocketAddr::V4(SocketAddrV4::new(ipv4, self.port)))
-        } else {
-            Ok(SocketAddr::V6(SocketAddrV6::new(ipv6, self.port, 0, 0)))
+        match self.ipv4() {
+            Some(ipv4) => Ok(SocketAddr::V4(SocketAddrV4::new(ipv4, self.port))),
+            None       => Ok(SocketAddr::V6(SocketAddrV6::new(self.ipv6(), self.port, 0, 0))),
         }
     }
 
     pub fn has_service(&self, service: u64) -> bool {
-        // implement
+        self.services & service == service
+    }
+
+    pub fn add_service(&mut self, service: u64) {
+        self.services |= service;
+    }
+
+    fn ipv6(&self) -> Ipv6Addr {
+        let addr = &self.ip;
+        Ipv6Addr::new(addr[0], addr[1], addr[2], addr[3], addr[4], addr[5], addr[6], addr[7])
     }
 
-    pub fn add_service(&self, service: u64) {
-        // self.services |= service;
-        // implement
+    /// Return the IPv4 address of an ipv4-mapped address
+    pub fn ipv4(&self) -> Option<Ipv4Addr> {
+        if self.ip[0..6] != [0, 0, 0, 0, 0, 0xffff] {
+            return None;
+        }
+        Some(Ipv4Addr::new((self.ip[6] >> 8) as u8, self.ip[6] as u8, (self.ip[7] >> 8) as u8, self.ip[7] as u8))
+    }
+
+    /// Check if the address is an onion service encoded as OnionCat IPv6
+    pub fn is_onion(&self) -> bool {
+        self.ip[0..3] == ONION
+    }
+
+    /// Check if the address may be reached on the public network
+    pub fn is_routable(&self) -> bool {
+        if self.is_onion() {
+            return true;
+        }
+        if let Some(ipv4) = self.ipv4() {
+            let octets = ipv4.octets();
+            return !(ipv4.is_unspecified() || ipv4.is_loopback() || ipv4.is_private()
+                || ipv4.is_link_local() || ipv4.is_broadcast() || ipv4.is_documentation()
+                || octets[0] == 0
+                // Shared address space, RFC6598
+                || (octets[0] == 100 && octets[1] & 0xc0 == 64)
+                // Benchmarking, RFC2544
+                || (octets[0] == 198 && octets[1] & 0xfe == 18));
+        }
+        let ipv6 = self.ipv6();
+        let segments = &self.ip;
+        !(ipv6.is_unspecified() || ipv6.is_loopback()
+            // Unique local, RFC4193
+            || segments[0] & 0xfe00 == 0xfc00
+            // Link local, RFC4862
+            || segments[0] & 0xffc0 == 0xfe80
+            // Documentation, RFC3849
+            || (segments[0] == 0x2001 && segments[1] == 0x0db8)
+            // ORCHID, RFC4843
+            || (segments[0] == 0x2001 && segments[1] & 0xfff0 == 0x0010))
     }
 
+    /// Network group of the address: the /16 of IPv4 addresses, the /32 of
+    /// IPv6 ones; peers of a group are likely run by the same operator
+    pub fn group(&self) -> Vec<u8> {
+        if !self.is_routable() {
+            return vec![0];
+        }
+        if let Some(ipv4) = self.ipv4() {
+            let octets = ipv4.octets();
+            return vec![1, octets[0], octets[1]];
+        }
+        // Onion services are grouped by the first 4 bits of their key
+        if self.is_onion() {
+            return vec![3, (self.ip[3] >> 12) as u8];
+        }
+        vec![2, (self.ip[0] >> 8) as u8, self.ip[0] as u8, (self.ip[1] >> 8) as u8, self.ip[1] as u8]
+    }
 }
 
 /// Convert adress segments to big endian from the source endianness
 fn addr_to_big_endian(addr: [u16; 8]) -> [u16; 8] {
     [addr[0].to_be(), addr[1].to_be(), addr[2].to_be(), addr[3].to_be(),
-    [addr[4].to_be(), arrd[5].to_be(), addr[6].to_be(), addr[7].to_be()]
+     addr[4].to_be(), addr[5].to_be(), addr[6].to_be(), addr[7].to_be()]
 }
 
+/// Encoded as in `addr` messages, with a 32-bit timestamp
 impl Encodable for NetAddress {
     #[inline]
     fn encode<Wr: io::Write>(
         &self,
         mut wr: Wr,
     )-> Result<usize, encode::Error> {
-        let length = self.timestamp(&mut wr)?
+        let length = (self.timestamp as u32).encode(&mut wr)?
             + self.services.encode(&mut wr)?
             + addr_to_big_endian(self.ip).encode(&mut wr)?
-            /// Covert port number to big endian from the source endianness
+            // Covert port number to big endian from the source endianness
             + self.port.to_be().encode(&mut wr)?;
         Ok(length)
     }
@@ -87,8 +144,9 @@ impl Encodable for NetAddress {
 impl Decodable for NetAddress {
     #[inline]
     fn decode<Decoder: io::Read>(mut decoder: Decoder) -> Result<Self, encode::Error> {
+        let timestamp: u32 = Decodable::decode(&mut decoder)?;
         Ok(NetAddress {
-            timestamp: Decodable::decode(&mut decoder)?,
+            timestamp: timestamp as i64,
             services:  Decodable::decode(&mut decoder)?,
             ip:        addr_to_big_endian(Decodable::decode(&mut decoder)?),
             port:      u16::from_be(Decodable::decode(decoder)?)
@@ -98,31 +156,9 @@ impl Decodable for NetAddress {
 
 impl fmt::Debug for NetAddress {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        //
         write!(
             f, "NetAddress {{time: {:?}, services: {:?}, ip: {:?}, port: {:?}}}",
             self.timestamp, self.services, self.ip, self.port
         )
     }
 }
-
-impl Clone for NetAddress {
-    fn clone (&self) -> NetAddress {
-        NetAddress {
-            timestamp: self.timestamp,
-            services:  self.services,
-            ip:        self.ip,
-            port:      self.port,
-        }
-    }
-}
-
-implf PartialEq for Address {
-    fn eq(&self, other: &NetAddress) -> bool {
-        self.timestamp == other.timestamp && 
-        self.services == other.services && 
-        &self.ip[..] == &other.ip[..] &&
-        self.port == other.port
-    }
-}
-
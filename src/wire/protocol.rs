@@ -2,8 +2,9 @@ use std::string;
 use std::string::String;
 
 /// Identifies services supported by the peer
-type ServiceFlag = u64;
-type IPAddress = [u16, 8];
+pub type ServiceFlag = u64;
+/// IPv6 address segments, IPv4 addresses being mapped into IPv6
+pub type IPAddress = [u16; 8];
 
 const SERVICE_FLAGS: [String; 3] = ["SFNodeNetwork", "SFNodeBloom", "SFNodeCF"];
 